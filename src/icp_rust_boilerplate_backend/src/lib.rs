@@ -3,676 +3,13984 @@ extern crate serde;
 use candid::{Decode, Encode};
 use ic_cdk::api::time;
 use std::sync::Mutex;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use ic_cdk::api;
+use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
 use std::{borrow::Cow, cell::RefCell};
+use std::time::Duration;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct Course {
-    id: u64,
+    id: CourseId,
     creator_name: String,
     creator_address: String, // Stores the principal of the caller in string format
     title: String,
     body: String,
     attachment_url: String,
+    // The first tag, kept for clients written before courses had several tags
     keyword: String,
+    // Every tag of the course, the keyword first
+    tags: Vec<String>,
     category: String,
     created_at: u64,
     updated_at: Option<u64>,
-    contact: String,
+    contact: Vec<ContactMethod>,
+    content_rating: Option<ContentRating>,
+    accessibility: Option<Accessibility>,
+    regions: Option<Vec<String>>,
+    // Frequently asked questions, in their order. Only filled in by get_course
+    faq: Option<Vec<FaqItem>>,
+    // created_at and updated_at as RFC 3339 UTC times, such as 2024-03-01T14:30:00Z. Filled in for responses
+    created_at_rfc3339: Option<String>,
+    updated_at_rfc3339: Option<String>,
 }
 
-// a trait that must be implemented for a struct that is stored in a stable struct
-impl Storable for Course {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+// A way to reach the creator of a course, typed so frontends can render proper links
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum ContactMethod {
+    Email(String),
+    Telegram(String),
+    Discord(String),
+    Website(String),
+    Principal(String),
+    // Free text contact of a course created before contacts were structured, that couldn't be recognized
+    Other(String),
+}
+
+impl ContactMethod {
+    // Name of the contact method, used in error messages
+    fn kind(&self) -> &'static str {
+        match self {
+            ContactMethod::Email(_) => "Email",
+            ContactMethod::Telegram(_) => "Telegram",
+            ContactMethod::Discord(_) => "Discord",
+            ContactMethod::Website(_) => "Website",
+            ContactMethod::Principal(_) => "Principal",
+            ContactMethod::Other(_) => "Other",
+        }
     }
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+// Course layout with a free text contact, only read from records written before contacts were structured
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyCourse {
+    id: CourseId,
+    creator_name: String,
+    creator_address: String,
+    title: String,
+    body: String,
+    attachment_url: String,
+    keyword: String,
+    category: String,
+    created_at: u64,
+    updated_at: Option<u64>,
+    contact: String,
+    content_rating: Option<ContentRating>,
+    accessibility: Option<Accessibility>,
+    regions: Option<Vec<String>>,
+}
+
+impl From<LegacyCourse> for Course {
+    fn from(course: LegacyCourse) -> Self {
+        Course {
+            id: course.id,
+            creator_name: course.creator_name,
+            creator_address: course.creator_address,
+            title: course.title,
+            body: course.body,
+            attachment_url: course.attachment_url,
+            tags: vec![course.keyword.clone()],
+            keyword: course.keyword,
+            category: course.category,
+            created_at: course.created_at,
+            updated_at: course.updated_at,
+            contact: _parse_legacy_contact(&course.contact),
+            content_rating: course.content_rating,
+            accessibility: course.accessibility,
+            regions: course.regions,
+            faq: None,
+            created_at_rfc3339: None,
+            updated_at_rfc3339: None,
+        }
     }
 }
 
-// another trait that must be implemented for a struct that is stored in a stable struct
-impl BoundedStorable for Course {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+// Accessibility features of a course, so learners with accessibility needs can find suitable courses
+#[derive(candid::CandidType, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Accessibility {
+    captions_available: bool,
+    transcript_url: Option<String>,
+    screen_reader_friendly: bool,
 }
 
-thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
+// Audience a course is suitable for. Courses without a rating count as AllAges
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum ContentRating {
+    AllAges,
+    Teen,
+    Mature,
+}
 
-    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter")
-    );
+// Identifier of a course. A distinct type so course ids can't be mixed up with other numbers
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct CourseId(u64);
 
-    static STORAGE: RefCell<StableBTreeMap<u64, Course, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
-    ));
+impl std::fmt::Display for CourseId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
-    // Stores a single admin address
-    static ADMIN_ADDRESS: Mutex<Option<String>> = Mutex::new(None);
+// Stored exactly like a u64, so existing keys keep their layout
+impl Storable for CourseId {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(self.0.to_bytes().into_owned())
+    }
 
-    // Stores the moderator addresses
-    static MODERATOR_ADDRESSES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        CourseId(u64::from_bytes(bytes))
+    }
+}
 
-    // Satores teh addresses of banned users
-    static BANNED_ADDRESSES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+impl BoundedStorable for CourseId {
+    const MAX_SIZE: u32 = u64::MAX_SIZE;
+    const IS_FIXED_SIZE: bool = u64::IS_FIXED_SIZE;
 }
 
-//Payload to add a new course obtained from the user
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct CoursePayLoad {
-    title: String,
+// Course as kept in stable memory. The keyword and category are stored as ids into
+// the string table instead of repeating the same strings in every record
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StoredCourse {
+    id: CourseId,
     creator_name: String,
+    creator_address: String,
+    title: String,
     body: String,
     attachment_url: String,
-    keyword: String,
-    category: String,
+    keyword_id: u32,
+    category_id: u32,
+    // Ids of the lower case shadow copies used for matching, so "Rust" and "rust" are one category.
+    // A synonym is stored as the keyword or category it stands for.
+    // Titles are folded when compared instead, to keep records within their bounded size
+    keyword_key: Option<u32>,
+    category_key: Option<u32>,
+    // Ids of the tags after the keyword and of their shadow copies, in the same order.
+    // None for records written before courses had several tags
+    tag_ids: Option<Vec<u32>>,
+    tag_keys: Option<Vec<u32>>,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Free text contact of records written before contacts were structured. Empty for newer records
     contact: String,
+    contacts: Option<Vec<ContactMethod>>,
+    content_rating: Option<ContentRating>,
+    accessibility: Option<Accessibility>,
+    regions: Option<Vec<String>>,
 }
 
-//Payload to update a  course obtained from the user
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct CourseUpdatePayLoad {
-    title: Option<String>,
-    creator_name: Option<String>,
-    body: Option<String>,
-    attachment_url: Option<String>,
-    keyword: Option<String>,
-    category: Option<String>,
-    contact: Option<String>,
-}
+impl StoredCourse {
+    // Id of the keyword used for matching. Records written before shadow copies existed use the keyword itself
+    fn keyword_key(&self) -> u32 {
+        self.keyword_key.unwrap_or(self.keyword_id)
+    }
 
-// Payload to filter all the available courses
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct FilterPayLoad {
-    keyword: Option<String>,
-    category: Option<String>,
-    creator_address: Option<String>,
+    // Id of the category used for matching and the category index
+    fn category_key(&self) -> u32 {
+        self.category_key.unwrap_or(self.category_id)
+    }
+
+    // Ids of every tag of the course with the id used for matching it, the keyword first
+    fn tag_pairs(&self) -> Vec<(u32, u32)> {
+        let extra_ids = self.tag_ids.iter().flatten().copied();
+        let extra_keys = self.tag_keys.iter().flatten().copied();
+        std::iter::once((self.keyword_id, self.keyword_key())).chain(extra_ids.zip(extra_keys)).collect()
+    }
 }
 
-// Function to set the admin
-// If the admin is not already set, the address input is set the admin,
-// If the admin is initialized, then only the current admin can change the admin
-#[ic_cdk::update]
-fn set_admin_address(address: String) -> Result<(), Error> {
-    let caller: String = api::caller().to_string();
-    ADMIN_ADDRESS.with(|admin_address| {
-        let mut admin = admin_address.lock().unwrap();
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for StoredCourse {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-        // If admin address is not set, or the caller is the current admin
-        if admin.is_none() || admin.as_ref().unwrap() == &caller {
-            *admin = Some(address);
-            Ok(())
-        } else {
-            Err(Error:: UnAuthorized {
-                msg: ("Only admin can change".to_string())
-            })
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        // Records written before the string table existed hold the plain Course layout
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(course) => course,
+            Err(_) => _to_stored(&Decode!(bytes.as_ref(), LegacyCourse).unwrap().into()),
         }
-    })
+    }
 }
 
-// Adds a moderator. Only the admin can add moderators.
-#[ic_cdk::update]
-fn add_moderator(address: String) -> Result<(), String> {
-    // Get the caller's principal
-    let caller = api::caller().to_string();
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for StoredCourse {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
 
-    // Check if admin address is set and if caller is admin
-    let is_admin = _is_admin(caller);
+// Implements Storable and BoundedStorable for a candid type with the given max size
+macro_rules! impl_storable {
+    ($type:ty, $max_size:expr) => {
+        impl Storable for $type {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                Cow::Owned(Encode!(self).unwrap())
+            }
 
-    if is_admin {
-        let result = MODERATOR_ADDRESSES.with(|moderator_addresses| {
-            let mut addresses = moderator_addresses.lock().unwrap();
-            
-            // Check if the maximum number of moderators is reached
-            if addresses.len() >= 5 {
-                return Err("Maximum number of moderators reached".to_string())
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                Decode!(bytes.as_ref(), Self).unwrap()
             }
-    
-            // Check if the moderator address already exists
-            if addresses.contains(&address) {
-                return Err("Moderator address already exists".to_string())
+        }
+
+        impl BoundedStorable for $type {
+            const MAX_SIZE: u32 = $max_size;
+            const IS_FIXED_SIZE: bool = false;
+        }
+    };
+    // Records that fail to decode are read in an older layout and converted
+    ($type:ty, $max_size:expr, legacy $legacy:ty) => {
+        impl Storable for $type {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                Cow::Owned(Encode!(self).unwrap())
             }
 
-            addresses.push(address);
-            Ok(())
-        });
-        result
-    } else {
-        Err("Only admin can add moderators".to_string())
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                match Decode!(bytes.as_ref(), Self) {
+                    Ok(value) => value,
+                    Err(_) => Decode!(bytes.as_ref(), $legacy).unwrap().into(),
+                }
+            }
+        }
+
+        impl BoundedStorable for $type {
+            const MAX_SIZE: u32 = $max_size;
+            const IS_FIXED_SIZE: bool = false;
+        }
+    };
+}
+
+// Checks that a record fits in the bounded size of its type before it is stored, since
+// inserting a larger record traps the canister
+fn _check_fits<T: BoundedStorable>(record: &T, name: &str) -> Result<(), Error> {
+    if record.to_bytes().len() > T::MAX_SIZE as usize {
+        return Err(Error::invalid_input(format!(
+            "The {} is too large to save, it must fit in {} bytes",
+            name,
+            T::MAX_SIZE
+        )));
     }
+    Ok(())
 }
 
-// Removes a moderator. Only admin can remove moderators.
-#[ic_cdk::update]
-fn remove_moderator(address: String) -> Result<(), Error> {
-    // Get the caller's principal
-    let caller = api::caller().to_string();
+// A string kept once in the string table (categories, keywords)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct InternedString(String);
 
-    // Check if the caller is admin
-    let is_admin: bool = _is_admin(caller);
+impl_storable!(InternedString, 512);
 
-    if is_admin {
-        MODERATOR_ADDRESSES.with(|moderator_addresses| {
-            let mut addresses = moderator_addresses.lock().unwrap();
-            // Check if the moderator address exists
-            if addresses.contains(&address) {
-                addresses.retain(|a| a != &address);
-                Ok(())
-            } else {
-                Err(Error::NotFound {
-                    msg: ("Provided addres is not a moderator".to_string())
-                })
-            }
-        })
-    } else {
-        Err(Error::UnAuthorized {
-            msg: ("only admin can remove moderators".to_string())
-        })
-    }
+// A lower case keyword or category used as a synonym of another one
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct TagKey(String);
+
+impl_storable!(TagKey, 512);
+
+// A keyword or category matched as another one, such as "js" as "javascript".
+// Both are lower case. Applies to keywords and categories alike
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TagSynonym {
+    alias: String,
+    canonical: String,
+    added_by: String,
+    added_at: u64,
 }
 
-// Retrieves a course based on its ID.
-#[ic_cdk::query]
-fn get_course(id: u64) -> Result<Course, Error> {
-    match _get_course_(&id) {
-        Some(course) => Ok(course),
-        None => Err(Error::NotFound {
-            msg: format!("a course with id={} not found", id),
-        }),
-    }
+impl_storable!(TagSynonym, 1024);
+
+// Key of the message catalog, ordering translations by language and then by pattern
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct MessageKey {
+    language: String,
+    pattern: String,
 }
 
-// Filters courses based on the provided criteria (AND condition)
-// The AND condition is such that it retreives the courses which satisfy all the
-// criteria provided by the user
-#[ic_cdk::query]
-fn filter_courses_and(payload: FilterPayLoad) -> Result<Vec<Course>, Error> {
-    // Check if the FilterPayLoad is empty
-    if payload.keyword.is_none() && payload.category.is_none() && payload.creator_address.is_none() {
-        return Err(Error::NotFound {
-            msg: "Filter payload is empty; at least one filter criterion must be provided".to_string(),
-        });
-    }
+impl_storable!(MessageKey, 1024);
 
-    let courses: Vec<Course> = STORAGE.with(|storage| {
-        storage.borrow().iter()
-            .filter_map(|(_, course)| {
-                let mut matches = true;
-                if let Some(ref keyword) = payload.keyword {
-                    matches &= course.keyword == *keyword;
-                }
-                if let Some(ref category) = payload.category {
-                    matches &= course.category == *category;
-                }
-                if let Some(ref creator_address) = payload.creator_address {
-                    matches &= course.creator_address == *creator_address;
-                }
-                if matches {
-                    Some(course.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    });
+// Translation of the English messages matching a pattern. {} in the pattern stands for any text,
+// such as an id or a title, and {1}, {2}... in the translation for the texts it matched, in order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MessageTranslation {
+    language: String,
+    pattern: String,
+    translation: String,
+    updated_by: String,
+    updated_at: u64,
+}
 
-    if courses.is_empty() {
-        Err(Error::NotFound{
-            msg: (
-                "couldn't find a course with provided inputs".to_string()
-            ),
-        })
-    } else {
-        Ok(courses)
-    }
+impl_storable!(MessageTranslation, 4096);
+
+// Courses pinned at the top of a category listing, in their order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CategoryPins {
+    course_ids: Vec<CourseId>,
+    pinned_by: String,
+    pinned_at: u64,
 }
 
-// Filters courses based on the provided criteria (OR condition).
-// The OR condition is such that it retreives the courses which satisfy any of the
-// criteria provided by the user
-#[ic_cdk::query]
-fn filter_courses_or(payload: FilterPayLoad) -> Result<Vec<Course>, Error> {
-    // Check if the FilterPayLoad is empty
-    if payload.keyword.is_none() && payload.category.is_none() && payload.creator_address.is_none() {
-        return Err(Error::NotFound {
-            msg: "Filter payload is empty; at least one filter criterion must be provided".to_string(),
-        });
-    }
-    let courses: Vec<Course> = STORAGE.with(|storage| {
-        storage.borrow().iter()
-            .filter_map(|(_, course)| {
-                let mut matches = false;
-                if let Some(ref keyword) = payload.keyword {
-                    matches |= course.keyword == *keyword; 
-                }
-                if let Some(ref category) = payload.category {
-                    matches |= course.category == *category; 
-                }
-                if let Some(ref creator_address) = payload.creator_address {
-                    matches |= course.creator_address == *creator_address; 
-                }
-                if matches {
-                    Some(course.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    });
+impl_storable!(CategoryPins, 512);
 
-    if courses.is_empty() {
-        Err(Error::NotFound{
-            msg: (
-                "couldn't find a course with provided inputs".to_string()
-            ),
-        })
-    } else {
-        Ok(courses)
-    }
+// Key for per-course, per-day activity counters
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct ActivityKey {
+    course_id: CourseId,
+    day: u64,
 }
 
-// Adds a new course to the storage
-#[ic_cdk::update]
-fn add_course(course: CoursePayLoad) -> Result<Course, Error> {
-    let address_string: String = api::caller().to_string();
-    // Check whether the user is banned
-    BANNED_ADDRESSES.with(|banned_addresses| {
-        let addresses = banned_addresses.lock().unwrap();
-        if addresses.contains(&address_string) {
-            return Err(Error::BannedUser {
-                msg: "User is banned. Cannot add course".to_string(),
-            });
-        } else {
-            //Validation Logic
-            if course.title.is_empty()
-            || course.creator_name.is_empty()
-            || course.body.is_empty()
-            || course.attachment_url.is_empty()
-            || course.keyword.is_empty()
-            || course.category.is_empty()
-            || course.contact.is_empty()
-            {
-                return Err(Error::EmptyFields {
-                    msg: "Please fill in all the required fields to create a course".to_string(),
-                });
-            }
-            let id = ID_COUNTER
-                .with(|counter| {
-                    let current_value = *counter.borrow().get();
-                    counter.borrow_mut().set(current_value + 1)
-                })
-                .expect("cannot increment id counter");
-        
-            let course = Course {
-                id,
-                creator_address: address_string,
-                creator_name: course.creator_name,
-                title: course.title,
-                body: course.body,
-                attachment_url: course.attachment_url,
-                created_at: time(),
-                updated_at: None,
-                category: course.category,
-                keyword: course.keyword,
-                contact: course.contact
-            };
-            do_insert(&course);
-            Ok(course)
-        }
-    })
+impl_storable!(ActivityKey, 64);
+
+// Number of views, likes and enrollments a course received on a single day
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ActivityCounts {
+    views: u64,
+    likes: u64,
+    enrollments: u64,
 }
 
-// Updates an existing course. Only the creator or the admin or a moderator can update
-#[ic_cdk::update]
-fn update_course(id: u64, payload: CourseUpdatePayLoad) -> Result<Course, Error> {
-    match STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut course) => {
-            let caller = api::caller().to_string();
-            let is_allowed = _is_allowed(id, caller);
-            if is_allowed {
-                if let Some(title) = payload.title {
-                    course.title = title;
-                }
-                if let Some(creator_name) = payload.creator_name {
-                    course.creator_name = creator_name;
-                }
-                if let Some(body) = payload.body {
-                    course.body = body;
-                }
-                if let Some(attachment_url) = payload.attachment_url {
-                    course.attachment_url = attachment_url;
-                }
-                if let Some(keyword) = payload.keyword {
-                    course.keyword = keyword;
-                }
-                if let Some(category) = payload.category {
-                    course.category = category;
-                }
-                if let Some(contact) = payload.contact {
-                    course.contact = contact;
-                }
-                course.updated_at = Some(time());
-                do_insert(&course);
-                Ok(course)
-            }else {
-                Err(Error::UnAuthorized {
-                    msg: format!("You are not authorized to update course with id={}", id),
-                })
-            }
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't update a course with id={}. course not found",
-                id
-            ),
-        }),
-    }
+impl_storable!(ActivityCounts, 64);
+
+// Number of completions and content ratings a course received on a single day
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct OutcomeCounts {
+    completions: u64,
+    ratings: u64,
+    rating_total: u64,
 }
 
-// Deletes a course based on the ID. Only the creator or the admin or a moderator can update
-#[ic_cdk::update]
-fn delete_course(id: u64) -> Result<Course, Error> {
-    match STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(course) => {
-            let caller = api::caller().to_string();
+impl_storable!(OutcomeCounts, 64);
 
-            // Checks if the caller is either the creator, or the admin or a moderator
-            let is_allowed = _is_allowed(id, caller);
+// Key for records tied to a course and a user, such as likes and enrollments
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct EngagementKey {
+    course_id: CourseId,
+    address: String,
+}
 
-            // Remove the course from storage
-            if is_allowed {
-                STORAGE.with(|service| service.borrow_mut().remove(&id));
-                Ok(course)
-            } else {
-                Err(Error::UnAuthorized {
-                    msg: format!("You are not authorized to update course with id={}", id),
-                })
-            }
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't update a course with id={}. course not found",
-                id
-            ),
+impl_storable!(EngagementKey, 128);
+
+// Enrollment of a learner in a course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Enrollment {
+    course_id: CourseId,
+    learner: String,
+    enrolled_at: u64,
+    completed_at: Option<u64>,
+    // Cohort the learner joined, for courses that run in cohorts
+    cohort_id: Option<u64>,
+}
+
+impl_storable!(Enrollment, 256);
+
+// Key of the cohorts, by course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CohortKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(CohortKey, 32);
+
+// Time zone a session or cohort was scheduled in, such as "Europe/Berlin" with an offset of 120 minutes
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TimeZone {
+    name: String,
+    utc_offset_minutes: i32,
+}
+
+// A point in time as UTC nanoseconds and as RFC 3339 local time in the time zone it was scheduled in
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct LocalTime {
+    utc: u64,
+    local: String,
+}
+
+// Start and end of a session or cohort in UTC and in local time
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Schedule {
+    starts_at: LocalTime,
+    ends_at: LocalTime,
+}
+
+// A group of learners taking a course together between starts_at and ends_at
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Cohort {
+    id: u64,
+    course_id: CourseId,
+    name: String,
+    starts_at: u64,
+    ends_at: u64,
+    seat_limit: u32,
+    timezone: Option<TimeZone>,
+}
+
+impl_storable!(Cohort, 512);
+
+// A cohort with the number of seats already taken
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CohortInfo {
+    cohort: Cohort,
+    seats_taken: u32,
+    schedule: Schedule,
+}
+
+// Key of the waitlists, ordering the learners of a cohort by when they joined
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct WaitlistKey {
+    course_id: CourseId,
+    cohort_id: u64,
+    position: u64,
+}
+
+impl_storable!(WaitlistKey, 48);
+
+// A learner waiting for a seat in a full cohort. offered_at is set once a seat is offered to them
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct WaitlistEntry {
+    learner: String,
+    joined_at: u64,
+    offered_at: Option<u64>,
+}
+
+impl_storable!(WaitlistEntry, 256);
+
+// Key of the category index, ordering courses by category and then by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CategoryIndexKey {
+    category_id: u32,
+    course_id: CourseId,
+}
+
+impl_storable!(CategoryIndexKey, 64);
+
+// Key of the creator index, ordering courses by creator and then by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CreatorIndexKey {
+    creator: String,
+    course_id: CourseId,
+}
+
+impl_storable!(CreatorIndexKey, 128);
+
+// Key of the search index, ordering courses by word and then by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct SearchIndexKey {
+    token: String,
+    course_id: CourseId,
+}
+
+impl_storable!(SearchIndexKey, 192);
+
+// Key of the prefix index, ordering courses by keyword or title and then by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct PrefixIndexKey {
+    phrase: String,
+    course_id: CourseId,
+}
+
+impl_storable!(PrefixIndexKey, 512);
+
+// What a notification is about
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum NotificationKind {
+    ModeratorTermExpired,
+    ModeratorNomination,
+    CourseTakenDown,
+    TakedownAppealed,
+    CourseReinstated,
+    TakedownAppealRejected,
+    CourseQueuedForReview,
+    CourseApproved,
+    CourseRejected,
+    BanProposed,
+    BanRejected,
+    BanLapsed,
+    CoursesTransferred,
+    PossibleDuplicate,
+    CourseAnnouncement,
+    SessionReminder,
+    SessionCancelled,
+    WaitlistSeatOffered,
+    WaitlistOfferLapsed,
+    FeedbackRequested,
+    AssignmentPosted,
+    SubmissionReviewed,
+    CourseUpdated,
+    VerificationRequested,
+    VerificationReviewed,
+    CourseExpiringSoon,
+    CourseArchived,
+    CourseEndorsed,
+    StreakBadgeEarned,
+    GoalReminder,
+    GoalAchieved,
+    DomainBurst,
+    WeeklyDigest,
+    LinksUnreachable,
+}
+
+// A message dropped into a user's inbox
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Notification {
+    id: u64,
+    recipient: String,
+    kind: NotificationKind,
+    message: String,
+    created_at: u64,
+    read: bool,
+}
+
+impl_storable!(Notification, 1024);
+
+// Key of the notification inboxes, ordering notifications by recipient and then by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct NotificationKey {
+    recipient: String,
+    id: u64,
+}
+
+impl_storable!(NotificationKey, 128);
+
+// Why a course was taken down
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum TakedownReason {
+    Spam,
+    Plagiarism,
+    Inappropriate,
+    Misleading,
+    BrokenContent,
+    Other,
+}
+
+// The creator's appeal against a takedown
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Appeal {
+    message: String,
+    appealed_at: u64,
+    rejected_at: Option<u64>,
+}
+
+// A course taken down by the staff, with the reason shown to its creator
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Takedown {
+    course: Course,
+    reason: TakedownReason,
+    note: String,
+    taken_down_by: String,
+    taken_down_at: u64,
+    appeal: Option<Appeal>,
+}
+
+impl_storable!(Takedown, 3072, legacy LegacyTakedown);
+
+// Takedown layout from before contacts were structured
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyTakedown {
+    course: LegacyCourse,
+    reason: TakedownReason,
+    note: String,
+    taken_down_by: String,
+    taken_down_at: u64,
+    appeal: Option<Appeal>,
+}
+
+impl From<LegacyTakedown> for Takedown {
+    fn from(takedown: LegacyTakedown) -> Self {
+        Takedown {
+            course: takedown.course.into(),
+            reason: takedown.reason,
+            note: takedown.note,
+            taken_down_by: takedown.taken_down_by,
+            taken_down_at: takedown.taken_down_at,
+            appeal: takedown.appeal,
+        }
+    }
+}
+
+// A staff pick: a course endorsed by the admin or a moderator, with an optional note on why
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Endorsement {
+    course_id: CourseId,
+    endorsed_by: String,
+    note: Option<String>,
+    endorsed_at: u64,
+}
+
+impl_storable!(Endorsement, 2560);
+
+// When a course is archived, and whether its creator was reminded beforehand
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseExpiry {
+    expires_at: u64,
+    reminded: bool,
+}
+
+impl_storable!(CourseExpiry, 64);
+
+// A link of a course that could not be reached, with the HTTP status it answered with, if any
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DeadLink {
+    url: String,
+    status: Option<u16>,
+    first_failed_at: u64,
+}
+
+// Outcome of the last check of a course's attachment and website links
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct LinkCheck {
+    checked_at: u64,
+    dead_links: Vec<DeadLink>,
+}
+
+impl_storable!(LinkCheck, 4096);
+
+// The dead links of a course, and when the course is archived if they stay dead
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct DeadLinkReport {
+    course_id: CourseId,
+    title: String,
+    checked_at: u64,
+    dead_links: Vec<DeadLink>,
+    archive_at: u64,
+}
+
+// A course archived when it expired or its links stayed dead. Only its creator can see or restore it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ArchivedCourse {
+    course: Course,
+    archived_at: u64,
+}
+
+impl_storable!(ArchivedCourse, 3072);
+
+// A blocked word or phrase, lower case. A word ending with * matches every word starting with it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct BlockedTerm(String);
+
+impl_storable!(BlockedTerm, 256);
+
+// A domain linked to from courses and comments, lower case and without www.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct DomainKey(String);
+
+impl_storable!(DomainKey, 320);
+
+// Whether links to a domain are always accepted or always rejected
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum DomainPolicy {
+    // Never counted towards bursts, for well known sites such as documentation and code hosting
+    Allow,
+    // Courses and comments linking to the domain are rejected
+    Deny,
+}
+
+// A domain on the allow or deny list, with who put it there and when
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DomainRule {
+    domain: String,
+    policy: DomainPolicy,
+    added_by: String,
+    added_at: u64,
+}
+
+impl_storable!(DomainRule, 512);
+
+// A course or comment linking to a domain
+#[derive(candid::CandidType, Clone, PartialEq, Serialize, Deserialize)]
+enum LinkSource {
+    Course(CourseId),
+    Comment { course_id: CourseId, comment_id: u64 },
+}
+
+// When a course or comment linked to a domain
+#[derive(Clone)]
+struct DomainSighting {
+    source: LinkSource,
+    seen_at: u64,
+}
+
+// A domain that many courses and comments linked to within an hour, waiting for the staff
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DomainBurst {
+    domain: String,
+    // The courses and comments that linked to the domain, oldest first
+    references: Vec<LinkSource>,
+    flagged_at: u64,
+    last_seen_at: u64,
+}
+
+// What the content filter does with a course containing a blocked term
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum ContentFilterAction {
+    Reject,
+    QueueForReview,
+}
+
+// A new or updated course waiting for the staff to review it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct QueuedCourse {
+    course: Course,
+    matched_terms: Vec<String>,
+    queued_at: u64,
+    spam_signals: Option<Vec<SpamSignal>>,
+}
+
+// A sign that a new course may be spam. Each signal adds to the spam score of the course
+#[derive(candid::CandidType, Clone, PartialEq, Serialize, Deserialize)]
+enum SpamSignal {
+    // Another course already has the same title
+    DuplicateTitle { course_id: CourseId },
+    // The body has more links than a course normally needs
+    LinkStuffing { links: u32 },
+    // The creator added many courses within the last hour
+    BurstCreation { courses: u32 },
+    // Many courses and comments linked to the same domain within the last hour
+    RepeatedDomain { domain: String, references: u32 },
+}
+
+impl SpamSignal {
+    fn score(&self) -> u32 {
+        match self {
+            SpamSignal::DuplicateTitle { .. } => 30,
+            SpamSignal::LinkStuffing { links } => (links.saturating_sub(SPAM_LINK_ALLOWANCE) * 10).min(60),
+            SpamSignal::BurstCreation { courses } => (courses.saturating_sub(SPAM_BURST_ALLOWANCE) * 15).min(60),
+            SpamSignal::RepeatedDomain { .. } => SPAM_SCORE_THRESHOLD,
+        }
+    }
+}
+
+// A new course whose body closely matches the body of another creator's course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PossibleDuplicate {
+    course_id: CourseId,
+    original_id: CourseId,
+    // Share of matching fingerprint bits, in percent
+    similarity: u32,
+    flagged_at: u64,
+}
+
+impl_storable!(PossibleDuplicate, 128);
+
+impl_storable!(QueuedCourse, 2048, legacy LegacyQueuedCourse);
+
+// Queued course layout from before contacts were structured
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyQueuedCourse {
+    course: LegacyCourse,
+    matched_terms: Vec<String>,
+    queued_at: u64,
+}
+
+impl From<LegacyQueuedCourse> for QueuedCourse {
+    fn from(queued: LegacyQueuedCourse) -> Self {
+        QueuedCourse {
+            course: queued.course.into(),
+            matched_terms: queued.matched_terms,
+            queued_at: queued.queued_at,
+            spam_signals: None,
+        }
+    }
+}
+
+// A kind of staff action recorded in the moderation log
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum ModerationAction {
+    CourseDeleted,
+    CoursesDeleted,
+    CourseTakenDown,
+    AppealResolved,
+    QueuedCourseApproved,
+    QueuedCourseRejected,
+    UserBanned,
+    UserUnbanned,
+    BanProposed,
+    BanConfirmed,
+    BanRejected,
+    ModeratorAppointed,
+    ModeratorRemoved,
+    PossibleDuplicateDismissed,
+    VerificationApproved,
+    VerificationRejected,
+    VerificationRevoked,
+    CourseEndorsed,
+    EndorsementWithdrawn,
+    ModeratorCategoriesChanged,
+}
+
+// A staff action. prev_hash is the hash of the previous entry, empty for the first one
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ModerationEntry {
+    seq: u64,
+    action: ModerationAction,
+    actor: String,
+    subject: String,
+    details: String,
+    timestamp: u64,
+    prev_hash: Vec<u8>,
+}
+
+// A moderation log entry with its hash: the SHA-256 of the candid encoding of the entry
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct LoggedModerationEntry {
+    entry: ModerationEntry,
+    hash: Vec<u8>,
+}
+
+impl_storable!(LoggedModerationEntry, 3072);
+
+// What a staff note is about
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+enum NoteSubject {
+    Course(CourseId),
+    User(String),
+}
+
+// Key of the staff notes, by subject and then by the order they were written in
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct StaffNoteKey {
+    subject: NoteSubject,
+    id: u64,
+}
+
+impl_storable!(StaffNoteKey, 160);
+
+// An internal note of the staff about a course or a user, such as an earlier warning.
+// Notes can't be edited or deleted, so the notes of a subject are its history
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StaffNote {
+    id: u64,
+    subject: NoteSubject,
+    author: String,
+    body: String,
+    written_at: u64,
+}
+
+impl_storable!(StaffNote, 2560);
+
+// Labels the staff put on courses to triage them
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+enum StaffLabel {
+    NeedsReview,
+    CopyrightQuestion,
+    QualityConcern,
+}
+
+// Key of the course labels, by label and then by course, so a label lists its courses
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CourseLabelKey {
+    label: StaffLabel,
+    course_id: CourseId,
+}
+
+impl_storable!(CourseLabelKey, 32);
+
+// A label on a course, with who put it there and when
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseLabel {
+    course_id: CourseId,
+    label: StaffLabel,
+    labeled_by: String,
+    labeled_at: u64,
+}
+
+impl_storable!(CourseLabel, 256);
+
+// A course on the triage board with its label
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct LabeledCourse {
+    course: Course,
+    label: CourseLabel,
+}
+
+// Principal of a user as a stable map key
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct UserKey(String);
+
+impl_storable!(UserKey, 128);
+
+// Key of the activity feed of a user, ordering it by time
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct UserActivityKey {
+    address: String,
+    at: u64,
+}
+
+impl_storable!(UserActivityKey, 128);
+
+// Kind of an entry in the activity feed of a user, either something they did or something that happened to them
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum UserActivityKind {
+    CoursePublished,
+    CourseEnrolled,
+    CourseCompleted,
+    CourseLiked,
+    CommentPosted,
+    CommentReceived,
+    FeedbackReceived,
+    AssignmentSubmitted,
+    SubmissionGraded,
+}
+
+// An entry in the activity feed of a user
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct UserActivity {
+    kind: UserActivityKind,
+    course_id: CourseId,
+    at: u64,
+}
+
+impl_storable!(UserActivity, 64);
+
+// A badge for learning on a number of consecutive days
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StreakBadge {
+    days: u32,
+    awarded_at: u64,
+}
+
+// Consecutive days on which a user checked in to a session, submitted an assignment or completed a course
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct LearningStreak {
+    current: u32,
+    longest: u32,
+    // Day of the latest learning activity, counted from the epoch
+    last_day: u64,
+    badges: Vec<StreakBadge>,
+}
+
+impl_storable!(LearningStreak, 256);
+
+// Key of the learning goals, by user
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct GoalKey {
+    address: String,
+    id: u64,
+}
+
+impl_storable!(GoalKey, 128);
+
+// What a learner aims for: finishing a course they are enrolled in by a date,
+// or learning on a number of days every week
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum GoalTarget {
+    FinishCourse { course_id: CourseId, by: u64 },
+    WeeklyLearningDays(u32),
+}
+
+// Whether a goal is still being worked on. Weekly goals stay active
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum GoalStatus {
+    Active,
+    Achieved,
+    Missed,
+}
+
+// A learning goal with its progress. For weekly goals, week is the week being counted,
+// from the epoch, and days_this_week the learning days in it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct LearningGoal {
+    id: u64,
+    target: GoalTarget,
+    status: GoalStatus,
+    created_at: u64,
+    last_reminded_at: Option<u64>,
+    week: u64,
+    days_this_week: u32,
+}
+
+impl_storable!(LearningGoal, 256);
+
+// State of a creator's request to be verified
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum VerificationStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Revoked,
+}
+
+// A creator's request to be verified, with the credentials they submitted and the admin's review
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct VerificationRequest {
+    address: String,
+    links: Vec<String>,
+    statement: String,
+    status: VerificationStatus,
+    submitted_at: u64,
+    reviewed_at: Option<u64>,
+}
+
+impl_storable!(VerificationRequest, 8192);
+
+// Public verification status of a creator
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CreatorVerification {
+    verified: bool,
+    reviewed_at: Option<u64>,
+}
+
+// Who can see a piece of a creator's details on their courses
+#[derive(candid::CandidType, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+enum Visibility {
+    #[default]
+    Public,
+    EnrolledLearners,
+    Hidden,
+}
+
+// Per user settings. Fields added later must be optional so stored settings keep decoding
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct UserSettings {
+    contact_visibility: Visibility,
+    profile_visibility: Visibility,
+    show_mature: Option<bool>,
+    // Whether the user gets the weekly digest in their notifications
+    weekly_digest: Option<bool>,
+    // Language errors and notifications are given in when the catalog has them, such as "es"
+    language: Option<String>,
+}
+
+impl_storable!(UserSettings, 256);
+
+// Key of the block list, ordering blocks by the blocking user
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct BlockKey {
+    blocker: String,
+    blocked: String,
+}
+
+impl_storable!(BlockKey, 192);
+
+// Key of the translations, ordering them by course and then by language
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct TranslationKey {
+    course_id: CourseId,
+    language: String,
+}
+
+impl_storable!(TranslationKey, 64);
+
+// A translated variant of a course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Translation {
+    title: String,
+    body: String,
+    attachment_url: String,
+    updated_at: u64,
+}
+
+impl_storable!(Translation, 1024);
+
+// Key of the announcements, ordering them by course and then by the order they were posted in
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct AnnouncementKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(AnnouncementKey, 32);
+
+// A message from the creator to the learners of a course, such as a schedule change or errata
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Announcement {
+    id: u64,
+    course_id: CourseId,
+    author: String,
+    title: String,
+    body: String,
+    posted_at: u64,
+}
+
+impl_storable!(Announcement, 4096);
+
+// Key of the changelog entries, by course and then by the order they were added in
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct ChangelogKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(ChangelogKey, 32);
+
+// A material update of the content of a course, as described by its creator
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ChangelogEntry {
+    id: u64,
+    course_id: CourseId,
+    version: String,
+    note: String,
+    added_at: u64,
+}
+
+impl_storable!(ChangelogEntry, 1024);
+
+// Key of the edits of a course, by course and then by the order they were made in
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CourseEditKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(CourseEditKey, 32);
+
+// A field changed by an edit, with the start of its previous value and the hash of the whole of it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FieldChange {
+    field: String,
+    old_excerpt: String,
+    old_hash: Vec<u8>,
+}
+
+// The fields an update of a course changed, who made it and when
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseEdit {
+    id: u64,
+    course_id: CourseId,
+    editor: String,
+    edited_at: u64,
+    changes: Vec<FieldChange>,
+}
+
+impl_storable!(CourseEdit, 8192);
+
+// Key of the comments, by course and then by the order they were posted in
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CommentKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(CommentKey, 32);
+
+// A comment of a user on a course. edited_at is set once the comment has been edited,
+// and pinned_at while the creator of the course has it pinned
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Comment {
+    id: u64,
+    course_id: CourseId,
+    author: String,
+    body: String,
+    posted_at: u64,
+    edited_at: Option<u64>,
+    pinned_at: Option<u64>,
+}
+
+impl_storable!(Comment, 2560);
+
+// Key of the previous versions of a comment, in the order they were replaced
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct CommentVersionKey {
+    course_id: CourseId,
+    comment_id: u64,
+    version: u32,
+}
+
+impl_storable!(CommentVersionKey, 48);
+
+// A previous text of a comment, with when it was written and when an edit replaced it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CommentVersion {
+    body: String,
+    written_at: u64,
+    replaced_at: u64,
+}
+
+impl_storable!(CommentVersion, 2560);
+
+// Key of the FAQ items, by course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct FaqKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(FaqKey, 32);
+
+// A frequently asked question about a course with its answer. Items are shown by ascending order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FaqItem {
+    id: u64,
+    question: String,
+    answer: String,
+    order: u32,
+}
+
+impl_storable!(FaqItem, 6144);
+
+// Key of the polls, by course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct PollKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(PollKey, 32);
+
+// A question to the learners of a course with a fixed set of options, open for voting between opens_at and closes_at
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Poll {
+    id: u64,
+    course_id: CourseId,
+    question: String,
+    options: Vec<String>,
+    opens_at: u64,
+    closes_at: u64,
+    created_by: String,
+}
+
+impl_storable!(Poll, 4096);
+
+// Key of the votes, grouping them by poll. The value is the index of the chosen option
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct PollVoteKey {
+    course_id: CourseId,
+    poll_id: u64,
+    voter: String,
+}
+
+impl_storable!(PollVoteKey, 128);
+
+// The votes cast in a poll, one count per option
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PollResults {
+    poll: Poll,
+    tallies: Vec<u64>,
+    total_votes: u64,
+}
+
+// Key of the live sessions, by course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct SessionKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(SessionKey, 32);
+
+// A live session of a course, such as a lecture or office hours
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Session {
+    id: u64,
+    course_id: CourseId,
+    title: String,
+    starts_at: u64,
+    duration_minutes: u32,
+    meeting_url: String,
+    capacity: u32,
+    created_by: String,
+    // Set once the scheduler has sent the reminders
+    reminded: bool,
+    timezone: Option<TimeZone>,
+}
+
+impl_storable!(Session, 1024);
+
+impl Session {
+    // Time the session ends
+    fn ends_at(&self) -> u64 {
+        self.starts_at + Duration::from_secs(self.duration_minutes as u64 * 60).as_nanos() as u64
+    }
+}
+
+// Key of the RSVPs, grouping them by session. The value is the time of the RSVP
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct RsvpKey {
+    course_id: CourseId,
+    session_id: u64,
+    learner: String,
+}
+
+impl_storable!(RsvpKey, 128);
+
+// An upcoming session with the number of learners attending and whether the caller is one of them
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct UpcomingSession {
+    session: Session,
+    rsvp_count: u32,
+    attending: bool,
+    schedule: Schedule,
+}
+
+// Key of the attendance records, grouping them by session
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct AttendanceKey {
+    course_id: CourseId,
+    session_id: u64,
+    learner: String,
+}
+
+impl_storable!(AttendanceKey, 128);
+
+// A learner's attendance of a session. marked_by is the staff member or creator who marked it,
+// or none when the learner checked in themselves
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AttendanceRecord {
+    session_id: u64,
+    learner: String,
+    attended_at: u64,
+    marked_by: Option<String>,
+}
+
+impl_storable!(AttendanceRecord, 256);
+
+// A learner's attendance of the sessions of a course
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AttendanceHistory {
+    learner: String,
+    records: Vec<AttendanceRecord>,
+    // Sessions of the course that have started so far
+    sessions_held: u32,
+}
+
+// Structured feedback of a learner who completed a course. Ratings go from 1 to 5
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FeedbackForm {
+    content_rating: u8,
+    clarity_rating: u8,
+    // 1 is too easy, 3 is about right and 5 is too hard
+    difficulty_rating: u8,
+    would_recommend: bool,
+    comments: Option<String>,
+}
+
+// Feedback as stored and shown to the creator, without the learner who gave it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FeedbackResponse {
+    form: FeedbackForm,
+    submitted_at: u64,
+}
+
+impl_storable!(FeedbackResponse, 2560);
+
+// Aggregate scores of the feedback on a course. Averages are in hundredths, so 425 is 4.25
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct FeedbackSummary {
+    responses: u64,
+    average_content_rating: u64,
+    average_clarity_rating: u64,
+    average_difficulty_rating: u64,
+    recommend_percent: u64,
+}
+
+// Key of the assignments, by course
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct AssignmentKey {
+    course_id: CourseId,
+    id: u64,
+}
+
+impl_storable!(AssignmentKey, 32);
+
+// Work the learners of a course hand in to be graded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Assignment {
+    id: u64,
+    course_id: CourseId,
+    title: String,
+    instructions: String,
+    due_at: Option<u64>,
+    max_score: u32,
+    created_at: u64,
+}
+
+impl_storable!(Assignment, 6144);
+
+// Key of the submissions, grouping them by assignment
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct SubmissionKey {
+    course_id: CourseId,
+    assignment_id: u64,
+    learner: String,
+}
+
+impl_storable!(SubmissionKey, 128);
+
+// Where a submission stands in grading
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum SubmissionStatus {
+    Submitted,
+    ReturnedForRevision,
+    Graded,
+}
+
+// A learner's work on an assignment, as text, a link to an attachment or both
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Submission {
+    assignment_id: u64,
+    learner: String,
+    text: Option<String>,
+    attachment_url: Option<String>,
+    submitted_at: u64,
+    status: SubmissionStatus,
+    score: Option<u32>,
+    feedback: Option<String>,
+    graded_by: Option<String>,
+    graded_at: Option<u64>,
+}
+
+impl_storable!(Submission, 12288);
+
+// Outcome of reviewing a submission
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum GradeDecision {
+    Grade(u32),
+    ReturnForRevision,
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+            .expect("Cannot create a counter")
+    );
+
+    static STORAGE: RefCell<StableBTreeMap<CourseId, StoredCourse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    ));
+
+    // String table: id -> string
+    static INTERNED_STRINGS: RefCell<StableBTreeMap<u32, InternedString, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+    ));
+
+    // Reverse lookup of the string table: string -> id
+    static INTERNED_IDS: RefCell<StableBTreeMap<InternedString, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+
+    // Version of the layout of the stored data, used to run migrations on upgrade
+    static SCHEMA_VERSION: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))), 0)
+            .expect("Cannot create the schema version")
+    );
+
+    // Heap state saved by pre_upgrade and restored by post_upgrade, empty in between
+    static HEAP_STATE: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(62))), Vec::new())
+            .expect("Cannot create the heap state")
+    );
+
+    // Daily view, like and enrollment counters per course
+    static ACTIVITY: RefCell<StableBTreeMap<ActivityKey, ActivityCounts, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+
+    // Likes per course and user, storing the time of the like
+    static LIKES: RefCell<StableBTreeMap<EngagementKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+    ));
+
+    // Enrollments per course and learner
+    static ENROLLMENTS: RefCell<StableBTreeMap<EngagementKey, Enrollment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+    ));
+
+    // Index of the courses by category
+    static CATEGORY_INDEX: RefCell<StableBTreeMap<CategoryIndexKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+    ));
+
+    // Index of the courses by creator
+    static CREATOR_INDEX: RefCell<StableBTreeMap<CreatorIndexKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+    ));
+
+    // Notification inbox of every user
+    static NOTIFICATIONS: RefCell<StableBTreeMap<NotificationKey, Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+
+    static NOTIFICATION_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))), 0)
+            .expect("Cannot create a notification counter")
+    );
+
+    // Courses taken down by the staff, kept out of the course storage until they are reinstated
+    static TAKEDOWNS: RefCell<StableBTreeMap<CourseId, Takedown, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+    ));
+
+    // Words and phrases that courses may not contain
+    static BLOCKED_TERMS: RefCell<StableBTreeMap<BlockedTerm, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+
+    // Courses held back by the content filter until the staff review them
+    static MODERATION_QUEUE: RefCell<StableBTreeMap<CourseId, QueuedCourse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+    ));
+
+    // Courses hidden while a ban of their creator waits for the admin
+    static HELD_COURSES: RefCell<StableBTreeMap<CourseId, StoredCourse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+    ));
+
+    // Hash chained log of the actions of the staff, keyed by sequence number
+    static MODERATION_LOG: RefCell<StableBTreeMap<u64, LoggedModerationEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+    ));
+
+    // Settings of the users who changed them from the defaults
+    static USER_SETTINGS: RefCell<StableBTreeMap<UserKey, UserSettings, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+
+    // Users blocked by other users, with the time they were blocked
+    static BLOCKS: RefCell<StableBTreeMap<BlockKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+
+    // Translated variants of courses, by course and language
+    static TRANSLATIONS: RefCell<StableBTreeMap<TranslationKey, Translation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+
+    // Simhash fingerprints of course bodies, used to detect near duplicate content
+    static FINGERPRINTS: RefCell<StableBTreeMap<CourseId, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+    ));
+
+    // New courses flagged as near duplicates of another creator's course, until the staff dismiss them
+    static POSSIBLE_DUPLICATES: RefCell<StableBTreeMap<CourseId, PossibleDuplicate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+    ));
+
+    // Announcements posted on courses, by course
+    static ANNOUNCEMENTS: RefCell<StableBTreeMap<AnnouncementKey, Announcement, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+    ));
+
+    // Frequently asked questions of courses, by course
+    static FAQ: RefCell<StableBTreeMap<FaqKey, FaqItem, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+    ));
+
+    // Polls on courses, by course
+    static POLLS: RefCell<StableBTreeMap<PollKey, Poll, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+    ));
+
+    // Votes cast in polls, by poll and voter
+    static POLL_VOTES: RefCell<StableBTreeMap<PollVoteKey, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+    ));
+
+    // Live sessions of courses, by course
+    static SESSIONS: RefCell<StableBTreeMap<SessionKey, Session, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+    ));
+
+    // Learners attending live sessions, by session
+    static RSVPS: RefCell<StableBTreeMap<RsvpKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+
+    // Cohorts of courses, by course
+    static COHORTS: RefCell<StableBTreeMap<CohortKey, Cohort, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+    ));
+
+    // Learners waiting for a seat in full cohorts, by cohort in the order they joined
+    static WAITLISTS: RefCell<StableBTreeMap<WaitlistKey, WaitlistEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+    ));
+
+    // Attendance of live sessions, by session and learner
+    static ATTENDANCE: RefCell<StableBTreeMap<AttendanceKey, AttendanceRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+    ));
+
+    // Feedback of learners who completed a course, by course and learner
+    static FEEDBACK: RefCell<StableBTreeMap<EngagementKey, FeedbackResponse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+
+    // Assignments of courses, by course
+    static ASSIGNMENTS: RefCell<StableBTreeMap<AssignmentKey, Assignment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+
+    // Submissions to assignments, by assignment and learner
+    static SUBMISSIONS: RefCell<StableBTreeMap<SubmissionKey, Submission, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    // Daily completion and rating counters per course
+    static OUTCOMES: RefCell<StableBTreeMap<ActivityKey, OutcomeCounts, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+    ));
+
+    // Changelog entries of courses, by course
+    static CHANGELOG: RefCell<StableBTreeMap<ChangelogKey, ChangelogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+    ));
+
+    // Field-level edit history of courses, by course
+    static COURSE_EDITS: RefCell<StableBTreeMap<CourseEditKey, CourseEdit, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+    ));
+
+    // Comments on courses, by course
+    static COMMENTS: RefCell<StableBTreeMap<CommentKey, Comment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)))
+    ));
+
+    // Previous versions of edited comments, by comment
+    static COMMENT_VERSIONS: RefCell<StableBTreeMap<CommentVersionKey, CommentVersion, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+    ));
+
+    // Verification requests of creators, by creator
+    static VERIFICATIONS: RefCell<StableBTreeMap<UserKey, VerificationRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)))
+    ));
+
+    // Expiry dates set on courses, by course
+    static COURSE_EXPIRY: RefCell<StableBTreeMap<CourseId, CourseExpiry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)))
+    ));
+
+    // Courses archived when they expired, by course
+    static ARCHIVED_COURSES: RefCell<StableBTreeMap<CourseId, ArchivedCourse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41)))
+    ));
+
+    // Activity feeds of users, by user and time
+    static USER_ACTIVITY: RefCell<StableBTreeMap<UserActivityKey, UserActivity, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42)))
+    ));
+
+    // Staff picks, by course
+    static ENDORSEMENTS: RefCell<StableBTreeMap<CourseId, Endorsement, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)))
+    ));
+
+    // Learning streaks, by user
+    static STREAKS: RefCell<StableBTreeMap<UserKey, LearningStreak, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)))
+    ));
+
+    // Learning goals, by user
+    static GOALS: RefCell<StableBTreeMap<GoalKey, LearningGoal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)))
+    ));
+
+    // API keys for reading the catalog over HTTP, by id
+    static API_KEYS: RefCell<StableBTreeMap<u64, ApiKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46)))
+    ));
+
+    // Canisters subscribed to course events, by principal
+    static SUBSCRIBERS: RefCell<StableBTreeMap<UserKey, Subscriber, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)))
+    ));
+
+    // Course events waiting to be sent to subscribers, oldest first
+    static EVENT_DELIVERIES: RefCell<StableBTreeMap<u64, EventDelivery, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)))
+    ));
+
+    static EVENT_SEQUENCE: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))), 0)
+            .expect("Cannot create an event sequence counter")
+    );
+
+    // Drafts of courses not published yet, by their reserved id
+    static DRAFTS: RefCell<StableBTreeMap<CourseId, Draft, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50)))
+    ));
+
+    // When each principal first made an update call
+    static FIRST_SEEN: RefCell<StableBTreeMap<UserKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)))
+    ));
+
+    // Ban records by banned address, including expired bans until they are cleared
+    static BANS: RefCell<StableBTreeMap<UserKey, BanRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)))
+    ));
+
+    // Internal notes of the staff, by course or user
+    static STAFF_NOTES: RefCell<StableBTreeMap<StaffNoteKey, StaffNote, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)))
+    ));
+
+    // Labels the staff put on courses, by label
+    static COURSE_LABELS: RefCell<StableBTreeMap<CourseLabelKey, CourseLabel, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)))
+    ));
+
+    // Domains on the allow or deny list
+    static DOMAIN_RULES: RefCell<StableBTreeMap<DomainKey, DomainRule, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)))
+    ));
+
+    // Calls and instructions of every profiled method, by day
+    static METHOD_USAGE: RefCell<StableBTreeMap<UsageKey, MethodUsage, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56)))
+    ));
+
+    // Keyword and category synonyms, by alias
+    static TAG_SYNONYMS: RefCell<StableBTreeMap<TagKey, TagSynonym, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57)))
+    ));
+
+    // Courses pinned at the top of each category, by the id of the lower case category
+    static CATEGORY_PINS: RefCell<StableBTreeMap<u32, CategoryPins, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58)))
+    ));
+
+    // Weekly digests computed by the scheduler, by week number since the epoch
+    static WEEKLY_DIGESTS: RefCell<StableBTreeMap<u64, WeeklyDigest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59)))
+    ));
+
+    // Last link check of each course, by course
+    static LINK_CHECKS: RefCell<StableBTreeMap<CourseId, LinkCheck, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(60)))
+    ));
+
+    // Translations of error and notification messages, by language and pattern
+    static MESSAGE_CATALOG: RefCell<StableBTreeMap<MessageKey, MessageTranslation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61)))
+    ));
+
+    // Words of the title, keyword, category and body of every course, by word and course.
+    // The value tells which of those fields have the word, as TOKEN_IN_* bits
+    static SEARCH_INDEX: RefCell<StableBTreeMap<SearchIndexKey, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63)))
+    ));
+
+    // Lower case keyword and title of every course, by phrase and course, for autocomplete.
+    // The value tells which of the two the phrase is, as TOKEN_IN_* bits
+    static PREFIX_INDEX: RefCell<StableBTreeMap<PrefixIndexKey, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(64)))
+    ));
+
+    // Stores a single admin address
+    static ADMIN_ADDRESS: Mutex<Option<String>> = const { Mutex::new(None) };
+
+    // Stores the moderator addresses
+    static MODERATOR_ADDRESSES: Mutex<Vec<String>> = const { Mutex::new(Vec::new()) };
+
+    // Stores the end of the term of moderators appointed for a fixed term
+    static MODERATOR_TERMS: Mutex<BTreeMap<String, u64>> = const { Mutex::new(BTreeMap::new()) };
+
+    // Categories that restricted moderators are limited to, lower case. Moderators without
+    // an entry moderate every category
+    static MODERATOR_CATEGORIES: Mutex<BTreeMap<String, Vec<String>>> = const { Mutex::new(BTreeMap::new()) };
+
+    // Stores the pending moderator nominations with the term offered to the nominee
+    static MODERATOR_NOMINATIONS: Mutex<BTreeMap<String, Option<u64>>> = const { Mutex::new(BTreeMap::new()) };
+
+    // Stores the moderation activity of the admin and the moderators
+    static MODERATOR_STATS: Mutex<BTreeMap<String, ModeratorStats>> = const { Mutex::new(BTreeMap::new()) };
+
+    // What happens to courses containing a blocked term
+    static CONTENT_FILTER_ACTION: Mutex<ContentFilterAction> = const { Mutex::new(ContentFilterAction::Reject) };
+
+    // Whether bans by moderators need to be confirmed by the admin
+    static BAN_APPROVAL_REQUIRED: Mutex<bool> = const { Mutex::new(false) };
+
+    // When the admin paused the canister, None while it is running
+    static PAUSED_AT: Mutex<Option<u64>> = const { Mutex::new(None) };
+
+    // Maintenance window in progress, None outside of maintenance
+    static MAINTENANCE: Mutex<Option<MaintenanceNotice>> = const { Mutex::new(None) };
+
+    // Requirements new creators must meet before publishing
+    static PUBLISHING_REQUIREMENTS: Mutex<PublishingRequirements> = const { Mutex::new(PublishingRequirements {
+        min_account_age_days: None,
+        settings_required: false,
+        verification_required: false,
+    }) };
+
+    // Stores the bans proposed by moderators and waiting for the admin
+    static PENDING_BANS: Mutex<Vec<PendingBan>> = const { Mutex::new(Vec::new()) };
+
+    // Stores the account deletions that still have data left to remove
+    static ACCOUNT_DELETIONS: Mutex<Vec<AccountDeletion>> = const { Mutex::new(Vec::new()) };
+
+    // Recent links to each domain, within the burst window
+    static DOMAIN_SIGHTINGS: Mutex<HashMap<String, Vec<DomainSighting>>> = Mutex::new(HashMap::new());
+
+    // Domains flagged for linking bursts, waiting for the staff
+    static DOMAIN_BURSTS: Mutex<BTreeMap<String, DomainBurst>> = const { Mutex::new(BTreeMap::new()) };
+
+    // Stores the banned addresses whose courses are still being deleted by the scheduler
+    static BAN_CLEANUPS: Mutex<Vec<String>> = const { Mutex::new(Vec::new()) };
+
+    // Whether a batch of link checks started by the scheduler is still waiting for its outcalls
+    static LINK_SWEEP_RUNNING: Mutex<bool> = const { Mutex::new(false) };
+
+    // Whether per-method instruction profiling is switched on (off by default)
+    static PROFILING_ENABLED: Mutex<bool> = const { Mutex::new(false) };
+
+    // Ring buffer of the most recent profiled invocations
+    static PROFILE_SAMPLES: Mutex<VecDeque<ProfileSample>> = const { Mutex::new(VecDeque::new()) };
+
+    // Popularity scores computed by the scheduler, sorted from most to least popular
+    static POPULARITY: Mutex<Vec<PopularityScore>> = const { Mutex::new(Vec::new()) };
+
+    // Reputation of the creators computed by the scheduler, by creator
+    static REPUTATION: Mutex<HashMap<String, CreatorReputation>> = Mutex::new(HashMap::new());
+
+    // Quality scores computed by the scheduler, by course
+    static QUALITY: Mutex<HashMap<CourseId, CourseQuality>> = Mutex::new(HashMap::new());
+
+    // Number of body characters included in course previews
+    static PREVIEW_LENGTH: Mutex<u32> = const { Mutex::new(DEFAULT_PREVIEW_LENGTH) };
+
+    // The site embedded course cards link to, if the admin set it
+    static EMBED_SITE: Mutex<Option<EmbedSite>> = const { Mutex::new(None) };
+}
+
+// Current version of the stored data layout
+const CURRENT_SCHEMA_VERSION: u64 = 7;
+
+// Maximum number of moderators the admin can appoint
+const MAX_MODERATORS: usize = 5;
+
+// Maximum length of a keyword or category
+const MAX_TAG_LENGTH: usize = 100;
+
+// Most tags a course can have, counting its keyword
+const MAX_TAGS: usize = 10;
+
+// Most courses pinned at the top of a category
+const MAX_CATEGORY_PINS: usize = 5;
+
+// Maximum number of courses compared side by side in one call
+const MAX_COMPARED_COURSES: usize = 4;
+
+// Maximum length of the optional links of a course, such as the transcript URL
+const MAX_URL_LENGTH: usize = 200;
+
+// Maximum number of regions a course can target
+const MAX_REGIONS: usize = 20;
+
+// Maximum length of a language tag such as "pt-BR"
+const MAX_LANGUAGE_LENGTH: usize = 35;
+
+// Maximum length of a message pattern and of its translation
+const MAX_MESSAGE_PATTERN_LENGTH: usize = 200;
+const MAX_MESSAGE_TRANSLATION_LENGTH: usize = 700;
+
+// Maximum number of contact methods on a course
+const MAX_CONTACTS: usize = 5;
+
+// Maximum length of the body of an announcement
+const MAX_ANNOUNCEMENT_LENGTH: usize = 700;
+
+// Maximum number of FAQ items on a course
+const MAX_FAQ_ITEMS: usize = 20;
+
+// Maximum lengths of an FAQ question and of its answer
+const MAX_FAQ_QUESTION_LENGTH: usize = 200;
+const MAX_FAQ_ANSWER_LENGTH: usize = 1000;
+
+// Bounds on the number of options of a poll
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 10;
+
+// Maximum length of a poll question
+const MAX_POLL_QUESTION_LENGTH: usize = 200;
+
+// Longest a live session can last, in minutes
+const MAX_SESSION_MINUTES: u32 = 24 * 60;
+
+// Maximum length of a time zone name, and the range of UTC offsets in minutes
+const MAX_TIMEZONE_NAME_LENGTH: usize = 32;
+const UTC_OFFSET_MINUTES: std::ops::RangeInclusive<i32> = -12 * 60..=14 * 60;
+
+// How long before a live session starts its attendees are reminded
+const SESSION_REMINDER_LEAD: u64 = 60 * 60 * 1_000_000_000;
+
+// How long before a course expires its creator is reminded
+const COURSE_EXPIRY_REMINDER_LEAD: u64 = 3 * NANOS_PER_DAY;
+
+// How often the links of a course are checked, how many courses are checked in one scheduler run,
+// and how long links can stay dead before their course is archived
+const LINK_CHECK_INTERVAL: u64 = NANOS_PER_DAY;
+const LINK_CHECK_BATCH: usize = 10;
+const LINK_GRACE_PERIOD: u64 = 7 * NANOS_PER_DAY;
+
+// Response size limit and cycles attached to a link check outcall. Unused cycles are refunded
+const LINK_CHECK_MAX_RESPONSE_BYTES: u64 = 8_192;
+const LINK_CHECK_CYCLES: u128 = 250_000_000;
+
+// Time a learner on a waitlist has to claim the seat offered to them
+const SEAT_CLAIM_WINDOW: u64 = NANOS_PER_DAY;
+
+// How early before a live session starts learners can check in
+const CHECK_IN_LEAD: u64 = 10 * 60 * 1_000_000_000;
+
+// Maximum number of learners marked in one call
+const MAX_ATTENDANCE_BATCH: usize = 100;
+
+// Maximum lengths of the instructions of an assignment and of the text of a submission
+const MAX_INSTRUCTIONS_LENGTH: usize = 1000;
+const MAX_SUBMISSION_LENGTH: usize = 2000;
+
+// Maximum lengths of the version label and of the note of a changelog entry
+const MAX_VERSION_LENGTH: usize = 32;
+const MAX_CHANGELOG_NOTE_LENGTH: usize = 500;
+
+// Number of characters of a previous value kept in the edit history
+const EDIT_EXCERPT_LENGTH: usize = 100;
+
+// Maximum length of a comment
+const MAX_COMMENT_LENGTH: usize = 500;
+
+// Maximum number of comments pinned on a course
+const MAX_PINNED_COMMENTS: usize = 3;
+
+// Maximum number of links a creator can submit with a verification request
+const MAX_VERIFICATION_LINKS: usize = 5;
+
+// Streak lengths in days that earn a badge
+const STREAK_BADGE_DAYS: [u32; 4] = [7, 30, 100, 365];
+
+// Maximum number of learning goals of a user, and the least time between two reminders of a goal
+const MAX_GOALS: usize = 10;
+const GOAL_REMINDER_INTERVAL: u64 = 3 * NANOS_PER_DAY;
+
+// Number of the latest bans shown on the admin dashboard
+const DASHBOARD_RECENT_BANS: usize = 10;
+
+// How long expired bans stay listed before they are cleared
+const BAN_RETENTION: u64 = 90 * NANOS_PER_DAY;
+
+// Size of a page of wasm memory
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+// Most requests per minute an API key can be allowed
+const MAX_API_KEY_REQUESTS_PER_MINUTE: u32 = 600;
+
+// Length of the rate limit window of API keys
+const API_KEY_WINDOW: u64 = 60 * 1_000_000_000;
+
+// Times the sending of a course event to a subscriber is tried before it is dropped
+const MAX_EVENT_DELIVERY_ATTEMPTS: u32 = 5;
+
+// Most drafts a creator can have at once
+const MAX_DRAFTS_PER_CREATOR: usize = 20;
+
+// Spam score at which a new course is queued for review instead of being published
+const SPAM_SCORE_THRESHOLD: u32 = 60;
+
+// Links a course body can have before they count towards the spam score
+const SPAM_LINK_ALLOWANCE: u32 = 5;
+
+// Courses a creator can add within an hour before they count towards the spam score
+const SPAM_BURST_ALLOWANCE: u32 = 3;
+
+// Distinct courses and comments linking to a domain within the window that make a burst
+const DOMAIN_BURST_THRESHOLD: usize = 5;
+
+// Time in which links to the same domain are counted towards a burst
+const DOMAIN_BURST_WINDOW: u64 = 60 * 60 * 1_000_000_000;
+
+// Most references kept with a flagged burst
+const MAX_BURST_REFERENCES: usize = 50;
+
+// Number of differing fingerprint bits up to which two course bodies count as near duplicates
+const MAX_DUPLICATE_DISTANCE: u32 = 3;
+
+// Bodies with fewer words aren't fingerprinted, since short texts match too easily
+const MIN_FINGERPRINT_WORDS: usize = 20;
+
+// Markup that can run code or pull in other pages when a frontend renders course content
+const FORBIDDEN_MARKUP: [&str; 11] = [
+    "<script", "<iframe", "<object", "<embed", "<style", "<form", "<frame", "<meta", "<link", "javascript:", "vbscript:",
+];
+
+// Link schemes allowed in markdown links and images. Links without a scheme are relative
+const ALLOWED_LINK_SCHEMES: [&str; 3] = ["http:", "https:", "mailto:"];
+
+// Maximum length of free text notes and messages attached to moderation actions
+const MAX_NOTE_LENGTH: usize = 500;
+
+// Time the admin has to confirm a ban proposed by a moderator before it lapses
+const BAN_APPROVAL_WINDOW: u64 = 3 * NANOS_PER_DAY;
+
+// Maximum number of records removed by one account deletion batch
+const ACCOUNT_DELETION_BATCH: usize = 100;
+
+// Maximum number of addresses in one bulk ban or unban
+const MAX_BULK_BAN: usize = 100;
+
+// Maximum number of courses of banned users deleted by one call or scheduler run
+const BAN_CLEANUP_BATCH: usize = 200;
+
+// Number of nanoseconds in a day
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// How often the scheduler runs its periodic jobs
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// Days of activity taken into account for popularity, and the half-life of its weight
+const POPULARITY_WINDOW_DAYS: u64 = 30;
+const POPULARITY_HALF_LIFE_DAYS: f64 = 7.0;
+
+// Number of courses in each list of the weekly digest, and the number of past digests kept
+const DIGEST_SIZE: usize = 5;
+const MAX_STORED_DIGESTS: u64 = 52;
+
+// Default and maximum number of days covered by the course analytics
+const DEFAULT_ANALYTICS_DAYS: u32 = 30;
+const MAX_ANALYTICS_DAYS: u32 = 366;
+
+// Maximum number of courses returned in a single page
+const MAX_PAGE_SIZE: u32 = 100;
+
+// Length of the words kept in the search index, and the most words a search can have
+const MIN_TOKEN_LENGTH: usize = 2;
+const MAX_TOKEN_LENGTH: usize = 32;
+const MAX_SEARCH_TOKENS: usize = 10;
+
+// Fields of a course a word of the search index comes from
+const TOKEN_IN_TITLE: u32 = 1;
+const TOKEN_IN_KEYWORD: u32 = 2;
+const TOKEN_IN_CATEGORY: u32 = 4;
+const TOKEN_IN_BODY: u32 = 8;
+
+// Most characters of a title kept in the prefix index, the most suggestions returned at once,
+// and the most prefix index entries read to find them
+const MAX_PHRASE_LENGTH: usize = 100;
+const MAX_SUGGESTIONS: u32 = 20;
+const MAX_SUGGESTION_SCAN: usize = 1000;
+
+// Default number of body characters included in course previews
+const DEFAULT_PREVIEW_LENGTH: u32 = 200;
+
+// Number of body characters included in embedded course cards
+const EMBED_EXCERPT_LENGTH: usize = 140;
+
+// Seconds embedding sites and browsers may cache an embed response
+const EMBED_CACHE_SECONDS: u64 = 300;
+
+// Size budget in bytes for a single chunk of courses, kept below the 2MB response limit
+const CHUNK_SIZE_LIMIT: usize = 1_500_000;
+
+// Sections of the data export of a user, in the order they are returned
+const EXPORT_SECTIONS: [&str; 20] = [
+    "account",
+    "courses",
+    "taken_down_courses",
+    "queued_courses",
+    "likes",
+    "enrollments",
+    "notifications",
+    "poll_votes",
+    "rsvps",
+    "waitlists",
+    "attendance",
+    "feedback",
+    "submissions",
+    "comments",
+    "verification",
+    "archived_courses",
+    "activity",
+    "streak",
+    "goals",
+    "drafts",
+];
+
+// Maximum number of samples kept in the profiling ring buffer
+const PROFILE_BUFFER_SIZE: usize = 500;
+
+// Days of per-method usage kept for the cycles report
+const USAGE_RETENTION_DAYS: u64 = 90;
+
+// Default number of days covered by the cycles report
+const DEFAULT_USAGE_DAYS: u32 = 7;
+
+// Cycles charged per 10 instructions on a 13 node application subnet
+const CYCLES_PER_TEN_INSTRUCTIONS: u64 = 4;
+
+// Cycles charged for executing an update message on a 13 node application subnet
+const UPDATE_MESSAGE_CYCLES: u64 = 590_000;
+
+//Payload to add a new course obtained from the user
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CoursePayLoad {
+    title: String,
+    creator_name: String,
+    body: String,
+    attachment_url: String,
+    // The first tag. Can be left empty when the tags are given
+    keyword: String,
+    // Tags of the course after the keyword
+    tags: Option<Vec<String>>,
+    category: String,
+    contact: Vec<ContactMethod>,
+    content_rating: Option<ContentRating>,
+    accessibility: Option<Accessibility>,
+    regions: Option<Vec<String>>,
+}
+
+impl CoursePayLoad {
+    // Sanitizes and normalizes the text fields, so values made only of whitespace end up empty
+    fn normalized(self) -> Result<Self, Error> {
+        Ok(CoursePayLoad {
+            title: _normalize_line("title", &self.title)?,
+            creator_name: _normalize_line("creator name", &self.creator_name)?,
+            body: _normalize_course_body(&self.body)?,
+            attachment_url: self.attachment_url.trim().to_string(),
+            keyword: _normalize_text(&self.keyword),
+            tags: self.tags.map(|tags| tags.iter().map(|tag| _normalize_text(tag)).collect()),
+            category: _normalize_text(&self.category),
+            ..self
+        })
+    }
+}
+
+//Payload to update a  course obtained from the user
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CourseUpdatePayLoad {
+    title: Option<String>,
+    creator_name: Option<String>,
+    body: Option<String>,
+    attachment_url: Option<String>,
+    // Replaces the first tag, keeping the others
+    keyword: Option<String>,
+    // Replaces every tag. When the keyword is given too it stays the first one
+    tags: Option<Vec<String>>,
+    category: Option<String>,
+    contact: Option<Vec<ContactMethod>>,
+    content_rating: Option<ContentRating>,
+    accessibility: Option<Accessibility>,
+    regions: Option<Vec<String>>,
+}
+
+impl CourseUpdatePayLoad {
+    // Sanitizes and normalizes the text fields that are being updated
+    fn normalized(self) -> Result<Self, Error> {
+        Ok(CourseUpdatePayLoad {
+            title: self.title.map(|title| _normalize_line("title", &title)).transpose()?,
+            creator_name: self.creator_name.map(|name| _normalize_line("creator name", &name)).transpose()?,
+            body: self.body.map(|body| _normalize_course_body(&body)).transpose()?,
+            attachment_url: self.attachment_url.map(|url| url.trim().to_string()),
+            keyword: self.keyword.map(|keyword| _normalize_text(&keyword)),
+            tags: self.tags.map(|tags| tags.iter().map(|tag| _normalize_text(tag)).collect()),
+            category: self.category.map(|category| _normalize_text(&category)),
+            ..self
+        })
+    }
+
+    // Name of the first text field being set to an empty value
+    fn emptied_field(&self) -> Option<&'static str> {
+        [
+            ("title", &self.title),
+            ("creator name", &self.creator_name),
+            ("body", &self.body),
+            ("attachment URL", &self.attachment_url),
+            ("keyword", &self.keyword),
+            ("category", &self.category),
+        ]
+        .into_iter()
+        .find(|(_, value)| matches!(value, Some(value) if value.is_empty()))
+        .map(|(name, _)| name)
+    }
+}
+
+// What a principal must meet before publishing their first course, to raise the cost of throwaway
+// spam identities. Creators with courses already published and the staff are not held to them
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct PublishingRequirements {
+    // Days since the principal first called the canister
+    min_account_age_days: Option<u32>,
+    // Whether the principal must have saved their settings with update_my_settings
+    settings_required: bool,
+    // Whether the principal must have been verified by the staff
+    verification_required: bool,
+}
+
+// Course being written in the editor, under an id reserved when the draft was created.
+// Drafts are kept apart from the courses and stay private until published
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Draft {
+    id: CourseId,
+    creator_address: String,
+    // Fields saved so far, as typed. They are only checked when the draft is published
+    content: CourseUpdatePayLoad,
+    created_at: u64,
+    saved_at: u64,
+}
+
+impl_storable!(Draft, 2048);
+
+// A draft shown as the course it will become, with the fields saved so far
+impl From<Draft> for Course {
+    fn from(draft: Draft) -> Self {
+        let content = draft.content;
+        Course {
+            id: draft.id,
+            creator_name: content.creator_name.unwrap_or_default(),
+            creator_address: draft.creator_address,
+            title: content.title.unwrap_or_default(),
+            body: content.body.unwrap_or_default(),
+            attachment_url: content.attachment_url.unwrap_or_default(),
+            tags: content
+                .keyword
+                .iter()
+                .filter(|keyword| !keyword.is_empty())
+                .chain(content.tags.iter().flatten())
+                .cloned()
+                .collect(),
+            keyword: content.keyword.unwrap_or_default(),
+            category: content.category.unwrap_or_default(),
+            created_at: draft.created_at,
+            updated_at: Some(draft.saved_at),
+            contact: content.contact.unwrap_or_default(),
+            content_rating: content.content_rating,
+            accessibility: content.accessibility,
+            regions: content.regions,
+            ..Default::default()
+        }
+    }
+}
+
+// Criteria to filter the available courses. The first criterion is separate so that a filter
+// always has at least one, and an empty filter can't be sent
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseFilter {
+    first: Criterion,
+    rest: Vec<Criterion>,
+    // How keyword and category criteria are compared, CaseInsensitive when None
+    match_mode: Option<MatchMode>,
+}
+
+impl CourseFilter {
+    // All the criteria of the filter
+    fn criteria(&self) -> Vec<Criterion> {
+        std::iter::once(self.first.clone()).chain(self.rest.iter().cloned()).collect()
+    }
+}
+
+// A keyword or title suggested for a prefix, with the number of courses using it.
+// is_keyword is set when the phrase is the keyword of at least one of them
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct KeywordSuggestion {
+    text: String,
+    is_keyword: bool,
+    course_count: u32,
+}
+
+// A chunk of courses. next_token is set when more courses are left to fetch
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CourseChunk {
+    courses: Vec<Course>,
+    next_token: Option<CourseId>,
+}
+
+// A page of courses by id. next_cursor is the id of the last course, set when more courses follow it
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CourseCursorPage {
+    courses: Vec<Course>,
+    next_cursor: Option<CourseId>,
+}
+
+// Every course, or as many as fit in a single response. When truncated, export_courses continues
+// from next_token
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AllCourses {
+    courses: Vec<Course>,
+    total_count: u64,
+    truncated: bool,
+    next_token: Option<CourseId>,
+}
+
+// A chunk of the caller's data export: a JSON array with records of one section of the export.
+// next_cursor is set when more chunks are left to fetch
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct DataExportChunk {
+    section: String,
+    data: String,
+    next_cursor: Option<ExportCursor>,
+}
+
+// Position in the caller's data export: the section, as an index into EXPORT_SECTIONS,
+// and the number of its records already returned
+#[derive(candid::CandidType, Clone, Copy, Default, Serialize, Deserialize)]
+struct ExportCursor {
+    section: u32,
+    offset: u64,
+}
+
+// The account record of a data export, the only record of its account section
+#[derive(Serialize)]
+struct AccountExport {
+    address: String,
+    exported_at: u64,
+    ban: Option<BanRecord>,
+    settings: UserSettings,
+    blocked_users: Vec<String>,
+}
+
+// A like of a course by the exporting user
+#[derive(Serialize)]
+struct LikeRecord {
+    course_id: CourseId,
+    liked_at: u64,
+}
+
+// A vote of the exporting user in a poll, with the index of the chosen option
+#[derive(Serialize)]
+struct PollVoteRecord {
+    course_id: CourseId,
+    poll_id: u64,
+    option: u32,
+}
+
+// An RSVP of the exporting user to a live session
+#[derive(Serialize)]
+struct RsvpRecord {
+    course_id: CourseId,
+    session_id: u64,
+    rsvped_at: u64,
+}
+
+// A record of the exporting user in a cohort of a course
+#[derive(Serialize)]
+struct CohortRecord<T> {
+    course_id: CourseId,
+    cohort_id: u64,
+    record: T,
+}
+
+// A record of the exporting user about a course, for records that don't name the course themselves
+#[derive(Serialize)]
+struct CourseRecord<T> {
+    course_id: CourseId,
+    record: T,
+}
+
+// A single condition of a course query
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum Criterion {
+    // Matches courses with the keyword among their tags
+    Keyword(String),
+    // Matches courses with any of the tags, or with all of them. An empty list matches no course
+    // with AnyTag and every course with AllTags
+    AnyTag(Vec<String>),
+    AllTags(Vec<String>),
+    Category(String),
+    Creator(String),
+    CreatedAfter(u64),
+    CreatedBefore(u64),
+    UpdatedSince(u64),
+    MaxContentRating(ContentRating),
+    HasCaptions,
+    HasTranscript,
+    ScreenReaderFriendly,
+    Region(String),
+    MinCreatorReputation(u32),
+    Endorsed,
+    MinQuality(u32),
+}
+
+// How the keyword and category criteria compare with the tags of a course.
+// CaseInsensitive matches whole tags ignoring case, so "rust" matches "Rust".
+// Contains matches tags containing the text ignoring case, so "rust" matches "Rust Programming".
+// Exact matches tags as they were written
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum MatchMode {
+    Exact,
+    CaseInsensitive,
+    Contains,
+}
+
+// How the criteria of a query are combined
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Combinator {
+    And,
+    Or,
+}
+
+// Order of the courses returned by a query. UpdatedAt puts the most recently changed courses first,
+// counting courses never updated as changed when created. Title and Creator are alphabetical, ignoring case
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum SortBy {
+    CreatedAtAsc,
+    CreatedAtDesc,
+    Popularity,
+    CreatorReputation,
+    Quality,
+    UpdatedAt,
+    Title,
+    Creator,
+}
+
+// Page of results to return
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Page {
+    offset: u64,
+    limit: u32,
+}
+
+// Payload of query_courses. Without filters every course matches,
+// without sort courses are returned by id, and without page the first page is returned
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct QueryRequest {
+    filters: Vec<Criterion>,
+    combinator: Combinator,
+    sort: Option<SortBy>,
+    page: Option<Page>,
+    // Fields to include in the returned courses besides the id, all of them when None
+    fields: Option<Vec<CourseField>>,
+    // How keyword and category criteria are compared, CaseInsensitive when None
+    match_mode: Option<MatchMode>,
+}
+
+// Field of a course that can be selected in query_courses
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum CourseField {
+    CreatorName,
+    CreatorAddress,
+    Title,
+    Body,
+    AttachmentUrl,
+    Keyword,
+    Category,
+    CreatedAt,
+    UpdatedAt,
+    Contact,
+    ContentRating,
+    Accessibility,
+    Regions,
+}
+
+// Criterion with the keyword or category resolved to the string table ids it matches
+enum ResolvedCriterion {
+    Keyword(TagMatch),
+    AnyTag(Vec<TagMatch>),
+    AllTags(Vec<TagMatch>),
+    Category(TagMatch),
+    Creator(String),
+    CreatedAfter(u64),
+    CreatedBefore(u64),
+    UpdatedSince(u64),
+    MaxContentRating(ContentRating),
+    HasCaptions,
+    HasTranscript,
+    ScreenReaderFriendly,
+    Region(String),
+    // The creators whose reputation is at least the minimum
+    MinCreatorReputation(HashSet<String>),
+    Endorsed,
+    // The courses whose quality score is at least the minimum
+    MinQuality(HashSet<CourseId>),
+}
+
+// Keyword or category of a criterion resolved for its match mode
+enum TagMatch {
+    // The id of the lower case tag, compared with the shadow copy of the course.
+    // None when the string isn't known
+    Key(Option<u32>),
+    // The id of the tag as written, compared with the keyword or category of the course as stored
+    Exact(Option<u32>),
+    // The ids of every lower case tag containing the text
+    AnyOf(HashSet<u32>),
+}
+
+impl TagMatch {
+    fn matches(&self, id: u32, key: u32) -> bool {
+        match self {
+            TagMatch::Key(expected) => *expected == Some(key),
+            TagMatch::Exact(expected) => *expected == Some(id),
+            TagMatch::AnyOf(keys) => keys.contains(&key),
+        }
+    }
+
+    // Checks the tag against every tag of a course, as given by StoredCourse::tag_pairs
+    fn matches_any(&self, tags: &[(u32, u32)]) -> bool {
+        tags.iter().any(|(id, key)| self.matches(*id, *key))
+    }
+}
+
+// A page of courses with the total number of matches, so UIs can render page numbers
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CoursePage {
+    courses: Vec<Course>,
+    total_count: u64,
+    has_more: bool,
+}
+
+// A page of all the courses with the total number of courses
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PaginatedCourses {
+    courses: Vec<Course>,
+    total_count: u64,
+    has_more: bool,
+}
+
+// Short version of a course for list hover-cards and link previews
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CoursePreview {
+    id: CourseId,
+    title: String,
+    creator_name: String,
+    creator_address: String,
+    keyword: String,
+    category: String,
+    created_at: u64,
+    updated_at: Option<u64>,
+    excerpt: String,
+    truncated: bool,
+    content_rating: Option<ContentRating>,
+}
+
+// A moderator and the end of their term, if they were appointed for a fixed term
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ModeratorInfo {
+    address: String,
+    term_expires_at: Option<u64>,
+    // Categories the moderator is restricted to, empty when they moderate every category
+    categories: Vec<String>,
+}
+
+// Moderation activity of a staff member
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct ModeratorStats {
+    address: String,
+    deletions: u64,
+    bans: u64,
+    approvals: u64,
+    report_resolutions: u64,
+    takedowns: u64,
+    last_action_at: Option<u64>,
+}
+
+// The latest moderation log hash with the certificate proving the canister committed to it
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ModerationLogHead {
+    length: u64,
+    head_hash: Vec<u8>,
+    certificate: Option<Vec<u8>>,
+}
+
+// Changes to the caller's settings. Fields left empty keep their current value
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct UserSettingsPayLoad {
+    contact_visibility: Option<Visibility>,
+    profile_visibility: Option<Visibility>,
+    show_mature: Option<bool>,
+    weekly_digest: Option<bool>,
+    // An empty language switches back to English
+    language: Option<String>,
+}
+
+// Payload to add or replace a translation of a course
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct TranslationPayLoad {
+    title: String,
+    body: String,
+    attachment_url: String,
+}
+
+// Payload to post an announcement on a course
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AnnouncementPayLoad {
+    title: String,
+    body: String,
+}
+
+// Payload to add an entry to the changelog of a course
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ChangelogPayLoad {
+    version: String,
+    note: String,
+}
+
+// Payload to request verification: links to the creator's credentials and a statement about them
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VerificationPayLoad {
+    links: Vec<String>,
+    statement: String,
+}
+
+// Payload to add or change an FAQ item. Without an order, a new item goes last and a changed item keeps its place
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct FaqPayLoad {
+    question: String,
+    answer: String,
+    order: Option<u32>,
+}
+
+// Payload to create a poll. Without opens_at the poll opens right away
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PollPayLoad {
+    question: String,
+    options: Vec<String>,
+    opens_at: Option<u64>,
+    closes_at: u64,
+}
+
+// Payload to schedule a live session
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct SessionPayLoad {
+    title: String,
+    starts_at: u64,
+    duration_minutes: u32,
+    meeting_url: String,
+    capacity: u32,
+    timezone: Option<TimeZone>,
+}
+
+// Payload to create a cohort
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CohortPayLoad {
+    name: String,
+    starts_at: u64,
+    ends_at: u64,
+    seat_limit: u32,
+    timezone: Option<TimeZone>,
+}
+
+// Payload to create an assignment
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AssignmentPayLoad {
+    title: String,
+    instructions: String,
+    due_at: Option<u64>,
+    max_score: u32,
+}
+
+// Payload to submit work on an assignment
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct SubmissionPayLoad {
+    text: Option<String>,
+    attachment_url: Option<String>,
+}
+
+impl TranslationPayLoad {
+    // Sanitizes and normalizes the translated texts
+    fn normalized(self) -> Result<Self, Error> {
+        Ok(TranslationPayLoad {
+            title: _normalize_line("title", &self.title)?,
+            body: _normalize_course_body(&self.body)?,
+            attachment_url: self.attachment_url.trim().to_string(),
+        })
+    }
+}
+
+// A page of the caller's notifications, newest first
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct NotificationPage {
+    notifications: Vec<Notification>,
+    total_count: u64,
+    unread_count: u64,
+}
+
+// A course in a list of the weekly digest. The count is its weighted activity of the week for new and
+// trending courses, and the number of feedback responses it got for the most reviewed ones
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DigestEntry {
+    course_id: CourseId,
+    title: String,
+    count: u64,
+}
+
+// Highlights of a week computed by the scheduler once the week is over. Weeks are numbered
+// from the epoch and run from starts_at to ends_at
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct WeeklyDigest {
+    week: u64,
+    starts_at: u64,
+    ends_at: u64,
+    new_courses: Vec<DigestEntry>,
+    trending: Vec<DigestEntry>,
+    most_reviewed: Vec<DigestEntry>,
+    generated_at: u64,
+}
+
+impl_storable!(WeeklyDigest, 8192);
+
+// Precomputed popularity of a course. The score is scaled by 1000 to keep it an integer
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PopularityScore {
+    course_id: CourseId,
+    score: u64,
+}
+
+// Precomputed reputation of a creator, from 0 to 100, with the figures it is based on.
+// The average rating is in hundredths and active_since is when their oldest course was created
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CreatorReputation {
+    address: String,
+    score: u32,
+    average_rating: Option<u64>,
+    completions: u64,
+    takedowns: u64,
+    active_since: u64,
+}
+
+// Precomputed quality of a course, from 0 to 100, with the completeness signals it is based on.
+// Lessons are the sessions and assignments of the course, the outline is the headings of its body,
+// and the average rating is in hundredths. Staff flags count possible duplicate flags and quality concern labels
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseQuality {
+    course_id: CourseId,
+    score: u32,
+    lessons: u64,
+    outline_headings: u64,
+    body_length: u64,
+    valid_attachment: bool,
+    average_rating: Option<u64>,
+    staff_flags: u64,
+}
+
+// Where one of the caller's courses stands: listed, waiting for review, archived when it expired, taken down
+// or still a draft
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum MyCourseState {
+    Published,
+    Queued,
+    Archived,
+    TakenDown,
+    Draft,
+}
+
+// One of the caller's courses found by search_my_courses
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct MyCourseMatch {
+    course: Course,
+    state: MyCourseState,
+}
+
+// Engagement totals of a course along with its latest popularity score
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CourseStats {
+    views: u64,
+    likes: u64,
+    enrollments: u64,
+    popularity_score: u64,
+}
+
+// Engagement totals of all the courses of a creator
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CreatorStats {
+    courses: u64,
+    views: u64,
+    likes: u64,
+    enrollments: u64,
+}
+
+// Everything a public creator page shows, in one call. The name is empty when the creator
+// hides their profile from the caller, and featured courses are the creator's courses that
+// staff endorsed or pinned in their category
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CreatorPage {
+    address: String,
+    name: String,
+    verified: bool,
+    reputation: Option<CreatorReputation>,
+    stats: CreatorStats,
+    featured: Vec<Course>,
+    courses: CoursePage,
+}
+
+// One column of a course comparison. Duration is the total length of the course's sessions,
+// lessons are its sessions and assignments, difficulty and rating are averages of the learners'
+// feedback in hundredths, and languages are the ones the course has been translated into
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CourseComparison {
+    course: Course,
+    duration_minutes: u64,
+    lessons: u64,
+    average_difficulty: Option<u64>,
+    average_rating: Option<u64>,
+    rating_count: u64,
+    enrollments: u64,
+    languages: Vec<String>,
+    quality: Option<u32>,
+}
+
+// Length of the buckets the course analytics are grouped in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum AnalyticsInterval {
+    Day,
+    Week,
+}
+
+impl AnalyticsInterval {
+    fn days(self) -> u64 {
+        match self {
+            AnalyticsInterval::Day => 1,
+            AnalyticsInterval::Week => 7,
+        }
+    }
+}
+
+// Engagement of a course over the days starting at start_day. The average rating is in hundredths
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct AnalyticsBucket {
+    start_day: u64,
+    views: u64,
+    likes: u64,
+    enrollments: u64,
+    completions: u64,
+    ratings: u64,
+    average_rating: Option<u64>,
+}
+
+// Engagement of a course over time, oldest bucket first
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CourseAnalytics {
+    course_id: CourseId,
+    interval: AnalyticsInterval,
+    buckets: Vec<AnalyticsBucket>,
+}
+
+// A ban of a user, with the optional reason and expiry given by the staff member
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BanRecord {
+    address: String,
+    reason: Option<String>,
+    banned_by: String,
+    banned_at: u64,
+    expires_at: Option<u64>,
+}
+
+impl_storable!(BanRecord, 2560);
+
+// Whether a ban still applies
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum BanState {
+    Active,
+    Expired,
+}
+
+// Which bans to list. search matches the address, the reason or the staff member who banned, ignoring case
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BanQuery {
+    state: Option<BanState>,
+    search: Option<String>,
+    page: Option<Page>,
+}
+
+// A page of bans with the total number of matches
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct BanPage {
+    bans: Vec<BanRecord>,
+    total_count: u64,
+    has_more: bool,
+}
+
+// A ban proposed by a moderator, waiting for the admin to confirm or reject it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PendingBan {
+    address: String,
+    reason: Option<String>,
+    expires_at: Option<u64>,
+    proposed_by: String,
+    proposed_at: u64,
+    confirm_before: u64,
+    hidden_course_ids: Vec<CourseId>,
+}
+
+// What happens to the courses of a deleted account
+#[derive(candid::CandidType, Clone, PartialEq, Serialize, Deserialize)]
+enum CoursePolicy {
+    Delete,
+    TransferTo(String),
+}
+
+// An account deletion in progress
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AccountDeletion {
+    address: String,
+    policy: CoursePolicy,
+    requested_at: u64,
+    removed_records: u64,
+}
+
+// Progress of an account deletion returned to the user
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AccountDeletionStatus {
+    policy: CoursePolicy,
+    removed_records: u64,
+    completed: bool,
+}
+
+// Outcome for one address of a bulk ban or unban
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct BulkBanResult {
+    address: String,
+    // Why the address was skipped, None when the ban or unban went through
+    error: Option<Error>,
+    // Number of courses deleted, or hidden for a proposed ban, during the call
+    courses_removed: u64,
+    // Whether the scheduler still has courses of the address to delete
+    cleanup_pending: bool,
+}
+
+// Ban status shown to the banned user
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct BanStatus {
+    is_banned: bool,
+    reason: Option<String>,
+    banned_at: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+// Operating status of the canister
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum ServiceStatus {
+    Ok,
+    ReadOnly,
+    Paused,
+}
+
+// Result of the health check, for monitors and the frontend's status banner
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct HealthCheck {
+    status: ServiceStatus,
+    version: String,
+    schema_version: u64,
+    course_count: u64,
+    enrollment_count: u64,
+    like_count: u64,
+    moderator_count: u64,
+    banned_count: u64,
+    // Notice to show while the canister is in maintenance
+    maintenance: Option<MaintenanceNotice>,
+}
+
+// Notice of a maintenance window, during which changes are rejected
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MaintenanceNotice {
+    message: String,
+    started_at: u64,
+    expected_end: u64,
+}
+
+// SHA-256 digest of the entries of a stable store
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct StoreDigest {
+    name: String,
+    entries: u64,
+    digest: Vec<u8>,
+}
+
+// Digests of all the stable stores with a combined digest, to compare the state before and after
+// an upgrade or a backup restore
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct StateDigest {
+    stores: Vec<StoreDigest>,
+    digest: Vec<u8>,
+    computed_at: u64,
+}
+
+// Everything waiting for the staff with the latest bans and the resources of the canister, for the admin UI
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AdminDashboard {
+    queued_courses: u64,
+    possible_duplicates: u64,
+    pending_appeals: u64,
+    pending_bans: u64,
+    pending_verifications: u64,
+    recent_bans: Vec<BanRecord>,
+    health: HealthCheck,
+    stable_memory_bytes: u64,
+    heap_memory_bytes: u64,
+    cycles: u128,
+}
+
+// Key issued by the admin to an off-chain service for reading the catalog over HTTP. Only the hash
+// of the key is stored. The number of requests is limited per minute
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ApiKey {
+    id: u64,
+    label: String,
+    key_hash: Vec<u8>,
+    requests_per_minute: u32,
+    created_by: String,
+    created_at: u64,
+    revoked_at: Option<u64>,
+    last_used_at: Option<u64>,
+    // Start of the current minute of requests and the requests made in it
+    window_start: u64,
+    window_requests: u32,
+}
+
+impl_storable!(ApiKey, 512);
+
+// A newly issued API key. The key itself is only shown once
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct IssuedApiKey {
+    key: String,
+    api_key: ApiKey,
+}
+
+// HTTP request received through the HTTP gateway
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// HTTP response sent back through the HTTP gateway. upgrade asks the gateway to retry the request as an update call
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    upgrade: Option<bool>,
+}
+
+// Canister receiving course events, once approved by the admin
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Subscriber {
+    canister: String,
+    // Method of the canister called with each CourseEvent
+    method: String,
+    status: SubscriberStatus,
+    requested_at: u64,
+    approved_at: Option<u64>,
+    delivered_count: u64,
+    // Events dropped after running out of delivery attempts
+    failed_count: u64,
+}
+
+impl_storable!(Subscriber, 512);
+
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum SubscriberStatus {
+    Pending,
+    Approved,
+}
+
+// Event pushed to subscriber canisters. The sequence increases with every event,
+// so subscribers can notice events they missed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CourseEvent {
+    sequence: u64,
+    kind: CourseEventKind,
+    course_id: CourseId,
+    creator_address: String,
+    occurred_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum CourseEventKind {
+    // The course was added to the catalog, or put back after a takedown, hold or archive
+    Published,
+    Updated,
+    // The course left the catalog: deleted, taken down, held by a ban or archived
+    Deleted,
+}
+
+// Event waiting to be sent to a subscriber
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct EventDelivery {
+    subscriber: String,
+    event: CourseEvent,
+    attempts: u32,
+    next_attempt_at: u64,
+}
+
+impl_storable!(EventDelivery, 1024);
+
+// Role of a user on the platform, from the most to the least privileged
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Role {
+    Admin,
+    Moderator,
+    Creator,
+    User,
+}
+
+// Limits that apply to the caller's submissions and actions
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct UserLimits {
+    max_tag_length: u32,
+    max_course_size: u32,
+    max_moderators: u32,
+}
+
+// Information about the caller, so frontends can render the right UI
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CallerInfo {
+    principal: String,
+    role: Role,
+    is_banned: bool,
+    limits: UserLimits,
+}
+
+// A single profiled invocation of an endpoint
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ProfileSample {
+    method: String,
+    instructions: u64,
+    timestamp: u64,
+}
+
+// Aggregated profiling figures for one endpoint
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MethodProfile {
+    method: String,
+    calls: u64,
+    total_instructions: u64,
+    max_instructions: u64,
+}
+
+// Key of the per-method usage, by day and then by method
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct UsageKey {
+    day: u64,
+    method: String,
+}
+
+impl_storable!(UsageKey, 128);
+
+// Calls and instructions of one method on one day
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct MethodUsage {
+    calls: u64,
+    instructions: u64,
+    max_instructions: u64,
+}
+
+impl_storable!(MethodUsage, 64);
+
+// Usage of one method over the report period. Cycles are estimated from the instructions and
+// the number of calls, at the rates of a 13 node subnet, and share is the method's percentage of them
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct MethodCycles {
+    method: String,
+    calls: u64,
+    instructions: u64,
+    max_instructions: u64,
+    estimated_cycles: u64,
+    share_percent: u32,
+}
+
+// Estimated cycles of all methods on one day
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct DailyCycles {
+    day: u64,
+    calls: u64,
+    estimated_cycles: u64,
+}
+
+// Cycles usage returned to the admin, with the methods burning the most first
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CyclesReport {
+    from_day: u64,
+    to_day: u64,
+    balance: u128,
+    methods: Vec<MethodCycles>,
+    days: Vec<DailyCycles>,
+}
+
+// The public site of the platform, with course pages at {url}/courses/{id} and creator
+// pages at {url}/creators/{principal}
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct EmbedSite {
+    name: String,
+    url: String,
+}
+
+// State kept on the heap and carried across upgrades. Caches the scheduler recomputes, such as popularity,
+// and the sliding window of domain sightings are left out
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct HeapState {
+    admin: Option<String>,
+    moderators: Vec<String>,
+    moderator_terms: BTreeMap<String, u64>,
+    moderator_categories: BTreeMap<String, Vec<String>>,
+    moderator_nominations: BTreeMap<String, Option<u64>>,
+    moderator_stats: BTreeMap<String, ModeratorStats>,
+    content_filter_action: ContentFilterAction,
+    ban_approval_required: bool,
+    paused_at: Option<u64>,
+    maintenance: Option<MaintenanceNotice>,
+    publishing_requirements: PublishingRequirements,
+    pending_bans: Vec<PendingBan>,
+    account_deletions: Vec<AccountDeletion>,
+    domain_bursts: BTreeMap<String, DomainBurst>,
+    ban_cleanups: Vec<String>,
+    profiling_enabled: bool,
+    profile_samples: VecDeque<ProfileSample>,
+    preview_length: u32,
+    embed_site: Option<EmbedSite>,
+}
+
+// Envelope of the saved heap state. A new layout gets a new variant, and post_upgrade converts
+// the state saved by the previous version into it
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum VersionedHeapState {
+    V1(HeapState),
+}
+
+// Course card served to third party sites. The rating is the average content rating in
+// hundredths, and the url is set once the admin set the site
+#[derive(Serialize)]
+struct CourseEmbed {
+    id: CourseId,
+    title: String,
+    excerpt: String,
+    creator_name: String,
+    creator_verified: bool,
+    category: String,
+    average_rating: Option<u64>,
+    rating_count: u64,
+    url: Option<String>,
+}
+
+// Creator card served to third party sites, with the creator's reputation score
+#[derive(Serialize)]
+struct CreatorEmbed {
+    address: String,
+    name: String,
+    verified: bool,
+    courses: u64,
+    reputation: Option<u32>,
+    url: Option<String>,
+}
+
+// oEmbed 1.0 response of the link type
+#[derive(Serialize)]
+struct OEmbed {
+    version: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: String,
+    author_name: String,
+    author_url: String,
+    provider_name: String,
+    provider_url: String,
+    cache_age: u64,
+}
+
+// Profiling data returned to the admin
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ProfilingReport {
+    enabled: bool,
+    worst_samples: Vec<ProfileSample>,
+    methods: Vec<MethodProfile>,
+}
+
+// Query code paths that can be profiled through an update call.
+// State changes made by queries are discarded, so their cost can only be recorded this way
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum ProfiledQuery {
+    GetCourse(CourseId),
+    FilterCoursesAnd(CourseFilter),
+    FilterCoursesOr(CourseFilter),
+    QueryCourses(QueryRequest),
+}
+
+// Records the instructions used by the current call when dropped, in the daily usage
+// and, while profiling is on, as a sample. Create one at the start of an update endpoint to profile it
+struct ProfileGuard {
+    method: &'static str,
+    start: u64,
+}
+
+impl ProfileGuard {
+    fn new(method: &'static str) -> Self {
+        ProfileGuard {
+            method,
+            start: api::performance_counter(0),
+        }
+    }
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        let instructions = api::performance_counter(0).saturating_sub(self.start);
+        _record_profile(self.method, instructions);
+    }
+}
+
+// Function to set the admin
+// If the admin is not already set, the address input is set the admin,
+// If the admin is initialized, then only the current admin can change the admin
+#[ic_cdk::update]
+fn set_admin_address(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_admin_address");
+    _ensure_running()?;
+    let caller: String = api::caller().to_string();
+    ADMIN_ADDRESS.with(|admin_address| {
+        let mut admin = admin_address.lock().unwrap();
+
+        // If admin address is not set, or the caller is the current admin
+        if admin.is_none() || admin.as_ref().unwrap() == &caller {
+            *admin = Some(address);
+            Ok(())
+        } else {
+            Err(Error::unauthorized("Only admin can change".to_string()))
+        }
+    })
+}
+
+// Adds a moderator. Only the admin can add moderators.
+// If term_expires_at is given, the moderator is demoted automatically once the term ends
+#[ic_cdk::update]
+fn add_moderator(address: String, term_expires_at: Option<u64>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("add_moderator");
+    _ensure_running()?;
+    // Get the caller's principal
+    let caller = api::caller().to_string();
+
+    // Check if admin address is set and if caller is admin
+    let is_admin = _is_admin(caller.clone());
+
+    if is_admin {
+        _appoint_moderator(address, term_expires_at, &caller)
+    } else {
+        Err(Error::unauthorized("Only admin can add moderators".to_string()))
+    }
+}
+
+// Nominates a moderator. Only the admin can nominate moderators.
+// The nominee gets moderator powers once they accept with accept_moderation,
+// so powers are never granted to an identity nobody controls
+#[ic_cdk::update]
+fn nominate_moderator(address: String, term_expires_at: Option<u64>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("nominate_moderator");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can nominate moderators".to_string()));
+    }
+    if let Some(expires_at) = term_expires_at {
+        if expires_at <= time() {
+            return Err(Error::invalid_input("The end of the moderator term must be in the future".to_string()));
+        }
+    }
+    let is_moderator = MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().contains(&address));
+    if is_moderator {
+        return Err(Error::invalid_input("Moderator address already exists".to_string()));
+    }
+    MODERATOR_NOMINATIONS.with(|nominations| {
+        nominations
+            .lock()
+            .unwrap()
+            .insert(address.clone(), term_expires_at)
+    });
+    _notify(
+        &address,
+        NotificationKind::ModeratorNomination,
+        "You have been nominated as a moderator. Call accept_moderation to accept.".to_string(),
+    );
+    Ok(())
+}
+
+// Withdraws a pending moderator nomination. Only the admin can access
+#[ic_cdk::update]
+fn withdraw_moderator_nomination(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("withdraw_moderator_nomination");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can withdraw moderator nominations".to_string()));
+    }
+    match MODERATOR_NOMINATIONS.with(|nominations| nominations.lock().unwrap().remove(&address)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found("Provided address has not been nominated".to_string())),
+    }
+}
+
+// Accepts a pending moderator nomination of the caller
+#[ic_cdk::update]
+fn accept_moderation() -> Result<(), Error> {
+    let _profile = ProfileGuard::new("accept_moderation");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    let term_expires_at = match MODERATOR_NOMINATIONS.with(|nominations| nominations.lock().unwrap().remove(&caller)) {
+        Some(term_expires_at) => term_expires_at,
+        None => {
+            return Err(Error::not_found("You have not been nominated as a moderator".to_string()))
+        }
+    };
+    _appoint_moderator(caller.clone(), term_expires_at, &caller)
+}
+
+// Lists the pending moderator nominations with the term they were offered. Only the admin can access
+#[ic_cdk::query]
+fn get_moderator_nominations() -> Result<Vec<ModeratorInfo>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can list moderator nominations".to_string()));
+    }
+    Ok(MODERATOR_NOMINATIONS.with(|nominations| {
+        nominations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(address, term_expires_at)| ModeratorInfo {
+                address: address.clone(),
+                term_expires_at: *term_expires_at,
+                categories: Vec::new(),
+            })
+            .collect()
+    }))
+}
+
+// Removes a moderator. Only admin can remove moderators.
+#[ic_cdk::update]
+fn remove_moderator(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_moderator");
+    _ensure_running()?;
+    // Get the caller's principal
+    let caller = api::caller().to_string();
+
+    // Check if the caller is admin
+    let is_admin: bool = _is_admin(caller.clone());
+
+    if is_admin {
+        MODERATOR_ADDRESSES.with(|moderator_addresses| {
+            let mut addresses = moderator_addresses.lock().unwrap();
+            // Check if the moderator address exists
+            if addresses.contains(&address) {
+                addresses.retain(|a| a != &address);
+                MODERATOR_TERMS.with(|terms| terms.lock().unwrap().remove(&address));
+                MODERATOR_CATEGORIES.with(|categories| categories.lock().unwrap().remove(&address));
+                _log_moderation(ModerationAction::ModeratorRemoved, &caller, address, String::new());
+                Ok(())
+            } else {
+                Err(Error::not_found("Provided addres is not a moderator".to_string()))
+            }
+        })
+    } else {
+        Err(Error::unauthorized("only admin can remove moderators".to_string()))
+    }
+}
+
+// Sets or clears the end of a moderator's term. Only the admin can access
+#[ic_cdk::update]
+fn set_moderator_term(address: String, term_expires_at: Option<u64>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_moderator_term");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change moderator terms".to_string()));
+    }
+    let is_moderator = MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().contains(&address));
+    if !is_moderator {
+        return Err(Error::not_found("Provided address is not a moderator".to_string()));
+    }
+    MODERATOR_TERMS.with(|terms| {
+        let mut terms = terms.lock().unwrap();
+        match term_expires_at {
+            Some(expires_at) if expires_at <= time() => Err(Error::invalid_input(
+                "The end of the moderator term must be in the future".to_string(),
+            )),
+            Some(expires_at) => {
+                terms.insert(address, expires_at);
+                Ok(())
+            }
+            None => {
+                terms.remove(&address);
+                Ok(())
+            }
+        }
+    })
+}
+
+// Restricts a moderator to some categories: their approve, delete, takedown and ban powers then
+// only apply to courses in those categories. An empty list lifts the restriction. Only the admin can access
+#[ic_cdk::update]
+fn set_moderator_categories(address: String, categories: Vec<String>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_moderator_categories");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can change moderator categories".to_string()));
+    }
+    let is_moderator = MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().contains(&address));
+    if !is_moderator {
+        return Err(Error::not_found("Provided address is not a moderator".to_string()));
+    }
+    let mut categories = categories
+        .iter()
+        .map(|category| {
+            let category = _fold(category.trim());
+            if category.is_empty() {
+                return Err(Error::empty_fields("Categories can't be empty".to_string()).with_field("categories"));
+            }
+            _validate_tag("category", &category)?;
+            Ok(_canonical_tag(&category))
+        })
+        .collect::<Result<Vec<String>, Error>>()?;
+    categories.sort();
+    categories.dedup();
+    let details = categories.join(", ");
+    MODERATOR_CATEGORIES.with(|scopes| {
+        let mut scopes = scopes.lock().unwrap();
+        if categories.is_empty() {
+            scopes.remove(&address);
+        } else {
+            scopes.insert(address.clone(), categories);
+        }
+    });
+    _log_moderation(ModerationAction::ModeratorCategoriesChanged, &caller, address, details);
+    Ok(())
+}
+
+// Lists the moderators with the end of their terms. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_moderators() -> Result<Vec<ModeratorInfo>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller) {
+        return Err(Error::unauthorized("Only admin or moderators can list the moderators".to_string()));
+    }
+    let moderators = MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().clone());
+    let terms = MODERATOR_TERMS.with(|terms| terms.lock().unwrap().clone());
+    Ok(moderators
+        .into_iter()
+        .map(|address| ModeratorInfo {
+            term_expires_at: terms.get(&address).copied(),
+            categories: _moderator_categories(&address).unwrap_or_default(),
+            address,
+        })
+        .collect())
+}
+
+// Retrieves a course based on its ID.
+// If a language is given and the course has a translation in it, the translated title, body
+// and attachment are returned instead of the original ones
+#[ic_cdk::query]
+fn get_course(id: CourseId, language: Option<String>) -> Result<Course, Error> {
+    match _get_course_(&id) {
+        Some(mut course) => {
+            if let Some(translation) = language.and_then(|language| _translation_for(id, &language)) {
+                course.title = translation.title;
+                course.body = translation.body;
+                course.attachment_url = translation.attachment_url;
+            }
+            course.faq = Some(_faq_of(id));
+            Ok(_visible_courses(vec![course]).remove(0))
+        }
+        None => Err(_course_not_found(id)),
+    }
+}
+
+// Adds or replaces the translation of a course into a language
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn set_course_translation(id: CourseId, language: String, payload: TranslationPayLoad) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_course_translation");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(format!("You are not authorized to translate course with id={}", id)));
+    }
+    let payload = payload.normalized()?;
+    if payload.title.is_empty() || payload.body.is_empty() || payload.attachment_url.is_empty() {
+        return Err(Error::empty_fields("Please fill in all the fields of the translation".to_string()));
+    }
+    let language = _normalize_language(&language)?;
+    _validate_attachment_url(&payload.attachment_url)?;
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", payload.title, payload.body));
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(
+            format!("The translation contains blocked terms: {}", matched_terms.join(", ")),
+        ));
+    }
+    let translation = Translation {
+        title: payload.title,
+        body: payload.body,
+        attachment_url: payload.attachment_url,
+        updated_at: time(),
+    };
+    TRANSLATIONS.with(|translations| {
+        translations
+            .borrow_mut()
+            .insert(TranslationKey { course_id: id, language }, translation)
+    });
+    Ok(())
+}
+
+// Removes the translation of a course into a language
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn remove_course_translation(id: CourseId, language: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_course_translation");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(format!("You are not authorized to translate course with id={}", id)));
+    }
+    let key = TranslationKey {
+        course_id: id,
+        language: _normalize_language(&language)?,
+    };
+    match TRANSLATIONS.with(|translations| translations.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("course with id={} has no translation into {}", id, key.language))),
+    }
+}
+
+// Lists the languages a course has been translated into
+#[ic_cdk::query]
+fn get_course_languages(id: CourseId) -> Result<Vec<String>, Error> {
+    _ensure_course_exists(id)?;
+    Ok(_translations_of(id).into_iter().map(|(key, _)| key.language).collect())
+}
+
+// Posts an announcement on a course and notifies the learners enrolled in it
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn post_course_announcement(id: CourseId, payload: AnnouncementPayLoad) -> Result<Announcement, Error> {
+    let _profile = ProfileGuard::new("post_course_announcement");
+    _ensure_running()?;
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller.clone()) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to post announcements on course with id={}", id),
+        ));
+    }
+    let title = _normalize_line("title", &payload.title)?;
+    let body = _normalize_body(&payload.body)?;
+    if title.is_empty() || body.is_empty() {
+        return Err(Error::empty_fields("Please fill in the title and the body of the announcement".to_string()));
+    }
+    _validate_tag("title", &title)?;
+    if body.chars().count() > MAX_ANNOUNCEMENT_LENGTH {
+        return Err(Error::invalid_input(
+            format!("Announcements can be at most {} characters long", MAX_ANNOUNCEMENT_LENGTH),
+        ));
+    }
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", title, body));
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(
+            format!("The announcement contains blocked terms: {}", matched_terms.join(", ")),
+        ));
+    }
+    let announcement = Announcement {
+        id: _announcements_of(id).last().map_or(0, |announcement| announcement.id + 1),
+        course_id: id,
+        author: caller,
+        title,
+        body,
+        posted_at: time(),
+    };
+    let key = AnnouncementKey {
+        course_id: id,
+        id: announcement.id,
+    };
+    ANNOUNCEMENTS.with(|announcements| announcements.borrow_mut().insert(key, announcement.clone()));
+    // Learners who blocked the author don't receive messages from them
+    for learner in _learners_of(id).into_iter().filter(|learner| !_has_blocked(learner, &announcement.author)) {
+        _notify(
+            &learner,
+            NotificationKind::CourseAnnouncement,
+            format!("New announcement on \"{}\" (id={}): {}", course.title, id, announcement.title),
+        );
+    }
+    Ok(announcement)
+}
+
+// Deletes an announcement of a course. Learners keep the notification they were sent
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn delete_course_announcement(id: CourseId, announcement_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("delete_course_announcement");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to delete announcements of course with id={}", id),
+        ));
+    }
+    let key = AnnouncementKey {
+        course_id: id,
+        id: announcement_id,
+    };
+    match ANNOUNCEMENTS.with(|announcements| announcements.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(
+            format!("course with id={} has no announcement with id={}", id, announcement_id),
+        ).with_id(announcement_id)),
+    }
+}
+
+// Retrieves a page of the announcements of a course, newest first
+#[ic_cdk::query]
+fn get_course_announcements(id: CourseId, page: Option<Page>) -> Result<Vec<Announcement>, Error> {
+    _ensure_course_exists(id)?;
+    let page = _validate_page(page)?;
+    Ok(_announcements_of(id)
+        .into_iter()
+        .rev()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Adds an entry to the changelog of a course and notifies the learners enrolled in it
+// Only the creator of the course can access
+#[ic_cdk::update]
+fn add_changelog_entry(id: CourseId, payload: ChangelogPayLoad) -> Result<ChangelogEntry, Error> {
+    let _profile = ProfileGuard::new("add_changelog_entry");
+    _ensure_running()?;
+    _ensure_creator(id)?;
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    let version = _normalize_line("version", &payload.version)?;
+    let note = _normalize_body(&payload.note)?;
+    if version.is_empty() || note.is_empty() {
+        return Err(Error::empty_fields("Please fill in the version and the note of the changelog entry".to_string()));
+    }
+    if version.chars().count() > MAX_VERSION_LENGTH {
+        return Err(Error::invalid_input(format!("The version must be at most {} characters long", MAX_VERSION_LENGTH)));
+    }
+    if note.chars().count() > MAX_CHANGELOG_NOTE_LENGTH {
+        return Err(Error::invalid_input(
+            format!("Changelog notes can be at most {} characters long", MAX_CHANGELOG_NOTE_LENGTH),
+        ));
+    }
+    let entries = _changelog_of(id);
+    if entries.iter().any(|entry| entry.version.eq_ignore_ascii_case(&version)) {
+        return Err(Error::invalid_input(
+            format!("The changelog of course with id={} already has version {}", id, version),
+        ));
+    }
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", version, note));
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(
+            format!("The changelog entry contains blocked terms: {}", matched_terms.join(", ")),
+        ));
+    }
+    let entry = ChangelogEntry {
+        id: entries.last().map_or(0, |entry| entry.id + 1),
+        course_id: id,
+        version,
+        note,
+        added_at: time(),
+    };
+    let key = ChangelogKey { course_id: id, id: entry.id };
+    CHANGELOG.with(|changelog| changelog.borrow_mut().insert(key, entry.clone()));
+    for learner in _learners_of(id) {
+        _notify(
+            &learner,
+            NotificationKind::CourseUpdated,
+            format!("\"{}\" (id={}) was updated to version {}: {}", course.title, id, entry.version, entry.note),
+        );
+    }
+    Ok(entry)
+}
+
+// Retrieves a page of the changelog of a course, newest first
+// Only learners enrolled in the course, its creator, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_course_changelog(id: CourseId, page: Option<Page>) -> Result<Vec<ChangelogEntry>, Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_enrolled(id, &caller) && !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("Only learners enrolled in the course with id={} can see its changelog", id),
+        ));
+    }
+    let page = _validate_page(page)?;
+    Ok(_changelog_of(id)
+        .into_iter()
+        .rev()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Posts a comment on a course
+#[ic_cdk::update]
+fn post_comment(id: CourseId, body: String) -> Result<Comment, Error> {
+    let _profile = ProfileGuard::new("post_comment");
+    _ensure_running()?;
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    let author = api::caller().to_string();
+    if _is_banned(&author) {
+        return Err(Error::banned_user("User is banned. Cannot comment".to_string()));
+    }
+    if _has_blocked(&course.creator_address, &author) {
+        return Err(Error::unauthorized("The creator of this course has blocked you. Cannot comment".to_string()));
+    }
+    let body = _validate_comment(&body)?;
+    let comment_id = _comments_of(id).last().map_or(0, |comment| comment.id + 1);
+    // Comments are not queued, a burst is only flagged for the staff
+    let source = LinkSource::Comment { course_id: id, comment_id };
+    let (domains, _) = _screen_links(&source, &body)?;
+    _record_links(source, domains);
+    let comment = Comment {
+        id: comment_id,
+        course_id: id,
+        author,
+        body,
+        posted_at: time(),
+        edited_at: None,
+        pinned_at: None,
+    };
+    let key = CommentKey { course_id: id, id: comment.id };
+    COMMENTS.with(|comments| comments.borrow_mut().insert(key, comment.clone()));
+    _record_user_activity(&comment.author, UserActivityKind::CommentPosted, id);
+    if let Some(course) = _get_course_(&id).filter(|course| course.creator_address != comment.author) {
+        _record_user_activity(&course.creator_address, UserActivityKind::CommentReceived, id);
+    }
+    Ok(comment)
+}
+
+// Changes the text of the caller's comment. The previous text is kept for the staff
+#[ic_cdk::update]
+fn edit_comment(id: CourseId, comment_id: u64, body: String) -> Result<Comment, Error> {
+    let _profile = ProfileGuard::new("edit_comment");
+    _ensure_running()?;
+    let mut comment = _get_comment(id, comment_id)?;
+    if comment.author != api::caller().to_string() {
+        return Err(Error::unauthorized("Only the author of a comment can edit it".to_string()));
+    }
+    let body = _validate_comment(&body)?;
+    if body == comment.body {
+        return Ok(comment);
+    }
+    let source = LinkSource::Comment { course_id: id, comment_id };
+    let (domains, _) = _screen_links(&source, &body)?;
+    _record_links(source, domains);
+    let now = time();
+    let version = CommentVersion {
+        body: std::mem::replace(&mut comment.body, body),
+        written_at: comment.edited_at.unwrap_or(comment.posted_at),
+        replaced_at: now,
+    };
+    let key = CommentVersionKey {
+        course_id: id,
+        comment_id,
+        version: _comment_versions_of(id, comment_id).len() as u32,
+    };
+    COMMENT_VERSIONS.with(|versions| versions.borrow_mut().insert(key, version));
+    comment.edited_at = Some(now);
+    COMMENTS.with(|comments| comments.borrow_mut().insert(CommentKey { course_id: id, id: comment_id }, comment.clone()));
+    Ok(comment)
+}
+
+// Deletes a comment with its previous versions
+// Only the author of the comment, the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn delete_comment(id: CourseId, comment_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("delete_comment");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let comment = _get_comment(id, comment_id)?;
+    let caller = api::caller().to_string();
+    if comment.author != caller && !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(format!("You are not authorized to delete comments of course with id={}", id)));
+    }
+    _remove_comment(id, comment_id);
+    Ok(())
+}
+
+// Pins a comment, such as errata or a key clarification, so it is listed before the other comments
+// Only the creator of the course can access
+#[ic_cdk::update]
+fn pin_comment(id: CourseId, comment_id: u64) -> Result<Comment, Error> {
+    let _profile = ProfileGuard::new("pin_comment");
+    _ensure_running()?;
+    _ensure_creator(id)?;
+    let mut comment = _get_comment(id, comment_id)?;
+    if comment.pinned_at.is_some() {
+        return Err(Error::invalid_input(format!("The comment with id={} is already pinned", comment_id)));
+    }
+    let pinned = _comments_of(id).iter().filter(|comment| comment.pinned_at.is_some()).count();
+    if pinned >= MAX_PINNED_COMMENTS {
+        return Err(Error::invalid_input(format!("A course can have at most {} pinned comments", MAX_PINNED_COMMENTS)));
+    }
+    comment.pinned_at = Some(time());
+    COMMENTS.with(|comments| comments.borrow_mut().insert(CommentKey { course_id: id, id: comment_id }, comment.clone()));
+    Ok(comment)
+}
+
+// Unpins a comment, which goes back to its place among the other comments
+// Only the creator of the course can access
+#[ic_cdk::update]
+fn unpin_comment(id: CourseId, comment_id: u64) -> Result<Comment, Error> {
+    let _profile = ProfileGuard::new("unpin_comment");
+    _ensure_running()?;
+    _ensure_creator(id)?;
+    let mut comment = _get_comment(id, comment_id)?;
+    if comment.pinned_at.take().is_none() {
+        return Err(Error::invalid_input(format!("The comment with id={} is not pinned", comment_id)));
+    }
+    COMMENTS.with(|comments| comments.borrow_mut().insert(CommentKey { course_id: id, id: comment_id }, comment.clone()));
+    Ok(comment)
+}
+
+// Retrieves a page of the comments on a course, leaving out comments of users the caller blocked.
+// Pinned comments come first in the order they were pinned, followed by the others newest first
+#[ic_cdk::query]
+fn get_course_comments(id: CourseId, page: Option<Page>) -> Result<Vec<Comment>, Error> {
+    _ensure_course_exists(id)?;
+    let page = _validate_page(page)?;
+    let blocked = _blocked_by(&api::caller().to_string());
+    let (mut pinned, others): (Vec<Comment>, Vec<Comment>) = _comments_of(id)
+        .into_iter()
+        .filter(|comment| !blocked.contains(&comment.author))
+        .partition(|comment| comment.pinned_at.is_some());
+    pinned.sort_by_key(|comment| comment.pinned_at);
+    Ok(pinned
+        .into_iter()
+        .chain(others.into_iter().rev())
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Retrieves the previous versions of a comment, oldest first. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_comment_history(id: CourseId, comment_id: u64) -> Result<Vec<CommentVersion>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("You are not authorized to see the history of comments".to_string()));
+    }
+    _get_comment(id, comment_id)?;
+    Ok(_comment_versions_of(id, comment_id))
+}
+
+// Adds a frequently asked question with its answer to a course
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn add_course_faq(id: CourseId, payload: FaqPayLoad) -> Result<FaqItem, Error> {
+    let _profile = ProfileGuard::new("add_course_faq");
+    _ensure_running()?;
+    _ensure_faq_editor(id)?;
+    let items = _faq_of(id);
+    if items.len() >= MAX_FAQ_ITEMS {
+        return Err(Error::invalid_input(format!("A course can have at most {} FAQ items", MAX_FAQ_ITEMS)));
+    }
+    let (question, answer) = _normalize_faq(&payload)?;
+    let item = FaqItem {
+        id: FAQ.with(|faq| {
+            faq.borrow()
+                .range(FaqKey { course_id: id, id: 0 }..)
+                .take_while(|(key, _)| key.course_id == id)
+                .last()
+                .map_or(0, |(key, _)| key.id + 1)
+        }),
+        question,
+        answer,
+        order: payload
+            .order
+            .unwrap_or_else(|| items.last().map_or(0, |item| item.order.saturating_add(1))),
+    };
+    FAQ.with(|faq| faq.borrow_mut().insert(FaqKey { course_id: id, id: item.id }, item.clone()));
+    Ok(item)
+}
+
+// Changes an FAQ item of a course
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn update_course_faq(id: CourseId, faq_id: u64, payload: FaqPayLoad) -> Result<FaqItem, Error> {
+    let _profile = ProfileGuard::new("update_course_faq");
+    _ensure_running()?;
+    _ensure_faq_editor(id)?;
+    let key = FaqKey { course_id: id, id: faq_id };
+    let item = FAQ.with(|faq| faq.borrow().get(&key)).ok_or(Error::not_found(
+        format!("course with id={} has no FAQ item with id={}", id, faq_id),
+    ))?;
+    let (question, answer) = _normalize_faq(&payload)?;
+    let item = FaqItem {
+        question,
+        answer,
+        order: payload.order.unwrap_or(item.order),
+        ..item
+    };
+    FAQ.with(|faq| faq.borrow_mut().insert(key, item.clone()));
+    Ok(item)
+}
+
+// Removes an FAQ item from a course
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn remove_course_faq(id: CourseId, faq_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_course_faq");
+    _ensure_running()?;
+    _ensure_faq_editor(id)?;
+    match FAQ.with(|faq| faq.borrow_mut().remove(&FaqKey { course_id: id, id: faq_id })) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(
+            format!("course with id={} has no FAQ item with id={}", id, faq_id),
+        ).with_id(faq_id)),
+    }
+}
+
+// Creates a poll on a course for its enrolled learners to vote in
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn create_course_poll(id: CourseId, payload: PollPayLoad) -> Result<Poll, Error> {
+    let _profile = ProfileGuard::new("create_course_poll");
+    _ensure_running()?;
+    _ensure_poll_manager(id)?;
+    let question = _normalize_line("question", &payload.question)?;
+    let options: Vec<String> = payload
+        .options
+        .iter()
+        .map(|option| _normalize_line("option", option))
+        .collect::<Result<_, _>>()?;
+    if question.is_empty() || options.iter().any(String::is_empty) {
+        return Err(Error::empty_fields("Please fill in the question and every option of the poll".to_string()));
+    }
+    if question.chars().count() > MAX_POLL_QUESTION_LENGTH {
+        return Err(Error::invalid_input(
+            format!("Poll questions can be at most {} characters long", MAX_POLL_QUESTION_LENGTH),
+        ));
+    }
+    if !(MIN_POLL_OPTIONS..=MAX_POLL_OPTIONS).contains(&options.len()) {
+        return Err(Error::invalid_input(
+            format!("A poll needs between {} and {} options", MIN_POLL_OPTIONS, MAX_POLL_OPTIONS),
+        ));
+    }
+    for option in options.iter() {
+        _validate_tag("option", option)?;
+    }
+    let now = time();
+    let opens_at = payload.opens_at.unwrap_or(now);
+    if payload.closes_at <= now.max(opens_at) {
+        return Err(Error::invalid_input("A poll must close in the future and after it opens".to_string()));
+    }
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", question, options.join("\n")));
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(format!("The poll contains blocked terms: {}", matched_terms.join(", "))));
+    }
+    let poll = Poll {
+        id: _polls_of(id).last().map_or(0, |poll| poll.id + 1),
+        course_id: id,
+        question,
+        options,
+        opens_at,
+        closes_at: payload.closes_at,
+        created_by: api::caller().to_string(),
+    };
+    POLLS.with(|polls| polls.borrow_mut().insert(PollKey { course_id: id, id: poll.id }, poll.clone()));
+    Ok(poll)
+}
+
+// Deletes a poll of a course with its votes
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn delete_course_poll(id: CourseId, poll_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("delete_course_poll");
+    _ensure_running()?;
+    _ensure_poll_manager(id)?;
+    POLLS
+        .with(|polls| polls.borrow_mut().remove(&PollKey { course_id: id, id: poll_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no poll with id={}", id, poll_id)).with_id(poll_id))?;
+    _remove_poll_votes(id, poll_id);
+    Ok(())
+}
+
+// Casts the caller's vote in a poll. Only learners enrolled in the course can vote, once per poll
+#[ic_cdk::update]
+fn vote_in_poll(id: CourseId, poll_id: u64, option: u32) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("vote_in_poll");
+    _ensure_running()?;
+    let poll = _get_poll(id, poll_id)?;
+    let voter = api::caller().to_string();
+    if !_is_enrolled(id, &voter) {
+        return Err(Error::unauthorized(format!("Only learners enrolled in the course with id={} can vote", id)));
+    }
+    let now = time();
+    if now < poll.opens_at || now >= poll.closes_at {
+        return Err(Error::invalid_input(format!("Poll with id={} is not open for voting", poll_id)));
+    }
+    if option as usize >= poll.options.len() {
+        return Err(Error::invalid_input(format!("Poll with id={} has no option {}", poll_id, option)));
+    }
+    let key = PollVoteKey {
+        course_id: id,
+        poll_id,
+        voter,
+    };
+    if POLL_VOTES.with(|votes| votes.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input(format!("You have already voted in poll with id={}", poll_id)));
+    }
+    POLL_VOTES.with(|votes| votes.borrow_mut().insert(key, option));
+    Ok(())
+}
+
+// Lists the polls of a course, oldest first
+#[ic_cdk::query]
+fn get_course_polls(id: CourseId) -> Result<Vec<Poll>, Error> {
+    _ensure_course_exists(id)?;
+    Ok(_polls_of(id))
+}
+
+// Retrieves the number of votes for each option of a poll
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_poll_results(id: CourseId, poll_id: u64) -> Result<PollResults, Error> {
+    _ensure_poll_manager(id)?;
+    let poll = _get_poll(id, poll_id)?;
+    let mut tallies = vec![0u64; poll.options.len()];
+    let start = PollVoteKey {
+        course_id: id,
+        poll_id,
+        voter: String::new(),
+    };
+    POLL_VOTES.with(|votes| {
+        for (_, option) in votes
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == id && key.poll_id == poll_id)
+        {
+            if let Some(tally) = tallies.get_mut(option as usize) {
+                *tally += 1;
+            }
+        }
+    });
+    Ok(PollResults {
+        total_votes: tallies.iter().sum(),
+        tallies,
+        poll,
+    })
+}
+
+// Schedules a live session of a course
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn schedule_course_session(id: CourseId, payload: SessionPayLoad) -> Result<Session, Error> {
+    let _profile = ProfileGuard::new("schedule_course_session");
+    _ensure_running()?;
+    _ensure_session_manager(id)?;
+    let title = _normalize_line("title", &payload.title)?;
+    let meeting_url = payload.meeting_url.trim().to_string();
+    if title.is_empty() || meeting_url.is_empty() {
+        return Err(Error::empty_fields("Please fill in the title and the meeting URL of the session".to_string()));
+    }
+    _validate_tag("title", &title)?;
+    if !meeting_url.to_lowercase().starts_with("https://") || meeting_url.chars().count() > MAX_URL_LENGTH {
+        return Err(Error::invalid_input(
+            format!("The meeting URL must be an https:// URL of at most {} characters", MAX_URL_LENGTH),
+        ));
+    }
+    if payload.starts_at <= time() {
+        return Err(Error::invalid_input("A session must start in the future".to_string()));
+    }
+    if payload.duration_minutes == 0 || payload.duration_minutes > MAX_SESSION_MINUTES {
+        return Err(Error::invalid_input(format!("A session must last between 1 and {} minutes", MAX_SESSION_MINUTES)));
+    }
+    if payload.capacity == 0 {
+        return Err(Error::invalid_input("A session must have room for at least one learner".to_string()));
+    }
+    let timezone = payload.timezone.as_ref().map(_normalize_timezone).transpose()?;
+    let session = Session {
+        id: _sessions_of(id).last().map_or(0, |session| session.id + 1),
+        course_id: id,
+        title,
+        starts_at: payload.starts_at,
+        duration_minutes: payload.duration_minutes,
+        meeting_url,
+        capacity: payload.capacity,
+        created_by: api::caller().to_string(),
+        reminded: false,
+        timezone,
+    };
+    SESSIONS.with(|sessions| sessions.borrow_mut().insert(SessionKey { course_id: id, id: session.id }, session.clone()));
+    Ok(session)
+}
+
+// Cancels a live session and notifies the learners who were attending
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn cancel_course_session(id: CourseId, session_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("cancel_course_session");
+    _ensure_running()?;
+    _ensure_session_manager(id)?;
+    let session = SESSIONS
+        .with(|sessions| sessions.borrow_mut().remove(&SessionKey { course_id: id, id: session_id }))
+        .ok_or(Error::not_found(
+            format!("course with id={} has no session with id={}", id, session_id),
+        ).with_id(session_id))?;
+    _remove_attendance(id, session_id);
+    for learner in _remove_rsvps(id, session_id) {
+        _notify(
+            &learner,
+            NotificationKind::SessionCancelled,
+            format!("The session \"{}\" of course with id={} was cancelled.", session.title, id),
+        );
+    }
+    Ok(())
+}
+
+// Signs the caller up for a live session. Only learners enrolled in the course can attend,
+// up to the capacity of the session
+#[ic_cdk::update]
+fn rsvp_session(id: CourseId, session_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("rsvp_session");
+    _ensure_running()?;
+    let session = _get_session(id, session_id)?;
+    let learner = api::caller().to_string();
+    if !_is_enrolled(id, &learner) {
+        return Err(Error::unauthorized(
+            format!("Only learners enrolled in the course with id={} can attend its sessions", id),
+        ));
+    }
+    if session.starts_at <= time() {
+        return Err(Error::invalid_input(format!("Session with id={} has already started", session_id)));
+    }
+    let attendees = _rsvps_of(id, session_id);
+    if attendees.contains(&learner) {
+        return Err(Error::invalid_input(format!("You are already attending session with id={}", session_id)));
+    }
+    if attendees.len() >= session.capacity as usize {
+        return Err(Error::invalid_input(format!("Session with id={} is full", session_id)));
+    }
+    let key = RsvpKey {
+        course_id: id,
+        session_id,
+        learner,
+    };
+    RSVPS.with(|rsvps| rsvps.borrow_mut().insert(key, time()));
+    Ok(())
+}
+
+// Withdraws the caller from a live session
+#[ic_cdk::update]
+fn cancel_rsvp(id: CourseId, session_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("cancel_rsvp");
+    _ensure_running()?;
+    let key = RsvpKey {
+        course_id: id,
+        session_id,
+        learner: api::caller().to_string(),
+    };
+    match RSVPS.with(|rsvps| rsvps.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("You are not attending session with id={}", session_id))),
+    }
+}
+
+// Checks the caller in to a live session. Learners enrolled in the course can check in from
+// shortly before the session starts until it ends
+#[ic_cdk::update]
+fn check_in_session(id: CourseId, session_id: u64) -> Result<AttendanceRecord, Error> {
+    let _profile = ProfileGuard::new("check_in_session");
+    _ensure_running()?;
+    let session = _get_session(id, session_id)?;
+    let learner = api::caller().to_string();
+    if !_is_enrolled(id, &learner) {
+        return Err(Error::unauthorized(
+            format!("Only learners enrolled in the course with id={} can check in to its sessions", id),
+        ));
+    }
+    let now = time();
+    if now + CHECK_IN_LEAD < session.starts_at || now >= session.ends_at() {
+        return Err(Error::invalid_input(format!("Check-in for session with id={} is not open", session_id)));
+    }
+    let key = AttendanceKey {
+        course_id: id,
+        session_id,
+        learner: learner.clone(),
+    };
+    if let Some(record) = ATTENDANCE.with(|attendance| attendance.borrow().get(&key)) {
+        return Ok(record);
+    }
+    let record = AttendanceRecord {
+        session_id,
+        learner,
+        attended_at: now,
+        marked_by: None,
+    };
+    ATTENDANCE.with(|attendance| attendance.borrow_mut().insert(key, record.clone()));
+    _record_learning_day(&record.learner);
+    Ok(record)
+}
+
+// Marks learners as having attended a live session, or clears their attendance
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn mark_attendance(id: CourseId, session_id: u64, learners: Vec<String>, attended: bool) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("mark_attendance");
+    _ensure_running()?;
+    _ensure_session_manager(id)?;
+    let session = _get_session(id, session_id)?;
+    if session.starts_at > time() {
+        return Err(Error::invalid_input(format!("Session with id={} hasn't started yet", session_id)));
+    }
+    if learners.len() > MAX_ATTENDANCE_BATCH {
+        return Err(Error::invalid_input(format!("At most {} learners can be marked at once", MAX_ATTENDANCE_BATCH)));
+    }
+    if let Some(learner) = learners.iter().find(|learner| !_is_enrolled(id, learner)) {
+        return Err(Error::not_found(format!("{} is not enrolled in the course with id={}", learner, id)));
+    }
+    let marked_by = api::caller().to_string();
+    ATTENDANCE.with(|attendance| {
+        let mut attendance = attendance.borrow_mut();
+        for learner in learners {
+            let key = AttendanceKey {
+                course_id: id,
+                session_id,
+                learner: learner.clone(),
+            };
+            if !attended {
+                attendance.remove(&key);
+            } else if !attendance.contains_key(&key) {
+                let record = AttendanceRecord {
+                    session_id,
+                    learner,
+                    attended_at: time(),
+                    marked_by: Some(marked_by.clone()),
+                };
+                attendance.insert(key, record);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Lists the learners who attended a live session
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_session_attendance(id: CourseId, session_id: u64) -> Result<Vec<AttendanceRecord>, Error> {
+    _ensure_session_manager(id)?;
+    _get_session(id, session_id)?;
+    Ok(_attendance_of(id, session_id))
+}
+
+// Retrieves a learner's attendance of the sessions of a course, the caller's own by default
+// Only the creator of the course, the admin or a moderator can see other learners' attendance
+#[ic_cdk::query]
+fn get_attendance_history(id: CourseId, learner: Option<String>) -> Result<AttendanceHistory, Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    let learner = learner.unwrap_or_else(|| caller.clone());
+    if learner != caller && !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to see the attendance of course with id={}", id),
+        ));
+    }
+    Ok(_attendance_history(id, &learner))
+}
+
+// Lists the sessions of a course that haven't ended yet, soonest first. The meeting URL is
+// only shown to the learners enrolled in the course, its creator and the staff
+#[ic_cdk::query]
+fn list_upcoming_sessions(id: CourseId) -> Result<Vec<UpcomingSession>, Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    let shows_url = _is_enrolled(id, &caller) || _is_allowed(id, caller.clone());
+    let now = time();
+    let mut sessions: Vec<UpcomingSession> = _sessions_of(id)
+        .into_iter()
+        .filter(|session| session.ends_at() > now)
+        .map(|mut session| {
+            let attendees = _rsvps_of(id, session.id);
+            if !shows_url {
+                session.meeting_url = String::new();
+            }
+            UpcomingSession {
+                rsvp_count: attendees.len() as u32,
+                attending: attendees.contains(&caller),
+                schedule: _schedule(session.starts_at, session.ends_at(), &session.timezone),
+                session,
+            }
+        })
+        .collect();
+    sessions.sort_by_key(|upcoming| (upcoming.session.starts_at, upcoming.session.id));
+    Ok(sessions)
+}
+
+// Retrieves a preview of a course with the start of its body instead of the full body
+#[ic_cdk::query]
+fn get_course_preview(id: CourseId) -> Result<CoursePreview, Error> {
+    let course = get_course(id, None)?;
+    let length = PREVIEW_LENGTH.with(|length| *length.lock().unwrap()) as usize;
+    let truncated = course.body.chars().count() > length;
+    Ok(CoursePreview {
+        id: course.id,
+        title: course.title,
+        creator_name: course.creator_name,
+        creator_address: course.creator_address,
+        keyword: course.keyword,
+        category: course.category,
+        created_at: course.created_at,
+        updated_at: course.updated_at,
+        excerpt: course.body.chars().take(length).collect(),
+        truncated,
+        content_rating: course.content_rating,
+    })
+}
+
+// Lists the moderation activity of the moderators, and of former moderators and the admin
+// if they have any. Only the admin can access
+#[ic_cdk::query]
+fn get_moderator_stats() -> Result<Vec<ModeratorStats>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can view moderator statistics".to_string()));
+    }
+    let mut stats = MODERATOR_STATS.with(|stats| stats.lock().unwrap().clone());
+    for address in MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().clone()) {
+        stats.entry(address.clone()).or_insert(ModeratorStats {
+            address,
+            ..Default::default()
+        });
+    }
+    Ok(stats.into_values().collect())
+}
+
+// Sets the number of body characters included in course previews. Only the admin can access
+#[ic_cdk::update]
+fn set_preview_length(length: u32) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_preview_length");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change the preview length".to_string()));
+    }
+    if length == 0 {
+        return Err(Error::invalid_input("The preview length must be greater than zero".to_string()));
+    }
+    PREVIEW_LENGTH.with(|preview_length| *preview_length.lock().unwrap() = length);
+    Ok(())
+}
+
+// Sets the public site embedded course and creator cards link to, or clears it.
+// Only the admin can access
+#[ic_cdk::update]
+fn set_embed_site(site: Option<EmbedSite>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_embed_site");
+    _ensure_running()?;
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can change the embed site".to_string()));
+    }
+    let site = match site {
+        Some(site) => {
+            let name = _normalize_line("name", &site.name)?;
+            if name.is_empty() {
+                return Err(Error::empty_fields("Please fill in the name of the site".to_string()).with_field("name"));
+            }
+            _validate_tag("name", &name)?;
+            let url = site.url.trim().trim_end_matches('/').to_string();
+            if !url.to_lowercase().starts_with("https://") {
+                return Err(Error::invalid_input("The site URL must start with https://".to_string()).with_field("url"));
+            }
+            Some(EmbedSite { name, url })
+        }
+        None => None,
+    };
+    EMBED_SITE.with(|embed_site| *embed_site.lock().unwrap() = site);
+    Ok(())
+}
+
+// Retrieves the public site embedded cards link to
+#[ic_cdk::query]
+fn get_embed_site() -> Option<EmbedSite> {
+    EMBED_SITE.with(|site| site.lock().unwrap().clone())
+}
+
+// Filters courses based on the provided criteria (AND condition)
+// The AND condition is such that it retreives the courses which satisfy all the
+// criteria provided by the user
+// If a limit is given, the scan stops as soon as that many matches are found, unless they are sorted
+#[ic_cdk::query]
+fn filter_courses_and(filter: CourseFilter, limit: Option<u32>, sort: Option<SortBy>) -> Result<Vec<Course>, Error> {
+    let criteria = _resolve_criteria(&filter.criteria(), filter.match_mode);
+    let mut courses = _matching_courses(&criteria, Combinator::And, if sort.is_some() { usize::MAX } else { _limit(limit) });
+    if let Some(sort) = sort {
+        _sort_courses(&mut courses, sort);
+        courses.truncate(_limit(limit));
+    }
+    let courses = _visible_courses(courses.into_iter().map(_to_course).collect());
+
+    if courses.is_empty() {
+        Err(Error::not_found("couldn't find a course with provided inputs".to_string()))
+    } else {
+        Ok(courses)
+    }
+}
+
+// Filters courses based on the provided criteria (OR condition).
+// The OR condition is such that it retreives the courses which satisfy any of the
+// criteria provided by the user
+// If a limit is given, the scan stops as soon as that many matches are found, unless they are sorted
+#[ic_cdk::query]
+fn filter_courses_or(filter: CourseFilter, limit: Option<u32>, sort: Option<SortBy>) -> Result<Vec<Course>, Error> {
+    let criteria = _resolve_criteria(&filter.criteria(), filter.match_mode);
+    let mut courses = _matching_courses(&criteria, Combinator::Or, if sort.is_some() { usize::MAX } else { _limit(limit) });
+    if let Some(sort) = sort {
+        _sort_courses(&mut courses, sort);
+        courses.truncate(_limit(limit));
+    }
+    let courses = _visible_courses(courses.into_iter().map(_to_course).collect());
+
+    if courses.is_empty() {
+        Err(Error::not_found("couldn't find a course with provided inputs".to_string()))
+    } else {
+        Ok(courses)
+    }
+}
+
+// Queries courses with a list of criteria combined with AND or OR,
+// an optional sort order and an optional page (at most 100 courses per page).
+// When fields are given the other fields of the courses are left empty
+#[ic_cdk::query]
+fn query_courses(request: QueryRequest) -> Result<CoursePage, Error> {
+    let page = _validate_page(request.page)?;
+    let criteria = _resolve_criteria(&request.filters, request.match_mode);
+    let candidates = _indexed_candidates(&criteria, request.combinator);
+    let viewer = _viewer();
+
+    let (courses, total_count): (Vec<StoredCourse>, u64) = STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let matching = _stored_candidates(&storage, candidates)
+            .filter(|course| viewer.sees(course) && _matches_criteria(course, &criteria, request.combinator));
+        match request.sort {
+            // Without sorting only the courses of the page are kept, the rest are just counted
+            None => {
+                let mut courses = Vec::new();
+                let mut total_count: u64 = 0;
+                for course in matching {
+                    if total_count >= page.offset && courses.len() < page.limit as usize {
+                        courses.push(course);
+                    }
+                    total_count += 1;
+                }
+                (courses, total_count)
+            }
+            Some(sort) => {
+                let mut courses: Vec<StoredCourse> = matching.collect();
+                let total_count = courses.len() as u64;
+                _sort_courses(&mut courses, sort);
+                let courses = courses
+                    .into_iter()
+                    .skip(page.offset as usize)
+                    .take(page.limit as usize)
+                    .collect();
+                (courses, total_count)
+            }
+        }
+    });
+    let mut course_page = _course_page(courses.into_iter().map(_to_course).collect(), page.offset, total_count);
+    if let Some(ref fields) = request.fields {
+        course_page.courses = course_page
+            .courses
+            .into_iter()
+            .map(|course| _project_course(course, fields))
+            .collect();
+    }
+    Ok(course_page)
+}
+
+// Retrieves a page of the courses in a category, starting with the pinned courses in their order.
+// The other courses are ordered by id unless a sort order is given
+#[ic_cdk::query]
+fn get_courses_by_category(category: String, page: Option<Page>, sort: Option<SortBy>) -> Result<CoursePage, Error> {
+    let course_ids = match _interned_id(&_canonical_tag(&_fold(&category))) {
+        Some(category_id) => {
+            let pinned = _category_pins(category_id);
+            let others = _course_ids_by_category(category_id).into_iter().filter(|id| !pinned.contains(id)).collect();
+            pinned.iter().copied().chain(_sorted_ids(others, sort)).collect()
+        }
+        None => Vec::new(),
+    };
+    _page_of_ids(&course_ids, page)
+}
+
+// Sets the courses pinned at the top of a category, in the order given, replacing the earlier pins.
+// An empty list unpins every course. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn set_category_pins(category: String, course_ids: Vec<CourseId>) -> Result<Vec<Course>, Error> {
+    let _profile = ProfileGuard::new("set_category_pins");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to pin courses".to_string()));
+    }
+    if course_ids.len() > MAX_CATEGORY_PINS {
+        return Err(Error::invalid_input(format!("At most {} courses can be pinned in a category", MAX_CATEGORY_PINS)).with_field("course_ids"));
+    }
+    _ensure_moderates(&caller, &category)?;
+    let category_id = _interned_id(&_canonical_tag(&_fold(&category)))
+        .ok_or(Error::not_found(format!("There are no courses in the category {}", category)).with_field("category"))?;
+    let mut courses = Vec::with_capacity(course_ids.len());
+    for id in course_ids.iter() {
+        if course_ids.iter().filter(|other| *other == id).count() > 1 {
+            return Err(Error::invalid_input(format!("The course with id={} is listed more than once", id)).with_id(id.0));
+        }
+        let stored = STORAGE.with(|storage| storage.borrow().get(id)).ok_or(_course_not_found(*id))?;
+        if stored.category_key() != category_id {
+            return Err(Error::invalid_input(format!("The course with id={} is not in the category {}", id, category)).with_id(id.0));
+        }
+        courses.push(_to_course(stored));
+    }
+    CATEGORY_PINS.with(|pins| {
+        let mut pins = pins.borrow_mut();
+        if course_ids.is_empty() {
+            pins.remove(&category_id);
+        } else {
+            pins.insert(
+                category_id,
+                CategoryPins {
+                    course_ids,
+                    pinned_by: caller,
+                    pinned_at: time(),
+                },
+            );
+        }
+    });
+    Ok(_visible_courses(courses))
+}
+
+// Retrieves the courses pinned at the top of a category, in their order
+#[ic_cdk::query]
+fn get_category_pins(category: String) -> Vec<Course> {
+    let course_ids = _interned_id(&_canonical_tag(&_fold(&category))).map(_category_pins).unwrap_or_default();
+    _visible_courses(_shown_ids(&course_ids).iter().filter_map(_get_course_).collect())
+}
+
+// Makes a keyword or category a synonym of another one, such as "js" of "javascript", so courses
+// tagged with either are found by both. Existing courses are matched again. Only the admin can access
+#[ic_cdk::update]
+fn add_tag_synonym(alias: String, canonical: String) -> Result<TagSynonym, Error> {
+    let _profile = ProfileGuard::new("add_tag_synonym");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can change the tag synonyms".to_string()));
+    }
+    let alias = _fold(alias.trim());
+    let canonical = _fold(canonical.trim());
+    if alias.is_empty() || canonical.is_empty() {
+        return Err(Error::empty_fields("Please provide the synonym and the tag it stands for".to_string()));
+    }
+    _validate_tag("synonym", &alias)?;
+    _validate_tag("tag", &canonical)?;
+    if alias == canonical {
+        return Err(Error::invalid_input("A tag can't be a synonym of itself".to_string()).with_field("canonical"));
+    }
+    // Synonyms don't chain, so every alias points straight at the tag it stands for
+    if TAG_SYNONYMS.with(|synonyms| synonyms.borrow().contains_key(&TagKey(canonical.clone()))) {
+        return Err(Error::invalid_input(format!("{} is itself a synonym", canonical)).with_field("canonical"));
+    }
+    let is_canonical = TAG_SYNONYMS.with(|synonyms| synonyms.borrow().iter().any(|(_, synonym)| synonym.canonical == alias));
+    if is_canonical {
+        return Err(Error::invalid_input(format!("{} already has synonyms", alias)).with_field("alias"));
+    }
+    let synonym = TagSynonym {
+        alias: alias.clone(),
+        canonical,
+        added_by: caller,
+        added_at: time(),
+    };
+    TAG_SYNONYMS.with(|synonyms| synonyms.borrow_mut().insert(TagKey(alias), synonym.clone()));
+    _rekey_courses();
+    Ok(synonym)
+}
+
+// Removes a synonym, so courses tagged with it are matched by their own tag again
+// Only the admin can access
+#[ic_cdk::update]
+fn remove_tag_synonym(alias: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_tag_synonym");
+    _ensure_running()?;
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can change the tag synonyms".to_string()));
+    }
+    let alias = _fold(alias.trim());
+    if TAG_SYNONYMS.with(|synonyms| synonyms.borrow_mut().remove(&TagKey(alias.clone()))).is_none() {
+        return Err(Error::not_found(format!("{} is not a synonym", alias)).with_field("alias"));
+    }
+    _rekey_courses();
+    Ok(())
+}
+
+// Lists the keyword and category synonyms
+#[ic_cdk::query]
+fn get_tag_synonyms() -> Vec<TagSynonym> {
+    TAG_SYNONYMS.with(|synonyms| synonyms.borrow().iter().map(|(_, synonym)| synonym).collect())
+}
+
+// Adds or replaces the translation of the English messages matching a pattern, such as
+// "Course with id={} not found" into "Curso con id={1} no encontrado". Only the admin can access
+#[ic_cdk::update]
+fn set_message_translation(language: String, pattern: String, translation: String) -> Result<MessageTranslation, Error> {
+    let _profile = ProfileGuard::new("set_message_translation");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can change the message catalog".to_string()));
+    }
+    let language = _normalize_language(&language)?;
+    let pattern = pattern.trim().to_string();
+    let translation = translation.trim().to_string();
+    if pattern.is_empty() || translation.is_empty() {
+        return Err(Error::empty_fields("Please provide the pattern and its translation".to_string()));
+    }
+    if pattern.chars().count() > MAX_MESSAGE_PATTERN_LENGTH {
+        return Err(Error::invalid_input(format!("The pattern can be at most {} characters long", MAX_MESSAGE_PATTERN_LENGTH)).with_field("pattern"));
+    }
+    if translation.chars().count() > MAX_MESSAGE_TRANSLATION_LENGTH {
+        return Err(Error::invalid_input(format!("The translation can be at most {} characters long", MAX_MESSAGE_TRANSLATION_LENGTH)).with_field("translation"));
+    }
+    if pattern.contains("{}{}") {
+        return Err(Error::invalid_input("Placeholders in the pattern must be separated by some text".to_string()).with_field("pattern"));
+    }
+    let placeholders = pattern.matches("{}").count();
+    if let Some(number) = _placeholder_numbers(&translation).into_iter().find(|number| *number == 0 || *number > placeholders) {
+        return Err(Error::invalid_input(format!("The pattern has no placeholder {{{}}}", number)).with_field("translation"));
+    }
+    let entry = MessageTranslation {
+        language: language.clone(),
+        pattern: pattern.clone(),
+        translation,
+        updated_by: caller,
+        updated_at: time(),
+    };
+    MESSAGE_CATALOG.with(|catalog| catalog.borrow_mut().insert(MessageKey { language, pattern }, entry.clone()));
+    Ok(entry)
+}
+
+// Removes the translation of a pattern, so its messages are given in English again. Only the admin can access
+#[ic_cdk::update]
+fn remove_message_translation(language: String, pattern: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_message_translation");
+    _ensure_running()?;
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can change the message catalog".to_string()));
+    }
+    let key = MessageKey {
+        language: _normalize_language(&language)?,
+        pattern: pattern.trim().to_string(),
+    };
+    match MESSAGE_CATALOG.with(|catalog| catalog.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("There is no {} translation of this pattern", key.language)).with_field("pattern")),
+    }
+}
+
+// Retrieves the message translations into a language, ordered by pattern
+#[ic_cdk::query]
+fn get_message_translations(language: String) -> Result<Vec<MessageTranslation>, Error> {
+    let language = _normalize_language(&language)?;
+    Ok(_message_translations_of(&language))
+}
+
+// Retrieves a page of the courses of a creator, by id unless a sort order is given
+#[ic_cdk::query]
+fn get_courses_by_creator(address: String, page: Option<Page>, sort: Option<SortBy>) -> Result<CoursePage, Error> {
+    _page_of_ids(&_sorted_ids(_course_ids_by_creator(&address), sort), page)
+}
+
+// Retrieves a creator's public page: their name, verified status, reputation, engagement totals,
+// featured courses and a page of their courses, newest first
+#[ic_cdk::query]
+fn get_creator_page(address: String, page: Option<Page>) -> Result<CreatorPage, Error> {
+    let course_ids = _course_ids_by_creator(&address);
+    let shown = _shown_ids(&course_ids);
+    let Some(latest) = shown.last().and_then(_get_course_) else {
+        return Err(Error::not_found(format!("{} has no published courses", address)).with_field("address"));
+    };
+
+    let mut stats = CreatorStats {
+        courses: shown.len() as u64,
+        ..Default::default()
+    };
+    ACTIVITY.with(|activity| {
+        let activity = activity.borrow();
+        for id in shown.iter() {
+            for (_, counts) in activity.range(_activity_range(*id, 0)) {
+                stats.views += counts.views;
+                stats.likes += counts.likes;
+                stats.enrollments += counts.enrollments;
+            }
+        }
+    });
+
+    let endorsed = |id: &CourseId| ENDORSEMENTS.with(|endorsements| endorsements.borrow().contains_key(id));
+    let pinned = |course: &Course| {
+        _interned_id(&_canonical_tag(&_fold(&course.category))).is_some_and(|category_id| _category_pins(category_id).contains(&course.id))
+    };
+    let featured: Vec<Course> = shown
+        .iter()
+        .rev()
+        .filter_map(_get_course_)
+        .filter(|course| endorsed(&course.id) || pinned(course))
+        .collect();
+
+    let newest_first: Vec<CourseId> = shown.into_iter().rev().collect();
+    Ok(CreatorPage {
+        name: _visible_courses(vec![latest]).remove(0).creator_name,
+        verified: get_creator_verification(address.clone()).verified,
+        reputation: REPUTATION.with(|reputation| reputation.lock().unwrap().get(&address).cloned()),
+        stats,
+        featured: _visible_courses(featured),
+        courses: _page_of_ids(&newest_first, page)?,
+        address,
+    })
+}
+
+// Retrieves the metadata of up to 4 courses aligned side by side, in the order given,
+// so a comparison table can be rendered from one call
+#[ic_cdk::query]
+fn compare_courses(course_ids: Vec<CourseId>) -> Result<Vec<CourseComparison>, Error> {
+    if course_ids.len() < 2 || course_ids.len() > MAX_COMPARED_COURSES {
+        return Err(Error::invalid_input(format!("Between 2 and {} courses can be compared", MAX_COMPARED_COURSES)).with_field("course_ids"));
+    }
+    let mut seen = HashSet::new();
+    if let Some(id) = course_ids.iter().find(|id| !seen.insert(**id)) {
+        return Err(Error::invalid_input(format!("The course with id={} is listed twice", id)).with_id(id.0));
+    }
+    let shown = _shown_ids(&course_ids);
+    let mut courses = Vec::with_capacity(course_ids.len());
+    for id in course_ids {
+        let course = shown.contains(&id).then(|| _get_course_(&id)).flatten().ok_or(_course_not_found(id))?;
+        courses.push(course);
+    }
+
+    Ok(_visible_courses(courses)
+        .into_iter()
+        .map(|course| {
+            let sessions = _sessions_of(course.id);
+            let feedback = _feedback_of(course.id);
+            let rating_count = feedback.len() as u64;
+            let average = |rating: fn(&FeedbackForm) -> u8| {
+                (rating_count > 0)
+                    .then(|| feedback.iter().map(|response| rating(&response.form) as u64).sum::<u64>() * 100 / rating_count)
+            };
+            let enrollments = ACTIVITY.with(|activity| {
+                activity.borrow().range(_activity_range(course.id, 0)).map(|(_, counts)| counts.enrollments).sum()
+            });
+            CourseComparison {
+                duration_minutes: sessions.iter().map(|session| session.duration_minutes as u64).sum(),
+                lessons: (sessions.len() + _assignments_of(course.id).len()) as u64,
+                average_difficulty: average(|form| form.difficulty_rating),
+                average_rating: average(|form| form.content_rating),
+                rating_count,
+                enrollments,
+                languages: _translations_of(course.id).into_iter().map(|(key, _)| key.language).collect(),
+                quality: QUALITY.with(|quality| quality.lock().unwrap().get(&course.id).map(|quality| quality.score)),
+                course,
+            }
+        })
+        .collect())
+}
+
+// Retrieves a page of all the courses by id, with the total number of courses, so browse pages
+// don't need to know the ids or download every course. Limits above the page size are capped
+#[ic_cdk::query]
+fn get_courses_paginated(offset: u64, limit: u64) -> PaginatedCourses {
+    let (courses, total_count) = _courses_from(&_viewer(), offset, limit.min(u64::from(MAX_PAGE_SIZE)));
+    PaginatedCourses {
+        has_more: offset.saturating_add(courses.len() as u64) < total_count,
+        courses: _visible_courses(courses),
+        total_count,
+    }
+}
+
+// Retrieves a page of the courses created in the time window [from, to)
+#[ic_cdk::query]
+fn get_courses_created_between(from: u64, to: u64, page: Option<Page>) -> Result<CoursePage, Error> {
+    if from >= to {
+        return Err(Error::invalid_input("The start of the time window must be before its end".to_string()));
+    }
+    query_courses(QueryRequest {
+        filters: vec![Criterion::CreatedAfter(from), Criterion::CreatedBefore(to)],
+        combinator: Combinator::And,
+        sort: None,
+        page,
+        fields: None,
+        match_mode: None,
+    })
+}
+
+// Retrieves a page of the courses created or updated since a timestamp,
+// so mirrors and caches can sync only what changed
+#[ic_cdk::query]
+fn get_courses_updated_since(timestamp: u64, page: Option<Page>) -> Result<CoursePage, Error> {
+    query_courses(QueryRequest {
+        filters: vec![Criterion::UpdatedSince(timestamp)],
+        combinator: Combinator::And,
+        sort: None,
+        page,
+        fields: None,
+        match_mode: None,
+    })
+}
+
+// Retrieves up to limit courses with an id after the cursor, or from the first course without one.
+// The scan starts at the cursor in the store instead of skipping an offset, so every page costs the same
+#[ic_cdk::query]
+fn list_after(id: Option<CourseId>, limit: u32) -> Result<CourseCursorPage, Error> {
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(Error::invalid_input(format!("The limit must be between 1 and {}", MAX_PAGE_SIZE)).with_field("limit"));
+    }
+    let (courses, next_cursor) = _courses_after(&_viewer(), id, limit as usize);
+    Ok(CourseCursorPage {
+        courses: _visible_courses(courses),
+        next_cursor,
+    })
+}
+
+// Retrieves every course in one call for small catalogs. The response stops at the chunk size budget,
+// so a large catalog comes back truncated instead of exceeding the message size limit
+#[ic_cdk::query]
+fn get_all_courses() -> AllCourses {
+    let viewer = _viewer();
+    let (courses, next_token) = _courses_within_budget(&viewer, None, None, |_| true);
+    let total_count = STORAGE.with(|storage| storage.borrow().iter().filter(|(_, course)| viewer.sees(course)).count() as u64);
+    AllCourses {
+        courses: _visible_courses(courses),
+        total_count,
+        truncated: next_token.is_some(),
+        next_token,
+    }
+}
+
+// Exports all the courses in chunks that fit in a single response
+// Pass the next_token of the previous chunk to continue, None starts from the beginning
+#[ic_cdk::query]
+fn export_courses(token: Option<CourseId>) -> CourseChunk {
+    _collect_chunk(token, None, |_| true)
+}
+
+// Returns everything stored about the caller, section by section, in chunks that fit in a single response.
+// Every chunk is a JSON array of whole records, so chunks fetched at different times never cut through
+// a record. Pass the returned next_cursor to fetch the following chunk
+#[ic_cdk::query]
+fn export_my_data(cursor: Option<ExportCursor>) -> Result<DataExportChunk, Error> {
+    let caller = api::caller().to_string();
+    let cursor = cursor.unwrap_or_default();
+    let section = *EXPORT_SECTIONS.get(cursor.section as usize).ok_or(
+        Error::not_found(format!("the export has only {} sections", EXPORT_SECTIONS.len())).with_field("section"),
+    )?;
+    let records = _export_records(&caller, section);
+    let total = records.len();
+    let start = (cursor.offset as usize).min(total);
+    let mut data: Vec<String> = Vec::new();
+    let mut size = 2;
+    for record in records.into_iter().skip(start) {
+        // A record is never split, so a chunk holds at least one
+        if size + record.len() + 1 > CHUNK_SIZE_LIMIT && !data.is_empty() {
+            break;
+        }
+        size += record.len() + 1;
+        data.push(record);
+    }
+    let end = start + data.len();
+    let next_cursor = if end < total {
+        Some(ExportCursor {
+            section: cursor.section,
+            offset: end as u64,
+        })
+    } else if (cursor.section as usize) + 1 < EXPORT_SECTIONS.len() {
+        Some(ExportCursor {
+            section: cursor.section + 1,
+            offset: 0,
+        })
+    } else {
+        None
+    };
+    Ok(DataExportChunk {
+        section: section.to_string(),
+        data: format!("[{}]", data.join(",")),
+        next_cursor,
+    })
+}
+
+// Same as filter_courses_and / filter_courses_or but returns the matches in chunks,
+// for result sets too large for a single response
+#[ic_cdk::query]
+fn filter_courses_chunked(filter: CourseFilter, match_all: bool, token: Option<CourseId>) -> Result<CourseChunk, Error> {
+    let criteria = _resolve_criteria(&filter.criteria(), filter.match_mode);
+    let combinator = if match_all { Combinator::And } else { Combinator::Or };
+    let candidates = _indexed_candidates(&criteria, combinator);
+    Ok(_collect_chunk(token, candidates, |course| _matches_criteria(course, &criteria, combinator)))
+}
+
+// Starts a draft, reserving the id the course will have once published, so the editor can
+// autosave to it right away
+#[ic_cdk::update]
+fn create_draft() -> Result<Draft, Error> {
+    let _profile = ProfileGuard::new("create_draft");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if _is_banned(&caller) || _pending_ban(&caller).is_some() {
+        return Err(Error::banned_user("User is banned. Cannot create drafts".to_string()));
+    }
+    if _drafts_of(&caller).len() >= MAX_DRAFTS_PER_CREATOR {
+        return Err(Error::invalid_input(format!(
+            "You can have at most {} drafts, please publish or delete one first",
+            MAX_DRAFTS_PER_CREATOR
+        )));
+    }
+    let draft = Draft {
+        id: _next_course_id(),
+        creator_address: caller,
+        content: CourseUpdatePayLoad::default(),
+        created_at: time(),
+        saved_at: time(),
+    };
+    DRAFTS.with(|drafts| drafts.borrow_mut().insert(draft.id, draft.clone()));
+    Ok(draft)
+}
+
+// Saves the given fields into a draft, keeping the fields left out. Only the author of the draft can access
+#[ic_cdk::update]
+fn save_draft(id: CourseId, content: CourseUpdatePayLoad) -> Result<Draft, Error> {
+    let _profile = ProfileGuard::new("save_draft");
+    _ensure_running()?;
+    let mut draft = _own_draft(id)?;
+    let saved = &mut draft.content;
+    let content = CourseUpdatePayLoad {
+        title: content.title.or(saved.title.take()),
+        creator_name: content.creator_name.or(saved.creator_name.take()),
+        body: content.body.or(saved.body.take()),
+        attachment_url: content.attachment_url.or(saved.attachment_url.take()),
+        keyword: content.keyword.or(saved.keyword.take()),
+        tags: content.tags.or(saved.tags.take()),
+        category: content.category.or(saved.category.take()),
+        contact: content.contact.or(saved.contact.take()),
+        content_rating: content.content_rating.or(saved.content_rating.take()),
+        accessibility: content.accessibility.or(saved.accessibility.take()),
+        regions: content.regions.or(saved.regions.take()),
+    };
+    draft.content = content;
+    draft.saved_at = time();
+    _check_fits(&draft, "draft")?;
+    DRAFTS.with(|drafts| drafts.borrow_mut().insert(id, draft.clone()));
+    Ok(draft)
+}
+
+// Publishes a draft as a course under its reserved id, with the same checks as add_course.
+// The draft is removed once published. Only the author of the draft can access
+#[ic_cdk::update]
+fn publish_draft(id: CourseId) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("publish_draft");
+    _ensure_running()?;
+    let content = _own_draft(id)?.content;
+    let payload = CoursePayLoad {
+        title: content.title.unwrap_or_default(),
+        creator_name: content.creator_name.unwrap_or_default(),
+        body: content.body.unwrap_or_default(),
+        attachment_url: content.attachment_url.unwrap_or_default(),
+        keyword: content.keyword.unwrap_or_default(),
+        tags: content.tags,
+        category: content.category.unwrap_or_default(),
+        contact: content.contact.unwrap_or_default(),
+        content_rating: content.content_rating,
+        accessibility: content.accessibility,
+        regions: content.regions,
+    };
+    let course = _create_course(payload, Some(id))?;
+    DRAFTS.with(|drafts| drafts.borrow_mut().remove(&id));
+    Ok(course)
+}
+
+// Deletes a draft. Its reserved id is not reused. Only the author of the draft can access
+#[ic_cdk::update]
+fn delete_draft(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("delete_draft");
+    _ensure_running()?;
+    _own_draft(id)?;
+    DRAFTS.with(|drafts| drafts.borrow_mut().remove(&id));
+    Ok(())
+}
+
+// Lists the caller's drafts, most recently saved first
+#[ic_cdk::query]
+fn get_my_drafts() -> Vec<Draft> {
+    let mut drafts = _drafts_of(&api::caller().to_string());
+    drafts.sort_by_key(|draft| Reverse(draft.saved_at));
+    drafts
+}
+
+// Adds a new course to the storage
+#[ic_cdk::update]
+fn add_course(course: CoursePayLoad) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("add_course");
+    _ensure_running()?;
+    _create_course(course, None)
+}
+
+// Creates a course from a payload, under the id reserved by a draft or a new one
+fn _create_course(course: CoursePayLoad, reserved_id: Option<CourseId>) -> Result<Course, Error> {
+    let address_string: String = api::caller().to_string();
+    // Check whether the user is banned
+    if _is_banned(&address_string) {
+        return Err(Error::banned_user("User is banned. Cannot add course".to_string()));
+    }
+    if _pending_ban(&address_string).is_some() {
+        return Err(Error::banned_user("User has a pending ban. Cannot add course".to_string()));
+    }
+    _check_publishing_requirements(&address_string)?;
+    let mut course = course.normalized()?;
+    let tags = _course_tags(&course.keyword, course.tags.take().unwrap_or_default())?;
+    //Validation Logic
+    if course.title.is_empty()
+    || course.creator_name.is_empty()
+    || course.body.is_empty()
+    || course.attachment_url.is_empty()
+    || tags.is_empty()
+    || course.category.is_empty()
+    || course.contact.is_empty()
+    {
+        return Err(Error::empty_fields("Please fill in all the required fields to create a course".to_string()));
+    }
+    _validate_attachment_url(&course.attachment_url)?;
+    _validate_tag("category", &course.category)?;
+    course.contact = _normalize_contacts(&course.contact)?;
+    if let Some(ref accessibility) = course.accessibility {
+        _validate_accessibility(accessibility)?;
+    }
+    let regions = course
+        .regions
+        .as_ref()
+        .map(|regions| _normalize_regions(regions))
+        .transpose()?
+        .filter(|regions| !regions.is_empty());
+    let id = match reserved_id {
+        Some(id) => id,
+        None => _next_course_id(),
+    };
+
+    let course = Course {
+        id,
+        creator_address: address_string,
+        creator_name: course.creator_name,
+        title: course.title,
+        body: course.body,
+        attachment_url: course.attachment_url,
+        created_at: time(),
+        updated_at: None,
+        category: course.category,
+        keyword: tags[0].clone(),
+        tags,
+        contact: course.contact,
+        content_rating: course.content_rating,
+        accessibility: course.accessibility,
+        regions,
+        faq: None,
+        created_at_rfc3339: None,
+        updated_at_rfc3339: None,
+    };
+    _publish_course(&course, _spam_signals(&course, true))?;
+    _flag_possible_duplicate(&course);
+    if _get_course_(&course.id).is_some() {
+        _record_user_activity(&course.creator_address, UserActivityKind::CoursePublished, course.id);
+    }
+    Ok(course)
+}
+
+// Updates an existing course. Only the creator or the admin or a moderator can update
+#[ic_cdk::update]
+fn update_course(id: CourseId, payload: CourseUpdatePayLoad) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("update_course");
+    _ensure_running()?;
+    match _get_course_(&id) {
+        Some(mut course) => {
+            let caller = api::caller().to_string();
+            let is_allowed = _is_allowed(id, caller.clone());
+            if is_allowed {
+                let previous = course.clone();
+                let payload = payload.normalized()?;
+                if let Some(field) = payload.emptied_field() {
+                    return Err(Error::empty_fields(format!("The {} of a course can't be empty", field)));
+                }
+                if let Some(ref attachment_url) = payload.attachment_url {
+                    _validate_attachment_url(attachment_url)?;
+                }
+                let tags = match (payload.keyword.as_ref(), payload.tags.clone()) {
+                    (None, None) => None,
+                    (Some(keyword), None) => Some(_course_tags(keyword, course.tags.iter().skip(1).cloned().collect())?),
+                    (keyword, Some(tags)) => Some(_course_tags(keyword.map_or("", String::as_str), tags)?),
+                };
+                if matches!(tags, Some(ref tags) if tags.is_empty()) {
+                    return Err(Error::empty_fields("A course needs at least one tag".to_string()).with_field("tags"));
+                }
+                if let Some(ref category) = payload.category {
+                    _validate_tag("category", category)?;
+                }
+                let contact = payload.contact.as_ref().map(|contact| _normalize_contacts(contact)).transpose()?;
+                if let Some(ref accessibility) = payload.accessibility {
+                    _validate_accessibility(accessibility)?;
+                }
+                let regions = payload.regions.as_ref().map(|regions| _normalize_regions(regions)).transpose()?;
+                if let Some(title) = payload.title {
+                    course.title = title;
+                }
+                if let Some(creator_name) = payload.creator_name {
+                    course.creator_name = creator_name;
+                }
+                if let Some(body) = payload.body {
+                    course.body = body;
+                }
+                if let Some(attachment_url) = payload.attachment_url {
+                    course.attachment_url = attachment_url;
+                }
+                if let Some(tags) = tags {
+                    course.keyword = tags[0].clone();
+                    course.tags = tags;
+                }
+                if let Some(category) = payload.category {
+                    course.category = category;
+                }
+                if let Some(contact) = contact {
+                    course.contact = contact;
+                }
+                if let Some(content_rating) = payload.content_rating {
+                    course.content_rating = Some(content_rating);
+                }
+                if let Some(accessibility) = payload.accessibility {
+                    course.accessibility = Some(accessibility);
+                }
+                // An empty list of regions makes the course meant for everyone again
+                if let Some(regions) = regions {
+                    course.regions = (!regions.is_empty()).then_some(regions);
+                }
+                course.updated_at = Some(time());
+                _publish_course(&course, _spam_signals(&course, false))?;
+                _record_course_edit(&previous, &course, caller);
+                Ok(course)
+            }else {
+                Err(Error::unauthorized(format!("You are not authorized to update course with id={}", id)))
+            }
+        }
+        None => Err(Error::not_found(
+            format!(
+            "couldn't update a course with id={}. course not found",
+            id
+        ),
+        )),
+    }
+}
+
+// Retrieves a page of the edits made to a course, newest first, with the fields each edit changed
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_course_edit_history(id: CourseId, page: Option<Page>) -> Result<Vec<CourseEdit>, Error> {
+    _ensure_course_exists(id)?;
+    if !_is_allowed(id, api::caller().to_string()) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to see the edit history of course with id={}", id),
+        ));
+    }
+    let page = _validate_page(page)?;
+    Ok(_course_edits_of(id)
+        .into_iter()
+        .rev()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Sets the time after which a course is archived by the scheduler, or clears it when expires_at is None.
+// The creator is reminded a few days before. Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn set_course_expiry(id: CourseId, expires_at: Option<u64>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_course_expiry");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    if !_is_allowed(id, api::caller().to_string()) {
+        return Err(Error::unauthorized(format!("You are not authorized to set the expiry of course with id={}", id)));
+    }
+    match expires_at {
+        Some(expires_at) if expires_at <= time() => Err(Error::invalid_input(
+            "The expiry must be in the future".to_string(),
+        )),
+        Some(expires_at) => {
+            _schedule_expiry(id, expires_at);
+            Ok(())
+        }
+        None => {
+            COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().remove(&id));
+            Ok(())
+        }
+    }
+}
+
+// Retrieves the time after which a course is archived, if it has one
+#[ic_cdk::query]
+fn get_course_expiry(id: CourseId) -> Result<Option<u64>, Error> {
+    _ensure_course_exists(id)?;
+    Ok(COURSE_EXPIRY.with(|expiries| expiries.borrow().get(&id)).map(|expiry| expiry.expires_at))
+}
+
+// Retrieves the caller's courses archived when they expired
+#[ic_cdk::query]
+fn get_my_archived_courses() -> Vec<ArchivedCourse> {
+    let caller = api::caller().to_string();
+    ARCHIVED_COURSES.with(|archived| {
+        archived
+            .borrow()
+            .iter()
+            .map(|(_, archived)| archived)
+            .filter(|archived| archived.course.creator_address == caller)
+            .collect()
+    })
+}
+
+// Publishes an archived course again, optionally with a new expiry. Only the creator of the course can access
+#[ic_cdk::update]
+fn restore_archived_course(id: CourseId, expires_at: Option<u64>) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("restore_archived_course");
+    _ensure_running()?;
+    let archived = ARCHIVED_COURSES
+        .with(|archived| archived.borrow().get(&id))
+        .ok_or(Error::not_found(format!("an archived course with id={} not found", id)).with_id(id.0))?;
+    if archived.course.creator_address != api::caller().to_string() {
+        return Err(Error::unauthorized("Only the creator of the course can restore it".to_string()));
+    }
+    if matches!(expires_at, Some(expires_at) if expires_at <= time()) {
+        return Err(Error::invalid_input("The expiry must be in the future".to_string()));
+    }
+    let mut course = archived.course;
+    course.updated_at = Some(time());
+    _publish_course(&course, Vec::new())?;
+    ARCHIVED_COURSES.with(|archived| archived.borrow_mut().remove(&id));
+    if let Some(expires_at) = expires_at {
+        _schedule_expiry(id, expires_at);
+    }
+    Ok(course)
+}
+
+// Retrieves the dead links found on the caller's courses
+#[ic_cdk::query]
+fn get_my_dead_links() -> Vec<DeadLinkReport> {
+    let caller = api::caller().to_string();
+    _dead_link_reports(Some(&caller))
+}
+
+// Retrieves the dead links found on every course. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_dead_links() -> Result<Vec<DeadLinkReport>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("You are not authorized to see the dead links".to_string()));
+    }
+    Ok(_dead_link_reports(None))
+}
+
+// Has the scheduler check the links of a course again on its next run, such as after fixing them.
+// The grace period of links still dead is kept. Only the creator of the course can access
+#[ic_cdk::update]
+fn recheck_course_links(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("recheck_course_links");
+    _ensure_running()?;
+    _ensure_creator(id)?;
+    LINK_CHECKS.with(|checks| {
+        let mut checks = checks.borrow_mut();
+        if let Some(mut check) = checks.get(&id) {
+            check.checked_at = 0;
+            checks.insert(id, check);
+        }
+    });
+    Ok(())
+}
+
+// Strips a link check response down to its status, so every replica agrees on it
+#[ic_cdk::query]
+fn transform_link_check(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+// Deletes a course based on the ID. Only the creator or the admin or a moderator can update
+#[ic_cdk::update]
+fn delete_course(id: CourseId) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("delete_course");
+    _ensure_running()?;
+    match _get_course_(&id) {
+        Some(course) => {
+            let caller = api::caller().to_string();
+
+            // Checks if the caller is either the creator, or the admin or a moderator
+            let is_allowed = _is_allowed(id, caller.clone());
+
+            // Remove the course from storage
+            if is_allowed {
+                do_remove(id);
+                if course.creator_address != caller {
+                    _record_moderator_action(&caller, |stats| stats.deletions += 1);
+                    _log_moderation(ModerationAction::CourseDeleted, &caller, id.to_string(), course.title.clone());
+                }
+                Ok(course)
+            } else {
+                Err(Error::unauthorized(format!("You are not authorized to update course with id={}", id)))
+            }
+        }
+        None => Err(Error::not_found(
+            format!(
+            "couldn't update a course with id={}. course not found",
+            id
+        ),
+        )),
+    }
+}
+
+// Deletes all courses by a creator based on the address. Only the creator, the admin or a moderator can access.
+// Restricted moderators can only delete the courses of creators whose courses are all in their categories
+#[ic_cdk::update]
+fn delete_courses_by_creator(address: String) -> Result<Vec<Course>, Error> {
+    let _profile = ProfileGuard::new("delete_courses_by_creator");
+    _ensure_running()?;
+    let caller = api::caller().to_string(); // Convert caller address to string
+    // Check if the caller is the input address, the admin or a moderator
+    let is_allowed = address == caller || _is_authorized(caller.clone());
+    if is_allowed {
+        if address != caller {
+            _ensure_may_ban(&caller, &address)?;
+        }
+        let deleted_courses = _delete_courses_of(&address);
+        if deleted_courses.is_empty() {
+            Err(Error::not_found("No courses found for the caller. Nothing to delete.".to_string()))
+        } else {
+            if address != caller {
+                let count = deleted_courses.len() as u64;
+                _record_moderator_action(&caller, |stats| stats.deletions += count);
+                _log_moderation(ModerationAction::CoursesDeleted, &caller, address, format!("courses deleted: {}", count));
+            }
+            Ok(deleted_courses)
+        }
+    } else {
+        Err(Error::unauthorized("You are not authorized to delete the course ".to_string()))
+    }
+}
+
+// Deletes all courses of the caller
+#[ic_cdk::update]
+fn delete_my_courses() -> Result<Vec<Course>, Error> {
+    let _profile = ProfileGuard::new("delete_my_courses");
+    _ensure_running()?;
+    let caller = api::caller().to_string(); // Convert caller address to string
+    let deleted_courses = _delete_courses_of(&caller);
+
+    if deleted_courses.is_empty() {
+        Err(Error::not_found("No courses found for the caller. Nothing to delete.".to_string()))
+    } else {
+        Ok(deleted_courses)
+    }
+}
+
+// Deletes the caller's account: their courses are deleted or transferred to another user,
+// and their likes, enrollments, notifications, taken down and queued courses are removed.
+// Data is removed in bounded batches; the scheduler carries on with the remaining batches
+// and calling again removes the next batch right away. Bans and the moderation log are kept
+#[ic_cdk::update]
+fn delete_my_account(policy: CoursePolicy) -> Result<AccountDeletionStatus, Error> {
+    let _profile = ProfileGuard::new("delete_my_account");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if _is_authorized(caller.clone()) {
+        return Err(Error::unauthorized(
+            "The admin and moderators must step down before deleting their account".to_string(),
+        ));
+    }
+    let in_progress = ACCOUNT_DELETIONS.with(|deletions| {
+        let mut deletions = deletions.lock().unwrap();
+        deletions
+            .iter()
+            .position(|deletion| deletion.address == caller)
+            .map(|pos| deletions.remove(pos))
+    });
+    let deletion = match in_progress {
+        Some(deletion) => deletion,
+        None => {
+            if let CoursePolicy::TransferTo(ref recipient) = policy {
+                if *recipient == caller || recipient.trim().is_empty() {
+                    return Err(Error::invalid_input("Courses must be transferred to another user".to_string()));
+                }
+                if _is_banned(recipient) {
+                    return Err(Error::banned_user("Courses can't be transferred to a banned user".to_string()));
+                }
+            }
+            AccountDeletion {
+                address: caller,
+                policy,
+                requested_at: time(),
+                removed_records: 0,
+            }
+        }
+    };
+    Ok(_run_account_deletion(deletion))
+}
+
+// Bans a creator from adding courses.
+// Deletes the courses by the creator, if any, so known bad actors can also be banned before posting
+// An optional reason and expiry time can be recorded with the ban
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn ban_creator(address: String, reason: Option<String>, expires_at: Option<u64>) -> Result<Vec<Course>, Error> {
+    let _profile = ProfileGuard::new("ban_creator");
+    _ensure_running()?;
+    // The caller must be admin or moderator
+    let caller = api::caller().to_string(); // Convert caller address to string
+
+    // Check if the caller is an admin or moderator
+    let is_authorized: bool = _is_authorized(caller.clone());
+
+    if let Some(expires_at) = expires_at {
+        if expires_at <= time() {
+            return Err(Error::invalid_input("The ban expiry must be in the future".to_string()));
+        }
+    }
+    let reason = _normalize_ban_reason(reason)?;
+    _check_ban_address(&address)?;
+
+    // Checks if the the input address is admin or a moderator
+    let is_allowed = _is_bannable(&address);
+
+    if is_allowed && is_authorized {
+        _ensure_may_ban(&caller, &address)?;
+        if _ban_requires_approval(&caller) {
+            _propose_ban(address, reason, expires_at, caller)
+        } else {
+            _apply_ban(address, reason, expires_at, caller)
+        }
+    } else {
+        Err(Error::unauthorized("You are not authorized to ban the user".to_string()))
+    }
+}
+
+// Bans several creators in one call, for handling coordinated spam waves.
+// Every address gets its own result, so one bad entry doesn't fail the rest
+// Courses are deleted up to a per-call budget, the scheduler deletes the rest
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn ban_creators(addresses: Vec<String>, reason: Option<String>) -> Result<Vec<BulkBanResult>, Error> {
+    let _profile = ProfileGuard::new("ban_creators");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to ban the user".to_string()));
+    }
+    _check_bulk_size(&addresses)?;
+    let reason = _normalize_ban_reason(reason)?;
+    let requires_approval = _ban_requires_approval(&caller);
+
+    let mut budget = BAN_CLEANUP_BATCH;
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut results = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let mut result = BulkBanResult {
+            address: address.clone(),
+            error: None,
+            courses_removed: 0,
+            cleanup_pending: false,
+        };
+        let checked = if !seen.insert(address.clone()) {
+            Err(Error::invalid_input("The address is listed more than once".to_string()).with_field("address"))
+        } else if let Err(err) = _check_ban_address(&address) {
+            Err(err)
+        } else if !_is_bannable(&address) {
+            Err(Error::unauthorized("You are not authorized to ban the user".to_string()))
+        } else if let Err(err) = _ensure_may_ban(&caller, &address) {
+            Err(err)
+        } else if requires_approval {
+            _propose_ban(address, reason.clone(), None, caller.clone()).map(|hidden| {
+                result.courses_removed = hidden.len() as u64;
+            })
+        } else {
+            let deleted = _delete_banned_courses(&address, budget);
+            budget -= deleted;
+            result.courses_removed = deleted as u64;
+            result.cleanup_pending = !_course_ids_by_creator(&address).is_empty();
+            if result.cleanup_pending {
+                BAN_CLEANUPS.with(|cleanups| cleanups.lock().unwrap().push(address.clone()));
+            }
+            _record_ban(address, reason.clone(), None, caller.clone(), deleted as u64);
+            Ok(())
+        };
+        result.error = checked.err();
+        results.push(result);
+    }
+    Ok(results)
+}
+
+// Chooses whether bans by moderators need to be confirmed by the admin. Only the admin can access
+#[ic_cdk::update]
+fn set_ban_approval_required(required: bool) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_ban_approval_required");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change the ban approval mode".to_string()));
+    }
+    BAN_APPROVAL_REQUIRED.with(|approval_required| *approval_required.lock().unwrap() = required);
+    Ok(())
+}
+
+// Pauses the canister: update calls of everyone but the admin are rejected with CanisterPaused
+// while queries are still served. Only the admin can access
+#[ic_cdk::update]
+fn pause() -> Result<(), Error> {
+    let _profile = ProfileGuard::new("pause");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can pause the canister".to_string()));
+    }
+    PAUSED_AT.with(|paused_at| {
+        let mut paused_at = paused_at.lock().unwrap();
+        if paused_at.is_some() {
+            return Err(Error::invalid_input("The canister is already paused".to_string()));
+        }
+        *paused_at = Some(time());
+        Ok(())
+    })
+}
+
+// Resumes the update calls of a paused canister. Only the admin can access
+#[ic_cdk::update]
+fn unpause() -> Result<(), Error> {
+    let _profile = ProfileGuard::new("unpause");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can unpause the canister".to_string()));
+    }
+    PAUSED_AT.with(|paused_at| match paused_at.lock().unwrap().take() {
+        Some(_) => Ok(()),
+        None => Err(Error::invalid_input("The canister isn't paused".to_string())),
+    })
+}
+
+// Puts the canister in maintenance until expected_end: changes by everyone but the admin are rejected with
+// the given message, which the frontend can display. The scheduler ends the maintenance at expected_end,
+// starting it again replaces the notice. Only the admin can access
+#[ic_cdk::update]
+fn start_maintenance(message: String, expected_end: u64) -> Result<MaintenanceNotice, Error> {
+    let _profile = ProfileGuard::new("start_maintenance");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can start a maintenance".to_string()));
+    }
+    let message = _normalize_line("message", &message)?;
+    if message.is_empty() {
+        return Err(Error::empty_fields("Please fill in a message for the maintenance".to_string()));
+    }
+    if message.chars().count() > MAX_NOTE_LENGTH {
+        return Err(Error::invalid_input(
+            format!("The maintenance message can be at most {} characters long", MAX_NOTE_LENGTH),
+        ));
+    }
+    if expected_end <= time() {
+        return Err(Error::invalid_input("The expected end of the maintenance must be in the future".to_string()));
+    }
+    let notice = MaintenanceNotice {
+        message,
+        started_at: time(),
+        expected_end,
+    };
+    MAINTENANCE.with(|maintenance| *maintenance.lock().unwrap() = Some(notice.clone()));
+    Ok(notice)
+}
+
+// Ends the maintenance before its expected end. Only the admin can access
+#[ic_cdk::update]
+fn end_maintenance() -> Result<(), Error> {
+    let _profile = ProfileGuard::new("end_maintenance");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can end a maintenance".to_string()));
+    }
+    MAINTENANCE.with(|maintenance| match maintenance.lock().unwrap().take() {
+        Some(_) => Ok(()),
+        None => Err(Error::invalid_input("The canister isn't in maintenance".to_string())),
+    })
+}
+
+// Returns the notice of the maintenance in progress, if any, for the frontend's banner
+#[ic_cdk::query]
+fn get_maintenance_notice() -> Option<MaintenanceNotice> {
+    MAINTENANCE.with(|maintenance| maintenance.lock().unwrap().clone())
+}
+
+// Sets what new creators must meet before publishing their first course. Only the admin can access
+#[ic_cdk::update]
+fn set_publishing_requirements(requirements: PublishingRequirements) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_publishing_requirements");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can change the publishing requirements".to_string()));
+    }
+    PUBLISHING_REQUIREMENTS.with(|current| *current.lock().unwrap() = requirements);
+    Ok(())
+}
+
+// Returns what new creators must meet before publishing their first course
+#[ic_cdk::query]
+fn get_publishing_requirements() -> PublishingRequirements {
+    PUBLISHING_REQUIREMENTS.with(|requirements| requirements.lock().unwrap().clone())
+}
+
+// Lists the bans waiting for the admin. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_pending_bans() -> Result<Vec<PendingBan>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller) {
+        return Err(Error::unauthorized("Only admin or moderators can list pending bans".to_string()));
+    }
+    Ok(PENDING_BANS.with(|pending| pending.lock().unwrap().clone()))
+}
+
+// Confirms a ban proposed by a moderator, deleting the hidden courses. Only the admin can access
+#[ic_cdk::update]
+fn confirm_ban(address: String) -> Result<Vec<Course>, Error> {
+    let _profile = ProfileGuard::new("confirm_ban");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can confirm bans".to_string()));
+    }
+    let pending = _take_pending_ban(&address)?;
+    _release_held_courses(&pending);
+    // A ban whose term ended before it was confirmed lapses instead of being applied
+    if matches!(pending.expires_at, Some(expires_at) if expires_at <= time()) {
+        _log_moderation(
+            ModerationAction::BanRejected,
+            &caller,
+            address,
+            format!("proposed by {}, expired before it was confirmed", pending.proposed_by),
+        );
+        _notify(
+            &pending.proposed_by,
+            NotificationKind::BanLapsed,
+            format!("Your ban of {} expired before it was confirmed and has lapsed.", pending.address),
+        );
+        return Err(Error::invalid_input("The proposed ban has already expired".to_string()));
+    }
+    _log_moderation(ModerationAction::BanConfirmed, &caller, address, format!("proposed by {}", pending.proposed_by));
+    _apply_ban(pending.address, pending.reason, pending.expires_at, pending.proposed_by)
+}
+
+// Rejects a ban proposed by a moderator, showing the hidden courses again. Only the admin can access
+#[ic_cdk::update]
+fn reject_ban(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("reject_ban");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can reject bans".to_string()));
+    }
+    let pending = _take_pending_ban(&address)?;
+    _release_held_courses(&pending);
+    _log_moderation(ModerationAction::BanRejected, &caller, address, format!("proposed by {}", pending.proposed_by));
+    _notify(
+        &pending.proposed_by,
+        NotificationKind::BanRejected,
+        format!("The admin rejected your ban of {}.", pending.address),
+    );
+    Ok(())
+}
+
+// Lists the bans, newest first, optionally only the active or expired ones and those matching a search.
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_bans(query: BanQuery) -> Result<BanPage, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin or moderators can list bans".to_string()));
+    }
+    let page = _validate_page(query.page)?;
+    let search = query.search.as_deref().map(_fold).filter(|search| !search.is_empty());
+    let now = time();
+    let mut bans: Vec<BanRecord> = BANS.with(|bans| {
+        bans.borrow()
+            .iter()
+            .map(|(_, ban)| ban)
+            .filter(|ban| match query.state {
+                Some(BanState::Active) => _ban_in_effect(ban, now),
+                Some(BanState::Expired) => !_ban_in_effect(ban, now),
+                None => true,
+            })
+            .filter(|ban| match search {
+                Some(ref search) => {
+                    _fold(&ban.address).contains(search.as_str())
+                        || _fold(&ban.banned_by).contains(search.as_str())
+                        || matches!(ban.reason, Some(ref reason) if _fold(reason).contains(search.as_str()))
+                }
+                None => true,
+            })
+            .collect()
+    });
+    bans.sort_by_key(|ban| Reverse(ban.banned_at));
+    let total_count = bans.len() as u64;
+    let bans: Vec<BanRecord> = bans
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect();
+    Ok(BanPage {
+        has_more: page.offset + (bans.len() as u64) < total_count,
+        bans,
+        total_count,
+    })
+}
+
+// Un ban a creator from adding courses
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn un_ban_creator(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("un_ban_creator");
+    _ensure_running()?;
+    // The caller must be admin or moderator
+    let caller = api::caller().to_string(); // Convert caller address to string
+
+    // cheks if the caller is the admin or a moderator
+    let is_authorized: bool = _is_authorized(caller.clone());
+
+    if is_authorized {
+        if !BANS.with(|bans| bans.borrow().contains_key(&UserKey(address.clone()))) {
+            return Err(Error::not_found("Address not found in banned list.".to_string()));
+        }
+        _ensure_may_unban(&caller, &address)?;
+        BANS.with(|bans| bans.borrow_mut().remove(&UserKey(address.clone())));
+        _log_moderation(ModerationAction::UserUnbanned, &caller, address, String::new());
+        Ok(())
+    } else {
+        Err(Error::unauthorized("You are not authorized to ban the user".to_string()))
+    }
+}
+
+// Un bans several creators in one call, with a result for every address
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn un_ban_creators(addresses: Vec<String>) -> Result<Vec<BulkBanResult>, Error> {
+    let _profile = ProfileGuard::new("un_ban_creators");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to ban the user".to_string()));
+    }
+    _check_bulk_size(&addresses)?;
+
+    let results = addresses
+        .into_iter()
+        .map(|address| {
+            let banned = BANS.with(|bans| bans.borrow().contains_key(&UserKey(address.clone())));
+            let error = if !banned {
+                Some(Error::not_found("Address not found in banned list.".to_string()))
+            } else if let Err(err) = _ensure_may_unban(&caller, &address) {
+                Some(err)
+            } else {
+                BANS.with(|bans| bans.borrow_mut().remove(&UserKey(address.clone())));
+                _log_moderation(ModerationAction::UserUnbanned, &caller, address.clone(), String::new());
+                None
+            };
+            BulkBanResult {
+                address,
+                error,
+                courses_removed: 0,
+                cleanup_pending: false,
+            }
+        })
+        .collect();
+    Ok(results)
+}
+
+// Checks if an address is currently banned
+#[ic_cdk::query]
+fn is_banned(address: String) -> bool {
+    _is_banned(&address)
+}
+
+// Returns whether the caller is banned, with the reason and expiry of the ban
+#[ic_cdk::query]
+fn get_my_ban_status() -> BanStatus {
+    let caller = api::caller().to_string();
+    match _active_ban(&caller) {
+        Some(ban) => BanStatus {
+            is_banned: true,
+            reason: ban.reason,
+            banned_at: Some(ban.banned_at),
+            expires_at: ban.expires_at,
+        },
+        None => BanStatus::default(),
+    }
+}
+
+// Lightweight health check returning the version, status and store sizes
+#[ic_cdk::query]
+fn health_check() -> HealthCheck {
+    HealthCheck {
+        status: _service_status(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: SCHEMA_VERSION.with(|version| *version.borrow().get()),
+        course_count: STORAGE.with(|storage| storage.borrow().len()),
+        enrollment_count: ENROLLMENTS.with(|enrollments| enrollments.borrow().len()),
+        like_count: LIKES.with(|likes| likes.borrow().len()),
+        moderator_count: MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().len() as u64),
+        banned_count: BANS.with(|bans| bans.borrow().len()),
+        maintenance: get_maintenance_notice(),
+    }
+}
+
+// Hashes every stable store, separately and combined, so operators can check that the data is the same
+// before and after an upgrade or when restoring a backup. Only the admin can access
+#[ic_cdk::query]
+fn compute_state_digest() -> Result<StateDigest, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can compute the state digest".to_string()));
+    }
+    macro_rules! digests {
+        ($($store:ident),*) => {
+            vec![$($store.with(|store| _store_digest(stringify!($store), &store.borrow()))),*]
+        };
+    }
+    let mut stores = digests!(
+        STORAGE, INTERNED_STRINGS, INTERNED_IDS, ACTIVITY, LIKES, ENROLLMENTS, CATEGORY_INDEX, CREATOR_INDEX,
+        NOTIFICATIONS, TAKEDOWNS, BLOCKED_TERMS, MODERATION_QUEUE, HELD_COURSES, MODERATION_LOG,
+        USER_SETTINGS, BLOCKS, TRANSLATIONS, FINGERPRINTS, POSSIBLE_DUPLICATES, ANNOUNCEMENTS, FAQ, POLLS,
+        POLL_VOTES, SESSIONS, RSVPS, COHORTS, WAITLISTS, ATTENDANCE, FEEDBACK, ASSIGNMENTS, SUBMISSIONS,
+        OUTCOMES, CHANGELOG, COURSE_EDITS, COMMENTS, COMMENT_VERSIONS, VERIFICATIONS, COURSE_EXPIRY,
+        ARCHIVED_COURSES, USER_ACTIVITY, ENDORSEMENTS, STREAKS, GOALS, API_KEYS, SUBSCRIBERS, EVENT_DELIVERIES,
+        DRAFTS, FIRST_SEEN, BANS, STAFF_NOTES, COURSE_LABELS, DOMAIN_RULES, METHOD_USAGE, TAG_SYNONYMS,
+        CATEGORY_PINS, WEEKLY_DIGESTS, LINK_CHECKS, MESSAGE_CATALOG, SEARCH_INDEX, PREFIX_INDEX
+    );
+    for (name, value) in [
+        ("ID_COUNTER", ID_COUNTER.with(|counter| *counter.borrow().get())),
+        ("NOTIFICATION_ID_COUNTER", NOTIFICATION_ID_COUNTER.with(|counter| *counter.borrow().get())),
+        ("EVENT_SEQUENCE", EVENT_SEQUENCE.with(|sequence| *sequence.borrow().get())),
+        ("SCHEMA_VERSION", SCHEMA_VERSION.with(|version| *version.borrow().get())),
+    ] {
+        stores.push(StoreDigest {
+            name: name.to_string(),
+            entries: 1,
+            digest: Sha256::digest(value.to_be_bytes()).to_vec(),
+        });
+    }
+    let mut combined = Sha256::new();
+    for store in &stores {
+        combined.update(store.name.as_bytes());
+        combined.update(store.entries.to_be_bytes());
+        combined.update(&store.digest);
+    }
+    Ok(StateDigest {
+        stores,
+        digest: combined.finalize().to_vec(),
+        computed_at: time(),
+    })
+}
+
+// Retrieves the pending reviews, appeals, bans and verifications, the latest bans and the storage and cycles
+// of the canister in one call. Only the admin can access
+#[ic_cdk::query]
+fn get_admin_dashboard() -> Result<AdminDashboard, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can see the dashboard".to_string()));
+    }
+    let mut recent_bans: Vec<BanRecord> = BANS.with(|bans| bans.borrow().iter().map(|(_, ban)| ban).collect());
+    recent_bans.sort_by_key(|ban| Reverse(ban.banned_at));
+    recent_bans.truncate(DASHBOARD_RECENT_BANS);
+    Ok(AdminDashboard {
+        queued_courses: MODERATION_QUEUE.with(|queue| queue.borrow().len()),
+        possible_duplicates: POSSIBLE_DUPLICATES.with(|duplicates| duplicates.borrow().len()),
+        pending_appeals: TAKEDOWNS.with(|takedowns| {
+            takedowns
+                .borrow()
+                .iter()
+                .filter(|(_, takedown)| matches!(&takedown.appeal, Some(appeal) if appeal.rejected_at.is_none()))
+                .count() as u64
+        }),
+        pending_bans: PENDING_BANS.with(|pending| pending.lock().unwrap().len() as u64),
+        pending_verifications: VERIFICATIONS.with(|verifications| {
+            verifications
+                .borrow()
+                .iter()
+                .filter(|(_, request)| request.status == VerificationStatus::Pending)
+                .count() as u64
+        }),
+        recent_bans,
+        health: health_check(),
+        stable_memory_bytes: api::stable::stable64_size() * WASM_PAGE_SIZE,
+        heap_memory_bytes: _heap_memory_bytes(),
+        cycles: api::canister_balance128(),
+    })
+}
+
+// Issues an API key for an off-chain service to read the catalog through http_request, allowed the given
+// number of requests per minute. The key is only returned here, the canister keeps its hash. Only the admin can access
+#[ic_cdk::update]
+async fn create_api_key(label: String, requests_per_minute: u32) -> Result<IssuedApiKey, Error> {
+    let _profile = ProfileGuard::new("create_api_key");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can issue API keys".to_string()));
+    }
+    let label = _normalize_line("label", &label)?;
+    if label.is_empty() {
+        return Err(Error::empty_fields("Please fill in a label for the API key".to_string()));
+    }
+    _validate_tag("label", &label)?;
+    if requests_per_minute == 0 || requests_per_minute > MAX_API_KEY_REQUESTS_PER_MINUTE {
+        return Err(Error::invalid_input(
+            format!("An API key can be allowed between 1 and {} requests per minute", MAX_API_KEY_REQUESTS_PER_MINUTE),
+        ));
+    }
+    let (random,) = api::management_canister::main::raw_rand().await.map_err(|(_, msg)| Error::invalid_input(
+        format!("Could not generate the API key: {}", msg),
+    ))?;
+    let id = API_KEYS.with(|keys| keys.borrow().iter().map(|(id, _)| id + 1).max().unwrap_or(0));
+    let key = format!("dck_{}_{}", id, _hex(&random));
+    let api_key = ApiKey {
+        id,
+        label,
+        key_hash: Sha256::digest(key.as_bytes()).to_vec(),
+        requests_per_minute,
+        created_by: caller,
+        created_at: time(),
+        revoked_at: None,
+        last_used_at: None,
+        window_start: 0,
+        window_requests: 0,
+    };
+    API_KEYS.with(|keys| keys.borrow_mut().insert(id, api_key.clone()));
+    Ok(IssuedApiKey { key, api_key })
+}
+
+// Revokes an API key, which is then refused by http_request. Only the admin can access
+#[ic_cdk::update]
+fn revoke_api_key(id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("revoke_api_key");
+    _ensure_running()?;
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can revoke API keys".to_string()));
+    }
+    let mut api_key = API_KEYS.with(|keys| keys.borrow().get(&id)).ok_or(Error::not_found(
+        format!("an API key with id={} not found", id),
+    ).with_id(id))?;
+    if api_key.revoked_at.is_some() {
+        return Err(Error::invalid_input(format!("The API key with id={} is already revoked", id)));
+    }
+    api_key.revoked_at = Some(time());
+    API_KEYS.with(|keys| keys.borrow_mut().insert(id, api_key));
+    Ok(())
+}
+
+// Lists the API keys with their usage, without the keys themselves. Only the admin can access
+#[ic_cdk::query]
+fn get_api_keys() -> Result<Vec<ApiKey>, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can list API keys".to_string()));
+    }
+    Ok(API_KEYS.with(|keys| keys.borrow().iter().map(|(_, api_key)| api_key).collect()))
+}
+
+// Serves HTTP requests from the gateway. Catalog requests under /api/ need an API key and are counted
+// against its rate limit, so they are upgraded to update calls
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let (path, query) = _split_url(&request.url);
+    if path.starts_with("/api/") {
+        return HttpResponse {
+            status_code: 200,
+            headers: Vec::new(),
+            body: Vec::new(),
+            upgrade: Some(true),
+        };
+    }
+    if request.method != "GET" {
+        return _http_error(405, "Only GET requests are supported");
+    }
+    // GET /courses/{id}/body serves the body of a course rendered as an HTML fragment
+    let rendered_id = path
+        .strip_prefix("/courses/")
+        .and_then(|rest| rest.strip_suffix("/body"))
+        .and_then(|id| id.parse::<u64>().ok());
+    if let Some(id) = rendered_id {
+        return match _shown_ids(&[CourseId(id)]).first().and_then(_get_course_) {
+            Some(course) => HttpResponse {
+                status_code: 200,
+                headers: vec![
+                    ("Content-Type".to_string(), "text/html; charset=utf-8".to_string()),
+                    ("Content-Security-Policy".to_string(), "default-src 'none'; img-src https:".to_string()),
+                ],
+                body: _render_markdown(&course.body).into_bytes(),
+                upgrade: None,
+            },
+            None => _http_error(404, "Course not found"),
+        };
+    }
+    // GET /embed/courses/{id} and GET /embed/creators/{principal} serve cards for third party sites
+    if let Some(id) = path.strip_prefix("/embed/courses/") {
+        return match id.parse::<u64>().ok().and_then(_course_embed) {
+            Some(embed) => _http_embed(&embed),
+            None => _http_error(404, "Course not found"),
+        };
+    }
+    if let Some(address) = path.strip_prefix("/embed/creators/") {
+        return match _creator_embed(address) {
+            Some(embed) => _http_embed(&embed),
+            None => _http_error(404, "Creator not found"),
+        };
+    }
+    // GET /oembed?url= describes a course or creator page of the embed site
+    if path == "/oembed" {
+        return _oembed(query);
+    }
+    _http_error(404, "Not found")
+}
+
+// Serves the catalog requests upgraded by http_request: GET /api/courses?offset=&limit= and GET /api/courses/{id}.
+// The API key is taken from the X-Api-Key header or an Authorization: Bearer header
+#[ic_cdk::update]
+fn http_request_update(request: HttpRequest) -> HttpResponse {
+    let _profile = ProfileGuard::new("http_request_update");
+    if _service_status() == ServiceStatus::Paused && !_is_admin(api::caller().to_string()) {
+        return _http_error(503, "The canister is paused");
+    }
+    if let Err(response) = _check_api_key(&request) {
+        return response;
+    }
+    if request.method != "GET" {
+        return _http_error(405, "Only GET requests are supported");
+    }
+    let (path, query) = _split_url(&request.url);
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.parse::<u64>().ok())
+    };
+    match path.strip_prefix("/api/courses") {
+        Some("") | Some("/") => {
+            let page = Page {
+                offset: param("offset").unwrap_or(0),
+                limit: param("limit").map_or(MAX_PAGE_SIZE, |limit| limit.min(MAX_PAGE_SIZE as u64) as u32),
+            };
+            let course_ids: Vec<CourseId> = STORAGE.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect());
+            match _page_of_ids(&course_ids, Some(page)) {
+                Ok(page) => _http_json(&page),
+                Err(_) => _http_error(400, "Invalid page"),
+            }
+        }
+        Some(id) => match id.trim_start_matches('/').parse::<u64>() {
+            Ok(id) => match _shown_ids(&[CourseId(id)]).first().and_then(_get_course_) {
+                Some(course) => _http_json(&_visible_courses(vec![course]).remove(0)),
+                None => _http_error(404, "Course not found"),
+            },
+            Err(_) => _http_error(404, "Not found"),
+        },
+        None => _http_error(404, "Not found"),
+    }
+}
+
+// Asks for the calling canister to receive course events by calls to the given method.
+// Events are only sent once the admin approved the subscription
+#[ic_cdk::update]
+fn register_subscriber(method: String) -> Result<Subscriber, Error> {
+    let _profile = ProfileGuard::new("register_subscriber");
+    _ensure_running()?;
+    let caller = api::caller();
+    if caller == candid::Principal::anonymous() {
+        return Err(Error::unauthorized("Anonymous callers can't subscribe to course events".to_string()));
+    }
+    let method = method.trim().to_string();
+    if method.is_empty() {
+        return Err(Error::empty_fields("Please fill in the method receiving the events".to_string()));
+    }
+    if method.chars().any(char::is_whitespace) {
+        return Err(Error::invalid_input("The method name can't contain spaces".to_string()));
+    }
+    _validate_tag("method", &method)?;
+    let key = UserKey(caller.to_string());
+    if SUBSCRIBERS.with(|subscribers| subscribers.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input("The canister is already subscribed".to_string()));
+    }
+    let subscriber = Subscriber {
+        canister: key.0.clone(),
+        method,
+        status: SubscriberStatus::Pending,
+        requested_at: time(),
+        approved_at: None,
+        delivered_count: 0,
+        failed_count: 0,
+    };
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().insert(key, subscriber.clone()));
+    Ok(subscriber)
+}
+
+// Approves a subscription, sending the canister the course events from now on. Only the admin can access
+#[ic_cdk::update]
+fn approve_subscriber(canister: String) -> Result<Subscriber, Error> {
+    let _profile = ProfileGuard::new("approve_subscriber");
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can approve subscribers".to_string()));
+    }
+    let key = UserKey(canister.clone());
+    let mut subscriber = SUBSCRIBERS.with(|subscribers| subscribers.borrow().get(&key)).ok_or(Error::not_found(
+        format!("No subscription found for {}", canister),
+    ))?;
+    if subscriber.status == SubscriberStatus::Approved {
+        return Err(Error::invalid_input("The subscription is already approved".to_string()));
+    }
+    subscriber.status = SubscriberStatus::Approved;
+    subscriber.approved_at = Some(time());
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().insert(key, subscriber.clone()));
+    Ok(subscriber)
+}
+
+// Ends a subscription and drops the events still waiting for it.
+// Only the admin or the subscribed canister can access
+#[ic_cdk::update]
+fn remove_subscriber(canister: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_subscriber");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if caller != canister && !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin or the subscribed canister can end a subscription".to_string()));
+    }
+    if SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().remove(&UserKey(canister.clone()))).is_none() {
+        return Err(Error::not_found(format!("No subscription found for {}", canister)));
+    }
+    EVENT_DELIVERIES.with(|deliveries| {
+        let mut deliveries = deliveries.borrow_mut();
+        let dropped: Vec<u64> = deliveries
+            .iter()
+            .filter(|(_, delivery)| delivery.subscriber == canister)
+            .map(|(id, _)| id)
+            .collect();
+        for id in dropped {
+            deliveries.remove(&id);
+        }
+    });
+    Ok(())
+}
+
+// Lists the subscriptions, pending and approved. Only the admin can access
+#[ic_cdk::query]
+fn get_subscribers() -> Result<Vec<Subscriber>, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can list subscribers".to_string()));
+    }
+    Ok(SUBSCRIBERS.with(|subscribers| subscribers.borrow().iter().map(|(_, subscriber)| subscriber).collect()))
+}
+
+// Takes down a course instead of deleting it. The course is hidden from everyone but
+// its creator and the staff, and the creator is notified with the reason and may appeal
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn take_down_course(id: CourseId, reason: TakedownReason, note: String) -> Result<Takedown, Error> {
+    let _profile = ProfileGuard::new("take_down_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can take down courses".to_string()));
+    }
+    _validate_note(&note)?;
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    _ensure_moderates(&caller, &course.category)?;
+    let takedown = Takedown {
+        course,
+        reason,
+        note,
+        taken_down_by: caller.clone(),
+        taken_down_at: time(),
+        appeal: None,
+    };
+    _check_fits(&takedown, "takedown")?;
+    _unstore_course(id);
+    TAKEDOWNS.with(|takedowns| takedowns.borrow_mut().insert(id, takedown.clone()));
+    _record_moderator_action(&caller, |stats| stats.takedowns += 1);
+    _log_moderation(
+        ModerationAction::CourseTakenDown,
+        &caller,
+        id.to_string(),
+        format!("reason: {:?}; note: {}", takedown.reason, takedown.note),
+    );
+    _notify(
+        &takedown.course.creator_address,
+        NotificationKind::CourseTakenDown,
+        format!(
+            "Your course \"{}\" (id={}) was taken down. You can see the reason with get_takedown and appeal with appeal_takedown.",
+            takedown.course.title, id
+        ),
+    );
+    Ok(takedown)
+}
+
+// Retrieves a taken down course with the reason. Only its creator, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_takedown(id: CourseId) -> Result<Takedown, Error> {
+    let caller = api::caller().to_string();
+    let takedown = _get_takedown(id)?;
+    if takedown.course.creator_address == caller || _is_authorized(caller) {
+        Ok(takedown)
+    } else {
+        Err(Error::unauthorized(format!("You are not authorized to view the takedown of course with id={}", id)))
+    }
+}
+
+// Retrieves the caller's taken down courses
+#[ic_cdk::query]
+fn get_my_takedowns() -> Vec<Takedown> {
+    let caller = api::caller().to_string();
+    TAKEDOWNS.with(|takedowns| {
+        takedowns
+            .borrow()
+            .iter()
+            .map(|(_, takedown)| takedown)
+            .filter(|takedown| takedown.course.creator_address == caller)
+            .collect()
+    })
+}
+
+// Appeals the takedown of one of the caller's courses. Each takedown can be appealed once
+#[ic_cdk::update]
+fn appeal_takedown(id: CourseId, message: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("appeal_takedown");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    let mut takedown = _get_takedown(id)?;
+    if takedown.course.creator_address != caller {
+        return Err(Error::unauthorized("Only the creator of the course can appeal its takedown".to_string()));
+    }
+    if takedown.appeal.is_some() {
+        return Err(Error::invalid_input(format!("The takedown of course with id={} has already been appealed", id)));
+    }
+    if message.trim().is_empty() {
+        return Err(Error::empty_fields("Please explain why the course should be reinstated".to_string()));
+    }
+    _validate_note(&message)?;
+    takedown.appeal = Some(Appeal {
+        message,
+        appealed_at: time(),
+        rejected_at: None,
+    });
+    _check_fits(&takedown, "takedown")?;
+    TAKEDOWNS.with(|takedowns| takedowns.borrow_mut().insert(id, takedown));
+    for address in _staff_addresses() {
+        _notify(
+            &address,
+            NotificationKind::TakedownAppealed,
+            format!("The takedown of course with id={} has been appealed.", id),
+        );
+    }
+    Ok(())
+}
+
+// Resolves a pending appeal, either reinstating the course or rejecting the appeal
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn resolve_takedown_appeal(id: CourseId, reinstate: bool) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("resolve_takedown_appeal");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can resolve appeals".to_string()));
+    }
+    let mut takedown = _get_takedown(id)?;
+    _ensure_moderates(&caller, &takedown.course.category)?;
+    let appeal = match takedown.appeal.as_mut() {
+        Some(appeal) if appeal.rejected_at.is_none() => appeal,
+        _ => {
+            return Err(Error::not_found(format!("The takedown of course with id={} has no pending appeal", id)))
+        }
+    };
+    if !reinstate {
+        appeal.rejected_at = Some(time());
+        _check_fits(&takedown, "takedown")?;
+    }
+    let creator = takedown.course.creator_address.clone();
+    let outcome = if reinstate { "reinstated" } else { "rejected" };
+    _log_moderation(ModerationAction::AppealResolved, &caller, id.to_string(), outcome.to_string());
+    if reinstate {
+        TAKEDOWNS.with(|takedowns| takedowns.borrow_mut().remove(&id));
+        do_insert(&takedown.course);
+        _notify(
+            &creator,
+            NotificationKind::CourseReinstated,
+            format!("Your appeal was accepted and course with id={} has been reinstated.", id),
+        );
+    } else {
+        TAKEDOWNS.with(|takedowns| takedowns.borrow_mut().insert(id, takedown));
+        _notify(
+            &creator,
+            NotificationKind::TakedownAppealRejected,
+            format!("Your appeal against the takedown of course with id={} was rejected.", id),
+        );
+    }
+    Ok(())
+}
+
+// Endorses a course as a staff pick and notifies its creator. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn endorse_course(id: CourseId, note: Option<String>) -> Result<Endorsement, Error> {
+    let _profile = ProfileGuard::new("endorse_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can endorse courses".to_string()));
+    }
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    _ensure_moderates(&caller, &course.category)?;
+    if ENDORSEMENTS.with(|endorsements| endorsements.borrow().contains_key(&id)) {
+        return Err(Error::invalid_input(format!("The course with id={} is already endorsed", id)));
+    }
+    let note = note.map(|note| _normalize_body(&note)).transpose()?.filter(|note| !note.is_empty());
+    if let Some(ref note) = note {
+        _validate_note(note)?;
+    }
+    let endorsement = Endorsement {
+        course_id: id,
+        endorsed_by: caller.clone(),
+        note,
+        endorsed_at: time(),
+    };
+    ENDORSEMENTS.with(|endorsements| endorsements.borrow_mut().insert(id, endorsement.clone()));
+    _log_moderation(
+        ModerationAction::CourseEndorsed,
+        &caller,
+        id.to_string(),
+        endorsement.note.clone().unwrap_or_default(),
+    );
+    _notify(
+        &course.creator_address,
+        NotificationKind::CourseEndorsed,
+        format!("Your course \"{}\" (id={}) was endorsed as a staff pick.", course.title, id),
+    );
+    Ok(endorsement)
+}
+
+// Withdraws the endorsement of a course. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn withdraw_endorsement(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("withdraw_endorsement");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can withdraw endorsements".to_string()));
+    }
+    if let Some(course) = _get_course_(&id) {
+        _ensure_moderates(&caller, &course.category)?;
+    }
+    match ENDORSEMENTS.with(|endorsements| endorsements.borrow_mut().remove(&id)) {
+        Some(_) => {
+            _log_moderation(ModerationAction::EndorsementWithdrawn, &caller, id.to_string(), String::new());
+            Ok(())
+        }
+        None => Err(Error::not_found(format!("The course with id={} is not endorsed", id))),
+    }
+}
+
+// Retrieves the endorsement of a course, if staff picked it
+#[ic_cdk::query]
+fn get_course_endorsement(id: CourseId) -> Result<Option<Endorsement>, Error> {
+    _ensure_course_exists(id)?;
+    Ok(ENDORSEMENTS.with(|endorsements| endorsements.borrow().get(&id)))
+}
+
+// Retrieves a page of the courses endorsed by the staff, most recently endorsed first
+#[ic_cdk::query]
+fn get_endorsed_courses(page: Option<Page>) -> Result<CoursePage, Error> {
+    let mut endorsements: Vec<Endorsement> =
+        ENDORSEMENTS.with(|endorsements| endorsements.borrow().iter().map(|(_, endorsement)| endorsement).collect());
+    endorsements.sort_by_key(|endorsement| Reverse(endorsement.endorsed_at));
+    let course_ids: Vec<CourseId> = endorsements.into_iter().map(|endorsement| endorsement.course_id).collect();
+    _page_of_ids(&course_ids, page)
+}
+
+// Asks the admin to verify the caller as a creator, with links to their credentials and a statement.
+// Creators can request again after a rejection or revocation, but not while a request is pending or approved
+#[ic_cdk::update]
+fn request_verification(payload: VerificationPayLoad) -> Result<VerificationRequest, Error> {
+    let _profile = ProfileGuard::new("request_verification");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if _course_ids_by_creator(&caller).is_empty() {
+        return Err(Error::unauthorized("Only creators of a course can request verification".to_string()));
+    }
+    if let Some(request) = VERIFICATIONS.with(|verifications| verifications.borrow().get(&UserKey(caller.clone()))) {
+        match request.status {
+            VerificationStatus::Pending => {
+                return Err(Error::invalid_input("Your verification request is still being reviewed".to_string()))
+            }
+            VerificationStatus::Approved => {
+                return Err(Error::invalid_input("You are already verified".to_string()))
+            }
+            VerificationStatus::Rejected | VerificationStatus::Revoked => {}
+        }
+    }
+    let statement = _normalize_body(&payload.statement)?;
+    if statement.is_empty() || payload.links.is_empty() {
+        return Err(Error::empty_fields("Please add links to your credentials and a statement about them".to_string()));
+    }
+    _validate_note(&statement)?;
+    if payload.links.len() > MAX_VERIFICATION_LINKS {
+        return Err(Error::invalid_input(
+            format!("A verification request can have at most {} links", MAX_VERIFICATION_LINKS),
+        ));
+    }
+    let links: Vec<String> = payload.links.iter().map(|link| link.trim().to_string()).collect();
+    for link in links.iter() {
+        _validate_attachment_url(link)?;
+        if link.chars().count() > MAX_URL_LENGTH {
+            return Err(Error::invalid_input(format!("Links must be at most {} characters long", MAX_URL_LENGTH)));
+        }
+    }
+    let request = VerificationRequest {
+        address: caller.clone(),
+        links,
+        statement,
+        status: VerificationStatus::Pending,
+        submitted_at: time(),
+        reviewed_at: None,
+    };
+    VERIFICATIONS.with(|verifications| verifications.borrow_mut().insert(UserKey(caller.clone()), request.clone()));
+    if let Some(admin) = ADMIN_ADDRESS.with(|admin| admin.lock().unwrap().clone()) {
+        _notify(
+            &admin,
+            NotificationKind::VerificationRequested,
+            format!("{} requested verification as a creator.", caller),
+        );
+    }
+    Ok(request)
+}
+
+// Retrieves the caller's latest verification request
+#[ic_cdk::query]
+fn get_my_verification_request() -> Option<VerificationRequest> {
+    VERIFICATIONS.with(|verifications| verifications.borrow().get(&UserKey(api::caller().to_string())))
+}
+
+// Retrieves the verification requests waiting for a review, oldest first. Only the admin can access
+#[ic_cdk::query]
+fn get_pending_verifications() -> Result<Vec<VerificationRequest>, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can see verification requests".to_string()));
+    }
+    let mut requests: Vec<VerificationRequest> = VERIFICATIONS.with(|verifications| {
+        verifications
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.status == VerificationStatus::Pending)
+            .collect()
+    });
+    requests.sort_by_key(|request| request.submitted_at);
+    Ok(requests)
+}
+
+// Approves or rejects a pending verification request and notifies the creator. Only the admin can access
+#[ic_cdk::update]
+fn review_verification(address: String, approve: bool) -> Result<VerificationRequest, Error> {
+    let _profile = ProfileGuard::new("review_verification");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can review verification requests".to_string()));
+    }
+    let mut request = VERIFICATIONS
+        .with(|verifications| verifications.borrow().get(&UserKey(address.clone())))
+        .filter(|request| request.status == VerificationStatus::Pending)
+        .ok_or(Error::not_found(format!("{} has no pending verification request", address)))?;
+    let (status, action, outcome) = if approve {
+        (VerificationStatus::Approved, ModerationAction::VerificationApproved, "approved")
+    } else {
+        (VerificationStatus::Rejected, ModerationAction::VerificationRejected, "rejected")
+    };
+    request.status = status;
+    request.reviewed_at = Some(time());
+    VERIFICATIONS.with(|verifications| verifications.borrow_mut().insert(UserKey(address.clone()), request.clone()));
+    _log_moderation(action, &caller, address.clone(), String::new());
+    _notify(
+        &address,
+        NotificationKind::VerificationReviewed,
+        format!("Your verification request was {}.", outcome),
+    );
+    Ok(request)
+}
+
+// Withdraws the verified status of a creator, who can then request verification again. Only the admin can access
+#[ic_cdk::update]
+fn revoke_verification(address: String) -> Result<VerificationRequest, Error> {
+    let _profile = ProfileGuard::new("revoke_verification");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can revoke verifications".to_string()));
+    }
+    let mut request = VERIFICATIONS
+        .with(|verifications| verifications.borrow().get(&UserKey(address.clone())))
+        .filter(|request| request.status == VerificationStatus::Approved)
+        .ok_or(Error::not_found(format!("{} is not verified", address)))?;
+    request.status = VerificationStatus::Revoked;
+    request.reviewed_at = Some(time());
+    VERIFICATIONS.with(|verifications| verifications.borrow_mut().insert(UserKey(address.clone()), request.clone()));
+    _log_moderation(ModerationAction::VerificationRevoked, &caller, address.clone(), String::new());
+    _notify(
+        &address,
+        NotificationKind::VerificationReviewed,
+        "Your verified status was revoked.".to_string(),
+    );
+    Ok(request)
+}
+
+// Retrieves whether a creator is verified and when their verification was last reviewed
+#[ic_cdk::query]
+fn get_creator_verification(address: String) -> CreatorVerification {
+    let request = VERIFICATIONS.with(|verifications| verifications.borrow().get(&UserKey(address)));
+    CreatorVerification {
+        verified: matches!(request, Some(ref request) if request.status == VerificationStatus::Approved),
+        reviewed_at: request.and_then(|request| request.reviewed_at),
+    }
+}
+
+// Retrieves a page of the caller's activity feed, newest first: what they did, such as publishing, enrolling
+// in, completing and liking courses, and what happened to them, such as comments and feedback on their courses
+#[ic_cdk::query]
+fn get_my_activity(page: Option<Page>) -> Result<Vec<UserActivity>, Error> {
+    let page = _validate_page(page)?;
+    let address = api::caller().to_string();
+    let start = UserActivityKey {
+        address: address.clone(),
+        at: 0,
+    };
+    let activity: Vec<UserActivity> = USER_ACTIVITY.with(|feed| {
+        feed.borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.address == address)
+            .map(|(_, activity)| activity)
+            .collect()
+    });
+    Ok(activity
+        .into_iter()
+        .rev()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Retrieves the caller's learning streak with the badges they earned. The current streak is 0
+// once a day has passed without learning activity
+#[ic_cdk::query]
+fn get_my_streak() -> LearningStreak {
+    let mut streak = STREAKS
+        .with(|streaks| streaks.borrow().get(&UserKey(api::caller().to_string())))
+        .unwrap_or_default();
+    if streak.last_day + 1 < _today() {
+        streak.current = 0;
+    }
+    streak
+}
+
+// Sets a learning goal for the caller. The scheduler tracks its progress and sends reminders and encouragement
+#[ic_cdk::update]
+fn set_learning_goal(target: GoalTarget) -> Result<LearningGoal, Error> {
+    let _profile = ProfileGuard::new("set_learning_goal");
+    _ensure_running()?;
+    let address = api::caller().to_string();
+    match target {
+        GoalTarget::FinishCourse { course_id, by } => {
+            let key = EngagementKey {
+                course_id,
+                address: address.clone(),
+            };
+            match ENROLLMENTS.with(|enrollments| enrollments.borrow().get(&key)) {
+                None => {
+                    return Err(Error::invalid_input(
+                        format!("You are not enrolled in the course with id={}", course_id),
+                    ))
+                }
+                Some(enrollment) if enrollment.completed_at.is_some() => {
+                    return Err(Error::invalid_input(
+                        format!("You have already completed the course with id={}", course_id),
+                    ))
+                }
+                Some(_) => {}
+            }
+            if by <= time() {
+                return Err(Error::invalid_input("The goal date must be in the future".to_string()));
+            }
+        }
+        GoalTarget::WeeklyLearningDays(days) if !(1..=7).contains(&days) => {
+            return Err(Error::invalid_input("A weekly goal must be between 1 and 7 learning days".to_string()));
+        }
+        GoalTarget::WeeklyLearningDays(_) => {}
+    }
+    let goals = _goals_of(&address);
+    if goals.len() >= MAX_GOALS {
+        return Err(Error::invalid_input(format!("You can have at most {} learning goals", MAX_GOALS)));
+    }
+    let goal = LearningGoal {
+        id: goals.last().map_or(0, |goal| goal.id + 1),
+        target,
+        status: GoalStatus::Active,
+        created_at: time(),
+        last_reminded_at: None,
+        week: _today() / 7,
+        days_this_week: 0,
+    };
+    GOALS.with(|stored| stored.borrow_mut().insert(GoalKey { address, id: goal.id }, goal.clone()));
+    Ok(goal)
+}
+
+// Removes one of the caller's learning goals
+#[ic_cdk::update]
+fn remove_learning_goal(goal_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_learning_goal");
+    _ensure_running()?;
+    let key = GoalKey {
+        address: api::caller().to_string(),
+        id: goal_id,
+    };
+    match GOALS.with(|goals| goals.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("You have no learning goal with id={}", goal_id))),
+    }
+}
+
+// Retrieves the caller's learning goals with their progress
+#[ic_cdk::query]
+fn get_my_goals() -> Vec<LearningGoal> {
+    _goals_of(&api::caller().to_string())
+}
+
+// Adds words or phrases to the content filter. A word ending with * blocks every word
+// starting with it. Only the admin can access
+#[ic_cdk::update]
+fn add_blocked_terms(terms: Vec<String>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("add_blocked_terms");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change the blocked terms".to_string()));
+    }
+    let terms = terms
+        .iter()
+        .map(|term| _normalize_blocked_term(term))
+        .collect::<Result<Vec<BlockedTerm>, Error>>()?;
+    BLOCKED_TERMS.with(|blocked| {
+        let mut blocked = blocked.borrow_mut();
+        for term in terms {
+            blocked.insert(term, ());
+        }
+    });
+    Ok(())
+}
+
+// Removes words or phrases from the content filter. Only the admin can access
+#[ic_cdk::update]
+fn remove_blocked_terms(terms: Vec<String>) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_blocked_terms");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change the blocked terms".to_string()));
+    }
+    BLOCKED_TERMS.with(|blocked| {
+        let mut blocked = blocked.borrow_mut();
+        for term in terms {
+            let words: Vec<&str> = term.split_whitespace().collect();
+            blocked.remove(&BlockedTerm(words.join(" ").to_lowercase()));
+        }
+    });
+    Ok(())
+}
+
+// Lists the blocked words and phrases. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_blocked_terms() -> Result<Vec<String>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller) {
+        return Err(Error::unauthorized("Only admin or moderators can list the blocked terms".to_string()));
+    }
+    Ok(BLOCKED_TERMS.with(|blocked| blocked.borrow().iter().map(|(term, _)| term.0).collect()))
+}
+
+// Puts a domain on the allow or deny list, replacing its earlier rule. Courses and comments
+// linking to a denied domain are rejected, and links to an allowed domain never make a burst.
+// Either way a flagged burst of the domain is dismissed. Only the admin can access
+#[ic_cdk::update]
+fn set_domain_rule(domain: String, policy: DomainPolicy) -> Result<DomainRule, Error> {
+    let _profile = ProfileGuard::new("set_domain_rule");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller.clone()) {
+        return Err(Error::unauthorized("Only admin can change the domain rules".to_string()));
+    }
+    let domain = _normalize_domain(&domain)?;
+    let rule = DomainRule {
+        domain: domain.clone(),
+        policy,
+        added_by: caller,
+        added_at: time(),
+    };
+    DOMAIN_RULES.with(|rules| rules.borrow_mut().insert(DomainKey(domain.clone()), rule.clone()));
+    DOMAIN_BURSTS.with(|bursts| bursts.lock().unwrap().remove(&domain));
+    Ok(rule)
+}
+
+// Takes a domain off the allow or deny list. Only the admin can access
+#[ic_cdk::update]
+fn remove_domain_rule(domain: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("remove_domain_rule");
+    _ensure_running()?;
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can change the domain rules".to_string()));
+    }
+    let domain = _normalize_domain(&domain)?;
+    match DOMAIN_RULES.with(|rules| rules.borrow_mut().remove(&DomainKey(domain.clone()))) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("{} is not on the allow or deny list", domain)).with_field("domain")),
+    }
+}
+
+// Lists the allowed and denied domains. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_domain_rules() -> Result<Vec<DomainRule>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin or moderators can list the domain rules".to_string()));
+    }
+    Ok(DOMAIN_RULES.with(|rules| rules.borrow().iter().map(|(_, rule)| rule).collect()))
+}
+
+// Lists the domains flagged for linking bursts, most recently seen first
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_domain_bursts() -> Result<Vec<DomainBurst>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin or moderators can list the domain bursts".to_string()));
+    }
+    let mut bursts: Vec<DomainBurst> = DOMAIN_BURSTS.with(|bursts| bursts.lock().unwrap().values().cloned().collect());
+    bursts.sort_by_key(|burst| Reverse(burst.last_seen_at));
+    Ok(bursts)
+}
+
+// Dismisses a flagged burst after review. New links to the domain can flag it again
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn dismiss_domain_burst(domain: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("dismiss_domain_burst");
+    _ensure_running()?;
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin or moderators can dismiss domain bursts".to_string()));
+    }
+    let domain = _normalize_domain(&domain)?;
+    if DOMAIN_BURSTS.with(|bursts| bursts.lock().unwrap().remove(&domain)).is_none() {
+        return Err(Error::not_found(format!("{} is not flagged", domain)).with_field("domain"));
+    }
+    // Links seen before the review don't count towards the next burst
+    DOMAIN_SIGHTINGS.with(|sightings| sightings.lock().unwrap().remove(&domain));
+    Ok(())
+}
+
+// Chooses whether courses containing a blocked term are rejected or queued for review
+// Only the admin can access
+#[ic_cdk::update]
+fn set_content_filter_action(action: ContentFilterAction) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("set_content_filter_action");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change the content filter".to_string()));
+    }
+    CONTENT_FILTER_ACTION.with(|filter_action| *filter_action.lock().unwrap() = action);
+    Ok(())
+}
+
+// Returns the blocked terms found in a text, so content can be checked before submitting it
+#[ic_cdk::query]
+fn test_content(text: String) -> Vec<String> {
+    _blocked_terms_in(&text)
+}
+
+// Lists the courses waiting for review, oldest first. Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_moderation_queue() -> Result<Vec<QueuedCourse>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller) {
+        return Err(Error::unauthorized("Only admin or moderators can view the moderation queue".to_string()));
+    }
+    let mut queue: Vec<QueuedCourse> =
+        MODERATION_QUEUE.with(|queue| queue.borrow().iter().map(|(_, queued)| queued).collect());
+    queue.sort_by_key(|queued| (queued.queued_at, queued.course.id));
+    Ok(queue)
+}
+
+// Approves a queued course, publishing it. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn approve_queued_course(id: CourseId) -> Result<Course, Error> {
+    let _profile = ProfileGuard::new("approve_queued_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can review queued courses".to_string()));
+    }
+    _ensure_moderates(&caller, &_queued_category(id)?)?;
+    let queued = _take_queued_course(id)?;
+    do_insert(&queued.course);
+    _record_user_activity(&queued.course.creator_address, UserActivityKind::CoursePublished, id);
+    _record_moderator_action(&caller, |stats| stats.approvals += 1);
+    _log_moderation(ModerationAction::QueuedCourseApproved, &caller, id.to_string(), String::new());
+    _notify(
+        &queued.course.creator_address,
+        NotificationKind::CourseApproved,
+        format!("Your course \"{}\" (id={}) was approved and published.", queued.course.title, id),
+    );
+    Ok(queued.course)
+}
+
+// Rejects a queued course, discarding the queued version. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn reject_queued_course(id: CourseId, note: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("reject_queued_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can review queued courses".to_string()));
+    }
+    _validate_note(&note)?;
+    _ensure_moderates(&caller, &_queued_category(id)?)?;
+    let queued = _take_queued_course(id)?;
+    _log_moderation(ModerationAction::QueuedCourseRejected, &caller, id.to_string(), note.clone());
+    _notify(
+        &queued.course.creator_address,
+        NotificationKind::CourseRejected,
+        format!("Your course \"{}\" (id={}) was not approved: {}", queued.course.title, id, note),
+    );
+    Ok(())
+}
+
+// Lists the courses flagged as near duplicates of another creator's course, most similar first
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_possible_duplicates() -> Result<Vec<PossibleDuplicate>, Error> {
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller) {
+        return Err(Error::unauthorized("Only admin or moderators can view possible duplicates".to_string()));
+    }
+    let mut duplicates: Vec<PossibleDuplicate> =
+        POSSIBLE_DUPLICATES.with(|duplicates| duplicates.borrow().iter().map(|(_, duplicate)| duplicate).collect());
+    duplicates.sort_by_key(|duplicate| (Reverse(duplicate.similarity), duplicate.course_id));
+    Ok(duplicates)
+}
+
+// Dismisses a possible duplicate after review, leaving the course as it is
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn dismiss_possible_duplicate(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("dismiss_possible_duplicate");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("Only admin or moderators can dismiss possible duplicates".to_string()));
+    }
+    let duplicate = POSSIBLE_DUPLICATES
+        .with(|duplicates| duplicates.borrow_mut().remove(&id))
+        .ok_or(Error::not_found(format!("course with id={} is not flagged as a possible duplicate", id)))?;
+    _log_moderation(
+        ModerationAction::PossibleDuplicateDismissed,
+        &caller,
+        id.to_string(),
+        format!("similar to course with id={}", duplicate.original_id),
+    );
+    Ok(())
+}
+
+// Retrieves a page of the moderation log, oldest first, optionally only the entries about one
+// subject (an address or a course id)
+#[ic_cdk::query]
+fn get_moderation_log(subject: Option<String>, page: Option<Page>) -> Result<Vec<LoggedModerationEntry>, Error> {
+    let page = _validate_page(page)?;
+    Ok(MODERATION_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .map(|(_, logged)| logged)
+            .filter(|logged| match &subject {
+                Some(subject) => &logged.entry.subject == subject,
+                None => true,
+            })
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect()
+    }))
+}
+
+// Returns the moderation log head hash with its certificate, so clients can check that the
+// entries they fetched haven't been rewritten
+#[ic_cdk::query]
+fn get_moderation_log_head() -> ModerationLogHead {
+    ModerationLogHead {
+        length: MODERATION_LOG.with(|log| log.borrow().len()),
+        head_hash: _moderation_log_head(),
+        certificate: api::data_certificate(),
+    }
+}
+
+// Adds an internal note about a course or a user, so context such as earlier warnings
+// is shared between the staff. Only the admin or a moderator can access
+#[ic_cdk::update]
+fn add_staff_note(subject: NoteSubject, body: String) -> Result<StaffNote, Error> {
+    let _profile = ProfileGuard::new("add_staff_note");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to add staff notes".to_string()));
+    }
+    match &subject {
+        NoteSubject::Course(id) => _ensure_course_exists(*id)?,
+        NoteSubject::User(address) => {
+            if candid::Principal::from_text(address).is_err() {
+                return Err(Error::invalid_input(format!("{} is not a valid principal", address)).with_field("subject"));
+            }
+        }
+    }
+    let body = body.trim().to_string();
+    if body.is_empty() {
+        return Err(Error::empty_fields("Please write the note".to_string()).with_field("body"));
+    }
+    _validate_note(&body)?;
+    let note = StaffNote {
+        id: _staff_notes_of(&subject).last().map_or(0, |note| note.id + 1),
+        subject,
+        author: caller,
+        body,
+        written_at: time(),
+    };
+    let key = StaffNoteKey { subject: note.subject.clone(), id: note.id };
+    STAFF_NOTES.with(|notes| notes.borrow_mut().insert(key, note.clone()));
+    Ok(note)
+}
+
+// Retrieves a page of the staff notes about a course or a user, newest first
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_staff_notes(subject: NoteSubject, page: Option<Page>) -> Result<Vec<StaffNote>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("You are not authorized to see staff notes".to_string()));
+    }
+    let page = _validate_page(page)?;
+    Ok(_staff_notes_of(&subject)
+        .into_iter()
+        .rev()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Puts a triage label on a course. Labeling a course again keeps the first label
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn label_course(id: CourseId, label: StaffLabel) -> Result<CourseLabel, Error> {
+    let _profile = ProfileGuard::new("label_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to label courses".to_string()));
+    }
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    _ensure_moderates(&caller, &course.category)?;
+    let key = CourseLabelKey { label, course_id: id };
+    if let Some(existing) = COURSE_LABELS.with(|labels| labels.borrow().get(&key)) {
+        return Ok(existing);
+    }
+    let course_label = CourseLabel {
+        course_id: id,
+        label,
+        labeled_by: caller,
+        labeled_at: time(),
+    };
+    COURSE_LABELS.with(|labels| labels.borrow_mut().insert(key, course_label.clone()));
+    Ok(course_label)
+}
+
+// Takes a triage label off a course
+// Only the admin or a moderator can access
+#[ic_cdk::update]
+fn unlabel_course(id: CourseId, label: StaffLabel) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("unlabel_course");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if !_is_authorized(caller.clone()) {
+        return Err(Error::unauthorized("You are not authorized to label courses".to_string()));
+    }
+    if let Some(course) = _get_course_(&id) {
+        _ensure_moderates(&caller, &course.category)?;
+    }
+    match COURSE_LABELS.with(|labels| labels.borrow_mut().remove(&CourseLabelKey { label, course_id: id })) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("The course with id={} doesn't have the label {:?}", id, label)).with_id(id.0)),
+    }
+}
+
+// Retrieves the triage labels of a course
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_course_labels(id: CourseId) -> Result<Vec<CourseLabel>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("You are not authorized to see course labels".to_string()));
+    }
+    _ensure_course_exists(id)?;
+    Ok(_labels_of(id))
+}
+
+// Retrieves a page of the courses with a triage label, most recently labeled first
+// Only the admin or a moderator can access
+#[ic_cdk::query]
+fn get_courses_by_label(label: StaffLabel, page: Option<Page>) -> Result<Vec<LabeledCourse>, Error> {
+    if !_is_authorized(api::caller().to_string()) {
+        return Err(Error::unauthorized("You are not authorized to see course labels".to_string()));
+    }
+    let page = _validate_page(page)?;
+    let mut labeled: Vec<CourseLabel> = COURSE_LABELS.with(|labels| {
+        labels
+            .borrow()
+            .range(CourseLabelKey { label, course_id: CourseId(0) }..)
+            .take_while(|(key, _)| key.label == label)
+            .map(|(_, course_label)| course_label)
+            .collect()
+    });
+    labeled.sort_by_key(|course_label| Reverse(course_label.labeled_at));
+    let labeled: Vec<CourseLabel> = labeled
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect();
+    let courses = _visible_courses(labeled.iter().filter_map(|course_label| _get_course_(&course_label.course_id)).collect());
+    Ok(labeled
+        .into_iter()
+        .zip(courses)
+        .map(|(label, course)| LabeledCourse { course, label })
+        .collect())
+}
+
+// Blocks a user: their courses are left out of the caller's feed
+#[ic_cdk::update]
+fn block_user(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("block_user");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    if address == caller {
+        return Err(Error::invalid_input("You cannot block yourself".to_string()));
+    }
+    let key = BlockKey {
+        blocker: caller,
+        blocked: address,
+    };
+    if BLOCKS.with(|blocks| blocks.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input("You already blocked this user".to_string()));
+    }
+    BLOCKS.with(|blocks| blocks.borrow_mut().insert(key, time()));
+    Ok(())
+}
+
+// Unblocks a user blocked by the caller
+#[ic_cdk::update]
+fn unblock_user(address: String) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("unblock_user");
+    _ensure_running()?;
+    let key = BlockKey {
+        blocker: api::caller().to_string(),
+        blocked: address,
+    };
+    match BLOCKS.with(|blocks| blocks.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found("You have not blocked this user".to_string())),
+    }
+}
+
+// Retrieves the users blocked by the caller
+#[ic_cdk::query]
+fn get_my_blocked_users() -> Vec<String> {
+    _blocked_by(&api::caller().to_string())
+}
+
+// Retrieves a page of the caller's feed: the newest courses, leaving out those of blocked users
+#[ic_cdk::query]
+fn get_my_feed(page: Option<Page>) -> Result<CoursePage, Error> {
+    let page = _validate_page(page)?;
+    let blocked = _blocked_by(&api::caller().to_string());
+    let viewer = _viewer();
+    let mut courses: Vec<StoredCourse> = STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, course)| course)
+            .filter(|course| viewer.sees(course) && !blocked.contains(&course.creator_address))
+            .collect()
+    });
+    let total_count = courses.len() as u64;
+    _sort_courses(&mut courses, SortBy::CreatedAtDesc);
+    let courses = courses
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .map(_to_course)
+        .collect();
+    Ok(_course_page(courses, page.offset, total_count))
+}
+
+// Retrieves the caller's settings
+#[ic_cdk::query]
+fn get_my_settings() -> UserSettings {
+    _settings_of(&api::caller().to_string())
+}
+
+// Changes the caller's settings, such as who can see the contact and the name on their courses
+#[ic_cdk::update]
+fn update_my_settings(payload: UserSettingsPayLoad) -> Result<UserSettings, Error> {
+    let _profile = ProfileGuard::new("update_my_settings");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    let mut settings = _settings_of(&caller);
+    if let Some(contact_visibility) = payload.contact_visibility {
+        settings.contact_visibility = contact_visibility;
+    }
+    if let Some(profile_visibility) = payload.profile_visibility {
+        settings.profile_visibility = profile_visibility;
+    }
+    if let Some(show_mature) = payload.show_mature {
+        settings.show_mature = Some(show_mature);
+    }
+    if let Some(weekly_digest) = payload.weekly_digest {
+        settings.weekly_digest = Some(weekly_digest);
+    }
+    if let Some(language) = payload.language {
+        if language.trim().is_empty() {
+            settings.language = None;
+        } else {
+            settings.language = Some(_normalize_language(&language)?);
+        }
+    }
+    USER_SETTINGS.with(|user_settings| user_settings.borrow_mut().insert(UserKey(caller), settings.clone()));
+    Ok(settings)
+}
+
+// Retrieves a page of the caller's notifications, newest first
+#[ic_cdk::query]
+fn get_my_notifications(page: Option<Page>) -> Result<NotificationPage, Error> {
+    let page = _validate_page(page)?;
+    let mut notifications = _notifications_of(&api::caller().to_string());
+    let total_count = notifications.len() as u64;
+    let unread_count = notifications.iter().filter(|notification| !notification.read).count() as u64;
+    notifications.reverse();
+    Ok(NotificationPage {
+        notifications: notifications
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect(),
+        total_count,
+        unread_count,
+    })
+}
+
+// Marks one of the caller's notifications as read
+#[ic_cdk::update]
+fn mark_notification_read(id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("mark_notification_read");
+    _ensure_running()?;
+    let key = NotificationKey {
+        recipient: api::caller().to_string(),
+        id,
+    };
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        match notifications.get(&key) {
+            Some(mut notification) => {
+                notification.read = true;
+                notifications.insert(key, notification);
+                Ok(())
+            }
+            None => Err(Error::not_found(format!("a notification with id={} not found", id)).with_id(id)),
+        }
+    })
+}
+
+// Marks all of the caller's notifications as read
+#[ic_cdk::update]
+fn mark_all_notifications_read() -> Result<(), Error> {
+    let _profile = ProfileGuard::new("mark_all_notifications_read");
+    _ensure_running()?;
+    let caller = api::caller().to_string();
+    let unread: Vec<Notification> = _notifications_of(&caller)
+        .into_iter()
+        .filter(|notification| !notification.read)
+        .collect();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        for mut notification in unread {
+            notification.read = true;
+            let key = NotificationKey {
+                recipient: caller.clone(),
+                id: notification.id,
+            };
+            notifications.insert(key, notification);
+        }
+    });
+    Ok(())
+}
+
+// Returns the caller's principal, role, banned status and limits
+#[ic_cdk::query]
+fn who_am_i() -> CallerInfo {
+    let caller = api::caller().to_string();
+    CallerInfo {
+        role: _role_of(&caller),
+        is_banned: _is_banned(&caller),
+        principal: caller,
+        limits: UserLimits {
+            max_tag_length: MAX_TAG_LENGTH as u32,
+            max_course_size: StoredCourse::MAX_SIZE,
+            max_moderators: MAX_MODERATORS as u32,
+        },
+    }
+}
+
+// Records a view of a course. Views are counted per day and feed the popularity score
+#[ic_cdk::update]
+fn record_course_view(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("record_course_view");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    _bump_activity(id, |counts| counts.views += 1);
+    Ok(())
+}
+
+// Likes a course. A user can like a course only once
+#[ic_cdk::update]
+fn like_course(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("like_course");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let key = EngagementKey {
+        course_id: id,
+        address: api::caller().to_string(),
+    };
+    if LIKES.with(|likes| likes.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input(format!("You already liked the course with id={}", id)));
+    }
+    LIKES.with(|likes| likes.borrow_mut().insert(key.clone(), time()));
+    _bump_activity(id, |counts| counts.likes += 1);
+    _record_user_activity(&key.address, UserActivityKind::CourseLiked, id);
+    Ok(())
+}
+
+// Removes the like of the caller from a course
+#[ic_cdk::update]
+fn unlike_course(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("unlike_course");
+    _ensure_running()?;
+    let key = EngagementKey {
+        course_id: id,
+        address: api::caller().to_string(),
+    };
+    match LIKES.with(|likes| likes.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(format!("You have not liked the course with id={}", id))),
+    }
+}
+
+// Enrolls the caller in a course. Courses that run in cohorts need a cohort that hasn't
+// ended and still has a free seat. The seat check and the enrollment happen in the same
+// call without awaiting in between, so two learners can't take the last seat
+#[ic_cdk::update]
+fn enroll_in_course(id: CourseId, cohort_id: Option<u64>) -> Result<Enrollment, Error> {
+    let _profile = ProfileGuard::new("enroll_in_course");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let learner = api::caller().to_string();
+    let key = EngagementKey {
+        course_id: id,
+        address: learner.clone(),
+    };
+    if ENROLLMENTS.with(|enrollments| enrollments.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input(format!("You are already enrolled in the course with id={}", id)));
+    }
+    let has_cohorts = !_cohorts_of(id).is_empty();
+    match cohort_id {
+        Some(cohort_id) => {
+            let cohort = _get_cohort(id, cohort_id)?;
+            if cohort.ends_at <= time() {
+                return Err(Error::invalid_input(format!("Cohort with id={} has already ended", cohort_id)));
+            }
+            // Seats offered to learners on the waitlist are kept for them, and nobody
+            // joins ahead of the learners still waiting for an offer
+            let waitlist = _waitlist_of(id, cohort_id);
+            let offer = waitlist
+                .iter()
+                .find(|(_, entry)| {
+                    entry.learner == learner
+                        && matches!(entry.offered_at, Some(offered_at) if offered_at + SEAT_CLAIM_WINDOW > time())
+                })
+                .map(|(key, _)| key.clone());
+            let reserved = waitlist.iter().filter(|(_, entry)| entry.offered_at.is_some()).count() as u32;
+            let free_seats = cohort.seat_limit.saturating_sub(_seats_taken(id, cohort_id));
+            let is_full = match offer {
+                Some(_) => free_seats == 0,
+                None => free_seats <= reserved || waitlist.len() as u32 > reserved,
+            };
+            if is_full {
+                return Err(Error::invalid_input(
+                    format!("Cohort with id={} is full. Join its waitlist to be offered a seat", cohort_id),
+                ));
+            }
+            if let Some(key) = offer {
+                WAITLISTS.with(|waitlists| waitlists.borrow_mut().remove(&key));
+            }
+        }
+        None if has_cohorts => {
+            return Err(Error::invalid_input(
+                format!("The course with id={} runs in cohorts. Please choose a cohort to enroll in", id),
+            ));
+        }
+        None => {}
+    }
+    let enrollment = Enrollment {
+        course_id: id,
+        learner,
+        enrolled_at: time(),
+        completed_at: None,
+        cohort_id,
+    };
+    ENROLLMENTS.with(|enrollments| enrollments.borrow_mut().insert(key, enrollment.clone()));
+    _bump_activity(id, |counts| counts.enrollments += 1);
+    _record_user_activity(&enrollment.learner, UserActivityKind::CourseEnrolled, id);
+    Ok(enrollment)
+}
+
+// Creates a cohort of a course. Once a course has cohorts, learners enroll in one of them
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn create_course_cohort(id: CourseId, payload: CohortPayLoad) -> Result<Cohort, Error> {
+    let _profile = ProfileGuard::new("create_course_cohort");
+    _ensure_running()?;
+    _ensure_cohort_manager(id)?;
+    let name = _normalize_line("name", &payload.name)?;
+    if name.is_empty() {
+        return Err(Error::empty_fields("Please fill in the name of the cohort".to_string()));
+    }
+    _validate_tag("name", &name)?;
+    if payload.ends_at <= payload.starts_at || payload.ends_at <= time() {
+        return Err(Error::invalid_input("A cohort must end in the future and after it starts".to_string()));
+    }
+    if payload.seat_limit == 0 {
+        return Err(Error::invalid_input("A cohort must have at least one seat".to_string()));
+    }
+    let timezone = payload.timezone.as_ref().map(_normalize_timezone).transpose()?;
+    let cohort = Cohort {
+        id: _cohorts_of(id).last().map_or(0, |cohort| cohort.id + 1),
+        course_id: id,
+        name,
+        starts_at: payload.starts_at,
+        ends_at: payload.ends_at,
+        seat_limit: payload.seat_limit,
+        timezone,
+    };
+    COHORTS.with(|cohorts| cohorts.borrow_mut().insert(CohortKey { course_id: id, id: cohort.id }, cohort.clone()));
+    Ok(cohort)
+}
+
+// Lists the cohorts of a course by start date, with the number of seats taken
+#[ic_cdk::query]
+fn get_course_cohorts(id: CourseId) -> Result<Vec<CohortInfo>, Error> {
+    _ensure_course_exists(id)?;
+    let mut cohorts: Vec<CohortInfo> = _cohorts_of(id)
+        .into_iter()
+        .map(|cohort| CohortInfo {
+            seats_taken: _seats_taken(id, cohort.id),
+            schedule: _schedule(cohort.starts_at, cohort.ends_at, &cohort.timezone),
+            cohort,
+        })
+        .collect();
+    cohorts.sort_by_key(|info| (info.cohort.starts_at, info.cohort.id));
+    Ok(cohorts)
+}
+
+// Lists the enrollments in a cohort. Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_cohort_roster(id: CourseId, cohort_id: u64) -> Result<Vec<Enrollment>, Error> {
+    _ensure_cohort_manager(id)?;
+    _get_cohort(id, cohort_id)?;
+    Ok(_cohort_enrollments(id, cohort_id))
+}
+
+// Withdraws the caller from a course. A seat freed in a cohort is offered to the waitlist
+#[ic_cdk::update]
+fn unenroll_from_course(id: CourseId) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("unenroll_from_course");
+    _ensure_running()?;
+    let key = EngagementKey {
+        course_id: id,
+        address: api::caller().to_string(),
+    };
+    let enrollment = ENROLLMENTS
+        .with(|enrollments| enrollments.borrow_mut().remove(&key))
+        .ok_or(Error::not_found(format!("You are not enrolled in the course with id={}", id)))?;
+    if let Some(cohort_id) = enrollment.cohort_id {
+        _offer_free_seats(id, cohort_id);
+    }
+    Ok(())
+}
+
+// Puts the caller on the waitlist of a full cohort, returning their place in line
+#[ic_cdk::update]
+fn join_waitlist(id: CourseId, cohort_id: u64) -> Result<u32, Error> {
+    let _profile = ProfileGuard::new("join_waitlist");
+    _ensure_running()?;
+    let cohort = _get_cohort(id, cohort_id)?;
+    let learner = api::caller().to_string();
+    if _is_enrolled(id, &learner) {
+        return Err(Error::invalid_input(format!("You are already enrolled in the course with id={}", id)));
+    }
+    if cohort.ends_at <= time() {
+        return Err(Error::invalid_input(format!("Cohort with id={} has already ended", cohort_id)));
+    }
+    let waitlist = _waitlist_of(id, cohort_id);
+    if waitlist.iter().any(|(_, entry)| entry.learner == learner) {
+        return Err(Error::invalid_input(format!("You are already on the waitlist of cohort with id={}", cohort_id)));
+    }
+    if waitlist.is_empty() && _seats_taken(id, cohort_id) < cohort.seat_limit {
+        return Err(Error::invalid_input(format!("Cohort with id={} has free seats. Enroll in it directly", cohort_id)));
+    }
+    let key = WaitlistKey {
+        course_id: id,
+        cohort_id,
+        position: waitlist.last().map_or(0, |(key, _)| key.position + 1),
+    };
+    let entry = WaitlistEntry {
+        learner,
+        joined_at: time(),
+        offered_at: None,
+    };
+    WAITLISTS.with(|waitlists| waitlists.borrow_mut().insert(key, entry));
+    Ok(waitlist.len() as u32 + 1)
+}
+
+// Takes the caller off the waitlist of a cohort, giving up a seat offered to them
+#[ic_cdk::update]
+fn leave_waitlist(id: CourseId, cohort_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("leave_waitlist");
+    _ensure_running()?;
+    let learner = api::caller().to_string();
+    let key = _waitlist_of(id, cohort_id)
+        .into_iter()
+        .find(|(_, entry)| entry.learner == learner)
+        .map(|(key, _)| key)
+        .ok_or(Error::not_found(format!("You are not on the waitlist of cohort with id={}", cohort_id)))?;
+    WAITLISTS.with(|waitlists| waitlists.borrow_mut().remove(&key));
+    _offer_free_seats(id, cohort_id);
+    Ok(())
+}
+
+// Lists the learners waiting for a seat in a cohort, in line order
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_cohort_waitlist(id: CourseId, cohort_id: u64) -> Result<Vec<WaitlistEntry>, Error> {
+    _ensure_cohort_manager(id)?;
+    _get_cohort(id, cohort_id)?;
+    Ok(_waitlist_of(id, cohort_id).into_iter().map(|(_, entry)| entry).collect())
+}
+
+// Marks the caller's enrollment in a course as completed and asks them for feedback
+#[ic_cdk::update]
+fn complete_course(id: CourseId) -> Result<Enrollment, Error> {
+    let _profile = ProfileGuard::new("complete_course");
+    _ensure_running()?;
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    let key = EngagementKey {
+        course_id: id,
+        address: api::caller().to_string(),
+    };
+    let mut enrollment = ENROLLMENTS
+        .with(|enrollments| enrollments.borrow().get(&key))
+        .ok_or(Error::not_found(format!("You are not enrolled in the course with id={}", id)))?;
+    if enrollment.completed_at.is_some() {
+        return Err(Error::invalid_input(format!("You have already completed the course with id={}", id)));
+    }
+    enrollment.completed_at = Some(time());
+    ENROLLMENTS.with(|enrollments| enrollments.borrow_mut().insert(key, enrollment.clone()));
+    _bump_outcomes(id, |counts| counts.completions += 1);
+    _record_user_activity(&enrollment.learner, UserActivityKind::CourseCompleted, id);
+    _record_learning_day(&enrollment.learner);
+    _notify(
+        &enrollment.learner,
+        NotificationKind::FeedbackRequested,
+        format!(
+            "Congratulations on completing \"{}\" (id={})! Tell the creator what you thought with submit_course_feedback.",
+            course.title, id
+        ),
+    );
+    Ok(enrollment)
+}
+
+// Submits the caller's feedback on a course they completed. Each learner can give feedback once.
+// Feedback is only shown to the creator of the course and doesn't name the learner
+#[ic_cdk::update]
+fn submit_course_feedback(id: CourseId, form: FeedbackForm) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("submit_course_feedback");
+    _ensure_running()?;
+    _ensure_course_exists(id)?;
+    let key = EngagementKey {
+        course_id: id,
+        address: api::caller().to_string(),
+    };
+    let completed = ENROLLMENTS.with(|enrollments| enrollments.borrow().get(&key));
+    if !matches!(completed, Some(enrollment) if enrollment.completed_at.is_some()) {
+        return Err(Error::unauthorized(
+            format!("Only learners who completed the course with id={} can give feedback", id),
+        ));
+    }
+    if FEEDBACK.with(|feedback| feedback.borrow().contains_key(&key)) {
+        return Err(Error::invalid_input(format!("You have already given feedback on the course with id={}", id)));
+    }
+    let ratings = [form.content_rating, form.clarity_rating, form.difficulty_rating];
+    if ratings.iter().any(|rating| !(1..=5).contains(rating)) {
+        return Err(Error::invalid_input("Ratings must be between 1 and 5".to_string()));
+    }
+    let comments = match form.comments {
+        Some(ref comments) => {
+            let comments = _normalize_body(comments)?;
+            _validate_note(&comments)?;
+            (!comments.is_empty()).then_some(comments)
+        }
+        None => None,
+    };
+    let response = FeedbackResponse {
+        form: FeedbackForm { comments, ..form },
+        submitted_at: time(),
+    };
+    FEEDBACK.with(|feedback| feedback.borrow_mut().insert(key, response));
+    if let Some(course) = _get_course_(&id) {
+        _record_user_activity(&course.creator_address, UserActivityKind::FeedbackReceived, id);
+    }
+    _bump_outcomes(id, |counts| {
+        counts.ratings += 1;
+        counts.rating_total += form.content_rating as u64;
+    });
+    Ok(())
+}
+
+// Retrieves a page of the feedback on a course, newest first. Only the creator of the course can access
+#[ic_cdk::query]
+fn get_course_feedback(id: CourseId, page: Option<Page>) -> Result<Vec<FeedbackResponse>, Error> {
+    _ensure_creator(id)?;
+    let page = _validate_page(page)?;
+    let mut responses = _feedback_of(id);
+    responses.sort_by_key(|response| Reverse(response.submitted_at));
+    Ok(responses
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Retrieves the aggregate scores of the feedback on a course. Only the creator of the course can access
+#[ic_cdk::query]
+fn get_course_feedback_summary(id: CourseId) -> Result<FeedbackSummary, Error> {
+    _ensure_creator(id)?;
+    let responses = _feedback_of(id);
+    let count = responses.len() as u64;
+    if count == 0 {
+        return Ok(FeedbackSummary::default());
+    }
+    let average = |rating: fn(&FeedbackForm) -> u8| {
+        responses.iter().map(|response| rating(&response.form) as u64).sum::<u64>() * 100 / count
+    };
+    Ok(FeedbackSummary {
+        responses: count,
+        average_content_rating: average(|form| form.content_rating),
+        average_clarity_rating: average(|form| form.clarity_rating),
+        average_difficulty_rating: average(|form| form.difficulty_rating),
+        recommend_percent: responses.iter().filter(|response| response.form.would_recommend).count() as u64 * 100 / count,
+    })
+}
+
+// Creates an assignment on a course and notifies the enrolled learners
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn create_assignment(id: CourseId, payload: AssignmentPayLoad) -> Result<Assignment, Error> {
+    let _profile = ProfileGuard::new("create_assignment");
+    _ensure_running()?;
+    _ensure_assignment_manager(id)?;
+    let title = _normalize_line("title", &payload.title)?;
+    let instructions = _normalize_body(&payload.instructions)?;
+    if title.is_empty() || instructions.is_empty() {
+        return Err(Error::empty_fields("Please fill in the title and the instructions of the assignment".to_string()));
+    }
+    _validate_tag("title", &title)?;
+    if instructions.chars().count() > MAX_INSTRUCTIONS_LENGTH {
+        return Err(Error::invalid_input(
+            format!("Instructions can be at most {} characters long", MAX_INSTRUCTIONS_LENGTH),
+        ));
+    }
+    if payload.max_score == 0 {
+        return Err(Error::invalid_input("The maximum score of an assignment must be at least 1".to_string()));
+    }
+    if matches!(payload.due_at, Some(due_at) if due_at <= time()) {
+        return Err(Error::invalid_input("An assignment must be due in the future".to_string()));
+    }
+    let assignment = Assignment {
+        id: _assignments_of(id).last().map_or(0, |assignment| assignment.id + 1),
+        course_id: id,
+        title,
+        instructions,
+        due_at: payload.due_at,
+        max_score: payload.max_score,
+        created_at: time(),
+    };
+    let key = AssignmentKey {
+        course_id: id,
+        id: assignment.id,
+    };
+    ASSIGNMENTS.with(|assignments| assignments.borrow_mut().insert(key, assignment.clone()));
+    for learner in _learners_of(id) {
+        _notify(
+            &learner,
+            NotificationKind::AssignmentPosted,
+            format!("New assignment on course with id={}: {}", id, assignment.title),
+        );
+    }
+    Ok(assignment)
+}
+
+// Deletes an assignment of a course with its submissions
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn delete_assignment(id: CourseId, assignment_id: u64) -> Result<(), Error> {
+    let _profile = ProfileGuard::new("delete_assignment");
+    _ensure_running()?;
+    _ensure_assignment_manager(id)?;
+    ASSIGNMENTS
+        .with(|assignments| assignments.borrow_mut().remove(&AssignmentKey { course_id: id, id: assignment_id }))
+        .ok_or(Error::not_found(
+            format!("course with id={} has no assignment with id={}", id, assignment_id),
+        ).with_id(assignment_id))?;
+    _remove_submissions(id, assignment_id);
+    Ok(())
+}
+
+// Lists the assignments of a course in the order they were created
+#[ic_cdk::query]
+fn get_course_assignments(id: CourseId) -> Result<Vec<Assignment>, Error> {
+    _ensure_course_exists(id)?;
+    Ok(_assignments_of(id))
+}
+
+// Hands in the caller's work on an assignment. Learners enrolled in the course can submit
+// until the assignment is due, and resubmit until their work is graded
+#[ic_cdk::update]
+fn submit_assignment(id: CourseId, assignment_id: u64, payload: SubmissionPayLoad) -> Result<Submission, Error> {
+    let _profile = ProfileGuard::new("submit_assignment");
+    _ensure_running()?;
+    let assignment = _get_assignment(id, assignment_id)?;
+    let learner = api::caller().to_string();
+    if !_is_enrolled(id, &learner) {
+        return Err(Error::unauthorized(
+            format!("Only learners enrolled in the course with id={} can submit assignments", id),
+        ));
+    }
+    if matches!(assignment.due_at, Some(due_at) if due_at <= time()) {
+        return Err(Error::invalid_input(format!("Assignment with id={} is past its due date", assignment_id)));
+    }
+    let text = payload.text.map(|text| _normalize_body(&text)).transpose()?.filter(|text| !text.is_empty());
+    let attachment_url = payload.attachment_url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty());
+    if text.is_none() && attachment_url.is_none() {
+        return Err(Error::empty_fields("Please hand in a text, an attachment or both".to_string()));
+    }
+    if matches!(text, Some(ref text) if text.chars().count() > MAX_SUBMISSION_LENGTH) {
+        return Err(Error::invalid_input(
+            format!("Submissions can be at most {} characters long", MAX_SUBMISSION_LENGTH),
+        ));
+    }
+    if let Some(ref url) = attachment_url {
+        _validate_attachment_url(url)?;
+    }
+    let key = SubmissionKey {
+        course_id: id,
+        assignment_id,
+        learner: learner.clone(),
+    };
+    let previous = SUBMISSIONS.with(|submissions| submissions.borrow().get(&key));
+    if matches!(previous, Some(ref previous) if previous.status == SubmissionStatus::Graded) {
+        return Err(Error::invalid_input(
+            format!("Your submission to assignment with id={} has already been graded", assignment_id),
+        ));
+    }
+    let submission = Submission {
+        assignment_id,
+        learner,
+        text,
+        attachment_url,
+        submitted_at: time(),
+        status: SubmissionStatus::Submitted,
+        score: None,
+        // Feedback asking for a revision stays visible next to the revised work
+        feedback: previous.and_then(|previous| previous.feedback),
+        graded_by: None,
+        graded_at: None,
+    };
+    SUBMISSIONS.with(|submissions| submissions.borrow_mut().insert(key, submission.clone()));
+    _record_user_activity(&submission.learner, UserActivityKind::AssignmentSubmitted, id);
+    _record_learning_day(&submission.learner);
+    Ok(submission)
+}
+
+// Grades a submission or returns it to the learner for revision, with optional feedback
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::update]
+fn grade_submission(
+    id: CourseId,
+    assignment_id: u64,
+    learner: String,
+    decision: GradeDecision,
+    feedback: Option<String>,
+) -> Result<Submission, Error> {
+    let _profile = ProfileGuard::new("grade_submission");
+    _ensure_running()?;
+    _ensure_assignment_manager(id)?;
+    let assignment = _get_assignment(id, assignment_id)?;
+    let key = SubmissionKey {
+        course_id: id,
+        assignment_id,
+        learner: learner.clone(),
+    };
+    let mut submission = SUBMISSIONS
+        .with(|submissions| submissions.borrow().get(&key))
+        .ok_or(Error::not_found(format!("{} has no submission to assignment with id={}", learner, assignment_id)))?;
+    let feedback = feedback.map(|feedback| _normalize_body(&feedback)).transpose()?.filter(|feedback| !feedback.is_empty());
+    if let Some(ref feedback) = feedback {
+        _validate_note(feedback)?;
+    }
+    match decision {
+        GradeDecision::Grade(score) if score > assignment.max_score => {
+            return Err(Error::invalid_input(format!("The score can be at most {}", assignment.max_score)));
+        }
+        GradeDecision::Grade(score) => {
+            submission.status = SubmissionStatus::Graded;
+            submission.score = Some(score);
+        }
+        GradeDecision::ReturnForRevision => {
+            submission.status = SubmissionStatus::ReturnedForRevision;
+            submission.score = None;
+        }
+    }
+    submission.feedback = feedback;
+    submission.graded_by = Some(api::caller().to_string());
+    submission.graded_at = Some(time());
+    SUBMISSIONS.with(|submissions| submissions.borrow_mut().insert(key, submission.clone()));
+    let outcome = match decision {
+        GradeDecision::Grade(score) => format!("was graded {}/{}", score, assignment.max_score),
+        GradeDecision::ReturnForRevision => "was returned for revision".to_string(),
+    };
+    _record_user_activity(&learner, UserActivityKind::SubmissionGraded, id);
+    _notify(
+        &learner,
+        NotificationKind::SubmissionReviewed,
+        format!("Your submission to \"{}\" on course with id={} {}.", assignment.title, id, outcome),
+    );
+    Ok(submission)
+}
+
+// Lists the submissions to an assignment
+// Only the creator of the course, the admin or a moderator can access
+#[ic_cdk::query]
+fn get_assignment_submissions(id: CourseId, assignment_id: u64) -> Result<Vec<Submission>, Error> {
+    _ensure_assignment_manager(id)?;
+    _get_assignment(id, assignment_id)?;
+    Ok(_submissions_of(id, assignment_id))
+}
+
+// Lists the caller's submissions to the assignments of a course
+#[ic_cdk::query]
+fn get_my_submissions(id: CourseId) -> Result<Vec<Submission>, Error> {
+    _ensure_course_exists(id)?;
+    let learner = api::caller().to_string();
+    Ok(_assignments_of(id)
+        .into_iter()
+        .filter_map(|assignment| {
+            let key = SubmissionKey {
+                course_id: id,
+                assignment_id: assignment.id,
+                learner: learner.clone(),
+            };
+            SUBMISSIONS.with(|submissions| submissions.borrow().get(&key))
+        })
+        .collect())
+}
+
+// Retrieves the engagement totals and the popularity score of a course
+#[ic_cdk::query]
+fn get_course_stats(id: CourseId) -> Result<CourseStats, Error> {
+    _ensure_course_exists(id)?;
+    let mut stats = CourseStats::default();
+    ACTIVITY.with(|activity| {
+        for (_, counts) in activity.borrow().range(_activity_range(id, 0)) {
+            stats.views += counts.views;
+            stats.likes += counts.likes;
+            stats.enrollments += counts.enrollments;
+        }
+    });
+    stats.popularity_score = POPULARITY.with(|popularity| {
+        popularity
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.course_id == id)
+            .map(|entry| entry.score)
+            .unwrap_or(0)
+    });
+    Ok(stats)
+}
+
+// Retrieves the reputation of a creator, as last computed by the scheduler
+#[ic_cdk::query]
+fn get_creator_reputation(address: String) -> Result<CreatorReputation, Error> {
+    REPUTATION
+        .with(|reputation| reputation.lock().unwrap().get(&address).cloned())
+        .ok_or(Error::not_found(format!("{} has no reputation yet", address)))
+}
+
+// Retrieves the quality score of a course, as last computed by the scheduler
+#[ic_cdk::query]
+fn get_course_quality(id: CourseId) -> Result<CourseQuality, Error> {
+    _ensure_course_exists(id)?;
+    QUALITY
+        .with(|quality| quality.lock().unwrap().get(&id).cloned())
+        .ok_or(Error::not_found(format!("The course with id={} has no quality score yet", id)).with_id(id.0))
+}
+
+// Retrieves the views, likes, enrollments, completions and rating trend of a course over the last days
+// (30 by default), grouped by day or week. Only the creator of the course can access
+#[ic_cdk::query]
+fn get_my_course_analytics(
+    id: CourseId,
+    interval: AnalyticsInterval,
+    days: Option<u32>,
+) -> Result<CourseAnalytics, Error> {
+    _ensure_creator(id)?;
+    let days = days.unwrap_or(DEFAULT_ANALYTICS_DAYS);
+    if days == 0 || days > MAX_ANALYTICS_DAYS {
+        return Err(Error::invalid_input(format!("days must be between 1 and {}", MAX_ANALYTICS_DAYS)));
+    }
+    let width = interval.days();
+    let from_day = (_today() + 1).saturating_sub(days as u64);
+    let mut buckets: Vec<AnalyticsBucket> = (0..(days as u64).div_ceil(width))
+        .map(|i| AnalyticsBucket {
+            start_day: from_day + i * width,
+            ..Default::default()
+        })
+        .collect();
+    let mut rating_totals = vec![0; buckets.len()];
+    ACTIVITY.with(|activity| {
+        for (key, counts) in activity.borrow().range(_activity_range(id, from_day)) {
+            let bucket = &mut buckets[((key.day - from_day) / width) as usize];
+            bucket.views += counts.views;
+            bucket.likes += counts.likes;
+            bucket.enrollments += counts.enrollments;
+        }
+    });
+    OUTCOMES.with(|outcomes| {
+        for (key, counts) in outcomes.borrow().range(_activity_range(id, from_day)) {
+            let index = ((key.day - from_day) / width) as usize;
+            buckets[index].completions += counts.completions;
+            buckets[index].ratings += counts.ratings;
+            rating_totals[index] += counts.rating_total;
+        }
+    });
+    for (bucket, total) in buckets.iter_mut().zip(rating_totals) {
+        bucket.average_rating = (bucket.ratings > 0).then(|| total * 100 / bucket.ratings);
+    }
+    Ok(CourseAnalytics {
+        course_id: id,
+        interval,
+        buckets,
+    })
+}
+
+// Retrieves courses from most to least popular, using the scores precomputed by the scheduler
+#[ic_cdk::query]
+fn get_popular_courses(page: Option<Page>) -> Result<CoursePage, Error> {
+    let ranked_ids: Vec<CourseId> =
+        POPULARITY.with(|popularity| popularity.lock().unwrap().iter().map(|entry| entry.course_id).collect());
+    _page_of_ids(&_shown_ids(&ranked_ids), page)
+}
+
+// Retrieves the weekly digest of a week, or the latest one. Courses removed since or not listed for the caller are left out
+#[ic_cdk::query]
+fn get_weekly_digest(week: Option<u64>) -> Result<WeeklyDigest, Error> {
+    let digest = WEEKLY_DIGESTS.with(|digests| {
+        let digests = digests.borrow();
+        match week {
+            Some(week) => digests.get(&week),
+            None => digests.iter().last().map(|(_, digest)| digest),
+        }
+    });
+    let mut digest = digest.ok_or(Error::not_found("There is no digest for this week".to_string()).with_field("week"))?;
+    for entries in [&mut digest.new_courses, &mut digest.trending, &mut digest.most_reviewed] {
+        let shown = _shown_ids(&entries.iter().map(|entry| entry.course_id).collect::<Vec<_>>());
+        entries.retain(|entry| shown.contains(&entry.course_id));
+    }
+    Ok(digest)
+}
+
+// Searches the courses for every word of a query in their title, keyword, category or body, ignoring case,
+// using the search index. Courses with the words in their title rank first, then keyword and category matches
+#[ic_cdk::query]
+fn search_courses(query: String, page: Option<Page>) -> Result<CoursePage, Error> {
+    let page = _validate_page(page)?;
+    let mut tokens = _tokenize(&query);
+    tokens.sort();
+    tokens.dedup();
+    if tokens.is_empty() {
+        return Err(Error::empty_fields(format!("Please enter a word of at least {} characters to search for", MIN_TOKEN_LENGTH)).with_field("query"));
+    }
+    if tokens.len() > MAX_SEARCH_TOKENS {
+        return Err(Error::invalid_input(format!("A search can have at most {} words", MAX_SEARCH_TOKENS)).with_field("query"));
+    }
+    _page_of_ids(&_search_index_matches(tokens), Some(page))
+}
+
+// Suggests keywords and titles starting with a prefix, ignoring case, for autocomplete in the search box.
+// Phrases used by the most courses come first, keywords before titles when tied, then alphabetically
+#[ic_cdk::query]
+fn suggest_keywords(prefix: String, limit: u32) -> Result<Vec<KeywordSuggestion>, Error> {
+    if limit == 0 || limit > MAX_SUGGESTIONS {
+        return Err(Error::invalid_input(format!("The limit must be between 1 and {}", MAX_SUGGESTIONS)).with_field("limit"));
+    }
+    let prefix = _fold(&prefix);
+    if prefix.is_empty() {
+        return Err(Error::empty_fields("Please enter the start of a keyword or title".to_string()).with_field("prefix"));
+    }
+    Ok(_prefix_suggestions(&_viewer(), &prefix, limit as usize))
+}
+
+// Searches the caller's own courses, including those queued for review, archived, taken down or drafted, for a text
+// in the title, body, keyword or category, ignoring case. Results are ordered by id
+#[ic_cdk::query]
+fn search_my_courses(query: String, page: Option<Page>) -> Result<Vec<MyCourseMatch>, Error> {
+    let page = _validate_page(page)?;
+    let query = _fold(query.trim());
+    if query.is_empty() {
+        return Err(Error::empty_fields("Please enter a text to search for".to_string()));
+    }
+    let creator = api::caller().to_string();
+    let mut courses: Vec<MyCourseMatch> = _course_ids_by_creator(&creator)
+        .iter()
+        .filter_map(_get_course_)
+        .map(|course| MyCourseMatch {
+            course,
+            state: MyCourseState::Published,
+        })
+        .collect();
+    MODERATION_QUEUE.with(|queue| {
+        courses.extend(
+            queue
+                .borrow()
+                .iter()
+                .filter(|(_, queued)| queued.course.creator_address == creator)
+                .map(|(_, queued)| MyCourseMatch {
+                    course: queued.course,
+                    state: MyCourseState::Queued,
+                }),
+        )
+    });
+    ARCHIVED_COURSES.with(|archived| {
+        courses.extend(
+            archived
+                .borrow()
+                .iter()
+                .filter(|(_, archived)| archived.course.creator_address == creator)
+                .map(|(_, archived)| MyCourseMatch {
+                    course: archived.course,
+                    state: MyCourseState::Archived,
+                }),
+        )
+    });
+    TAKEDOWNS.with(|takedowns| {
+        courses.extend(
+            takedowns
+                .borrow()
+                .iter()
+                .filter(|(_, takedown)| takedown.course.creator_address == creator)
+                .map(|(_, takedown)| MyCourseMatch {
+                    course: takedown.course,
+                    state: MyCourseState::TakenDown,
+                }),
+        )
+    });
+    courses.extend(_drafts_of(&creator).into_iter().map(|draft| MyCourseMatch {
+        course: draft.into(),
+        state: MyCourseState::Draft,
+    }));
+    courses.retain(|found| {
+        let course = &found.course;
+        [&course.title, &course.body, &course.category]
+            .into_iter()
+            .chain(course.tags.iter())
+            .any(|field| _fold(field).contains(&query))
+    });
+    courses.sort_by_key(|found| found.course.id);
+    Ok(courses
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .collect())
+}
+
+// Retrieves a page of courses recommended to the caller: courses they aren't enrolled in, scored by how
+// often their category and keyword appear among the courses the caller enrolled in, with completed courses
+// counting double. Ties, and callers without enrollments, fall back to popularity
+#[ic_cdk::query]
+fn get_recommended_courses(page: Option<Page>) -> Result<CoursePage, Error> {
+    let page = _validate_page(page)?;
+    let viewer = _viewer();
+    let history: Vec<(CourseId, u64)> = ENROLLMENTS.with(|enrollments| {
+        enrollments
+            .borrow()
+            .iter()
+            .filter(|(key, _)| key.address == viewer.address)
+            .map(|(key, enrollment)| (key.course_id, if enrollment.completed_at.is_some() { 2 } else { 1 }))
+            .collect()
+    });
+    let ranks: HashMap<CourseId, usize> = POPULARITY.with(|popularity| {
+        popularity
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(rank, entry)| (entry.course_id, rank))
+            .collect()
+    });
+    let blocked = _blocked_by(&viewer.address);
+    let mut scored: Vec<(u64, usize, CourseId)> = STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let mut category_weights: HashMap<u32, u64> = HashMap::new();
+        let mut keyword_weights: HashMap<u32, u64> = HashMap::new();
+        for (course_id, weight) in history.iter() {
+            if let Some(course) = storage.get(course_id) {
+                *category_weights.entry(course.category_key()).or_default() += weight;
+                for (_, key) in course.tag_pairs() {
+                    *keyword_weights.entry(key).or_default() += weight;
+                }
+            }
+        }
+        storage
+            .iter()
+            .filter(|(id, course)| {
+                viewer.sees(course)
+                    && course.creator_address != viewer.address
+                    && !blocked.contains(&course.creator_address)
+                    && !history.iter().any(|(course_id, _)| course_id == id)
+            })
+            .map(|(id, course)| {
+                let score = 2 * category_weights.get(&course.category_key()).copied().unwrap_or(0)
+                    + 3 * course
+                        .tag_pairs()
+                        .iter()
+                        .map(|(_, key)| keyword_weights.get(key).copied().unwrap_or(0))
+                        .sum::<u64>();
+                (score, ranks.get(&id).copied().unwrap_or(usize::MAX), id)
+            })
+            .collect()
+    });
+    scored.sort_by_key(|(score, rank, id)| (Reverse(*score), *rank, *id));
+    let total_count = scored.len() as u64;
+    let courses = scored
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .filter_map(|(_, _, id)| _get_course_(&id))
+        .collect();
+    Ok(_course_page(courses, page.offset, total_count))
+}
+
+// Switches per-method profiling on or off. Only the admin can access
+// Enabling profiling clears the previously recorded samples
+#[ic_cdk::update]
+fn set_profiling(enabled: bool) -> Result<(), Error> {
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can change profiling".to_string()));
+    }
+    PROFILING_ENABLED.with(|profiling| *profiling.lock().unwrap() = enabled);
+    if enabled {
+        PROFILE_SAMPLES.with(|samples| samples.lock().unwrap().clear());
+    }
+    Ok(())
+}
+
+// Runs a query code path inside an update call so its instruction count is recorded
+// Returns the number of instructions used. Only the admin can access
+#[ic_cdk::update]
+fn profile_query(query: ProfiledQuery) -> Result<u64, Error> {
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can profile queries".to_string()));
+    }
+    let start = api::performance_counter(0);
+    let method = match query {
+        ProfiledQuery::GetCourse(id) => {
+            let _ = get_course(id, None);
+            "get_course"
+        }
+        ProfiledQuery::FilterCoursesAnd(filter) => {
+            let _ = filter_courses_and(filter, None, None);
+            "filter_courses_and"
+        }
+        ProfiledQuery::FilterCoursesOr(filter) => {
+            let _ = filter_courses_or(filter, None, None);
+            "filter_courses_or"
+        }
+        ProfiledQuery::QueryCourses(request) => {
+            let _ = query_courses(request);
+            "query_courses"
+        }
+    };
+    let instructions = api::performance_counter(0).saturating_sub(start);
+    _record_profile(method, instructions);
+    Ok(instructions)
+}
+
+// Returns the most expensive recorded invocations and per-method aggregates
+// Only the admin can access
+#[ic_cdk::query]
+fn get_profiling_report(limit: u32) -> Result<ProfilingReport, Error> {
+    let caller = api::caller().to_string();
+    if !_is_admin(caller) {
+        return Err(Error::unauthorized("Only admin can view profiling data".to_string()));
+    }
+    let enabled = PROFILING_ENABLED.with(|profiling| *profiling.lock().unwrap());
+    let mut worst_samples: Vec<ProfileSample> = PROFILE_SAMPLES.with(|samples| {
+        samples.lock().unwrap().iter().cloned().collect()
+    });
+
+    // Aggregate the samples per method
+    let mut methods: Vec<MethodProfile> = Vec::new();
+    for sample in worst_samples.iter() {
+        match methods.iter_mut().find(|m| m.method == sample.method) {
+            Some(profile) => {
+                profile.calls += 1;
+                profile.total_instructions += sample.instructions;
+                profile.max_instructions = profile.max_instructions.max(sample.instructions);
+            }
+            None => methods.push(MethodProfile {
+                method: sample.method.clone(),
+                calls: 1,
+                total_instructions: sample.instructions,
+                max_instructions: sample.instructions,
+            }),
+        }
+    }
+    methods.sort_by_key(|m| Reverse(m.max_instructions));
+
+    worst_samples.sort_by_key(|sample| Reverse(sample.instructions));
+    worst_samples.truncate(limit as usize);
+
+    Ok(ProfilingReport {
+        enabled,
+        worst_samples,
+        methods,
+    })
+}
+
+// Retrieves the estimated cycles burned by each update method and scheduler run over the last days
+// (7 by default, at most 90), so operators can see which features dominate the burn rate.
+// Queries are free and are not counted. Only the admin can access
+#[ic_cdk::query]
+fn get_cycles_report(days: Option<u32>) -> Result<CyclesReport, Error> {
+    if !_is_admin(api::caller().to_string()) {
+        return Err(Error::unauthorized("Only admin can view the cycles report".to_string()));
+    }
+    let days = days.unwrap_or(DEFAULT_USAGE_DAYS);
+    if days == 0 || days as u64 > USAGE_RETENTION_DAYS {
+        return Err(Error::invalid_input(format!("days must be between 1 and {}", USAGE_RETENTION_DAYS)).with_field("days"));
+    }
+    let to_day = _today();
+    let from_day = to_day + 1 - days as u64;
+    let start = UsageKey {
+        day: from_day,
+        method: String::new(),
+    };
+    let mut methods: BTreeMap<String, MethodUsage> = BTreeMap::new();
+    let mut daily: BTreeMap<u64, MethodUsage> = BTreeMap::new();
+    METHOD_USAGE.with(|usage| {
+        for (key, usage) in usage.borrow().range(start..) {
+            let method = methods.entry(key.method).or_default();
+            method.calls += usage.calls;
+            method.instructions += usage.instructions;
+            method.max_instructions = method.max_instructions.max(usage.max_instructions);
+            let day = daily.entry(key.day).or_default();
+            day.calls += usage.calls;
+            day.instructions += usage.instructions;
+        }
+    });
+    let total_cycles: u64 = methods.values().map(|usage| _estimated_cycles(usage.calls, usage.instructions)).sum();
+    let mut methods: Vec<MethodCycles> = methods
+        .into_iter()
+        .map(|(method, usage)| {
+            let estimated_cycles = _estimated_cycles(usage.calls, usage.instructions);
+            MethodCycles {
+                method,
+                calls: usage.calls,
+                instructions: usage.instructions,
+                max_instructions: usage.max_instructions,
+                estimated_cycles,
+                share_percent: (estimated_cycles as u128 * 100 / total_cycles.max(1) as u128) as u32,
+            }
+        })
+        .collect();
+    methods.sort_by_key(|method| Reverse(method.estimated_cycles));
+    Ok(CyclesReport {
+        from_day,
+        to_day,
+        balance: api::canister_balance128(),
+        methods,
+        days: daily
+            .into_iter()
+            .map(|(day, usage)| DailyCycles {
+                day,
+                calls: usage.calls,
+                estimated_cycles: _estimated_cycles(usage.calls, usage.instructions),
+            })
+            .collect(),
+    })
+}
+
+// Stores every course again in the current layout
+fn _rewrite_courses() {
+    let courses: Vec<StoredCourse> = STORAGE.with(|storage| storage.borrow().iter().map(|(_, course)| course).collect());
+    STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for course in courses {
+            storage.insert(course.id, _to_stored(&_to_course(course)));
+        }
+    });
+}
+
+// Saves the heap state to stable memory before an upgrade
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let state = VersionedHeapState::V1(_heap_state());
+    let bytes = Encode!(&state).expect("cannot encode the heap state");
+    HEAP_STATE
+        .with(|saved| saved.borrow_mut().set(bytes))
+        .expect("cannot save the heap state");
+}
+
+// Starts the scheduler when the canister is first installed
+#[ic_cdk::init]
+fn init() {
+    _start_scheduler();
+    _certify_moderation_log();
+}
+
+// Restores the heap state, migrates the stored data to the current layout after an upgrade
+// and restarts the scheduler, since timers don't survive upgrades
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // Versions without pre_upgrade saved nothing, so their heap state starts empty
+    let saved = HEAP_STATE.with(|saved| saved.borrow().get().clone());
+    if !saved.is_empty() {
+        let VersionedHeapState::V1(state) = Decode!(&saved, VersionedHeapState).expect("cannot decode the heap state");
+        _restore_heap_state(state);
+        HEAP_STATE
+            .with(|saved| saved.borrow_mut().set(Vec::new()))
+            .expect("cannot clear the heap state");
+    }
+    let version = SCHEMA_VERSION.with(|version| *version.borrow().get());
+    if version < 1 {
+        // Rewrite courses stored before the string table existed. Reading them converts
+        // them to the new layout, so inserting them back stores the interned form
+        let courses: Vec<(CourseId, StoredCourse)> = STORAGE.with(|storage| storage.borrow().iter().collect());
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            for (id, course) in courses {
+                storage.insert(id, course);
+            }
+        });
+    }
+    if version < 2 {
+        // Fill the category and creator indexes for the courses stored before they existed
+        _rebuild_course_indexes();
+    }
+    if version < 3 {
+        // Add the lower case shadow copies to the stored courses and key the category index by them
+        CATEGORY_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            let keys: Vec<CategoryIndexKey> = index.iter().map(|(key, _)| key).collect();
+            for key in keys {
+                index.remove(&key);
+            }
+        });
+        _rewrite_courses();
+        _rebuild_course_indexes();
+    }
+    if version == 3 {
+        // Convert the free text contacts into contact methods. Older versions were rewritten above
+        _rewrite_courses();
+    }
+    if version < 5 {
+        // Fingerprint the courses stored before near duplicate detection existed
+        _rebuild_course_indexes();
+    }
+    if version == 5 || version == 6 {
+        // Fill the search and prefix indexes for the courses stored before they existed.
+        // Older versions were indexed above
+        _rebuild_course_indexes();
+    }
+    SCHEMA_VERSION
+        .with(|version| version.borrow_mut().set(CURRENT_SCHEMA_VERSION))
+        .expect("cannot update the schema version");
+
+    _start_scheduler();
+    _refresh_popularity();
+    _refresh_reputation();
+    _refresh_quality();
+    _certify_moderation_log();
+}
+
+// Collects the state kept on the heap
+fn _heap_state() -> HeapState {
+    fn read<T: Clone>(key: &'static std::thread::LocalKey<Mutex<T>>) -> T {
+        key.with(|value| value.lock().unwrap().clone())
+    }
+    HeapState {
+        admin: read(&ADMIN_ADDRESS),
+        moderators: read(&MODERATOR_ADDRESSES),
+        moderator_terms: read(&MODERATOR_TERMS),
+        moderator_categories: read(&MODERATOR_CATEGORIES),
+        moderator_nominations: read(&MODERATOR_NOMINATIONS),
+        moderator_stats: read(&MODERATOR_STATS),
+        content_filter_action: read(&CONTENT_FILTER_ACTION),
+        ban_approval_required: read(&BAN_APPROVAL_REQUIRED),
+        paused_at: read(&PAUSED_AT),
+        maintenance: read(&MAINTENANCE),
+        publishing_requirements: read(&PUBLISHING_REQUIREMENTS),
+        pending_bans: read(&PENDING_BANS),
+        account_deletions: read(&ACCOUNT_DELETIONS),
+        domain_bursts: read(&DOMAIN_BURSTS),
+        ban_cleanups: read(&BAN_CLEANUPS),
+        profiling_enabled: read(&PROFILING_ENABLED),
+        profile_samples: read(&PROFILE_SAMPLES),
+        preview_length: read(&PREVIEW_LENGTH),
+        embed_site: read(&EMBED_SITE),
+    }
+}
+
+// Puts back the state saved by pre_upgrade
+fn _restore_heap_state(state: HeapState) {
+    fn write<T>(key: &'static std::thread::LocalKey<Mutex<T>>, value: T) {
+        key.with(|current| *current.lock().unwrap() = value);
+    }
+    write(&ADMIN_ADDRESS, state.admin);
+    write(&MODERATOR_ADDRESSES, state.moderators);
+    write(&MODERATOR_TERMS, state.moderator_terms);
+    write(&MODERATOR_CATEGORIES, state.moderator_categories);
+    write(&MODERATOR_NOMINATIONS, state.moderator_nominations);
+    write(&MODERATOR_STATS, state.moderator_stats);
+    write(&CONTENT_FILTER_ACTION, state.content_filter_action);
+    write(&BAN_APPROVAL_REQUIRED, state.ban_approval_required);
+    write(&PAUSED_AT, state.paused_at);
+    write(&MAINTENANCE, state.maintenance);
+    write(&PUBLISHING_REQUIREMENTS, state.publishing_requirements);
+    write(&PENDING_BANS, state.pending_bans);
+    write(&ACCOUNT_DELETIONS, state.account_deletions);
+    write(&DOMAIN_BURSTS, state.domain_bursts);
+    write(&BAN_CLEANUPS, state.ban_cleanups);
+    write(&PROFILING_ENABLED, state.profiling_enabled);
+    write(&PROFILE_SAMPLES, state.profile_samples);
+    write(&PREVIEW_LENGTH, state.preview_length);
+    write(&EMBED_SITE, state.embed_site);
+}
+
+// Internal helper functions
+
+//Retreive the course from storage
+fn _get_course_(id: &CourseId) -> Option<Course> {
+    STORAGE.with(|service| service.borrow().get(id)).map(_to_course)
+}
+
+// Resolves the criteria strings to their string table ids once, so courses can be compared by id
+fn _resolve_criteria(criteria: &[Criterion], mode: Option<MatchMode>) -> Vec<ResolvedCriterion> {
+    let mode = mode.unwrap_or(MatchMode::CaseInsensitive);
+    criteria
+        .iter()
+        .map(|criterion| match criterion {
+            Criterion::Keyword(keyword) => ResolvedCriterion::Keyword(_resolve_tag(keyword, mode)),
+            Criterion::AnyTag(tags) => ResolvedCriterion::AnyTag(tags.iter().map(|tag| _resolve_tag(tag, mode)).collect()),
+            Criterion::AllTags(tags) => ResolvedCriterion::AllTags(tags.iter().map(|tag| _resolve_tag(tag, mode)).collect()),
+            Criterion::Category(category) => ResolvedCriterion::Category(_resolve_tag(category, mode)),
+            Criterion::Creator(address) => ResolvedCriterion::Creator(address.clone()),
+            Criterion::CreatedAfter(timestamp) => ResolvedCriterion::CreatedAfter(*timestamp),
+            Criterion::CreatedBefore(timestamp) => ResolvedCriterion::CreatedBefore(*timestamp),
+            Criterion::UpdatedSince(timestamp) => ResolvedCriterion::UpdatedSince(*timestamp),
+            Criterion::MaxContentRating(rating) => ResolvedCriterion::MaxContentRating(*rating),
+            Criterion::HasCaptions => ResolvedCriterion::HasCaptions,
+            Criterion::HasTranscript => ResolvedCriterion::HasTranscript,
+            Criterion::ScreenReaderFriendly => ResolvedCriterion::ScreenReaderFriendly,
+            Criterion::Region(code) => ResolvedCriterion::Region(code.trim().to_ascii_uppercase()),
+            Criterion::MinCreatorReputation(minimum) => ResolvedCriterion::MinCreatorReputation(REPUTATION.with(|reputation| {
+                reputation
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|reputation| reputation.score >= *minimum)
+                    .map(|reputation| reputation.address.clone())
+                    .collect()
+            })),
+            Criterion::Endorsed => ResolvedCriterion::Endorsed,
+            Criterion::MinQuality(minimum) => ResolvedCriterion::MinQuality(QUALITY.with(|quality| {
+                quality
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|quality| quality.score >= *minimum)
+                    .map(|quality| quality.course_id)
+                    .collect()
+            })),
+        })
+        .collect()
+}
+
+// Resolves the keyword or category of a criterion to the ids it matches in the given mode
+fn _resolve_tag(tag: &str, mode: MatchMode) -> TagMatch {
+    match mode {
+        MatchMode::Exact => TagMatch::Exact(_interned_id(&_normalize_text(tag))),
+        MatchMode::CaseInsensitive => TagMatch::Key(_interned_id(&_canonical_tag(&_fold(tag)))),
+        MatchMode::Contains => {
+            // Text that folds to nothing would match every tag, so it matches none instead
+            let needle = _fold(tag);
+            if needle.is_empty() {
+                return TagMatch::AnyOf(HashSet::new());
+            }
+            // A single pass over the string table. Tags are stored folded as well as written,
+            // so comparing the folded strings finds every shadow copy containing the text
+            TagMatch::AnyOf(INTERNED_STRINGS.with(|strings| {
+                strings
+                    .borrow()
+                    .iter()
+                    .filter(|(_, value)| _fold(&value.0).contains(&needle))
+                    .map(|(id, _)| id)
+                    .collect()
+            }))
+        }
+    }
+}
+
+// Returns the ids of the only courses that can match the criteria, in id order, read from the category
+// index, or None when every course has to be checked. With AND any case insensitive or contains category
+// criterion narrows the courses to its categories, with OR every criterion has to be one
+fn _indexed_candidates(criteria: &[ResolvedCriterion], combinator: Combinator) -> Option<Vec<CourseId>> {
+    let categories = |criterion: &ResolvedCriterion| match criterion {
+        ResolvedCriterion::Category(TagMatch::Key(id)) => Some(id.iter().copied().collect::<Vec<u32>>()),
+        ResolvedCriterion::Category(TagMatch::AnyOf(keys)) => Some(keys.iter().copied().collect()),
+        _ => None,
+    };
+    let category_ids: Vec<u32> = match combinator {
+        Combinator::And => criteria.iter().find_map(categories)?,
+        Combinator::Or if criteria.is_empty() => return None,
+        Combinator::Or => criteria.iter().map(categories).collect::<Option<Vec<_>>>()?.concat(),
+    };
+    let mut ids: Vec<CourseId> = category_ids.into_iter().flat_map(_course_ids_by_category).collect();
+    ids.sort();
+    ids.dedup();
+    Some(ids)
+}
+
+// Returns up to limit courses the caller sees that match the criteria, in id order.
+// Only the courses of the category index are read when the criteria allow it
+fn _matching_courses(criteria: &[ResolvedCriterion], combinator: Combinator, limit: usize) -> Vec<StoredCourse> {
+    let candidates = _indexed_candidates(criteria, combinator);
+    let viewer = _viewer();
+    STORAGE.with(|storage| {
+        _stored_candidates(&storage.borrow(), candidates)
+            .filter(|course| viewer.sees(course) && _matches_criteria(course, criteria, combinator))
+            .take(limit)
+            .collect()
+    })
+}
+
+// Iterates over the stored courses with the candidate ids, or over every stored course without candidates
+fn _stored_candidates(
+    storage: &StableBTreeMap<CourseId, StoredCourse, Memory>,
+    candidates: Option<Vec<CourseId>>,
+) -> Box<dyn Iterator<Item = StoredCourse> + '_> {
+    match candidates {
+        Some(ids) => Box::new(ids.into_iter().filter_map(|id| storage.get(&id))),
+        None => Box::new(storage.iter().map(|(_, course)| course)),
+    }
+}
+
+// Checks if a course matches all (AND) or any (OR) of the criteria.
+// A course always matches an empty list of criteria
+fn _matches_criteria(course: &StoredCourse, criteria: &[ResolvedCriterion], combinator: Combinator) -> bool {
+    if criteria.is_empty() {
+        return true;
+    }
+    let matches = |criterion: &ResolvedCriterion| match criterion {
+        ResolvedCriterion::Keyword(tag) => tag.matches_any(&course.tag_pairs()),
+        ResolvedCriterion::AnyTag(tags) => {
+            let course_tags = course.tag_pairs();
+            tags.iter().any(|tag| tag.matches_any(&course_tags))
+        }
+        ResolvedCriterion::AllTags(tags) => {
+            let course_tags = course.tag_pairs();
+            tags.iter().all(|tag| tag.matches_any(&course_tags))
+        }
+        ResolvedCriterion::Category(tag) => tag.matches(course.category_id, course.category_key()),
+        ResolvedCriterion::Creator(address) => course.creator_address == *address,
+        ResolvedCriterion::CreatedAfter(timestamp) => course.created_at >= *timestamp,
+        ResolvedCriterion::CreatedBefore(timestamp) => course.created_at < *timestamp,
+        // A course that was never updated counts as modified when it was created
+        ResolvedCriterion::UpdatedSince(timestamp) => course.updated_at.unwrap_or(course.created_at) >= *timestamp,
+        ResolvedCriterion::MaxContentRating(rating) => _rating_of(course) <= *rating,
+        ResolvedCriterion::HasCaptions => {
+            matches!(&course.accessibility, Some(accessibility) if accessibility.captions_available)
+        }
+        ResolvedCriterion::HasTranscript => {
+            matches!(&course.accessibility, Some(accessibility) if accessibility.transcript_url.is_some())
+        }
+        ResolvedCriterion::ScreenReaderFriendly => {
+            matches!(&course.accessibility, Some(accessibility) if accessibility.screen_reader_friendly)
+        }
+        // Only courses targeting the region match, courses without regions are meant for everyone
+        ResolvedCriterion::Region(code) => matches!(&course.regions, Some(regions) if regions.contains(code)),
+        ResolvedCriterion::MinCreatorReputation(creators) => creators.contains(&course.creator_address),
+        ResolvedCriterion::Endorsed => ENDORSEMENTS.with(|endorsements| endorsements.borrow().contains_key(&course.id)),
+        ResolvedCriterion::MinQuality(courses) => courses.contains(&course.id),
+    };
+    match combinator {
+        Combinator::And => criteria.iter().all(matches),
+        Combinator::Or => criteria.iter().any(matches),
+    }
+}
+
+// Sorts courses in the requested order
+fn _sort_courses(courses: &mut [StoredCourse], sort: SortBy) {
+    match sort {
+        SortBy::CreatedAtAsc => courses.sort_by_key(|course| (course.created_at, course.id)),
+        SortBy::CreatedAtDesc => courses.sort_by_key(|course| Reverse((course.created_at, course.id))),
+        SortBy::Popularity => {
+            // Courses without a score yet come last
+            let ranks: HashMap<CourseId, usize> = POPULARITY.with(|popularity| {
+                popularity
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, entry)| (entry.course_id, rank))
+                    .collect()
+            });
+            courses.sort_by_key(|course| (ranks.get(&course.id).copied().unwrap_or(usize::MAX), course.id));
+        }
+        SortBy::CreatorReputation => {
+            // Courses of creators without a reputation yet come last
+            let scores: HashMap<String, u32> = REPUTATION.with(|reputation| {
+                reputation
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|reputation| (reputation.address.clone(), reputation.score))
+                    .collect()
+            });
+            courses.sort_by_key(|course| (Reverse(scores.get(&course.creator_address).copied()), course.id));
+        }
+        SortBy::Quality => {
+            // Courses without a quality score yet come last
+            let scores: HashMap<CourseId, u32> = QUALITY.with(|quality| {
+                quality
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|quality| (quality.course_id, quality.score))
+                    .collect()
+            });
+            courses.sort_by_key(|course| (Reverse(scores.get(&course.id).copied()), course.id));
+        }
+        SortBy::UpdatedAt => courses.sort_by_key(|course| Reverse((course.updated_at.unwrap_or(course.created_at), course.id))),
+        SortBy::Title => courses.sort_by_cached_key(|course| (_fold(&course.title), course.id)),
+        SortBy::Creator => courses.sort_by_cached_key(|course| (_fold(&course.creator_name), course.creator_address.clone(), course.id)),
+    }
+}
+
+// Orders course ids the way the courses are sorted, keeping them as they are without a sort order
+fn _sorted_ids(course_ids: Vec<CourseId>, sort: Option<SortBy>) -> Vec<CourseId> {
+    let Some(sort) = sort else {
+        return course_ids;
+    };
+    let mut courses: Vec<StoredCourse> = STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        course_ids.iter().filter_map(|id| storage.get(id)).collect()
+    });
+    _sort_courses(&mut courses, sort);
+    courses.into_iter().map(|course| course.id).collect()
+}
+
+// Converts an optional result limit into a count for Iterator::take
+fn _limit(limit: Option<u32>) -> usize {
+    limit.map_or(usize::MAX, |limit| limit as usize)
+}
+
+// Builds a page of courses starting at offset out of total_count matches
+fn _course_page(courses: Vec<Course>, offset: u64, total_count: u64) -> CoursePage {
+    let has_more = offset + (courses.len() as u64) < total_count;
+    CoursePage {
+        courses: _visible_courses(courses),
+        total_count,
+        has_more,
+    }
+}
+
+// Returns the users blocked by a user
+fn _blocked_by(address: &str) -> Vec<String> {
+    let start = BlockKey {
+        blocker: address.to_string(),
+        blocked: String::new(),
+    };
+    BLOCKS.with(|blocks| {
+        blocks
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.blocker == address)
+            .map(|(key, _)| key.blocked)
+            .collect()
+    })
+}
+
+// The user listing courses, to decide which courses they are shown
+struct Viewer {
+    address: String,
+    show_mature: bool,
+}
+
+impl Viewer {
+    // Mature courses are only listed for users who opted in, their creators and the staff
+    fn sees(&self, course: &StoredCourse) -> bool {
+        self.show_mature || _rating_of(course) != ContentRating::Mature || course.creator_address == self.address
+    }
+}
+
+// Returns the caller as a viewer of course listings
+fn _viewer() -> Viewer {
+    let address = api::caller().to_string();
+    let show_mature = _is_authorized(address.clone()) || _settings_of(&address).show_mature.unwrap_or(false);
+    Viewer { address, show_mature }
+}
+
+// Keeps the ids of the courses listed for the caller
+fn _shown_ids(course_ids: &[CourseId]) -> Vec<CourseId> {
+    let viewer = _viewer();
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        course_ids
+            .iter()
+            .filter(|id| matches!(storage.get(id), Some(course) if viewer.sees(&course)))
+            .copied()
+            .collect()
+    })
+}
+
+// Returns the content rating of a course, AllAges when it has none
+fn _rating_of(course: &StoredCourse) -> ContentRating {
+    course.content_rating.unwrap_or(ContentRating::AllAges)
+}
+
+// Returns whether a user has blocked another user
+fn _has_blocked(blocker: &str, blocked: &str) -> bool {
+    let key = BlockKey {
+        blocker: blocker.to_string(),
+        blocked: blocked.to_string(),
+    };
+    BLOCKS.with(|blocks| blocks.borrow().contains_key(&key))
+}
+
+// Returns the settings of a user, or the defaults if they never changed them
+fn _settings_of(address: &str) -> UserSettings {
+    USER_SETTINGS
+        .with(|settings| settings.borrow().get(&UserKey(address.to_string())))
+        .unwrap_or_default()
+}
+
+// Blanks the contact and creator name of courses whose creators don't share them with the caller.
+// Creators always see their own courses in full, and so do the admin and moderators
+fn _visible_courses(courses: Vec<Course>) -> Vec<Course> {
+    let viewer = api::caller().to_string();
+    let is_staff = _is_authorized(viewer.clone());
+    courses
+        .into_iter()
+        .map(|mut course| {
+            course.created_at_rfc3339 = Some(_format_rfc3339(course.created_at, 0));
+            course.updated_at_rfc3339 = course.updated_at.map(|updated_at| _format_rfc3339(updated_at, 0));
+            if is_staff || course.creator_address == viewer {
+                return course;
+            }
+            let settings = _settings_of(&course.creator_address);
+            if !_is_visible_to(settings.contact_visibility, course.id, &viewer) {
+                course.contact = Vec::new();
+            }
+            if !_is_visible_to(settings.profile_visibility, course.id, &viewer) {
+                course.creator_name = String::new();
+            }
+            course
+        })
+        .collect()
+}
+
+// Checks if a viewer may see a detail of a course with the given visibility
+fn _is_visible_to(visibility: Visibility, course_id: CourseId, viewer: &str) -> bool {
+    match visibility {
+        Visibility::Public => true,
+        Visibility::Hidden => false,
+        Visibility::EnrolledLearners => {
+            let key = EngagementKey {
+                course_id,
+                address: viewer.to_string(),
+            };
+            ENROLLMENTS.with(|enrollments| enrollments.borrow().contains_key(&key))
+        }
+    }
+}
+
+// Collects courses from the given id onwards until the chunk size budget is used up
+fn _collect_chunk(token: Option<CourseId>, candidates: Option<Vec<CourseId>>, predicate: impl Fn(&StoredCourse) -> bool) -> CourseChunk {
+    let (courses, next_token) = _courses_within_budget(&_viewer(), token, candidates, predicate);
+    CourseChunk {
+        courses: _visible_courses(courses),
+        next_token,
+    }
+}
+
+// Returns the courses listed for a viewer from token on that fit in the chunk size budget,
+// with the id of the first course left out. Only the candidates are read when there are some
+fn _courses_within_budget(
+    viewer: &Viewer,
+    token: Option<CourseId>,
+    candidates: Option<Vec<CourseId>>,
+    predicate: impl Fn(&StoredCourse) -> bool,
+) -> (Vec<Course>, Option<CourseId>) {
+    let start = token.unwrap_or_default();
+    let mut courses: Vec<Course> = Vec::new();
+    let mut size: usize = 0;
+    let mut next_token = None;
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let stored: Box<dyn Iterator<Item = (CourseId, StoredCourse)>> = match candidates {
+            Some(ids) => Box::new(
+                ids.into_iter()
+                    .filter(|id| *id >= start)
+                    .filter_map(|id| storage.get(&id).map(|course| (id, course))),
+            ),
+            None => Box::new(storage.range(start..)),
+        };
+        for (id, course) in stored {
+            if !viewer.sees(&course) || !predicate(&course) {
+                continue;
+            }
+            let course = _to_course(course);
+            let course_size = Encode!(&course).map(|bytes| bytes.len()).unwrap_or(0);
+            if size + course_size > CHUNK_SIZE_LIMIT && !courses.is_empty() {
+                next_token = Some(id);
+                break;
+            }
+            size += course_size;
+            courses.push(course);
+        }
+    });
+    (courses, next_token)
+}
+
+// Returns the records of a section of the data export of an address as JSON, in a stable order
+fn _export_records(address: &str, section: &str) -> Vec<String> {
+    fn values<T: serde::Serialize>(records: Vec<T>) -> Vec<String> {
+        records.iter().filter_map(|record| serde_json::to_string(record).ok()).collect()
+    }
+    match section {
+        "account" => values(vec![AccountExport {
+            address: address.to_string(),
+            exported_at: time(),
+            ban: _active_ban(address),
+            settings: _settings_of(address),
+            blocked_users: _blocked_by(address),
+        }]),
+        "courses" => values(_course_ids_by_creator(address).iter().filter_map(_get_course_).collect()),
+        "taken_down_courses" => values(TAKEDOWNS.with(|takedowns| {
+            takedowns
+                .borrow()
+                .iter()
+                .map(|(_, takedown)| takedown)
+                .filter(|takedown| takedown.course.creator_address == address)
+                .collect()
+        })),
+        "queued_courses" => values(MODERATION_QUEUE.with(|queue| {
+            queue
+                .borrow()
+                .iter()
+                .map(|(_, queued)| queued)
+                .filter(|queued| queued.course.creator_address == address)
+                .collect()
+        })),
+        "likes" => values(LIKES.with(|likes| {
+            likes
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.address == address)
+                .map(|(key, liked_at)| LikeRecord {
+                    course_id: key.course_id,
+                    liked_at,
+                })
+                .collect()
+        })),
+        "enrollments" => values(ENROLLMENTS.with(|enrollments| {
+            enrollments
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.address == address)
+                .map(|(_, enrollment)| enrollment)
+                .collect()
+        })),
+        "notifications" => values(_notifications_of(address)),
+        "poll_votes" => values(POLL_VOTES.with(|votes| {
+            votes
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.voter == address)
+                .map(|(key, option)| PollVoteRecord {
+                    course_id: key.course_id,
+                    poll_id: key.poll_id,
+                    option,
+                })
+                .collect()
+        })),
+        "rsvps" => values(RSVPS.with(|rsvps| {
+            rsvps
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.learner == address)
+                .map(|(key, rsvped_at)| RsvpRecord {
+                    course_id: key.course_id,
+                    session_id: key.session_id,
+                    rsvped_at,
+                })
+                .collect()
+        })),
+        "waitlists" => values(WAITLISTS.with(|waitlists| {
+            waitlists
+                .borrow()
+                .iter()
+                .filter(|(_, entry)| entry.learner == address)
+                .map(|(key, entry)| CohortRecord {
+                    course_id: key.course_id,
+                    cohort_id: key.cohort_id,
+                    record: entry,
+                })
+                .collect()
+        })),
+        "attendance" => values(ATTENDANCE.with(|attendance| {
+            attendance
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.learner == address)
+                .map(|(key, record)| CourseRecord {
+                    course_id: key.course_id,
+                    record,
+                })
+                .collect()
+        })),
+        "feedback" => values(FEEDBACK.with(|feedback| {
+            feedback
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.address == address)
+                .map(|(key, response)| CourseRecord {
+                    course_id: key.course_id,
+                    record: response,
+                })
+                .collect()
+        })),
+        "submissions" => values(SUBMISSIONS.with(|submissions| {
+            submissions
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.learner == address)
+                .map(|(key, submission)| CourseRecord {
+                    course_id: key.course_id,
+                    record: submission,
+                })
+                .collect()
+        })),
+        "comments" => values(COMMENTS.with(|comments| {
+            comments
+                .borrow()
+                .iter()
+                .map(|(_, comment)| comment)
+                .filter(|comment| comment.author == address)
+                .collect()
+        })),
+        "verification" => values(
+            VERIFICATIONS
+                .with(|verifications| verifications.borrow().get(&UserKey(address.to_string())))
+                .into_iter()
+                .collect(),
+        ),
+        "archived_courses" => values(ARCHIVED_COURSES.with(|archived| {
+            archived
+                .borrow()
+                .iter()
+                .map(|(_, archived)| archived)
+                .filter(|archived| archived.course.creator_address == address)
+                .collect()
+        })),
+        "activity" => values(USER_ACTIVITY.with(|feed| {
+            feed.borrow()
+                .iter()
+                .filter(|(key, _)| key.address == address)
+                .map(|(_, activity)| activity)
+                .collect()
+        })),
+        "streak" => values(
+            STREAKS
+                .with(|streaks| streaks.borrow().get(&UserKey(address.to_string())))
+                .into_iter()
+                .collect(),
+        ),
+        "goals" => values(GOALS.with(|goals| {
+            goals
+                .borrow()
+                .iter()
+                .filter(|(key, _)| key.address == address)
+                .map(|(_, goal)| goal)
+                .collect()
+        })),
+        "drafts" => values(_drafts_of(address)),
+        _ => Vec::new(),
+    }
+}
+
+// Returns the id of a string in the string table, adding it if it is new
+fn _intern(value: &str) -> u32 {
+    let key = InternedString(value.to_string());
+    if let Some(id) = INTERNED_IDS.with(|ids| ids.borrow().get(&key)) {
+        return id;
+    }
+    // Strings are never removed, so the table size is the next free id
+    let id = INTERNED_STRINGS.with(|strings| strings.borrow().len()) as u32;
+    INTERNED_STRINGS.with(|strings| strings.borrow_mut().insert(id, key.clone()));
+    INTERNED_IDS.with(|ids| ids.borrow_mut().insert(key, id));
+    id
+}
+
+// Looks up the id of a string without adding it to the string table
+fn _interned_id(value: &str) -> Option<u32> {
+    INTERNED_IDS.with(|ids| ids.borrow().get(&InternedString(value.to_string())))
+}
+
+// Returns the keyword or category a lower case tag stands for, or the tag itself
+fn _canonical_tag(tag: &str) -> String {
+    TAG_SYNONYMS
+        .with(|synonyms| synonyms.borrow().get(&TagKey(tag.to_string())))
+        .map_or_else(|| tag.to_string(), |synonym| synonym.canonical)
+}
+
+// Matches the stored courses again after the synonyms changed, updating the category index
+fn _rekey_courses() {
+    let courses: Vec<StoredCourse> = STORAGE.with(|storage| storage.borrow().iter().map(|(_, course)| course).collect());
+    for mut course in courses {
+        let keyword_key = _intern(&_canonical_tag(&_fold(&_resolve(course.keyword_id))));
+        let category_key = _intern(&_canonical_tag(&_fold(&_resolve(course.category_id))));
+        let tag_keys = course
+            .tag_ids
+            .as_ref()
+            .map(|ids| ids.iter().map(|id| _intern(&_canonical_tag(&_fold(&_resolve(*id))))).collect());
+        if course.keyword_key == Some(keyword_key) && course.category_key == Some(category_key) && course.tag_keys == tag_keys {
+            continue;
+        }
+        _unindex_course(&course);
+        course.keyword_key = Some(keyword_key);
+        course.category_key = Some(category_key);
+        course.tag_keys = tag_keys;
+        STORAGE.with(|storage| storage.borrow_mut().insert(course.id, course.clone()));
+        _index_course(&course);
+    }
+}
+
+// Returns the string stored under an id in the string table
+fn _resolve(id: u32) -> String {
+    INTERNED_STRINGS
+        .with(|strings| strings.borrow().get(&id))
+        .map(|value| value.0)
+        .unwrap_or_default()
+}
+
+// Converts a course into its stored form, interning the tags and category. The tags are
+// the source of the keyword, which is stored as the first tag
+fn _to_stored(course: &Course) -> StoredCourse {
+    let (keyword, extra_tags) = course.tags.split_first().map_or(("", &[][..]), |(first, rest)| (first.as_str(), rest));
+    StoredCourse {
+        id: course.id,
+        creator_name: course.creator_name.clone(),
+        creator_address: course.creator_address.clone(),
+        title: course.title.clone(),
+        body: course.body.clone(),
+        attachment_url: course.attachment_url.clone(),
+        keyword_id: _intern(keyword),
+        category_id: _intern(&course.category),
+        keyword_key: Some(_intern(&_canonical_tag(&_fold(keyword)))),
+        category_key: Some(_intern(&_canonical_tag(&_fold(&course.category)))),
+        tag_ids: Some(extra_tags.iter().map(|tag| _intern(tag)).collect()),
+        tag_keys: Some(extra_tags.iter().map(|tag| _intern(&_canonical_tag(&_fold(tag)))).collect()),
+        created_at: course.created_at,
+        updated_at: course.updated_at,
+        contact: String::new(),
+        contacts: Some(course.contact.clone()),
+        content_rating: course.content_rating,
+        accessibility: course.accessibility.clone(),
+        regions: course.regions.clone(),
+    }
+}
+
+// Empties the fields of a course that aren't selected, keeping its id
+fn _project_course(course: Course, fields: &[CourseField]) -> Course {
+    let keep = |field: CourseField| fields.contains(&field);
+    Course {
+        id: course.id,
+        creator_name: if keep(CourseField::CreatorName) { course.creator_name } else { String::new() },
+        creator_address: if keep(CourseField::CreatorAddress) { course.creator_address } else { String::new() },
+        title: if keep(CourseField::Title) { course.title } else { String::new() },
+        body: if keep(CourseField::Body) { course.body } else { String::new() },
+        attachment_url: if keep(CourseField::AttachmentUrl) { course.attachment_url } else { String::new() },
+        keyword: if keep(CourseField::Keyword) { course.keyword } else { String::new() },
+        // The tags come with the keyword
+        tags: if keep(CourseField::Keyword) { course.tags } else { Vec::new() },
+        category: if keep(CourseField::Category) { course.category } else { String::new() },
+        created_at: if keep(CourseField::CreatedAt) { course.created_at } else { 0 },
+        updated_at: course.updated_at.filter(|_| keep(CourseField::UpdatedAt)),
+        created_at_rfc3339: course.created_at_rfc3339.filter(|_| keep(CourseField::CreatedAt)),
+        updated_at_rfc3339: course.updated_at_rfc3339.filter(|_| keep(CourseField::UpdatedAt)),
+        contact: if keep(CourseField::Contact) { course.contact } else { Vec::new() },
+        content_rating: course.content_rating.filter(|_| keep(CourseField::ContentRating)),
+        accessibility: course.accessibility.filter(|_| keep(CourseField::Accessibility)),
+        regions: course.regions.filter(|_| keep(CourseField::Regions)),
+        faq: None,
+    }
+}
+
+// Converts a stored course back into the course returned to users
+fn _to_course(course: StoredCourse) -> Course {
+    let tags = course.tag_pairs().into_iter().map(|(id, _)| _resolve(id)).collect();
+    Course {
+        id: course.id,
+        creator_name: course.creator_name,
+        creator_address: course.creator_address,
+        title: course.title,
+        body: course.body,
+        attachment_url: course.attachment_url,
+        keyword: _resolve(course.keyword_id),
+        tags,
+        category: _resolve(course.category_id),
+        created_at: course.created_at,
+        updated_at: course.updated_at,
+        contact: course.contacts.unwrap_or_else(|| _parse_legacy_contact(&course.contact)),
+        content_rating: course.content_rating,
+        accessibility: course.accessibility,
+        regions: course.regions,
+        faq: None,
+        created_at_rfc3339: None,
+        updated_at_rfc3339: None,
+    }
+}
+
+// Recognizes the contact method of a free text contact. Text that isn't recognized is kept as Other
+fn _parse_legacy_contact(contact: &str) -> Vec<ContactMethod> {
+    let contact = contact.trim();
+    if contact.is_empty() {
+        return Vec::new();
+    }
+    let lowered = contact.to_lowercase();
+    let method = if lowered.starts_with("https://t.me/") {
+        ContactMethod::Telegram(contact["https://t.me/".len()..].to_string())
+    } else if lowered.starts_with("https://") || lowered.starts_with("http://") {
+        ContactMethod::Website(contact.to_string())
+    } else if let Some(handle) = contact.strip_prefix('@') {
+        ContactMethod::Telegram(handle.to_string())
+    } else if contact.contains('@') && !contact.contains(char::is_whitespace) {
+        ContactMethod::Email(contact.to_string())
+    } else if candid::Principal::from_text(contact).is_ok() {
+        ContactMethod::Principal(contact.to_string())
+    } else {
+        ContactMethod::Other(contact.to_string())
+    };
+    vec![method]
+}
+
+// Brings text to a canonical form: NFKC normalized, trimmed and with runs of whitespace
+// collapsed to a single space, so visually identical values are stored identically
+fn _normalize_text(value: &str) -> String {
+    let normalized: String = value.nfkc().collect();
+    normalized.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Normalizes a single line field such as a title and checks it for unsafe markup
+fn _normalize_line(field: &str, value: &str) -> Result<String, Error> {
+    let normalized = _normalize_text(value);
+    _check_markup(field, &normalized)?;
+    Ok(normalized)
+}
+
+// Normalizes the markdown body of a course and checks that it is well formed
+fn _normalize_course_body(body: &str) -> Result<String, Error> {
+    let body = _normalize_body(body)?;
+    _validate_markdown(&body)?;
+    Ok(body)
+}
+
+// Rejects markdown that would render differently than intended: code blocks that are never
+// closed and links or images missing the closing parenthesis of their URL
+fn _validate_markdown(body: &str) -> Result<(), Error> {
+    let mut fence: Option<&str> = None;
+    for (number, line) in body.lines().enumerate() {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(open) => {
+                if trimmed.starts_with(open) {
+                    fence = None;
+                }
+            }
+            None => {
+                if let Some(open) = ["```", "~~~"].into_iter().find(|open| trimmed.starts_with(open)) {
+                    fence = Some(open);
+                } else if _has_unclosed_link(trimmed) {
+                    return Err(Error::invalid_input(
+                        format!("The link on line {} of the body is missing its closing parenthesis", number + 1),
+                    ));
+                }
+            }
+        }
+    }
+    if fence.is_some() {
+        return Err(Error::invalid_input("The body has a code block that is never closed".to_string()));
+    }
+    Ok(())
+}
+
+// Whether a line has a link or image whose URL isn't closed, ignoring inline code
+fn _has_unclosed_link(line: &str) -> bool {
+    let mut in_code = false;
+    let mut rest = line;
+    while let Some(index) = rest.find(['`', ']']) {
+        if rest[index..].starts_with('`') {
+            in_code = !in_code;
+        } else if !in_code && rest[index..].starts_with("](") && !rest[index..].contains(')') {
+            return true;
+        }
+        rest = &rest[index + 1..];
+    }
+    false
+}
+
+// Renders a markdown body as an HTML fragment: headings, paragraphs, lists, quotes, code blocks,
+// rules, emphasis, inline code, links and images. All text is escaped, so no HTML from the body
+// gets through, and links and images are limited to http, https and mailto URLs
+fn _render_markdown(body: &str) -> String {
+    let mut html = String::with_capacity(body.len() * 2);
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&str> = None;
+    let mut lines = body.lines().peekable();
+    let flush = |html: &mut String, paragraph: &mut Vec<&str>, list: &mut Option<&str>| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", _render_inline(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+        if let Some(tag) = list.take() {
+            html.push_str(&format!("</{}>\n", tag));
+        }
+    };
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        let fence = ["```", "~~~"].into_iter().find(|open| trimmed.starts_with(open));
+        if let Some(open) = fence {
+            flush(&mut html, &mut paragraph, &mut list);
+            html.push_str("<pre><code>");
+            for code in lines.by_ref() {
+                if code.trim_start().starts_with(open) {
+                    break;
+                }
+                html.push_str(&_escape_html(code));
+                html.push('\n');
+            }
+            html.push_str("</code></pre>\n");
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut html, &mut paragraph, &mut list);
+            continue;
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            flush(&mut html, &mut paragraph, &mut list);
+            html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, _render_inline(trimmed[level..].trim())));
+            continue;
+        }
+        if ["---", "***", "___"].contains(&trimmed.trim_end()) {
+            flush(&mut html, &mut paragraph, &mut list);
+            html.push_str("<hr>\n");
+            continue;
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            flush(&mut html, &mut paragraph, &mut list);
+            html.push_str(&format!("<blockquote>{}</blockquote>\n", _render_inline(quote.trim())));
+            continue;
+        }
+        let bullet = ["- ", "* ", "+ "].into_iter().find_map(|marker| trimmed.strip_prefix(marker));
+        let numbered = trimmed
+            .split_once(". ")
+            .filter(|(number, _)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .map(|(_, item)| item);
+        if let Some((tag, item)) = bullet.map(|item| ("ul", item)).or(numbered.map(|item| ("ol", item))) {
+            if list != Some(tag) {
+                flush(&mut html, &mut paragraph, &mut list);
+                html.push_str(&format!("<{}>\n", tag));
+                list = Some(tag);
+            }
+            html.push_str(&format!("<li>{}</li>\n", _render_inline(item.trim())));
+            continue;
+        }
+        if list.is_some() {
+            flush(&mut html, &mut paragraph, &mut list);
+        }
+        paragraph.push(trimmed);
+    }
+    flush(&mut html, &mut paragraph, &mut list);
+    html
+}
+
+// Renders the inline markdown of a line: inline code, bold, italic, links and images
+fn _render_inline(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                html.push_str(&format!("<code>{}</code>", _escape_html(&rest[1..end + 1])));
+                rest = &rest[end + 2..];
+                continue;
+            }
+        } else if rest.starts_with("**") {
+            match rest[2..].find("**").filter(|end| *end > 0) {
+                Some(end) => {
+                    html.push_str(&format!("<strong>{}</strong>", _render_inline(&rest[2..end + 2])));
+                    rest = &rest[end + 4..];
+                }
+                None => {
+                    html.push_str("**");
+                    rest = &rest[2..];
+                }
+            }
+            continue;
+        } else if c == '*' && !rest[1..].starts_with(char::is_whitespace) {
+            if let Some(end) = rest[1..].find('*').filter(|end| *end > 0) {
+                html.push_str(&format!("<em>{}</em>", _render_inline(&rest[1..end + 1])));
+                rest = &rest[end + 2..];
+                continue;
+            }
+        } else if c == '[' || rest.starts_with("![") {
+            let is_image = c == '!';
+            let label_start = if is_image { 2 } else { 1 };
+            let link = rest[label_start..].find("](").and_then(|label_end| {
+                let url_start = label_start + label_end + 2;
+                let url_end = url_start + rest[url_start..].find(')')?;
+                Some((&rest[label_start..label_start + label_end], rest[url_start..url_end].trim(), url_end))
+            });
+            if let Some((label, url, url_end)) = link {
+                let lowered = url.to_lowercase();
+                if ["http://", "https://", "mailto:"].iter().any(|scheme| lowered.starts_with(scheme)) {
+                    if is_image {
+                        html.push_str(&format!("<img src=\"{}\" alt=\"{}\">", _escape_html(url), _escape_html(label)));
+                    } else {
+                        html.push_str(&format!(
+                            "<a href=\"{}\" rel=\"nofollow noopener\">{}</a>",
+                            _escape_html(url),
+                            _render_inline(label)
+                        ));
+                    }
+                } else {
+                    html.push_str(&_escape_html(label));
+                }
+                rest = &rest[url_end + 1..];
+                continue;
+            }
+        }
+        html.push_str(&_escape_html(&rest[..c.len_utf8()]));
+        rest = &rest[c.len_utf8()..];
+    }
+    html
+}
+
+// Escapes the characters with a meaning in HTML
+fn _escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Checks a markdown body for unsafe markup, trims it and collapses runs of blank lines into one.
+// Whitespace inside lines is kept, since markdown gives indentation and trailing spaces meaning
+fn _normalize_body(body: &str) -> Result<String, Error> {
+    _check_markup("body", body)?;
+    let mut normalized = String::with_capacity(body.len());
+    let mut blank_lines = 0;
+    for line in body.trim().lines() {
+        if line.trim().is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        normalized.push_str(line);
+        blank_lines = 0;
+    }
+    Ok(normalized)
+}
+
+// Rejects user content that could run code when rendered: script tags, frames, embedded objects,
+// javascript: links, inline event handlers, and markdown links to other schemes than http, https
+// or mailto. Anything else is stored as written, so text such as Vec<String> survives, and is
+// escaped when rendered
+fn _check_markup(field: &str, value: &str) -> Result<(), Error> {
+    let lowered = value.to_lowercase();
+    if let Some(markup) = FORBIDDEN_MARKUP.iter().find(|markup| lowered.contains(*markup)) {
+        return Err(Error::rejected_content(
+            format!("The {} contains forbidden markup: {}", field, markup),
+        ).with_field(field));
+    }
+
+    // Reject HTML tags with inline event handlers such as onclick
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let after = &rest[start + 1..];
+        if after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            if let Some(end) = after.find('>') {
+                let has_handler = after[..end]
+                    .to_lowercase()
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .skip(1)
+                    .any(|attribute| attribute.starts_with("on") && attribute.contains('='));
+                if has_handler {
+                    return Err(Error::rejected_content(
+                        format!("The {} contains an inline event handler", field),
+                    ).with_field(field));
+                }
+            }
+        }
+        rest = after;
+    }
+
+    // Check the targets of markdown links and images: [text](target)
+    for (index, _) in value.match_indices("](") {
+        let target = value[index + 2..].trim_start().to_lowercase();
+        let scheme = target.split(|c: char| c == '/' || c == ')' || c.is_whitespace()).next().unwrap_or_default();
+        if scheme.contains(':') && !ALLOWED_LINK_SCHEMES.iter().any(|allowed| scheme.starts_with(allowed)) {
+            return Err(Error::rejected_content(
+                format!("The {} links to a {} address; only http, https and mailto links are allowed", field, scheme),
+            ).with_field(field));
+        }
+    }
+    Ok(())
+}
+
+// Validates the contact methods of a course and brings them to a canonical form, so creators stay
+// reachable. Errors name the offending entry, e.g. "contact 2 (Telegram)"
+fn _normalize_contacts(contacts: &[ContactMethod]) -> Result<Vec<ContactMethod>, Error> {
+    if contacts.len() > MAX_CONTACTS {
+        return Err(Error::invalid_input(format!("A course can have at most {} contact methods", MAX_CONTACTS)));
+    }
+    contacts
+        .iter()
+        .enumerate()
+        .map(|(index, contact)| {
+            _normalize_contact(contact).map_err(|problem| Error::invalid_input(
+                format!("contact {} ({}): {}", index + 1, contact.kind(), problem),
+            ))
+        })
+        .collect()
+}
+
+// Validates a single contact method, returning what is wrong with it
+fn _normalize_contact(contact: &ContactMethod) -> Result<ContactMethod, String> {
+    match contact {
+        ContactMethod::Email(email) => {
+            let email = email.trim();
+            if _is_valid_email(email) {
+                Ok(ContactMethod::Email(email.to_string()))
+            } else {
+                Err(format!("{} is not a valid email address", email))
+            }
+        }
+        ContactMethod::Telegram(handle) => {
+            // Telegram usernames are 5 to 32 letters, digits or underscores, starting with a letter
+            let handle = handle.trim().trim_start_matches('@');
+            let is_valid = (5..=32).contains(&handle.len())
+                && handle.starts_with(|c: char| c.is_ascii_alphabetic())
+                && handle.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if is_valid {
+                Ok(ContactMethod::Telegram(handle.to_string()))
+            } else {
+                Err("a Telegram username has 5 to 32 letters, digits or underscores and starts with a letter".to_string())
+            }
+        }
+        ContactMethod::Discord(handle) => {
+            // Discord usernames are 2 to 32 lower case letters, digits, underscores or periods
+            let handle = handle.trim().trim_start_matches('@').to_lowercase();
+            let is_valid = (2..=32).contains(&handle.len())
+                && handle.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                && !handle.contains("..");
+            if is_valid {
+                Ok(ContactMethod::Discord(handle))
+            } else {
+                Err("a Discord username has 2 to 32 letters, digits, underscores or single periods".to_string())
+            }
+        }
+        ContactMethod::Website(url) => {
+            let url = url.trim();
+            let host = url.strip_prefix("https://").map(|rest| rest.split(['/', '?', '#']).next().unwrap_or_default());
+            match host {
+                _ if url.chars().count() > MAX_URL_LENGTH => Err(format!("the URL must be at most {} characters long", MAX_URL_LENGTH)),
+                Some(host) if host.contains('.') && !url.contains(char::is_whitespace) => Ok(ContactMethod::Website(url.to_string())),
+                _ => Err(format!("{} is not a valid https:// URL", url)),
+            }
+        }
+        ContactMethod::Principal(principal) => {
+            let principal = principal.trim();
+            match candid::Principal::from_text(principal) {
+                Ok(_) => Ok(ContactMethod::Principal(principal.to_string())),
+                Err(_) => Err(format!("{} is not a valid principal", principal)),
+            }
+        }
+        ContactMethod::Other(_) => Err("free text contacts are no longer accepted, use one of the other contact methods".to_string()),
+    }
+}
+
+// Checks the shape of an email address: a local part of letters, digits and ._%+- and
+// a domain of at least two labels, ending in a top level domain of at least two letters
+fn _is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_local = !local.is_empty()
+        && local.len() <= 64
+        && local.chars().all(|c| c.is_ascii_alphanumeric() || "._%+-".contains(c));
+    let valid_labels = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    let valid_tld = matches!(labels.last(), Some(tld) if tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+    email.len() <= 254 && valid_local && valid_labels && valid_tld
+}
+
+// Checks that an attachment links to a web page
+fn _validate_attachment_url(url: &str) -> Result<(), Error> {
+    let lowered = url.trim().to_lowercase();
+    if !lowered.starts_with("https://") && !lowered.starts_with("http://") {
+        return Err(Error::rejected_content("The attachment URL must start with http:// or https://".to_string()));
+    }
+    Ok(())
+}
+
+// Returns the lower case shadow copy of a text used for matching
+fn _fold(value: &str) -> String {
+    _normalize_text(value).to_lowercase()
+}
+
+// Checks that a keyword or category is short enough to be kept in the string table
+fn _validate_tag(name: &str, value: &str) -> Result<(), Error> {
+    if value.chars().count() > MAX_TAG_LENGTH {
+        return Err(Error::invalid_input(
+            format!("The {} must be at most {} characters long", name, MAX_TAG_LENGTH),
+        ).with_field(name));
+    }
+    Ok(())
+}
+
+// Returns the tags of a course, the keyword first unless it is empty, leaving out tags repeated
+// with another case. Tags must not be empty or too long, and a course has at most MAX_TAGS of them
+fn _course_tags(keyword: &str, tags: Vec<String>) -> Result<Vec<String>, Error> {
+    if !keyword.is_empty() {
+        _validate_tag("keyword", keyword)?;
+    }
+    let mut seen = HashSet::new();
+    let mut course_tags = Vec::new();
+    for tag in std::iter::once(keyword.to_string()).filter(|keyword| !keyword.is_empty()).chain(tags) {
+        if tag.is_empty() {
+            return Err(Error::empty_fields("A tag can't be empty".to_string()).with_field("tags"));
+        }
+        _validate_tag("tag", &tag)?;
+        if seen.insert(_fold(&tag)) {
+            course_tags.push(tag);
+        }
+    }
+    if course_tags.len() > MAX_TAGS {
+        return Err(Error::invalid_input(format!("A course can have at most {} tags", MAX_TAGS)).with_field("tags"));
+    }
+    Ok(course_tags)
+}
+
+// Checks that the transcript link of a course is short enough to store
+fn _validate_accessibility(accessibility: &Accessibility) -> Result<(), Error> {
+    match accessibility.transcript_url {
+        Some(ref url) if url.trim().is_empty() => Err(Error::empty_fields(
+            "The transcript URL can't be empty".to_string(),
+        )),
+        Some(ref url) if url.chars().count() > MAX_URL_LENGTH => Err(Error::invalid_input(
+            format!("The transcript URL must be at most {} characters long", MAX_URL_LENGTH),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Checks a list of ISO 3166-1 alpha-2 country codes, returning them upper case and deduplicated
+fn _normalize_regions(regions: &[String]) -> Result<Vec<String>, Error> {
+    if regions.len() > MAX_REGIONS {
+        return Err(Error::invalid_input(format!("A course can target at most {} regions", MAX_REGIONS)));
+    }
+    let mut codes: Vec<String> = Vec::new();
+    for region in regions {
+        let code = region.trim().to_ascii_uppercase();
+        if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(Error::invalid_input(format!("\"{}\" is not a two letter country code", region)));
+        }
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    Ok(codes)
+}
+
+// Error for a course that doesn't exist or can't be seen
+fn _course_not_found(id: CourseId) -> Error {
+    Error::not_found(format!("a course with id={} not found", id)).with_id(id.0)
+}
+
+// Returns the drafts of a creator
+fn _drafts_of(address: &str) -> Vec<Draft> {
+    DRAFTS.with(|drafts| {
+        drafts
+            .borrow()
+            .iter()
+            .map(|(_, draft)| draft)
+            .filter(|draft| draft.creator_address == address)
+            .collect()
+    })
+}
+
+// Checks that a principal meets the publishing requirements, unless they already have courses or are staff
+fn _check_publishing_requirements(address: &str) -> Result<(), Error> {
+    if _is_authorized(address.to_string()) || !_course_ids_by_creator(address).is_empty() {
+        return Ok(());
+    }
+    let requirements = PUBLISHING_REQUIREMENTS.with(|requirements| requirements.lock().unwrap().clone());
+    let key = UserKey(address.to_string());
+    if let Some(days) = requirements.min_account_age_days {
+        let first_seen = FIRST_SEEN.with(|first_seen| first_seen.borrow().get(&key)).unwrap_or(time());
+        let allowed_at = first_seen + days as u64 * NANOS_PER_DAY;
+        if time() < allowed_at {
+            return Err(Error::unauthorized(format!(
+                "New creators can publish {} days after first using the platform",
+                days
+            ))
+            .with_retry_after((allowed_at - time()).div_ceil(1_000_000_000)));
+        }
+    }
+    if requirements.settings_required && !USER_SETTINGS.with(|settings| settings.borrow().contains_key(&key)) {
+        return Err(Error::unauthorized(
+            "Please complete your settings before publishing your first course".to_string(),
+        ));
+    }
+    let verified = VERIFICATIONS
+        .with(|verifications| verifications.borrow().get(&key))
+        .is_some_and(|request| request.status == VerificationStatus::Approved);
+    if requirements.verification_required && !verified {
+        return Err(Error::unauthorized(
+            "Please get verified by the moderators before publishing your first course".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Takes the next course id from the counter
+fn _next_course_id() -> CourseId {
+    let id = ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+    CourseId(id)
+}
+
+// Returns a draft of the caller
+fn _own_draft(id: CourseId) -> Result<Draft, Error> {
+    match DRAFTS.with(|drafts| drafts.borrow().get(&id)) {
+        Some(draft) if draft.creator_address == api::caller().to_string() => Ok(draft),
+        _ => Err(Error::not_found(format!("a draft with id={} not found", id)).with_id(id.0)),
+    }
+}
+
+// Add the course into the storage and keep the indexes in sync
+fn do_insert(course: &Course) {
+    let stored = _to_stored(course);
+    let previous = STORAGE.with(|service| service.borrow_mut().insert(course.id, stored.clone()));
+    let kind = if let Some(previous) = previous {
+        _unindex_course(&previous);
+        CourseEventKind::Updated
+    } else {
+        CourseEventKind::Published
+    };
+    _index_course(&stored);
+    _emit_course_event(kind, course.id, &course.creator_address);
+}
+
+// Remove the course, its index entries and the data linked to it from the storage
+fn do_remove(id: CourseId) {
+    _unstore_course(id);
+    _remove_course_data(id);
+}
+
+// Remove the course and its index entries from the storage, keeping the data linked to it
+fn _unstore_course(id: CourseId) -> Option<StoredCourse> {
+    let course = STORAGE.with(|service| service.borrow_mut().remove(&id));
+    if let Some(course) = course.as_ref() {
+        _unindex_course(course);
+        _emit_course_event(CourseEventKind::Deleted, id, &course.creator_address);
+    }
+    course
+}
+
+// Stores a new or updated course if it passes the content filter. A course containing a
+// blocked term is either rejected or queued for review, depending on the filter action
+fn _publish_course(course: &Course, mut spam_signals: Vec<SpamSignal>) -> Result<(), Error> {
+    let links = format!("{}\n{}", course.body, course.attachment_url);
+    let source = LinkSource::Course(course.id);
+    let (domains, link_signals) = _screen_links(&source, &links)?;
+    spam_signals.extend(link_signals);
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", course.title, course.body));
+    let spam_score: u32 = spam_signals.iter().map(SpamSignal::score).sum();
+    if matched_terms.is_empty() && spam_score < SPAM_SCORE_THRESHOLD {
+        do_insert(course);
+        _record_links(source, domains);
+        return Ok(());
+    }
+    let action = if matched_terms.is_empty() {
+        ContentFilterAction::QueueForReview
+    } else {
+        CONTENT_FILTER_ACTION.with(|action| *action.lock().unwrap())
+    };
+    match action {
+        ContentFilterAction::Reject => Err(Error::invalid_input(
+            format!("The course contains blocked terms: {}", matched_terms.join(", ")),
+        )),
+        ContentFilterAction::QueueForReview => {
+            let queued = QueuedCourse {
+                course: course.clone(),
+                matched_terms,
+                queued_at: time(),
+                spam_signals: (!spam_signals.is_empty()).then_some(spam_signals),
+            };
+            _check_fits(&queued, "course")?;
+            MODERATION_QUEUE.with(|queue| queue.borrow_mut().insert(course.id, queued));
+            _record_links(source, domains);
+            _notify(
+                &course.creator_address,
+                NotificationKind::CourseQueuedForReview,
+                format!(
+                    "Your course \"{}\" (id={}) will be published once a moderator has reviewed it.",
+                    course.title, course.id
+                ),
+            );
+            Ok(())
+        }
+    }
+}
+
+// Scores a new or updated course for signs of spam: a title another course already has, a body
+// stuffed with links and, for new courses, many courses from the same creator within the last hour
+fn _spam_signals(course: &Course, is_new: bool) -> Vec<SpamSignal> {
+    let mut signals = Vec::new();
+
+    let title = _fold(&course.title);
+    let duplicate = STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .find(|(id, stored)| *id != course.id && _fold(&stored.title) == title)
+            .map(|(id, _)| id)
+    });
+    if let Some(course_id) = duplicate {
+        signals.push(SpamSignal::DuplicateTitle { course_id });
+    }
+
+    let links = course.body.to_lowercase().matches("://").count() as u32;
+    if links > SPAM_LINK_ALLOWANCE {
+        signals.push(SpamSignal::LinkStuffing { links });
+    }
+    if !is_new {
+        return signals;
+    }
+
+    // Course ids grow over time, so the creator's latest courses come last
+    let since = time().saturating_sub(Duration::from_secs(60 * 60).as_nanos() as u64);
+    let published = _course_ids_by_creator(&course.creator_address)
+        .into_iter()
+        .rev()
+        .filter(|id| *id != course.id)
+        .map_while(|id| _get_course_(&id).filter(|recent| recent.created_at >= since))
+        .count();
+    let queued = MODERATION_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .filter(|(_, queued)| queued.course.creator_address == course.creator_address && queued.queued_at >= since)
+            .count()
+    });
+    // The new course counts too
+    let courses = (published + queued) as u32 + 1;
+    if courses > SPAM_BURST_ALLOWANCE {
+        signals.push(SpamSignal::BurstCreation { courses });
+    }
+    signals
+}
+
+// Returns the domains a text links to, lower case and without www.
+fn _domains_in(text: &str) -> Vec<String> {
+    let mut domains: Vec<String> = text
+        .split("://")
+        .skip(1)
+        .filter_map(|rest| {
+            let host: String = rest
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '-')
+                .collect::<String>()
+                .to_lowercase();
+            let host = host.trim_end_matches('.');
+            let host = host.strip_prefix("www.").unwrap_or(host);
+            host.contains('.').then(|| host.to_string())
+        })
+        .collect();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+// Normalizes a domain given by the admin, which may be pasted as a full URL
+fn _normalize_domain(domain: &str) -> Result<String, Error> {
+    let domain = domain.trim().to_lowercase();
+    let domain = domain.split_once("://").map_or(domain.as_str(), |(_, rest)| rest);
+    let domain = domain.split(['/', '?', '#', ':']).next().unwrap_or_default();
+    let domain = domain.strip_prefix("www.").unwrap_or(domain);
+    let valid = domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    if !valid {
+        return Err(Error::invalid_input(format!("{} is not a valid domain", domain)).with_field("domain"));
+    }
+    Ok(domain.to_string())
+}
+
+// Checks the links of a course or comment against the domain rules without recording them.
+// Rejects links to a denied domain, and returns the domains to count towards bursts with a spam
+// signal for every domain that many courses and comments linked to within the last hour,
+// counting this one. The domains are only counted by _record_links, once the content is kept
+fn _screen_links(source: &LinkSource, text: &str) -> Result<(Vec<String>, Vec<SpamSignal>), Error> {
+    let mut domains = Vec::new();
+    for domain in _domains_in(text) {
+        match DOMAIN_RULES.with(|rules| rules.borrow().get(&DomainKey(domain.clone()))).map(|rule| rule.policy) {
+            Some(DomainPolicy::Deny) => {
+                return Err(Error::rejected_content(format!("Links to {} are not allowed", domain)));
+            }
+            Some(DomainPolicy::Allow) => {}
+            None => domains.push(domain),
+        }
+    }
+    let since = time().saturating_sub(DOMAIN_BURST_WINDOW);
+    let signals = domains
+        .iter()
+        .filter_map(|domain| {
+            let others = DOMAIN_SIGHTINGS.with(|sightings| {
+                sightings.lock().unwrap().get(domain).map_or(0, |seen| {
+                    seen.iter().filter(|sighting| sighting.seen_at >= since && sighting.source != *source).count()
+                })
+            });
+            (others + 1 >= DOMAIN_BURST_THRESHOLD).then(|| SpamSignal::RepeatedDomain {
+                domain: domain.clone(),
+                references: (others + 1) as u32,
+            })
+        })
+        .collect();
+    Ok((domains, signals))
+}
+
+// Counts the links of a course or comment that is kept towards bursts of their domains.
+// New bursts are flagged for the staff
+fn _record_links(source: LinkSource, domains: Vec<String>) {
+    let now = time();
+    let since = now.saturating_sub(DOMAIN_BURST_WINDOW);
+    for domain in domains {
+        let references: Vec<LinkSource> = DOMAIN_SIGHTINGS.with(|sightings| {
+            let mut sightings = sightings.lock().unwrap();
+            let seen = sightings.entry(domain.clone()).or_default();
+            seen.retain(|sighting| sighting.seen_at >= since && sighting.source != source);
+            seen.push(DomainSighting { source: source.clone(), seen_at: now });
+            seen.iter().map(|sighting| sighting.source.clone()).collect()
+        });
+        if references.len() < DOMAIN_BURST_THRESHOLD {
+            continue;
+        }
+        let is_new = DOMAIN_BURSTS.with(|bursts| {
+            let mut bursts = bursts.lock().unwrap();
+            let is_new = !bursts.contains_key(&domain);
+            let burst = bursts.entry(domain.clone()).or_insert_with(|| DomainBurst {
+                domain: domain.clone(),
+                references: Vec::new(),
+                flagged_at: now,
+                last_seen_at: now,
+            });
+            for reference in references {
+                if !burst.references.contains(&reference) && burst.references.len() < MAX_BURST_REFERENCES {
+                    burst.references.push(reference);
+                }
+            }
+            burst.last_seen_at = now;
+            is_new
+        });
+        if is_new {
+            for address in _staff_addresses() {
+                _notify(
+                    &address,
+                    NotificationKind::DomainBurst,
+                    format!("{} courses and comments linked to {} within the last hour.", DOMAIN_BURST_THRESHOLD, domain),
+                );
+            }
+        }
+    }
+}
+
+// Forgets the links that fell out of the burst window
+fn _prune_domain_sightings() {
+    let since = time().saturating_sub(DOMAIN_BURST_WINDOW);
+    DOMAIN_SIGHTINGS.with(|sightings| {
+        let mut sightings = sightings.lock().unwrap();
+        for seen in sightings.values_mut() {
+            seen.retain(|sighting| sighting.seen_at >= since);
+        }
+        sightings.retain(|_, seen| !seen.is_empty());
+    });
+}
+
+// Computes a 64 bit simhash of a course body out of its three word shingles. Bodies that
+// differ in a few words get fingerprints that differ in a few bits
+fn _fingerprint(body: &str) -> Option<u64> {
+    let folded = _fold(body);
+    let words: Vec<&str> = folded.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+    if words.len() < MIN_FINGERPRINT_WORDS {
+        return None;
+    }
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(3) {
+        let hash = _fnv1a(shingle.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if (hash >> bit) & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0u64, |fingerprint, (bit, _)| fingerprint | 1 << bit),
+    )
+}
+
+// 64 bit FNV-1a hash, stable across upgrades unlike the standard library hasher
+fn _fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Flags a new course whose body closely matches a course of another creator and lets the staff know
+fn _flag_possible_duplicate(course: &Course) {
+    let Some(fingerprint) = _fingerprint(&course.body) else {
+        return;
+    };
+    let closest = FINGERPRINTS.with(|fingerprints| {
+        fingerprints
+            .borrow()
+            .iter()
+            .filter(|(id, _)| *id != course.id)
+            .map(|(id, other)| (id, (fingerprint ^ other).count_ones()))
+            .filter(|(id, distance)| {
+                *distance <= MAX_DUPLICATE_DISTANCE
+                    && matches!(_get_course_(id), Some(other) if other.creator_address != course.creator_address)
+            })
+            .min_by_key(|(id, distance)| (*distance, *id))
+    });
+    let Some((original_id, distance)) = closest else {
+        return;
+    };
+    let duplicate = PossibleDuplicate {
+        course_id: course.id,
+        original_id,
+        similarity: (64 - distance) * 100 / 64,
+        flagged_at: time(),
+    };
+    POSSIBLE_DUPLICATES.with(|duplicates| duplicates.borrow_mut().insert(course.id, duplicate));
+    for address in _staff_addresses() {
+        _notify(
+            &address,
+            NotificationKind::PossibleDuplicate,
+            format!("Course with id={} closely matches course with id={} by another creator.", course.id, original_id),
+        );
+    }
+}
+
+// Returns the category of a queued course
+fn _queued_category(id: CourseId) -> Result<String, Error> {
+    MODERATION_QUEUE
+        .with(|queue| queue.borrow().get(&id))
+        .map(|queued| queued.course.category)
+        .ok_or(Error::not_found(format!("course with id={} is not waiting for review", id)))
+}
+
+// Removes a course from the moderation queue
+fn _take_queued_course(id: CourseId) -> Result<QueuedCourse, Error> {
+    MODERATION_QUEUE
+        .with(|queue| queue.borrow_mut().remove(&id))
+        .ok_or(Error::not_found(format!("course with id={} is not waiting for review", id)))
+}
+
+// Checks a blocked term and brings it to its stored form: lower case words separated by single spaces
+fn _normalize_blocked_term(term: &str) -> Result<BlockedTerm, Error> {
+    let words: Vec<String> = term.split_whitespace().map(|word| word.to_lowercase()).collect();
+    let is_valid = !words.is_empty()
+        && words.iter().all(|word| {
+            let stem = word.strip_suffix('*').unwrap_or(word);
+            !stem.is_empty() && stem.chars().all(char::is_alphanumeric)
+        });
+    if !is_valid {
+        return Err(Error::invalid_input(
+            format!("\"{}\" is not a valid blocked term. Use words, each optionally ending with *", term),
+        ));
+    }
+    let term = words.join(" ");
+    _validate_tag("blocked term", &term)?;
+    Ok(BlockedTerm(term))
+}
+
+// Returns the blocked terms found in a text, ignoring case and punctuation
+fn _blocked_terms_in(text: &str) -> Vec<String> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    BLOCKED_TERMS.with(|blocked| {
+        blocked
+            .borrow()
+            .iter()
+            .map(|(term, _)| term.0)
+            .filter(|term| {
+                let patterns: Vec<&str> = term.split(' ').collect();
+                words.windows(patterns.len()).any(|window| {
+                    window.iter().zip(patterns.iter()).all(|(word, pattern)| match pattern.strip_suffix('*') {
+                        Some(prefix) => word.starts_with(prefix),
+                        None => word == pattern,
+                    })
+                })
+            })
+            .collect()
+    })
+}
+
+// Retrieves a takedown by the id of the course
+fn _get_takedown(id: CourseId) -> Result<Takedown, Error> {
+    TAKEDOWNS
+        .with(|takedowns| takedowns.borrow().get(&id))
+        .ok_or(Error::not_found(format!("course with id={} has not been taken down", id)))
+}
+
+// Checks the length of a moderation note or message
+fn _validate_note(note: &str) -> Result<(), Error> {
+    if note.chars().count() > MAX_NOTE_LENGTH {
+        return Err(Error::invalid_input(format!("Notes can be at most {} characters long", MAX_NOTE_LENGTH)));
+    }
+    Ok(())
+}
+
+// Returns the addresses of the admin and the moderators
+fn _staff_addresses() -> Vec<String> {
+    let mut addresses = MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().clone());
+    if let Some(admin) = ADMIN_ADDRESS.with(|admin| admin.lock().unwrap().clone()) {
+        addresses.push(admin);
+    }
+    addresses
+}
+
+// Deletes all the courses of a creator and returns them
+fn _delete_courses_of(address: &str) -> Vec<Course> {
+    let deleted_courses: Vec<Course> = _course_ids_by_creator(address)
+        .iter()
+        .filter_map(_get_course_)
+        .collect();
+    for course in deleted_courses.iter() {
+        do_remove(course.id);
+    }
+    deleted_courses
+}
+
+// Removes the next batch of an account's data, and keeps the deletion for the scheduler if
+// there is more left
+fn _run_account_deletion(mut deletion: AccountDeletion) -> AccountDeletionStatus {
+    let removed = _delete_account_batch(&deletion, ACCOUNT_DELETION_BATCH);
+    deletion.removed_records += removed as u64;
+    // A batch that didn't use its whole budget found nothing more to remove
+    let completed = removed < ACCOUNT_DELETION_BATCH;
+    let status = AccountDeletionStatus {
+        policy: deletion.policy.clone(),
+        removed_records: deletion.removed_records,
+        completed,
+    };
+    if !completed {
+        ACCOUNT_DELETIONS.with(|deletions| deletions.lock().unwrap().push(deletion));
+    }
+    status
+}
+
+// Continues the account deletions that have data left to remove
+fn _continue_account_deletions() {
+    let deletions = ACCOUNT_DELETIONS.with(|deletions| std::mem::take(&mut *deletions.lock().unwrap()));
+    for deletion in deletions {
+        _run_account_deletion(deletion);
+    }
+}
+
+// Removes up to limit records of an account and returns how many were removed
+fn _delete_account_batch(deletion: &AccountDeletion, limit: usize) -> usize {
+    let address = deletion.address.as_str();
+    let mut removed = 0;
+
+    let course_ids: Vec<CourseId> = _course_ids_by_creator(address).into_iter().take(limit).collect();
+    let mut transferred = 0;
+    for id in course_ids.iter() {
+        match &deletion.policy {
+            CoursePolicy::Delete => do_remove(*id),
+            CoursePolicy::TransferTo(recipient) => {
+                if let Some(mut course) = _get_course_(id) {
+                    // The name and contact belong to the deleted account
+                    course.creator_address = recipient.clone();
+                    course.creator_name = recipient.clone();
+                    course.contact = vec![ContactMethod::Principal(recipient.clone())];
+                    course.updated_at = Some(time());
+                    do_insert(&course);
+                    transferred += 1;
+                }
+            }
+        }
+    }
+    if let CoursePolicy::TransferTo(recipient) = &deletion.policy {
+        if transferred > 0 {
+            _notify(
+                recipient,
+                NotificationKind::CoursesTransferred,
+                format!("{} courses were transferred to you from a deleted account.", transferred),
+            );
+        }
+    }
+    removed += course_ids.len();
+
+    removed += TAKEDOWNS.with(|takedowns| {
+        _remove_matching(&mut takedowns.borrow_mut(), limit - removed, |_, takedown| {
+            takedown.course.creator_address == address
+        })
+    });
+    removed += MODERATION_QUEUE.with(|queue| {
+        _remove_matching(&mut queue.borrow_mut(), limit - removed, |_, queued| {
+            queued.course.creator_address == address
+        })
+    });
+    removed += ARCHIVED_COURSES.with(|archived| {
+        _remove_matching(&mut archived.borrow_mut(), limit - removed, |_, archived| {
+            archived.course.creator_address == address
+        })
+    });
+    removed += LIKES.with(|likes| {
+        _remove_matching(&mut likes.borrow_mut(), limit - removed, |key, _| key.address == address)
+    });
+    removed += ENROLLMENTS.with(|enrollments| {
+        _remove_matching(&mut enrollments.borrow_mut(), limit - removed, |key, _| key.address == address)
+    });
+    removed += WAITLISTS.with(|waitlists| {
+        _remove_matching(&mut waitlists.borrow_mut(), limit - removed, |_, entry| entry.learner == address)
+    });
+    removed += SUBMISSIONS.with(|submissions| {
+        _remove_matching(&mut submissions.borrow_mut(), limit - removed, |key, _| key.learner == address)
+    });
+    removed += FEEDBACK.with(|feedback| {
+        _remove_matching(&mut feedback.borrow_mut(), limit - removed, |key, _| key.address == address)
+    });
+    removed += ATTENDANCE.with(|attendance| {
+        _remove_matching(&mut attendance.borrow_mut(), limit - removed, |key, _| key.learner == address)
+    });
+    removed += RSVPS.with(|rsvps| {
+        _remove_matching(&mut rsvps.borrow_mut(), limit - removed, |key, _| key.learner == address)
+    });
+    removed += POLL_VOTES.with(|votes| {
+        _remove_matching(&mut votes.borrow_mut(), limit - removed, |key, _| key.voter == address)
+    });
+    let comments: Vec<CommentKey> = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .filter(|(_, comment)| comment.author == address)
+            .map(|(key, _)| key)
+            .take(limit - removed)
+            .collect()
+    });
+    for key in comments.iter() {
+        _remove_comment(key.course_id, key.id);
+    }
+    removed += comments.len();
+    removed += NOTIFICATIONS.with(|notifications| {
+        _remove_matching(&mut notifications.borrow_mut(), limit - removed, |key, _| key.recipient == address)
+    });
+    removed += USER_ACTIVITY.with(|feed| {
+        _remove_matching(&mut feed.borrow_mut(), limit - removed, |key, _| key.address == address)
+    });
+    removed += GOALS.with(|goals| {
+        _remove_matching(&mut goals.borrow_mut(), limit - removed, |key, _| key.address == address)
+    });
+    removed += DRAFTS.with(|drafts| {
+        _remove_matching(&mut drafts.borrow_mut(), limit - removed, |_, draft| draft.creator_address == address)
+    });
+    removed += BLOCKS.with(|blocks| {
+        _remove_matching(&mut blocks.borrow_mut(), limit - removed, |key, _| key.blocker == address)
+    });
+    if removed < limit && USER_SETTINGS.with(|settings| settings.borrow_mut().remove(&UserKey(address.to_string()))).is_some() {
+        removed += 1;
+    }
+    if removed < limit && VERIFICATIONS.with(|verifications| verifications.borrow_mut().remove(&UserKey(address.to_string()))).is_some() {
+        removed += 1;
+    }
+    if removed < limit && STREAKS.with(|streaks| streaks.borrow_mut().remove(&UserKey(address.to_string()))).is_some() {
+        removed += 1;
+    }
+    removed
+}
+
+// Removes up to limit entries matching a condition from a stable map and returns how many were removed
+fn _remove_matching<K, V>(map: &mut StableBTreeMap<K, V, Memory>, limit: usize, matches: impl Fn(&K, &V) -> bool) -> usize
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let keys: Vec<K> = map
+        .iter()
+        .filter(|(key, value)| matches(key, value))
+        .map(|(key, _)| key)
+        .take(limit)
+        .collect();
+    for key in keys.iter() {
+        map.remove(key);
+    }
+    keys.len()
+}
+
+// Adds the index entries of a course
+fn _index_course(course: &StoredCourse) {
+    CATEGORY_INDEX.with(|index| {
+        index.borrow_mut().insert(
+            CategoryIndexKey {
+                category_id: course.category_key(),
+                course_id: course.id,
+            },
+            (),
+        )
+    });
+    CREATOR_INDEX.with(|index| {
+        index.borrow_mut().insert(
+            CreatorIndexKey {
+                creator: course.creator_address.clone(),
+                course_id: course.id,
+            },
+            (),
+        )
+    });
+    if let Some(fingerprint) = _fingerprint(&course.body) {
+        FINGERPRINTS.with(|fingerprints| fingerprints.borrow_mut().insert(course.id, fingerprint));
+    }
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for (token, fields) in _search_tokens(course) {
+            index.insert(SearchIndexKey { token, course_id: course.id }, fields);
+        }
+    });
+    PREFIX_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for (phrase, fields) in _prefix_phrases(course) {
+            index.insert(PrefixIndexKey { phrase, course_id: course.id }, fields);
+        }
+    });
+}
+
+// Removes the index entries of a course
+fn _unindex_course(course: &StoredCourse) {
+    CATEGORY_INDEX.with(|index| {
+        index.borrow_mut().remove(&CategoryIndexKey {
+            category_id: course.category_key(),
+            course_id: course.id,
+        })
+    });
+    CREATOR_INDEX.with(|index| {
+        index.borrow_mut().remove(&CreatorIndexKey {
+            creator: course.creator_address.clone(),
+            course_id: course.id,
+        })
+    });
+    FINGERPRINTS.with(|fingerprints| fingerprints.borrow_mut().remove(&course.id));
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for (token, _) in _search_tokens(course) {
+            index.remove(&SearchIndexKey { token, course_id: course.id });
+        }
+    });
+    PREFIX_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for (phrase, _) in _prefix_phrases(course) {
+            index.remove(&PrefixIndexKey { phrase, course_id: course.id });
+        }
+    });
+}
+
+// Returns the ids of the courses with every word in the search index, best matches first
+fn _search_index_matches(tokens: Vec<String>) -> Vec<CourseId> {
+    let mut scores: Option<HashMap<CourseId, u32>> = None;
+    for token in tokens {
+        let range = SearchIndexKey {
+            token: token.clone(),
+            course_id: CourseId(0),
+        }..=SearchIndexKey {
+            token,
+            course_id: CourseId(u64::MAX),
+        };
+        let matches: HashMap<CourseId, u32> = SEARCH_INDEX.with(|index| {
+            index.borrow().range(range).map(|(key, fields)| (key.course_id, _token_weight(fields))).collect()
+        });
+        let combined: HashMap<CourseId, u32> = match scores {
+            None => matches,
+            Some(scores) => scores
+                .into_iter()
+                .filter_map(|(id, score)| matches.get(&id).map(|weight| (id, score + weight)))
+                .collect(),
+        };
+        let done = combined.is_empty();
+        scores = Some(combined);
+        if done {
+            break;
+        }
+    }
+    let mut ranked: Vec<(CourseId, u32)> = scores.unwrap_or_default().into_iter().collect();
+    ranked.sort_by_key(|(id, score)| (Reverse(*score), *id));
+    ranked.into_iter().map(|(id, _)| id).collect()
+}
+
+// Splits a text into the lower case words kept in the search index
+fn _tokenize(text: &str) -> Vec<String> {
+    _fold(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| (MIN_TOKEN_LENGTH..=MAX_TOKEN_LENGTH).contains(&token.chars().count()))
+        .map(str::to_string)
+        .collect()
+}
+
+// Returns the words of a course with the fields they appear in
+fn _search_tokens(course: &StoredCourse) -> BTreeMap<String, u32> {
+    let mut tokens: BTreeMap<String, u32> = BTreeMap::new();
+    let tags = course.tag_pairs().into_iter().map(|(id, _)| (_resolve(id), TOKEN_IN_KEYWORD));
+    let fields = [
+        (course.title.clone(), TOKEN_IN_TITLE),
+        (_resolve(course.category_id), TOKEN_IN_CATEGORY),
+        (course.body.clone(), TOKEN_IN_BODY),
+    ];
+    for (text, field) in fields.into_iter().chain(tags) {
+        for token in _tokenize(&text) {
+            *tokens.entry(token).or_default() |= field;
+        }
+    }
+    tokens
+}
+
+// Returns the lower case tags and title of a course as kept in the prefix index,
+// with long titles cut to MAX_PHRASE_LENGTH characters
+fn _prefix_phrases(course: &StoredCourse) -> BTreeMap<String, u32> {
+    let mut phrases: BTreeMap<String, u32> = BTreeMap::new();
+    let tags = course.tag_pairs().into_iter().map(|(id, _)| (_resolve(id), TOKEN_IN_KEYWORD));
+    for (text, field) in std::iter::once((course.title.clone(), TOKEN_IN_TITLE)).chain(tags) {
+        let phrase: String = _fold(&text).chars().take(MAX_PHRASE_LENGTH).collect();
+        let phrase = phrase.trim_end().to_string();
+        if !phrase.is_empty() {
+            *phrases.entry(phrase).or_default() |= field;
+        }
+    }
+    phrases
+}
+
+// Returns up to limit of the keywords and titles starting with a folded prefix among the courses listed for a viewer
+fn _prefix_suggestions(viewer: &Viewer, prefix: &str, limit: usize) -> Vec<KeywordSuggestion> {
+    let start = PrefixIndexKey {
+        phrase: prefix.to_string(),
+        course_id: CourseId(0),
+    };
+    // Very short prefixes can match much of the index, so only the first entries are read
+    let entries: Vec<(PrefixIndexKey, u32)> = PREFIX_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.phrase.starts_with(prefix))
+            .take(MAX_SUGGESTION_SCAN)
+            .collect()
+    });
+    let mut suggestions: BTreeMap<String, KeywordSuggestion> = BTreeMap::new();
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        for (key, fields) in entries {
+            if !matches!(storage.get(&key.course_id), Some(course) if viewer.sees(&course)) {
+                continue;
+            }
+            let suggestion = suggestions.entry(key.phrase.clone()).or_insert(KeywordSuggestion {
+                text: key.phrase,
+                is_keyword: false,
+                course_count: 0,
+            });
+            suggestion.is_keyword |= fields & TOKEN_IN_KEYWORD != 0;
+            suggestion.course_count += 1;
+        }
+    });
+    let mut suggestions: Vec<KeywordSuggestion> = suggestions.into_values().collect();
+    suggestions.sort_by(|a, b| {
+        (Reverse(a.course_count), !a.is_keyword, &a.text).cmp(&(Reverse(b.course_count), !b.is_keyword, &b.text))
+    });
+    suggestions.truncate(limit);
+    suggestions
+}
+
+// Weight of a word match by the fields it was found in, so title matches rank above body matches
+fn _token_weight(fields: u32) -> u32 {
+    [(TOKEN_IN_TITLE, 8), (TOKEN_IN_KEYWORD, 4), (TOKEN_IN_CATEGORY, 4), (TOKEN_IN_BODY, 1)]
+        .iter()
+        .filter(|(field, _)| fields & field != 0)
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+// Rebuilds the indexes from the stored courses
+fn _rebuild_course_indexes() {
+    let courses: Vec<StoredCourse> = STORAGE.with(|storage| storage.borrow().iter().map(|(_, course)| course).collect());
+    for course in courses.iter() {
+        _index_course(course);
+    }
+}
+
+// Returns the ids of the courses in a category, using the category index
+fn _course_ids_by_category(category_id: u32) -> Vec<CourseId> {
+    let range = CategoryIndexKey {
+        category_id,
+        course_id: CourseId(0),
+    }..=CategoryIndexKey {
+        category_id,
+        course_id: CourseId(u64::MAX),
+    };
+    CATEGORY_INDEX.with(|index| index.borrow().range(range).map(|(key, _)| key.course_id).collect())
+}
+
+// Returns the courses pinned in a category, leaving out courses that were deleted or moved
+// to another category since they were pinned
+fn _category_pins(category_id: u32) -> Vec<CourseId> {
+    let pins = CATEGORY_PINS.with(|pins| pins.borrow().get(&category_id));
+    pins.map(|pins| pins.course_ids)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|id| STORAGE.with(|storage| storage.borrow().get(id)).is_some_and(|course| course.category_key() == category_id))
+        .collect()
+}
+
+// Returns the ids of the courses of a creator, using the creator index
+fn _course_ids_by_creator(address: &str) -> Vec<CourseId> {
+    let start = CreatorIndexKey {
+        creator: address.to_string(),
+        course_id: CourseId(0),
+    };
+    CREATOR_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.creator == address)
+            .map(|(key, _)| key.course_id)
+            .collect()
+    })
+}
+
+// Builds a page of courses out of a list of course ids
+fn _page_of_ids(course_ids: &[CourseId], page: Option<Page>) -> Result<CoursePage, Error> {
+    let page = _validate_page(page)?;
+    let course_ids = _shown_ids(course_ids);
+    let courses = course_ids
+        .iter()
+        .skip(page.offset as usize)
+        .take(page.limit as usize)
+        .filter_map(_get_course_)
+        .collect();
+    Ok(_course_page(courses, page.offset, course_ids.len() as u64))
+}
+
+// Returns up to limit of the courses listed for a viewer from an offset, in id order,
+// with the number of courses listed for them in all
+fn _courses_from(viewer: &Viewer, offset: u64, limit: u64) -> (Vec<Course>, u64) {
+    STORAGE.with(|storage| {
+        let mut courses = Vec::new();
+        let mut total_count: u64 = 0;
+        for (_, course) in storage.borrow().iter().filter(|(_, course)| viewer.sees(course)) {
+            if total_count >= offset && (courses.len() as u64) < limit {
+                courses.push(_to_course(course));
+            }
+            total_count += 1;
+        }
+        (courses, total_count)
+    })
+}
+
+// Returns up to limit of the courses listed for a viewer with an id after the cursor, with the id
+// of the last one when more courses follow it
+fn _courses_after(viewer: &Viewer, cursor: Option<CourseId>, limit: usize) -> (Vec<Course>, Option<CourseId>) {
+    let start = match cursor {
+        Some(CourseId(id)) => match id.checked_add(1) {
+            Some(next) => CourseId(next),
+            None => return (Vec::new(), None),
+        },
+        None => CourseId(0),
+    };
+    let mut courses = Vec::new();
+    let mut has_more = false;
+    STORAGE.with(|storage| {
+        for (_, course) in storage.borrow().range(start..).filter(|(_, course)| viewer.sees(course)) {
+            if courses.len() == limit {
+                has_more = true;
+                break;
+            }
+            courses.push(_to_course(course));
+        }
+    });
+    let next_cursor = if has_more { courses.last().map(|course| course.id) } else { None };
+    (courses, next_cursor)
+}
+
+// Returns the requested page, or the first page when none is given
+fn _validate_page(page: Option<Page>) -> Result<Page, Error> {
+    let page = page.unwrap_or(Page {
+        offset: 0,
+        limit: MAX_PAGE_SIZE,
+    });
+    if page.limit == 0 || page.limit > MAX_PAGE_SIZE {
+        return Err(Error::invalid_input(format!("The page limit must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    Ok(page)
+}
+
+// Stores a profiling sample if profiling is enabled, evicting the oldest one when full
+fn _record_profile(method: &str, instructions: u64) {
+    _record_usage(method, instructions);
+    let enabled = PROFILING_ENABLED.with(|profiling| *profiling.lock().unwrap());
+    if !enabled {
+        return;
+    }
+    PROFILE_SAMPLES.with(|samples| {
+        let mut samples = samples.lock().unwrap();
+        if samples.len() >= PROFILE_BUFFER_SIZE {
+            samples.pop_front();
+        }
+        samples.push_back(ProfileSample {
+            method: method.to_string(),
+            instructions,
+            timestamp: time(),
+        });
+    });
+}
+
+// Adds a call of a method to its usage of the day
+fn _record_usage(method: &str, instructions: u64) {
+    let key = UsageKey {
+        day: _today(),
+        method: method.to_string(),
+    };
+    METHOD_USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let mut today = usage.get(&key).unwrap_or_default();
+        today.calls += 1;
+        today.instructions += instructions;
+        today.max_instructions = today.max_instructions.max(instructions);
+        usage.insert(key, today);
+    });
+}
+
+// Estimates the cycles charged for a number of calls using the given instructions
+fn _estimated_cycles(calls: u64, instructions: u64) -> u64 {
+    instructions / 10 * CYCLES_PER_TEN_INSTRUCTIONS + calls * UPDATE_MESSAGE_CYCLES
+}
+
+// Removes the usage of days past the retention period
+fn _clear_old_usage() {
+    let before = _today().saturating_sub(USAGE_RETENTION_DAYS);
+    let expired: Vec<UsageKey> = METHOD_USAGE.with(|usage| {
+        usage
+            .borrow()
+            .iter()
+            .take_while(|(key, _)| key.day < before)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    METHOD_USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        for key in expired.iter() {
+            usage.remove(key);
+        }
+    });
+}
+
+// Checks if the address is the admin
+fn _is_admin(address: String) -> bool {
+    let admin_address = ADMIN_ADDRESS.with(|admin_address| {
+        admin_address.lock().unwrap().clone()
+    });
+
+    if let Some(admin) = &admin_address {
+        address == *admin
+    } else {
+        false
+    }
+}
+
+// Returns the current operating status of the canister
+fn _service_status() -> ServiceStatus {
+    if PAUSED_AT.with(|paused_at| paused_at.lock().unwrap().is_some()) {
+        ServiceStatus::Paused
+    } else if MAINTENANCE.with(|maintenance| maintenance.lock().unwrap().is_some()) {
+        ServiceStatus::ReadOnly
+    } else {
+        ServiceStatus::Ok
+    }
+}
+
+// Rejects update calls while the canister is paused or in maintenance, except from the admin.
+// Calls that are let through note when the caller was first seen, for the publishing requirements
+fn _ensure_running() -> Result<(), Error> {
+    let caller = api::caller();
+    if !_is_admin(caller.to_string()) {
+        if _service_status() == ServiceStatus::Paused {
+            return Err(Error::canister_paused("The canister is paused, please try again later".to_string()));
+        }
+        if let Some(notice) = get_maintenance_notice() {
+            return Err(Error::under_maintenance(notice.message, notice.expected_end));
+        }
+    }
+    _note_first_seen(&caller);
+    Ok(())
+}
+
+// Notes the first time a principal made an update call. The anonymous principal is shared, so it isn't noted
+fn _note_first_seen(caller: &candid::Principal) {
+    if *caller == candid::Principal::anonymous() {
+        return;
+    }
+    let key = UserKey(caller.to_string());
+    if !FIRST_SEEN.with(|first_seen| first_seen.borrow().contains_key(&key)) {
+        FIRST_SEEN.with(|first_seen| first_seen.borrow_mut().insert(key, time()));
+    }
+}
+
+// Ends the maintenance window once its expected end has passed
+fn _end_finished_maintenance() {
+    MAINTENANCE.with(|maintenance| {
+        let mut maintenance = maintenance.lock().unwrap();
+        if matches!(*maintenance, Some(ref notice) if notice.expected_end <= time()) {
+            *maintenance = None;
+        }
+    });
+}
+
+// Size of the heap memory of the canister
+#[cfg(target_arch = "wasm32")]
+fn _heap_memory_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn _heap_memory_bytes() -> u64 {
+    0
+}
+
+// Checks the API key of an HTTP request and counts the request against its rate limit
+fn _check_api_key(request: &HttpRequest) -> Result<(), HttpResponse> {
+    let key = request.headers.iter().find_map(|(name, value)| {
+        let name = name.to_ascii_lowercase();
+        if name == "x-api-key" {
+            Some(value.trim())
+        } else if name == "authorization" {
+            value.trim().strip_prefix("Bearer ").map(str::trim)
+        } else {
+            None
+        }
+    });
+    let Some(key) = key else {
+        return Err(_http_error(401, "An API key is required"));
+    };
+    let key_hash = Sha256::digest(key.as_bytes()).to_vec();
+    let api_key = API_KEYS.with(|keys| {
+        keys.borrow()
+            .iter()
+            .map(|(_, api_key)| api_key)
+            .find(|api_key| api_key.key_hash == key_hash && api_key.revoked_at.is_none())
+    });
+    let Some(mut api_key) = api_key else {
+        return Err(_http_error(401, "The API key is invalid or revoked"));
+    };
+    let now = time();
+    if now >= api_key.window_start + API_KEY_WINDOW {
+        api_key.window_start = now;
+        api_key.window_requests = 0;
+    }
+    if api_key.window_requests >= api_key.requests_per_minute {
+        let retry_after = (api_key.window_start + API_KEY_WINDOW - now).div_ceil(1_000_000_000);
+        let mut response = _http_error(429, "Too many requests");
+        response.headers.push(("Retry-After".to_string(), retry_after.to_string()));
+        return Err(response);
+    }
+    api_key.window_requests += 1;
+    api_key.last_used_at = Some(now);
+    API_KEYS.with(|keys| keys.borrow_mut().insert(api_key.id, api_key));
+    Ok(())
+}
+
+// Hashes the entries of a stable store in key order. Keys and values are prefixed with their length
+// so that different entries can't produce the same bytes
+fn _store_digest<K: BoundedStorable + Ord + Clone, V: BoundedStorable>(
+    name: &str,
+    store: &StableBTreeMap<K, V, Memory>,
+) -> StoreDigest {
+    let mut hasher = Sha256::new();
+    for (key, value) in store.iter() {
+        for bytes in [key.to_bytes(), value.to_bytes()] {
+            hasher.update((bytes.len() as u64).to_be_bytes());
+            hasher.update(&bytes);
+        }
+    }
+    StoreDigest {
+        name: name.to_string(),
+        entries: store.len(),
+        digest: hasher.finalize().to_vec(),
+    }
+}
+
+// Queues a course event for every approved subscriber and tries to send it right away
+fn _emit_course_event(kind: CourseEventKind, course_id: CourseId, creator_address: &str) {
+    let subscribers: Vec<String> = SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .iter()
+            .filter(|(_, subscriber)| subscriber.status == SubscriberStatus::Approved)
+            .map(|(key, _)| key.0)
+            .collect()
+    });
+    if subscribers.is_empty() {
+        return;
+    }
+    let sequence = EVENT_SEQUENCE
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment event sequence");
+    let event = CourseEvent {
+        sequence,
+        kind,
+        course_id,
+        creator_address: creator_address.to_string(),
+        occurred_at: time(),
+    };
+    EVENT_DELIVERIES.with(|deliveries| {
+        let mut deliveries = deliveries.borrow_mut();
+        let next_id = deliveries.iter().last().map_or(0, |(id, _)| id + 1);
+        for (id, subscriber) in (next_id..).zip(subscribers) {
+            let delivery = EventDelivery {
+                subscriber,
+                event: event.clone(),
+                attempts: 0,
+                next_attempt_at: 0,
+            };
+            deliveries.insert(id, delivery);
+        }
+    });
+    _deliver_course_events();
+}
+
+// Sends the queued course events that are due with one-way calls. Events that couldn't be sent
+// are tried again later with a growing delay, and dropped after MAX_EVENT_DELIVERY_ATTEMPTS.
+// One-way calls get no reply, so events are only retried when they couldn't be sent at all
+fn _deliver_course_events() {
+    let now = time();
+    let due: Vec<(u64, EventDelivery)> = EVENT_DELIVERIES.with(|deliveries| {
+        deliveries
+            .borrow()
+            .iter()
+            .filter(|(_, delivery)| delivery.next_attempt_at <= now)
+            .collect()
+    });
+    for (id, mut delivery) in due {
+        let key = UserKey(delivery.subscriber.clone());
+        let Some(mut subscriber) = SUBSCRIBERS.with(|subscribers| subscribers.borrow().get(&key)) else {
+            EVENT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().remove(&id));
+            continue;
+        };
+        let sent = match candid::Principal::from_text(&subscriber.canister) {
+            Ok(canister) => ic_cdk::notify(canister, &subscriber.method, (delivery.event.clone(),)).is_ok(),
+            Err(_) => false,
+        };
+        delivery.attempts += 1;
+        if sent || delivery.attempts >= MAX_EVENT_DELIVERY_ATTEMPTS {
+            if sent {
+                subscriber.delivered_count += 1;
+            } else {
+                subscriber.failed_count += 1;
+            }
+            SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().insert(key, subscriber));
+            EVENT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().remove(&id));
+        } else {
+            delivery.next_attempt_at = now + SCHEDULER_INTERVAL.as_nanos() as u64 * (1 << delivery.attempts);
+            EVENT_DELIVERIES.with(|deliveries| deliveries.borrow_mut().insert(id, delivery));
+        }
+    }
+}
+
+// Splits a request URL into its path and query string
+fn _split_url(url: &str) -> (&str, &str) {
+    url.split_once('?').unwrap_or((url, ""))
+}
+
+// Builds the card of a course the caller can see
+fn _course_embed(id: u64) -> Option<CourseEmbed> {
+    let course = _shown_ids(&[CourseId(id)]).first().and_then(_get_course_)?;
+    let course = _visible_courses(vec![course]).remove(0);
+    let feedback = _feedback_of(course.id);
+    let rating_count = feedback.len() as u64;
+    let site = EMBED_SITE.with(|site| site.lock().unwrap().clone());
+    Some(CourseEmbed {
+        id: course.id,
+        excerpt: course.body.chars().take(EMBED_EXCERPT_LENGTH).collect(),
+        creator_verified: get_creator_verification(course.creator_address.clone()).verified,
+        average_rating: (rating_count > 0).then(|| {
+            feedback.iter().map(|response| response.form.content_rating as u64).sum::<u64>() * 100 / rating_count
         }),
+        rating_count,
+        url: site.map(|site| format!("{}/courses/{}", site.url, course.id)),
+        title: course.title,
+        creator_name: course.creator_name,
+        category: course.category,
+    })
+}
+
+// Builds the card of a creator with courses the caller can see
+fn _creator_embed(address: &str) -> Option<CreatorEmbed> {
+    let shown = _shown_ids(&_course_ids_by_creator(address));
+    let latest = shown.last().and_then(_get_course_)?;
+    let site = EMBED_SITE.with(|site| site.lock().unwrap().clone());
+    Some(CreatorEmbed {
+        address: address.to_string(),
+        name: _visible_courses(vec![latest]).remove(0).creator_name,
+        verified: get_creator_verification(address.to_string()).verified,
+        courses: shown.len() as u64,
+        reputation: REPUTATION.with(|reputation| reputation.lock().unwrap().get(address).map(|reputation| reputation.score)),
+        url: site.map(|site| format!("{}/creators/{}", site.url, address)),
+    })
+}
+
+// Answers an oEmbed request for a course or creator page of the embed site
+fn _oembed(query: &str) -> HttpResponse {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| _percent_decode(value))
+    };
+    if matches!(param("format"), Some(format) if format != "json") {
+        return _http_error(501, "Only the json format is supported");
+    }
+    let Some(site) = EMBED_SITE.with(|site| site.lock().unwrap().clone()) else {
+        return _http_error(404, "Embedding is not set up");
+    };
+    let Some(url) = param("url") else {
+        return _http_error(400, "The url parameter is missing");
+    };
+    let page = url.strip_prefix(&site.url).unwrap_or_default().trim_end_matches('/');
+    let (title, author_name, author_address) = if let Some(id) = page.strip_prefix("/courses/") {
+        match id.parse::<u64>().ok().and_then(_course_embed) {
+            Some(embed) => (embed.title, embed.creator_name, _get_course_(&embed.id).map(|course| course.creator_address).unwrap_or_default()),
+            None => return _http_error(404, "Course not found"),
+        }
+    } else if let Some(address) = page.strip_prefix("/creators/") {
+        match _creator_embed(address) {
+            Some(embed) => (embed.name.clone(), embed.name, embed.address),
+            None => return _http_error(404, "Creator not found"),
+        }
+    } else {
+        return _http_error(404, "The url is not a course or creator page");
+    };
+    _http_embed(&OEmbed {
+        version: "1.0",
+        kind: "link",
+        title,
+        author_name,
+        author_url: format!("{}/creators/{}", site.url, author_address),
+        provider_name: site.name,
+        provider_url: site.url,
+        cache_age: EMBED_CACHE_SECONDS,
+    })
+}
+
+// Decodes a percent encoded query string value, with + standing for a space
+fn _percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Builds a JSON response embedding sites can fetch from the browser and cache
+fn _http_embed<T: serde::Serialize>(value: &T) -> HttpResponse {
+    let mut response = _http_json(value);
+    if response.status_code == 200 {
+        response.headers.push(("Access-Control-Allow-Origin".to_string(), "*".to_string()));
+        response.headers.push(("Cache-Control".to_string(), format!("public, max-age={}", EMBED_CACHE_SECONDS)));
+    }
+    response
+}
+
+// Builds a JSON HTTP response
+fn _http_json<T: serde::Serialize>(value: &T) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => HttpResponse {
+            status_code: 200,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body,
+            upgrade: None,
+        },
+        Err(_) => _http_error(500, "Could not encode the response"),
+    }
+}
+
+// Builds a plain text HTTP error response
+fn _http_error(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+        body: message.as_bytes().to_vec(),
+        upgrade: None,
+    }
+}
+
+// Encodes bytes as lower case hexadecimal
+fn _hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Returns the highest role of an address
+fn _role_of(address: &str) -> Role {
+    if _is_admin(address.to_string()) {
+        Role::Admin
+    } else if _is_authorized(address.to_string()) {
+        Role::Moderator
+    } else if !_course_ids_by_creator(address).is_empty() {
+        Role::Creator
+    } else {
+        Role::User
+    }
+}
+
+// Returns the ban of an address, unless there is none or it has expired
+fn _active_ban(address: &str) -> Option<BanRecord> {
+    BANS.with(|bans| bans.borrow().get(&UserKey(address.to_string()))).filter(|ban| _ban_in_effect(ban, time()))
+}
+
+// Checks if a ban has no expiry or hasn't expired yet
+fn _ban_in_effect(ban: &BanRecord, now: u64) -> bool {
+    !matches!(ban.expires_at, Some(expires_at) if expires_at <= now)
+}
+
+// Checks if the address is currently banned
+fn _is_banned(address: &str) -> bool {
+    _active_ban(address).is_some()
+}
+
+// Bans a user right away: deletes their courses and adds them to the banned list
+fn _apply_ban(address: String, reason: Option<String>, expires_at: Option<u64>, banned_by: String) -> Result<Vec<Course>, Error> {
+    // Delete the courses of the user, if any. Users without courses can be banned ahead of time
+    let courses = _delete_courses_of(&address);
+    _record_ban(address, reason, expires_at, banned_by, courses.len() as u64);
+    Ok(courses)
+}
+
+// Stores the ban of an address and records it in the moderator stats and the moderation log
+fn _record_ban(address: String, reason: Option<String>, expires_at: Option<u64>, banned_by: String, deletions: u64) {
+    let (address_copy, reason_copy) = (address.clone(), reason.clone());
+    //Add the address to banned list, replacing an earlier ban of the same address
+    let ban = BanRecord {
+        address: address.clone(),
+        reason,
+        banned_by: banned_by.clone(),
+        banned_at: time(),
+        expires_at,
+    };
+    BANS.with(|bans| bans.borrow_mut().insert(UserKey(address), ban));
+    _record_moderator_action(&banned_by, |stats| {
+        stats.bans += 1;
+        stats.deletions += deletions;
+    });
+    _log_moderation(
+        ModerationAction::UserBanned,
+        &banned_by,
+        address_copy,
+        format!("reason: {}; courses deleted: {}", reason_copy.unwrap_or_default(), deletions),
+    );
+}
+
+// Normalizes the optional reason given for a ban
+fn _normalize_ban_reason(reason: Option<String>) -> Result<Option<String>, Error> {
+    let reason = reason.map(|reason| _normalize_line("reason", &reason)).transpose()?.filter(|reason| !reason.is_empty());
+    if let Some(ref reason) = reason {
+        _validate_note(reason)?;
+    }
+    Ok(reason)
+}
+
+// Whether the address can be banned. The admin and the moderators can't be, and nobody can
+// be banned before the admin is set
+fn _is_bannable(address: &str) -> bool {
+    let admin_address = ADMIN_ADDRESS.with(|admin_address| admin_address.lock().unwrap().clone());
+    match admin_address {
+        Some(admin) if address != admin => {
+            !MODERATOR_ADDRESSES.with(|moderator_addresses| moderator_addresses.lock().unwrap().iter().any(|moderator| moderator == address))
+        }
+        _ => false,
+    }
+}
+
+// Whether a ban by the caller has to be confirmed by the admin
+fn _ban_requires_approval(caller: &str) -> bool {
+    !_is_admin(caller.to_string()) && BAN_APPROVAL_REQUIRED.with(|required| *required.lock().unwrap())
+}
+
+// Checks the number of addresses given to a bulk ban or unban
+fn _check_bulk_size(addresses: &[String]) -> Result<(), Error> {
+    if addresses.is_empty() {
+        return Err(Error::empty_fields("Please provide at least one address".to_string()).with_field("addresses"));
+    }
+    if addresses.len() > MAX_BULK_BAN {
+        return Err(Error::invalid_input(format!("At most {} addresses can be given at once", MAX_BULK_BAN)).with_field("addresses"));
+    }
+    Ok(())
+}
+
+// Deletes up to limit courses of a banned address and returns how many were deleted
+fn _delete_banned_courses(address: &str, limit: usize) -> usize {
+    let course_ids: Vec<CourseId> = _course_ids_by_creator(address).into_iter().take(limit).collect();
+    for id in course_ids.iter() {
+        do_remove(*id);
+    }
+    course_ids.len()
+}
+
+// Continues deleting the courses of addresses banned in bulk, within the batch budget.
+// Addresses that were un banned in the meantime keep their remaining courses
+fn _continue_ban_cleanups() {
+    let cleanups = BAN_CLEANUPS.with(|cleanups| std::mem::take(&mut *cleanups.lock().unwrap()));
+    let mut budget = BAN_CLEANUP_BATCH;
+    for address in cleanups {
+        let ban = BANS.with(|bans| bans.borrow().get(&UserKey(address.clone())));
+        let Some(ban) = ban else {
+            continue;
+        };
+        let deleted = _delete_banned_courses(&address, budget);
+        budget -= deleted;
+        if deleted > 0 {
+            _record_moderator_action(&ban.banned_by, |stats| stats.deletions += deleted as u64);
+        }
+        if !_course_ids_by_creator(&address).is_empty() {
+            BAN_CLEANUPS.with(|cleanups| cleanups.lock().unwrap().push(address));
+        }
+    }
+}
+
+// The address may have no courses yet, so make sure it is at least a principal
+fn _check_ban_address(address: &str) -> Result<(), Error> {
+    if candid::Principal::from_text(address).is_err() {
+        return Err(Error::invalid_input(format!("{} is not a valid principal", address)).with_field("address"));
+    }
+    Ok(())
+}
+
+// Proposes a ban for the admin to confirm, hiding the user's courses in the meantime
+fn _propose_ban(address: String, reason: Option<String>, expires_at: Option<u64>, proposed_by: String) -> Result<Vec<Course>, Error> {
+    if _pending_ban(&address).is_some() {
+        return Err(Error::invalid_input("A ban of this address is already waiting for the admin".to_string()));
+    }
+    let courses: Vec<Course> = _course_ids_by_creator(&address)
+        .iter()
+        .filter_map(_get_course_)
+        .collect();
+    for course in courses.iter() {
+        if let Some(stored) = _unstore_course(course.id) {
+            HELD_COURSES.with(|held| held.borrow_mut().insert(course.id, stored));
+        }
+    }
+    let now = time();
+    let pending = PendingBan {
+        address,
+        reason,
+        expires_at,
+        proposed_by,
+        proposed_at: now,
+        confirm_before: now + BAN_APPROVAL_WINDOW,
+        hidden_course_ids: courses.iter().map(|course| course.id).collect(),
+    };
+    if let Some(admin) = ADMIN_ADDRESS.with(|admin| admin.lock().unwrap().clone()) {
+        _notify(
+            &admin,
+            NotificationKind::BanProposed,
+            format!(
+                "{} proposed to ban {}. Confirm it with confirm_ban or reject it with reject_ban.",
+                pending.proposed_by, pending.address
+            ),
+        );
+    }
+    _log_moderation(
+        ModerationAction::BanProposed,
+        &pending.proposed_by,
+        pending.address.clone(),
+        format!("reason: {}; courses hidden: {}", pending.reason.clone().unwrap_or_default(), courses.len()),
+    );
+    PENDING_BANS.with(|pending_bans| pending_bans.lock().unwrap().push(pending));
+    Ok(courses)
+}
+
+// Returns the pending ban of an address
+fn _pending_ban(address: &str) -> Option<PendingBan> {
+    PENDING_BANS.with(|pending| {
+        pending
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|ban| ban.address == address)
+            .cloned()
+    })
+}
+
+// Removes the pending ban of an address
+fn _take_pending_ban(address: &str) -> Result<PendingBan, Error> {
+    PENDING_BANS.with(|pending| {
+        let mut pending = pending.lock().unwrap();
+        match pending.iter().position(|ban| ban.address == address) {
+            Some(pos) => Ok(pending.remove(pos)),
+            None => Err(Error::not_found("No pending ban found for the address".to_string())),
+        }
+    })
+}
+
+// Puts the courses hidden by a pending ban back into the course storage
+fn _release_held_courses(pending: &PendingBan) {
+    for id in pending.hidden_course_ids.iter() {
+        if let Some(stored) = HELD_COURSES.with(|held| held.borrow_mut().remove(id)) {
+            do_insert(&_to_course(stored));
+        }
+    }
+}
+
+// Drops the proposed bans the admin did not confirm in time and lets the proposers know
+fn _expire_pending_bans() {
+    let now = time();
+    let lapsed: Vec<PendingBan> = PENDING_BANS.with(|pending| {
+        let mut pending = pending.lock().unwrap();
+        let (lapsed, waiting) = pending.drain(..).partition(|ban| ban.confirm_before <= now);
+        *pending = waiting;
+        lapsed
+    });
+    for pending in lapsed.iter() {
+        _release_held_courses(pending);
+        _notify(
+            &pending.proposed_by,
+            NotificationKind::BanLapsed,
+            format!("Your ban of {} was not confirmed in time and has lapsed.", pending.address),
+        );
+    }
+}
+
+// Reminds the attendees of the live sessions starting within the reminder lead time
+fn _send_session_reminders() {
+    let now = time();
+    let due: Vec<Session> = SESSIONS.with(|sessions| {
+        sessions
+            .borrow()
+            .iter()
+            .map(|(_, session)| session)
+            .filter(|session| !session.reminded && session.starts_at > now && session.starts_at <= now + SESSION_REMINDER_LEAD)
+            .collect()
+    });
+    for mut session in due {
+        for learner in _rsvps_of(session.course_id, session.id) {
+            _notify(
+                &learner,
+                NotificationKind::SessionReminder,
+                format!("The session \"{}\" of course with id={} starts soon: {}", session.title, session.course_id, session.meeting_url),
+            );
+        }
+        session.reminded = true;
+        let key = SessionKey {
+            course_id: session.course_id,
+            id: session.id,
+        };
+        SESSIONS.with(|sessions| sessions.borrow_mut().insert(key, session));
+    }
+}
+
+// Sets the expiry of a course. A course expiring within the reminder lead gets no reminder
+fn _schedule_expiry(id: CourseId, expires_at: u64) {
+    let expiry = CourseExpiry {
+        expires_at,
+        reminded: expires_at <= time() + COURSE_EXPIRY_REMINDER_LEAD,
+    };
+    COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().insert(id, expiry));
+}
+
+// Archives the courses whose expiry has passed and reminds the creators of courses about to expire.
+// The data linked to an archived course is kept so it can be restored
+fn _archive_expired_courses() {
+    let now = time();
+    let due: Vec<(CourseId, CourseExpiry)> = COURSE_EXPIRY.with(|expiries| {
+        expiries
+            .borrow()
+            .iter()
+            .filter(|(_, expiry)| expiry.expires_at <= now || (!expiry.reminded && expiry.expires_at <= now + COURSE_EXPIRY_REMINDER_LEAD))
+            .collect()
+    });
+    for (id, mut expiry) in due {
+        // Courses taken down or queued since the expiry was set have nothing left to archive
+        let Some(course) = _get_course_(&id) else {
+            COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().remove(&id));
+            continue;
+        };
+        if expiry.expires_at <= now {
+            let archived = ArchivedCourse { course: course.clone(), archived_at: now };
+            // A course too large for the archive stays published rather than being lost
+            if _check_fits(&archived, "archived course").is_err() {
+                continue;
+            }
+            _unstore_course(id);
+            COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().remove(&id));
+            _notify(
+                &course.creator_address,
+                NotificationKind::CourseArchived,
+                format!(
+                    "Your course \"{}\" (id={}) expired and was archived. You can publish it again with restore_archived_course.",
+                    course.title, id
+                ),
+            );
+            ARCHIVED_COURSES.with(|stored| stored.borrow_mut().insert(id, archived));
+        } else {
+            _notify(
+                &course.creator_address,
+                NotificationKind::CourseExpiringSoon,
+                format!("Your course \"{}\" (id={}) expires soon and will then be archived.", course.title, id),
+            );
+            expiry.reminded = true;
+            COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().insert(id, expiry));
+        }
+    }
+}
+
+// Starts checking the links of the courses not checked for a day, unless the last batch is still running.
+// Only http and https links are checked: the attachment and the website contacts
+fn _sweep_links() {
+    if LINK_SWEEP_RUNNING.with(|running| *running.lock().unwrap()) {
+        return;
+    }
+    let now = time();
+    let due: Vec<CourseId> = STORAGE.with(|storage| {
+        LINK_CHECKS.with(|checks| {
+            let checks = checks.borrow();
+            storage
+                .borrow()
+                .iter()
+                .map(|(id, _)| id)
+                .filter(|id| checks.get(id).map_or(0, |check| check.checked_at) + LINK_CHECK_INTERVAL <= now)
+                .take(LINK_CHECK_BATCH)
+                .collect()
+        })
+    });
+    if due.is_empty() {
+        return;
+    }
+    let batch: Vec<(CourseId, Vec<String>)> = due
+        .into_iter()
+        .filter_map(|id| _get_course_(&id))
+        .map(|course| {
+            let websites = course.contact.iter().filter_map(|contact| match contact {
+                ContactMethod::Website(url) => Some(url.clone()),
+                _ => None,
+            });
+            let mut urls: Vec<String> = std::iter::once(course.attachment_url.clone())
+                .chain(websites)
+                .filter(|url| _validate_attachment_url(url).is_ok())
+                .collect();
+            urls.sort();
+            urls.dedup();
+            (course.id, urls)
+        })
+        .collect();
+    let running = LinkSweepGuard::new();
+    ic_cdk::spawn(async move {
+        for (id, urls) in batch {
+            let mut failures = Vec::new();
+            for url in urls {
+                if let Err(status) = _check_link(&url).await {
+                    failures.push((url, status));
+                }
+            }
+            _record_link_check(id, failures);
+        }
+        drop(running);
+    });
+}
+
+// Marks a link sweep as running until dropped. The sweep's future is also dropped when one of its
+// callbacks traps, so a failed sweep doesn't block the next ones
+struct LinkSweepGuard;
+
+impl LinkSweepGuard {
+    fn new() -> Self {
+        LINK_SWEEP_RUNNING.with(|running| *running.lock().unwrap() = true);
+        LinkSweepGuard
+    }
+}
+
+impl Drop for LinkSweepGuard {
+    fn drop(&mut self) {
+        LINK_SWEEP_RUNNING.with(|running| *running.lock().unwrap() = false);
+    }
+}
+
+// Checks that a link answers a HEAD request. Redirects and statuses that may only mean the server refuses
+// automated or HEAD requests count as reachable. Fails with the status, or none if there was no answer
+async fn _check_link(url: &str) -> Result<(), Option<u16>> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(LINK_CHECK_MAX_RESPONSE_BYTES),
+        method: HttpMethod::HEAD,
+        headers: vec![HttpHeader {
+            name: "User-Agent".to_string(),
+            value: "course-link-check".to_string(),
+        }],
+        body: None,
+        transform: Some(TransformContext::from_name("transform_link_check".to_string(), Vec::new())),
+    };
+    let (response,) = api::management_canister::http_request::http_request(request, LINK_CHECK_CYCLES)
+        .await
+        .map_err(|_| None)?;
+    let status = u16::try_from(&response.status.0).unwrap_or(0);
+    match status {
+        404 | 410 => Err(Some(status)),
+        500..=599 if status != 501 => Err(Some(status)),
+        _ => Ok(()),
+    }
+}
+
+// Stores the outcome of a course's link check. The creator is notified of links newly found dead,
+// and the course is archived once a link stayed dead for the whole grace period
+fn _record_link_check(id: CourseId, failures: Vec<(String, Option<u16>)>) {
+    let now = time();
+    // The course may have been deleted, taken down or archived while its links were checked
+    let Some(course) = _get_course_(&id) else {
+        LINK_CHECKS.with(|checks| checks.borrow_mut().remove(&id));
+        return;
+    };
+    let previous = LINK_CHECKS.with(|checks| checks.borrow().get(&id)).map_or(Vec::new(), |check| check.dead_links);
+    let dead_links: Vec<DeadLink> = failures
+        .into_iter()
+        .map(|(url, status)| DeadLink {
+            first_failed_at: previous.iter().find(|dead| dead.url == url).map_or(now, |dead| dead.first_failed_at),
+            url,
+            status,
+        })
+        .collect();
+
+    let archived = ArchivedCourse { course: course.clone(), archived_at: now };
+    // A course too large for the archive stays published, with its dead links recorded
+    let is_archivable = _check_fits(&archived, "archived course").is_ok();
+    if is_archivable && dead_links.iter().any(|dead| dead.first_failed_at + LINK_GRACE_PERIOD <= now) {
+        _unstore_course(id);
+        LINK_CHECKS.with(|checks| checks.borrow_mut().remove(&id));
+        let urls: Vec<&str> = dead_links.iter().map(|dead| dead.url.as_str()).collect();
+        _notify(
+            &course.creator_address,
+            NotificationKind::CourseArchived,
+            format!(
+                "Your course \"{}\" (id={}) was archived because these links stayed unreachable: {}. Fix them and publish it again with restore_archived_course.",
+                course.title,
+                id,
+                urls.join(", ")
+            ),
+        );
+        ARCHIVED_COURSES.with(|stored| stored.borrow_mut().insert(id, archived));
+        return;
+    }
+
+    let new_urls: Vec<&str> = dead_links
+        .iter()
+        .filter(|dead| dead.first_failed_at == now)
+        .map(|dead| dead.url.as_str())
+        .collect();
+    if !new_urls.is_empty() {
+        _notify(
+            &course.creator_address,
+            NotificationKind::LinksUnreachable,
+            format!(
+                "These links of your course \"{}\" (id={}) could not be reached: {}. Fix them within {} days or the course will be archived.",
+                course.title,
+                id,
+                new_urls.join(", "),
+                LINK_GRACE_PERIOD / NANOS_PER_DAY
+            ),
+        );
+    }
+    LINK_CHECKS.with(|checks| checks.borrow_mut().insert(id, LinkCheck { checked_at: now, dead_links }));
+}
+
+// Returns the courses with dead links, by course id, optionally only those of a creator
+fn _dead_link_reports(creator: Option<&str>) -> Vec<DeadLinkReport> {
+    let checks: Vec<(CourseId, LinkCheck)> = LINK_CHECKS.with(|checks| {
+        checks.borrow().iter().filter(|(_, check)| !check.dead_links.is_empty()).collect()
+    });
+    checks
+        .into_iter()
+        .filter_map(|(course_id, check)| {
+            let course = _get_course_(&course_id)?;
+            if matches!(creator, Some(creator) if course.creator_address != creator) {
+                return None;
+            }
+            Some(DeadLinkReport {
+                course_id,
+                title: course.title,
+                checked_at: check.checked_at,
+                archive_at: check.dead_links.iter().map(|dead| dead.first_failed_at).min().unwrap_or(check.checked_at) + LINK_GRACE_PERIOD,
+                dead_links: check.dead_links,
+            })
+        })
+        .collect()
+}
+
+// Adds an entry to the activity feed of a user. Entries made at the same time are kept in the order they were added
+fn _record_user_activity(address: &str, kind: UserActivityKind, course_id: CourseId) {
+    let mut key = UserActivityKey {
+        address: address.to_string(),
+        at: time(),
+    };
+    USER_ACTIVITY.with(|feed| {
+        let mut feed = feed.borrow_mut();
+        while feed.contains_key(&key) {
+            key.at += 1;
+        }
+        let activity = UserActivity { kind, course_id, at: key.at };
+        feed.insert(key, activity);
+    });
+}
+
+// Counts today as a learning day in the streak of a user, awarding a badge when the streak reaches a milestone
+fn _record_learning_day(address: &str) {
+    let today = _today();
+    let key = UserKey(address.to_string());
+    let mut streak = STREAKS.with(|streaks| streaks.borrow().get(&key)).unwrap_or_default();
+    if streak.current > 0 && streak.last_day == today {
+        return;
+    }
+    streak.current = if streak.current > 0 && streak.last_day + 1 == today { streak.current + 1 } else { 1 };
+    streak.longest = streak.longest.max(streak.current);
+    streak.last_day = today;
+    let earned = STREAK_BADGE_DAYS.contains(&streak.current) && !streak.badges.iter().any(|badge| badge.days == streak.current);
+    if earned {
+        streak.badges.push(StreakBadge {
+            days: streak.current,
+            awarded_at: time(),
+        });
+        _notify(
+            address,
+            NotificationKind::StreakBadgeEarned,
+            format!("You earned a badge for learning {} days in a row!", streak.current),
+        );
+    }
+    STREAKS.with(|streaks| streaks.borrow_mut().insert(key, streak));
+    _count_learning_day_in_goals(address, today);
+}
+
+// Returns the learning goals of a user in the order they were set
+fn _goals_of(address: &str) -> Vec<LearningGoal> {
+    let start = GoalKey {
+        address: address.to_string(),
+        id: 0,
+    };
+    GOALS.with(|goals| {
+        goals
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.address == address)
+            .map(|(_, goal)| goal)
+            .collect()
+    })
+}
+
+// Counts a learning day towards the weekly goals of a user, congratulating them when they reach a goal
+fn _count_learning_day_in_goals(address: &str, today: u64) {
+    for mut goal in _goals_of(address) {
+        let GoalTarget::WeeklyLearningDays(target) = goal.target else {
+            continue;
+        };
+        if goal.week != today / 7 {
+            goal.week = today / 7;
+            goal.days_this_week = 0;
+        }
+        goal.days_this_week += 1;
+        if goal.days_this_week == target {
+            _notify(
+                address,
+                NotificationKind::GoalAchieved,
+                format!("You reached your goal of learning {} days this week. Well done!", target),
+            );
+        }
+        let key = GoalKey {
+            address: address.to_string(),
+            id: goal.id,
+        };
+        GOALS.with(|goals| goals.borrow_mut().insert(key, goal));
+    }
+}
+
+// Checks the progress of the learning goals: marks course goals achieved or missed, and reminds
+// learners at most every few days of course goals in progress and weekly goals they are behind on
+fn _evaluate_learning_goals() {
+    let now = time();
+    let today = _today();
+    let goals: Vec<(GoalKey, LearningGoal)> = GOALS.with(|goals| {
+        goals
+            .borrow()
+            .iter()
+            .filter(|(_, goal)| goal.status == GoalStatus::Active)
+            .collect()
+    });
+    for (key, mut goal) in goals {
+        let reminder_due = !matches!(goal.last_reminded_at, Some(at) if at + GOAL_REMINDER_INTERVAL > now);
+        match goal.target {
+            GoalTarget::FinishCourse { course_id, by } => {
+                let enrollment = ENROLLMENTS.with(|enrollments| {
+                    enrollments.borrow().get(&EngagementKey {
+                        course_id,
+                        address: key.address.clone(),
+                    })
+                });
+                match enrollment {
+                    Some(enrollment) if enrollment.completed_at.is_some_and(|completed_at| completed_at <= by) => {
+                        goal.status = GoalStatus::Achieved;
+                        _notify(
+                            &key.address,
+                            NotificationKind::GoalAchieved,
+                            format!("You finished the course with id={} in time for your goal. Congratulations!", course_id),
+                        );
+                    }
+                    // Unenrolling or missing the date ends the goal
+                    _ if by <= now || enrollment.is_none() => goal.status = GoalStatus::Missed,
+                    _ if reminder_due => {
+                        goal.last_reminded_at = Some(now);
+                        _notify(
+                            &key.address,
+                            NotificationKind::GoalReminder,
+                            format!(
+                                "{} days left to finish the course with id={} as you planned. Keep going!",
+                                (by - now).div_ceil(NANOS_PER_DAY),
+                                course_id
+                            ),
+                        );
+                    }
+                    _ => continue,
+                }
+            }
+            GoalTarget::WeeklyLearningDays(target) => {
+                if goal.week != today / 7 {
+                    goal.week = today / 7;
+                    goal.days_this_week = 0;
+                }
+                let days_left = 7 - today % 7;
+                let behind = goal.days_this_week < target && (target - goal.days_this_week) as u64 + 1 >= days_left;
+                if !behind || !reminder_due {
+                    continue;
+                }
+                goal.last_reminded_at = Some(now);
+                _notify(
+                    &key.address,
+                    NotificationKind::GoalReminder,
+                    format!(
+                        "You learned on {} of the {} days you planned this week. There is still time to reach your goal!",
+                        goal.days_this_week, target
+                    ),
+                );
+            }
+        }
+        GOALS.with(|goals| goals.borrow_mut().insert(key, goal));
+    }
+}
+
+// Removes the bans that expired more than BAN_RETENTION ago. Until then they are listed as expired
+fn _clear_expired_bans() {
+    let now = time();
+    BANS.with(|bans| {
+        _remove_matching(&mut bans.borrow_mut(), usize::MAX, |_, ban| {
+            matches!(ban.expires_at, Some(expires_at) if expires_at + BAN_RETENTION <= now)
+        })
+    });
+}
+
+// Checks if the caller is either the admin or a moderator
+fn _is_authorized(address: String) -> bool {
+    let admin_address = ADMIN_ADDRESS.with(|admin_address| {
+        admin_address.lock().unwrap().clone()
+    });
+    if let Some(admin) = &admin_address {
+        if address == *admin {
+            true
+        } else {
+            // Check if the caller is one of the moderators
+            let moderators = MODERATOR_ADDRESSES.with(|moderator_addresses| {
+                moderator_addresses.lock().unwrap().clone()
+            });
+            moderators.contains(&address.to_string())
+        }
+    } else {
+        false
+    }
+}
+
+// Checks if the caller is either the creator of the id, or the admin or a moderator
+fn _is_allowed(id: CourseId, caller: String) -> bool {
+    let course = STORAGE.with(|service| service.borrow().get(&id)).unwrap();
+    // Check if the caller is the creator of the course
+    if course.creator_address == caller {
+        true
+    } else {
+        // Check if the caller is the admin or a moderator of the course's category
+        _moderates_category(&caller, &_resolve(course.category_id))
+    }
+}
+
+// Returns the categories a moderator is restricted to, None when they moderate every category
+fn _moderator_categories(address: &str) -> Option<Vec<String>> {
+    MODERATOR_CATEGORIES.with(|categories| categories.lock().unwrap().get(address).cloned())
+}
+
+// Checks if the address can moderate courses in a category: the admin and unrestricted
+// moderators moderate every category, restricted moderators only their own
+fn _moderates_category(address: &str, category: &str) -> bool {
+    if _is_admin(address.to_string()) {
+        return true;
+    }
+    if !_is_authorized(address.to_string()) {
+        return false;
+    }
+    match _moderator_categories(address) {
+        Some(categories) => categories.contains(&_canonical_tag(&_fold(category))),
+        None => true,
+    }
+}
+
+// Returns an error if the caller can't moderate courses in the category
+fn _ensure_moderates(caller: &str, category: &str) -> Result<(), Error> {
+    if !_moderates_category(caller, category) {
+        return Err(Error::unauthorized(format!("You can only moderate courses in your categories, not in {}", category)));
+    }
+    Ok(())
+}
+
+// Returns an error if the caller is a restricted moderator and the address has courses outside
+// their categories. Restricted moderators can only ban creators of courses in their categories
+fn _ensure_may_ban(caller: &str, address: &str) -> Result<(), Error> {
+    if _is_admin(caller.to_string()) || _moderator_categories(caller).is_none() {
+        return Ok(());
+    }
+    let courses: Vec<Course> = _course_ids_by_creator(address).iter().filter_map(_get_course_).collect();
+    if courses.is_empty() {
+        return Err(Error::unauthorized("You can only ban creators of courses in your categories".to_string()));
+    }
+    for course in courses.iter() {
+        _ensure_moderates(caller, &course.category)?;
+    }
+    Ok(())
+}
+
+// Returns an error if the caller is a restricted moderator who may not lift the ban of the address.
+// They can lift bans they imposed themselves, and bans of creators they could ban
+fn _ensure_may_unban(caller: &str, address: &str) -> Result<(), Error> {
+    let banned_by = BANS.with(|bans| bans.borrow().get(&UserKey(address.to_string()))).map(|ban| ban.banned_by);
+    if banned_by.as_deref() == Some(caller) {
+        return Ok(());
+    }
+    _ensure_may_ban(caller, address)
+        .map_err(|_| Error::unauthorized("You can only lift bans of creators of courses in your categories".to_string()))
+}
+
+// Returns an error if there is no course with the given id
+fn _ensure_course_exists(id: CourseId) -> Result<(), Error> {
+    if STORAGE.with(|service| service.borrow().contains_key(&id)) {
+        Ok(())
+    } else {
+        Err(_course_not_found(id))
+    }
+}
+
+// Returns the current day number
+fn _today() -> u64 {
+    time() / NANOS_PER_DAY
+}
+
+// Range of activity keys of a course starting from the given day
+fn _activity_range(course_id: CourseId, from_day: u64) -> std::ops::RangeInclusive<ActivityKey> {
+    ActivityKey { course_id, day: from_day }..=ActivityKey { course_id, day: u64::MAX }
+}
+
+// Updates today's activity counters of a course
+fn _bump_activity(course_id: CourseId, update: impl FnOnce(&mut ActivityCounts)) {
+    let key = ActivityKey { course_id, day: _today() };
+    ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        let mut counts = activity.get(&key).unwrap_or_default();
+        update(&mut counts);
+        activity.insert(key, counts);
+    });
+}
+
+// Updates today's completion and rating counters of a course
+fn _bump_outcomes(course_id: CourseId, update: impl FnOnce(&mut OutcomeCounts)) {
+    let key = ActivityKey { course_id, day: _today() };
+    OUTCOMES.with(|outcomes| {
+        let mut outcomes = outcomes.borrow_mut();
+        let mut counts = outcomes.get(&key).unwrap_or_default();
+        update(&mut counts);
+        outcomes.insert(key, counts);
+    });
+}
+
+// Removes the likes, enrollments and activity of a deleted course
+fn _remove_course_data(course_id: CourseId) {
+    for course_label in _labels_of(course_id) {
+        COURSE_LABELS.with(|labels| labels.borrow_mut().remove(&CourseLabelKey { label: course_label.label, course_id }));
+    }
+    ENDORSEMENTS.with(|endorsements| endorsements.borrow_mut().remove(&course_id));
+    COURSE_EXPIRY.with(|expiries| expiries.borrow_mut().remove(&course_id));
+    LINK_CHECKS.with(|checks| checks.borrow_mut().remove(&course_id));
+    MODERATION_QUEUE.with(|queue| queue.borrow_mut().remove(&course_id));
+    POSSIBLE_DUPLICATES.with(|duplicates| duplicates.borrow_mut().remove(&course_id));
+    let faq_items = _faq_of(course_id);
+    FAQ.with(|faq| {
+        let mut faq = faq.borrow_mut();
+        for item in faq_items {
+            faq.remove(&FaqKey { course_id, id: item.id });
+        }
+    });
+    for assignment in _assignments_of(course_id) {
+        ASSIGNMENTS.with(|assignments| assignments.borrow_mut().remove(&AssignmentKey { course_id, id: assignment.id }));
+        _remove_submissions(course_id, assignment.id);
+    }
+    for cohort in _cohorts_of(course_id) {
+        COHORTS.with(|cohorts| cohorts.borrow_mut().remove(&CohortKey { course_id, id: cohort.id }));
+        let waitlist = _waitlist_of(course_id, cohort.id);
+        WAITLISTS.with(|waitlists| {
+            let mut waitlists = waitlists.borrow_mut();
+            for (key, _) in waitlist {
+                waitlists.remove(&key);
+            }
+        });
+    }
+    for session in _sessions_of(course_id) {
+        SESSIONS.with(|sessions| sessions.borrow_mut().remove(&SessionKey { course_id, id: session.id }));
+        _remove_rsvps(course_id, session.id);
+        _remove_attendance(course_id, session.id);
+    }
+    for poll in _polls_of(course_id) {
+        POLLS.with(|polls| polls.borrow_mut().remove(&PollKey { course_id, id: poll.id }));
+        _remove_poll_votes(course_id, poll.id);
+    }
+    let announcements = _announcements_of(course_id);
+    ANNOUNCEMENTS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for announcement in announcements {
+            stored.remove(&AnnouncementKey {
+                course_id,
+                id: announcement.id,
+            });
+        }
+    });
+    for comment in _comments_of(course_id) {
+        _remove_comment(course_id, comment.id);
+    }
+    let edits = _course_edits_of(course_id);
+    COURSE_EDITS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for edit in edits {
+            stored.remove(&CourseEditKey { course_id, id: edit.id });
+        }
+    });
+    let entries = _changelog_of(course_id);
+    CHANGELOG.with(|changelog| {
+        let mut changelog = changelog.borrow_mut();
+        for entry in entries {
+            changelog.remove(&ChangelogKey { course_id, id: entry.id });
+        }
+    });
+    let translations = _translations_of(course_id);
+    TRANSLATIONS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for (key, _) in translations {
+            stored.remove(&key);
+        }
+    });
+    let start = EngagementKey { course_id, address: String::new() };
+    LIKES.with(|likes| {
+        let mut likes = likes.borrow_mut();
+        let keys: Vec<EngagementKey> = likes
+            .range(start.clone()..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            likes.remove(&key);
+        }
+    });
+    ENROLLMENTS.with(|enrollments| {
+        let mut enrollments = enrollments.borrow_mut();
+        let keys: Vec<EngagementKey> = enrollments
+            .range(start.clone()..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            enrollments.remove(&key);
+        }
+    });
+    FEEDBACK.with(|feedback| {
+        let mut feedback = feedback.borrow_mut();
+        let keys: Vec<EngagementKey> = feedback
+            .range(start.clone()..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            feedback.remove(&key);
+        }
+    });
+    ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        let keys: Vec<ActivityKey> = activity
+            .range(_activity_range(course_id, 0))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            activity.remove(&key);
+        }
+    });
+    OUTCOMES.with(|outcomes| {
+        let mut outcomes = outcomes.borrow_mut();
+        let keys: Vec<ActivityKey> = outcomes
+            .range(_activity_range(course_id, 0))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            outcomes.remove(&key);
+        }
+    });
+}
+
+// Returns the translations of a course
+fn _translations_of(course_id: CourseId) -> Vec<(TranslationKey, Translation)> {
+    let start = TranslationKey {
+        course_id,
+        language: String::new(),
+    };
+    TRANSLATIONS.with(|translations| {
+        translations
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .collect()
+    })
+}
+
+// Returns the announcements of a course, oldest first
+fn _announcements_of(course_id: CourseId) -> Vec<Announcement> {
+    let start = AnnouncementKey { course_id, id: 0 };
+    ANNOUNCEMENTS.with(|announcements| {
+        announcements
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, announcement)| announcement)
+            .collect()
+    })
+}
+
+// Returns the comments on a course, oldest first
+fn _comments_of(course_id: CourseId) -> Vec<Comment> {
+    COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .range(CommentKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, comment)| comment)
+            .collect()
+    })
+}
+
+// Returns the staff notes about a subject, oldest first
+fn _staff_notes_of(subject: &NoteSubject) -> Vec<StaffNote> {
+    STAFF_NOTES.with(|notes| {
+        notes
+            .borrow()
+            .range(StaffNoteKey { subject: subject.clone(), id: 0 }..)
+            .take_while(|(key, _)| key.subject == *subject)
+            .map(|(_, note)| note)
+            .collect()
+    })
+}
+
+// Returns the triage labels of a course
+fn _labels_of(course_id: CourseId) -> Vec<CourseLabel> {
+    [StaffLabel::NeedsReview, StaffLabel::CopyrightQuestion, StaffLabel::QualityConcern]
+        .into_iter()
+        .filter_map(|label| COURSE_LABELS.with(|labels| labels.borrow().get(&CourseLabelKey { label, course_id })))
+        .collect()
+}
+
+// Returns a comment on a course
+fn _get_comment(course_id: CourseId, comment_id: u64) -> Result<Comment, Error> {
+    COMMENTS
+        .with(|comments| comments.borrow().get(&CommentKey { course_id, id: comment_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no comment with id={}", course_id, comment_id)))
+}
+
+// Returns the previous versions of a comment, oldest first
+fn _comment_versions_of(course_id: CourseId, comment_id: u64) -> Vec<CommentVersion> {
+    let start = CommentVersionKey {
+        course_id,
+        comment_id,
+        version: 0,
+    };
+    COMMENT_VERSIONS.with(|versions| {
+        versions
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.comment_id == comment_id)
+            .map(|(_, version)| version)
+            .collect()
+    })
+}
+
+// Removes a comment along with its previous versions
+fn _remove_comment(course_id: CourseId, comment_id: u64) {
+    COMMENTS.with(|comments| comments.borrow_mut().remove(&CommentKey { course_id, id: comment_id }));
+    let count = _comment_versions_of(course_id, comment_id).len() as u32;
+    COMMENT_VERSIONS.with(|versions| {
+        let mut versions = versions.borrow_mut();
+        for version in 0..count {
+            versions.remove(&CommentVersionKey {
+                course_id,
+                comment_id,
+                version,
+            });
+        }
+    });
+}
+
+// Normalizes the text of a comment and checks it is filled in, short enough and free of blocked terms
+fn _validate_comment(body: &str) -> Result<String, Error> {
+    let body = _normalize_body(body)?;
+    if body.is_empty() {
+        return Err(Error::empty_fields("A comment can't be empty".to_string()));
+    }
+    if body.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(Error::invalid_input(format!("Comments can be at most {} characters long", MAX_COMMENT_LENGTH)));
+    }
+    let matched_terms = _blocked_terms_in(&body);
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(format!("The comment contains blocked terms: {}", matched_terms.join(", "))));
+    }
+    Ok(body)
+}
+
+// Returns the edits of a course, oldest first
+fn _course_edits_of(course_id: CourseId) -> Vec<CourseEdit> {
+    COURSE_EDITS.with(|edits| {
+        edits
+            .borrow()
+            .range(CourseEditKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, edit)| edit)
+            .collect()
+    })
+}
+
+// Records the fields an update changed in the edit history of the course. Updates that change nothing are skipped
+fn _record_course_edit(previous: &Course, course: &Course, editor: String) {
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, old: String, new: String| {
+        if old != new {
+            changes.push(FieldChange {
+                field: field.to_string(),
+                old_excerpt: old.chars().take(EDIT_EXCERPT_LENGTH).collect(),
+                old_hash: Sha256::digest(old.as_bytes()).to_vec(),
+            });
+        }
+    };
+    fn json<T: serde::Serialize>(value: &T) -> String {
+        serde_json::to_string(value).unwrap_or_default()
+    }
+    compare("title", previous.title.clone(), course.title.clone());
+    compare("creator_name", previous.creator_name.clone(), course.creator_name.clone());
+    compare("body", previous.body.clone(), course.body.clone());
+    compare("attachment_url", previous.attachment_url.clone(), course.attachment_url.clone());
+    compare("keyword", previous.keyword.clone(), course.keyword.clone());
+    compare("tags", json(&previous.tags), json(&course.tags));
+    compare("category", previous.category.clone(), course.category.clone());
+    compare("contact", json(&previous.contact), json(&course.contact));
+    compare("content_rating", json(&previous.content_rating), json(&course.content_rating));
+    compare("accessibility", json(&previous.accessibility), json(&course.accessibility));
+    compare("regions", json(&previous.regions), json(&course.regions));
+    if changes.is_empty() {
+        return;
+    }
+    let edit = CourseEdit {
+        id: _course_edits_of(course.id).last().map_or(0, |edit| edit.id + 1),
+        course_id: course.id,
+        editor,
+        edited_at: time(),
+        changes,
+    };
+    let key = CourseEditKey {
+        course_id: course.id,
+        id: edit.id,
+    };
+    COURSE_EDITS.with(|edits| edits.borrow_mut().insert(key, edit));
+}
+
+// Returns the changelog entries of a course, oldest first
+fn _changelog_of(course_id: CourseId) -> Vec<ChangelogEntry> {
+    CHANGELOG.with(|changelog| {
+        changelog
+            .borrow()
+            .range(ChangelogKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+// Returns the FAQ items of a course by ascending order
+fn _faq_of(course_id: CourseId) -> Vec<FaqItem> {
+    let mut items: Vec<FaqItem> = FAQ.with(|faq| {
+        faq.borrow()
+            .range(FaqKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, item)| item)
+            .collect()
+    });
+    items.sort_by_key(|item| (item.order, item.id));
+    items
+}
+
+// Checks that the course exists and the caller may edit its FAQ
+fn _ensure_faq_editor(id: CourseId) -> Result<(), Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(format!("You are not authorized to edit the FAQ of course with id={}", id)));
+    }
+    Ok(())
+}
+
+// Normalizes and checks the question and answer of an FAQ item
+fn _normalize_faq(payload: &FaqPayLoad) -> Result<(String, String), Error> {
+    let question = _normalize_line("question", &payload.question)?;
+    let answer = _normalize_body(&payload.answer)?;
+    if question.is_empty() || answer.is_empty() {
+        return Err(Error::empty_fields("Please fill in the question and the answer".to_string()));
+    }
+    if question.chars().count() > MAX_FAQ_QUESTION_LENGTH || answer.chars().count() > MAX_FAQ_ANSWER_LENGTH {
+        return Err(Error::invalid_input(
+            format!(
+            "FAQ questions can be at most {} characters long and answers at most {}",
+            MAX_FAQ_QUESTION_LENGTH, MAX_FAQ_ANSWER_LENGTH
+        ),
+        ));
+    }
+    let matched_terms = _blocked_terms_in(&format!("{}\n{}", question, answer));
+    if !matched_terms.is_empty() {
+        return Err(Error::invalid_input(format!("The FAQ item contains blocked terms: {}", matched_terms.join(", "))));
+    }
+    Ok((question, answer))
+}
+
+// Returns the polls of a course, oldest first
+fn _polls_of(course_id: CourseId) -> Vec<Poll> {
+    POLLS.with(|polls| {
+        polls
+            .borrow()
+            .range(PollKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, poll)| poll)
+            .collect()
+    })
+}
+
+// Retrieves a poll of a course
+fn _get_poll(course_id: CourseId, poll_id: u64) -> Result<Poll, Error> {
+    POLLS
+        .with(|polls| polls.borrow().get(&PollKey { course_id, id: poll_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no poll with id={}", course_id, poll_id)))
+}
+
+// Checks that the course exists and the caller may manage its polls
+fn _ensure_poll_manager(id: CourseId) -> Result<(), Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(format!("You are not authorized to manage the polls of course with id={}", id)));
+    }
+    Ok(())
+}
+
+// Removes the votes cast in a poll
+fn _remove_poll_votes(course_id: CourseId, poll_id: u64) {
+    let start = PollVoteKey {
+        course_id,
+        poll_id,
+        voter: String::new(),
+    };
+    POLL_VOTES.with(|votes| {
+        let mut votes = votes.borrow_mut();
+        let keys: Vec<PollVoteKey> = votes
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.poll_id == poll_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            votes.remove(&key);
+        }
+    });
+}
+
+// Returns the live sessions of a course in the order they were scheduled
+fn _sessions_of(course_id: CourseId) -> Vec<Session> {
+    SESSIONS.with(|sessions| {
+        sessions
+            .borrow()
+            .range(SessionKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, session)| session)
+            .collect()
+    })
+}
+
+// Retrieves a live session of a course
+fn _get_session(course_id: CourseId, session_id: u64) -> Result<Session, Error> {
+    SESSIONS
+        .with(|sessions| sessions.borrow().get(&SessionKey { course_id, id: session_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no session with id={}", course_id, session_id)))
+}
+
+// Checks that a time zone has a short name made of letters, digits and /_+- and a real UTC offset
+fn _normalize_timezone(timezone: &TimeZone) -> Result<TimeZone, Error> {
+    let name = timezone.name.trim();
+    let valid_name = !name.is_empty()
+        && name.len() <= MAX_TIMEZONE_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "/_+-".contains(c));
+    if !valid_name {
+        return Err(Error::invalid_input(
+            format!("The time zone must be a name such as Europe/Berlin of at most {} characters", MAX_TIMEZONE_NAME_LENGTH),
+        ));
+    }
+    if !UTC_OFFSET_MINUTES.contains(&timezone.utc_offset_minutes) {
+        return Err(Error::invalid_input("The UTC offset must be between -720 and 840 minutes".to_string()));
+    }
+    Ok(TimeZone {
+        name: name.to_string(),
+        utc_offset_minutes: timezone.utc_offset_minutes,
+    })
+}
+
+// Returns the start and end of a session or cohort in UTC and in the time zone it was scheduled in
+fn _schedule(starts_at: u64, ends_at: u64, timezone: &Option<TimeZone>) -> Schedule {
+    let offset = timezone.as_ref().map_or(0, |timezone| timezone.utc_offset_minutes);
+    Schedule {
+        starts_at: LocalTime {
+            utc: starts_at,
+            local: _format_rfc3339(starts_at, offset),
+        },
+        ends_at: LocalTime {
+            utc: ends_at,
+            local: _format_rfc3339(ends_at, offset),
+        },
+    }
+}
+
+// Formats nanoseconds since the epoch as an RFC 3339 time at the given UTC offset, such as 2024-03-01T14:30:00+01:00
+fn _format_rfc3339(nanos: u64, utc_offset_minutes: i32) -> String {
+    let seconds = (nanos / 1_000_000_000) as i64 + utc_offset_minutes as i64 * 60;
+    let days = seconds.div_euclid(86_400);
+    let second_of_day = seconds.rem_euclid(86_400);
+    // Converts days since the epoch to a civil date (Howard Hinnant's days_from_civil inverse)
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    let offset = if utc_offset_minutes == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if utc_offset_minutes < 0 { '-' } else { '+' };
+        let minutes = utc_offset_minutes.abs();
+        format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60,
+        offset
+    )
+}
+
+// Checks that the course exists and the caller may manage its sessions
+fn _ensure_session_manager(id: CourseId) -> Result<(), Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to manage the sessions of course with id={}", id),
+        ));
+    }
+    Ok(())
+}
+
+// Returns the learners attending a live session
+fn _rsvps_of(course_id: CourseId, session_id: u64) -> Vec<String> {
+    let start = RsvpKey {
+        course_id,
+        session_id,
+        learner: String::new(),
+    };
+    RSVPS.with(|rsvps| {
+        rsvps
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.session_id == session_id)
+            .map(|(key, _)| key.learner)
+            .collect()
+    })
+}
+
+// Removes the RSVPs of a live session, returning the learners who were attending
+fn _remove_rsvps(course_id: CourseId, session_id: u64) -> Vec<String> {
+    let learners = _rsvps_of(course_id, session_id);
+    RSVPS.with(|rsvps| {
+        let mut rsvps = rsvps.borrow_mut();
+        for learner in learners.iter() {
+            rsvps.remove(&RsvpKey {
+                course_id,
+                session_id,
+                learner: learner.clone(),
+            });
+        }
+    });
+    learners
+}
+
+// Returns the attendance records of a live session
+fn _attendance_of(course_id: CourseId, session_id: u64) -> Vec<AttendanceRecord> {
+    let start = AttendanceKey {
+        course_id,
+        session_id,
+        learner: String::new(),
+    };
+    ATTENDANCE.with(|attendance| {
+        attendance
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.session_id == session_id)
+            .map(|(_, record)| record)
+            .collect()
+    })
+}
+
+// Removes the attendance records of a live session
+fn _remove_attendance(course_id: CourseId, session_id: u64) {
+    let records = _attendance_of(course_id, session_id);
+    ATTENDANCE.with(|attendance| {
+        let mut attendance = attendance.borrow_mut();
+        for record in records {
+            attendance.remove(&AttendanceKey {
+                course_id,
+                session_id,
+                learner: record.learner,
+            });
+        }
+    });
+}
+
+// Collects a learner's attendance of the sessions of a course, the basis for completion
+// criteria that require attending live sessions
+fn _attendance_history(course_id: CourseId, learner: &str) -> AttendanceHistory {
+    let now = time();
+    let sessions = _sessions_of(course_id);
+    let records = sessions
+        .iter()
+        .filter_map(|session| {
+            let key = AttendanceKey {
+                course_id,
+                session_id: session.id,
+                learner: learner.to_string(),
+            };
+            ATTENDANCE.with(|attendance| attendance.borrow().get(&key))
+        })
+        .collect();
+    AttendanceHistory {
+        learner: learner.to_string(),
+        records,
+        sessions_held: sessions.iter().filter(|session| session.starts_at <= now).count() as u32,
+    }
+}
+
+// Returns the feedback on a course
+fn _feedback_of(course_id: CourseId) -> Vec<FeedbackResponse> {
+    let start = EngagementKey {
+        course_id,
+        address: String::new(),
+    };
+    FEEDBACK.with(|feedback| {
+        feedback
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, response)| response)
+            .collect()
+    })
+}
+
+// Checks that the caller created the course, for data only shown to its creator such as feedback and analytics
+fn _ensure_creator(id: CourseId) -> Result<(), Error> {
+    let course = _get_course_(&id).ok_or(_course_not_found(id))?;
+    if course.creator_address != api::caller().to_string() {
+        return Err(Error::unauthorized(format!("Only the creator of the course with id={} can access", id)));
+    }
+    Ok(())
+}
+
+// Returns the assignments of a course in the order they were created
+fn _assignments_of(course_id: CourseId) -> Vec<Assignment> {
+    ASSIGNMENTS.with(|assignments| {
+        assignments
+            .borrow()
+            .range(AssignmentKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, assignment)| assignment)
+            .collect()
+    })
+}
+
+// Retrieves an assignment of a course
+fn _get_assignment(course_id: CourseId, assignment_id: u64) -> Result<Assignment, Error> {
+    ASSIGNMENTS
+        .with(|assignments| assignments.borrow().get(&AssignmentKey { course_id, id: assignment_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no assignment with id={}", course_id, assignment_id)))
+}
+
+// Checks that the course exists and the caller may manage its assignments
+fn _ensure_assignment_manager(id: CourseId) -> Result<(), Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to manage the assignments of course with id={}", id),
+        ));
+    }
+    Ok(())
+}
+
+// Returns the submissions to an assignment
+fn _submissions_of(course_id: CourseId, assignment_id: u64) -> Vec<Submission> {
+    let start = SubmissionKey {
+        course_id,
+        assignment_id,
+        learner: String::new(),
+    };
+    SUBMISSIONS.with(|submissions| {
+        submissions
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.assignment_id == assignment_id)
+            .map(|(_, submission)| submission)
+            .collect()
+    })
+}
+
+// Removes the submissions to an assignment
+fn _remove_submissions(course_id: CourseId, assignment_id: u64) {
+    let submissions = _submissions_of(course_id, assignment_id);
+    SUBMISSIONS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for submission in submissions {
+            stored.remove(&SubmissionKey {
+                course_id,
+                assignment_id,
+                learner: submission.learner,
+            });
+        }
+    });
+}
+
+// Checks whether a user is enrolled in a course
+fn _is_enrolled(course_id: CourseId, address: &str) -> bool {
+    let key = EngagementKey {
+        course_id,
+        address: address.to_string(),
+    };
+    ENROLLMENTS.with(|enrollments| enrollments.borrow().contains_key(&key))
+}
+
+// Returns the cohorts of a course in the order they were created
+fn _cohorts_of(course_id: CourseId) -> Vec<Cohort> {
+    COHORTS.with(|cohorts| {
+        cohorts
+            .borrow()
+            .range(CohortKey { course_id, id: 0 }..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, cohort)| cohort)
+            .collect()
+    })
+}
+
+// Retrieves a cohort of a course
+fn _get_cohort(course_id: CourseId, cohort_id: u64) -> Result<Cohort, Error> {
+    COHORTS
+        .with(|cohorts| cohorts.borrow().get(&CohortKey { course_id, id: cohort_id }))
+        .ok_or(Error::not_found(format!("course with id={} has no cohort with id={}", course_id, cohort_id)))
+}
+
+// Checks that the course exists and the caller may manage its cohorts
+fn _ensure_cohort_manager(id: CourseId) -> Result<(), Error> {
+    _ensure_course_exists(id)?;
+    let caller = api::caller().to_string();
+    if !_is_allowed(id, caller) {
+        return Err(Error::unauthorized(
+            format!("You are not authorized to manage the cohorts of course with id={}", id),
+        ));
+    }
+    Ok(())
+}
+
+// Returns the enrollments in a cohort
+fn _cohort_enrollments(course_id: CourseId, cohort_id: u64) -> Vec<Enrollment> {
+    let start = EngagementKey {
+        course_id,
+        address: String::new(),
+    };
+    ENROLLMENTS.with(|enrollments| {
+        enrollments
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(_, enrollment)| enrollment)
+            .filter(|enrollment| enrollment.cohort_id == Some(cohort_id))
+            .collect()
+    })
+}
+
+// Returns the waitlist of a cohort in line order
+fn _waitlist_of(course_id: CourseId, cohort_id: u64) -> Vec<(WaitlistKey, WaitlistEntry)> {
+    let start = WaitlistKey {
+        course_id,
+        cohort_id,
+        position: 0,
+    };
+    WAITLISTS.with(|waitlists| {
+        waitlists
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id && key.cohort_id == cohort_id)
+            .collect()
+    })
+}
+
+// Offers the free seats of a cohort to the next learners on its waitlist, after dropping
+// the offers that weren't claimed in time
+fn _offer_free_seats(course_id: CourseId, cohort_id: u64) {
+    let now = time();
+    let Ok(cohort) = _get_cohort(course_id, cohort_id) else {
+        return;
+    };
+    let mut waitlist = _waitlist_of(course_id, cohort_id);
+    let lapsed: Vec<(WaitlistKey, WaitlistEntry)> = waitlist
+        .iter()
+        .filter(|(_, entry)| matches!(entry.offered_at, Some(offered_at) if offered_at + SEAT_CLAIM_WINDOW <= now))
+        .cloned()
+        .collect();
+    for (key, entry) in lapsed.iter() {
+        WAITLISTS.with(|waitlists| waitlists.borrow_mut().remove(key));
+        _notify(
+            &entry.learner,
+            NotificationKind::WaitlistOfferLapsed,
+            format!("The seat offered to you in cohort \"{}\" of course with id={} was not claimed in time.", cohort.name, course_id),
+        );
+    }
+    waitlist.retain(|(key, _)| !lapsed.iter().any(|(lapsed_key, _)| lapsed_key == key));
+    if cohort.ends_at <= now {
+        return;
+    }
+
+    let reserved = waitlist.iter().filter(|(_, entry)| entry.offered_at.is_some()).count() as u32;
+    let free_seats = cohort
+        .seat_limit
+        .saturating_sub(_seats_taken(course_id, cohort_id))
+        .saturating_sub(reserved);
+    let offers: Vec<(WaitlistKey, WaitlistEntry)> = waitlist
+        .into_iter()
+        .filter(|(_, entry)| entry.offered_at.is_none())
+        .take(free_seats as usize)
+        .collect();
+    for (key, mut entry) in offers {
+        entry.offered_at = Some(now);
+        _notify(
+            &entry.learner,
+            NotificationKind::WaitlistSeatOffered,
+            format!(
+                "A seat is free in cohort \"{}\" of course with id={}. Enroll within 24 hours to claim it.",
+                cohort.name, course_id
+            ),
+        );
+        WAITLISTS.with(|waitlists| waitlists.borrow_mut().insert(key, entry));
+    }
+}
+
+// Offers the free seats of every cohort that has a waitlist
+fn _offer_waitlisted_seats() {
+    let mut cohorts: Vec<(CourseId, u64)> =
+        WAITLISTS.with(|waitlists| waitlists.borrow().iter().map(|(key, _)| (key.course_id, key.cohort_id)).collect());
+    cohorts.dedup();
+    for (course_id, cohort_id) in cohorts {
+        _offer_free_seats(course_id, cohort_id);
+    }
+}
+
+// Counts the seats taken in a cohort
+fn _seats_taken(course_id: CourseId, cohort_id: u64) -> u32 {
+    _cohort_enrollments(course_id, cohort_id).len() as u32
+}
+
+// Returns the addresses of the learners enrolled in a course
+fn _learners_of(course_id: CourseId) -> Vec<String> {
+    let start = EngagementKey {
+        course_id,
+        address: String::new(),
+    };
+    ENROLLMENTS.with(|enrollments| {
+        enrollments
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.course_id == course_id)
+            .map(|(key, _)| key.address)
+            .collect()
+    })
+}
+
+// Returns the translation of a course best matching a language: the exact language,
+// or else the same language without its region ("pt-br" falls back to "pt")
+fn _translation_for(course_id: CourseId, language: &str) -> Option<Translation> {
+    let language = _normalize_language(language).ok()?;
+    let primary = language.split('-').next().unwrap_or_default().to_string();
+    [language, primary].into_iter().find_map(|language| {
+        TRANSLATIONS.with(|translations| translations.borrow().get(&TranslationKey { course_id, language }))
+    })
+}
+
+// Checks a language tag such as "en" or "pt-BR" and returns it lower case
+fn _normalize_language(language: &str) -> Result<String, Error> {
+    let language = language.trim().to_ascii_lowercase();
+    let is_valid = language.len() <= MAX_LANGUAGE_LENGTH
+        && language
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+        && (2..=3).contains(&language.split('-').next().unwrap_or_default().len());
+    if !is_valid {
+        return Err(Error::invalid_input(format!("\"{}\" is not a valid language tag", language)));
+    }
+    Ok(language)
+}
+
+// Returns the message translations into a language, ordered by pattern
+fn _message_translations_of(language: &str) -> Vec<MessageTranslation> {
+    let start = MessageKey {
+        language: language.to_string(),
+        pattern: String::new(),
+    };
+    MESSAGE_CATALOG.with(|catalog| {
+        catalog
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.language == language)
+            .map(|(_, translation)| translation)
+            .collect()
+    })
+}
+
+// Translates an English message into the language a user chose, trying "pt" after "pt-br". When several
+// patterns match, the one with the most fixed text wins. Messages without a translation stay in English.
+// Errors may be built while the settings or the catalog are borrowed, so those are only read if they are free
+fn _localize(address: &str, message: String) -> String {
+    let language = USER_SETTINGS.with(|settings| {
+        settings
+            .try_borrow()
+            .ok()
+            .and_then(|settings| settings.get(&UserKey(address.to_string())))
+            .and_then(|settings| settings.language)
+    });
+    let Some(language) = language else {
+        return message;
+    };
+    let mut languages = vec![language.clone()];
+    if let Some((primary, _)) = language.split_once('-') {
+        languages.push(primary.to_string());
+    }
+    for language in languages {
+        let start = MessageKey {
+            language: language.clone(),
+            pattern: String::new(),
+        };
+        let translated = MESSAGE_CATALOG.with(|catalog| {
+            let catalog = catalog.try_borrow().ok()?;
+            catalog
+                .range(start..)
+                .take_while(|(key, _)| key.language == language)
+                .filter_map(|(key, entry)| _match_pattern(&key.pattern, &message).map(|texts| (key.pattern.len() - 2 * texts.len(), entry, texts)))
+                .max_by_key(|(fixed, _, _)| *fixed)
+                .map(|(_, entry, texts)| _fill_placeholders(&entry.translation, &texts))
+        });
+        if let Some(translated) = translated {
+            return translated;
+        }
+    }
+    message
+}
+
+// Matches a message against a pattern, returning the texts standing for its {} placeholders
+fn _match_pattern<'a>(pattern: &str, message: &'a str) -> Option<Vec<&'a str>> {
+    let mut parts = pattern.split("{}");
+    let mut rest = message.strip_prefix(parts.next().unwrap_or_default())?;
+    let parts: Vec<&str> = parts.collect();
+    if parts.is_empty() {
+        return rest.is_empty().then(Vec::new);
+    }
+    let mut texts = Vec::with_capacity(parts.len());
+    for (index, part) in parts.iter().enumerate() {
+        // The last fixed text has to end the message, the others are matched as early as possible
+        let end = if index + 1 == parts.len() { rest.strip_suffix(part)?.len() } else { rest.find(part)? };
+        texts.push(&rest[..end]);
+        rest = &rest[end + part.len()..];
+    }
+    Some(texts)
+}
+
+// Replaces the {1}, {2}... placeholders of a translation with the texts a message matched
+fn _fill_placeholders(translation: &str, texts: &[&str]) -> String {
+    let mut filled = translation.to_string();
+    // Higher numbers first, so {1} doesn't replace the start of {10}
+    for (index, text) in texts.iter().enumerate().rev() {
+        filled = filled.replace(&format!("{{{}}}", index + 1), text);
+    }
+    filled
+}
+
+// Returns the numbers of the {1}, {2}... placeholders in a translation
+fn _placeholder_numbers(translation: &str) -> Vec<usize> {
+    translation
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}'))
+        .filter_map(|(number, _)| number.parse().ok())
+        .collect()
+}
+
+// Starts the timer that runs the periodic jobs
+fn _start_scheduler() {
+    ic_cdk_timers::set_timer_interval(SCHEDULER_INTERVAL, _run_scheduled_jobs);
+}
+
+// Periodic jobs run by the scheduler
+fn _run_scheduled_jobs() {
+    let _profile = ProfileGuard::new("scheduler");
+    _refresh_popularity();
+    _refresh_reputation();
+    _refresh_quality();
+    _publish_weekly_digest();
+    _prune_domain_sightings();
+    _clear_old_usage();
+    _clear_expired_bans();
+    _expire_moderator_terms();
+    _expire_pending_bans();
+    _continue_account_deletions();
+    _continue_ban_cleanups();
+    _send_session_reminders();
+    _offer_waitlisted_seats();
+    _archive_expired_courses();
+    _sweep_links();
+    _evaluate_learning_goals();
+    _end_finished_maintenance();
+    _deliver_course_events();
+}
+
+// Counts a moderation action of a staff member
+fn _record_moderator_action(address: &str, update: impl FnOnce(&mut ModeratorStats)) {
+    MODERATOR_STATS.with(|stats| {
+        let mut stats = stats.lock().unwrap();
+        let entry = stats.entry(address.to_string()).or_insert(ModeratorStats {
+            address: address.to_string(),
+            ..Default::default()
+        });
+        update(entry);
+        entry.last_action_at = Some(time());
+    });
+}
+
+// Makes an address a moderator, optionally until the end of a term
+fn _appoint_moderator(address: String, term_expires_at: Option<u64>, appointed_by: &str) -> Result<(), Error> {
+    if let Some(expires_at) = term_expires_at {
+        if expires_at <= time() {
+            return Err(Error::invalid_input("The end of the moderator term must be in the future".to_string()).with_field("term_expires_at"));
+        }
+    }
+    MODERATOR_ADDRESSES.with(|moderator_addresses| {
+        let mut addresses = moderator_addresses.lock().unwrap();
+
+        // Check if the maximum number of moderators is reached
+        if addresses.len() >= MAX_MODERATORS {
+            return Err(Error::invalid_input("Maximum number of moderators reached".to_string()))
+        }
+
+        // Check if the moderator address already exists
+        if addresses.contains(&address) {
+            return Err(Error::invalid_input("Moderator address already exists".to_string()).with_field("address"))
+        }
+
+        if let Some(expires_at) = term_expires_at {
+            MODERATOR_TERMS.with(|terms| terms.lock().unwrap().insert(address.clone(), expires_at));
+        }
+        let details = match term_expires_at {
+            Some(expires_at) => format!("term ends at {}", expires_at),
+            None => String::new(),
+        };
+        _log_moderation(ModerationAction::ModeratorAppointed, appointed_by, address.clone(), details);
+        addresses.push(address);
+        Ok(())
+    })
+}
+
+// Demotes the moderators whose term has ended and lets them know
+fn _expire_moderator_terms() {
+    let now = time();
+    let expired: Vec<String> = MODERATOR_TERMS.with(|terms| {
+        let mut terms = terms.lock().unwrap();
+        let expired: Vec<String> = terms
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in expired.iter() {
+            terms.remove(address);
+        }
+        expired
+    });
+    if expired.is_empty() {
+        return;
+    }
+    MODERATOR_ADDRESSES.with(|moderators| {
+        moderators.lock().unwrap().retain(|address| !expired.contains(address));
+    });
+    MODERATOR_CATEGORIES.with(|categories| {
+        categories.lock().unwrap().retain(|address, _| !expired.contains(address));
+    });
+    for address in expired.iter() {
+        _log_moderation(
+            ModerationAction::ModeratorRemoved,
+            &api::id().to_string(),
+            address.clone(),
+            "term ended".to_string(),
+        );
+        _notify(
+            address,
+            NotificationKind::ModeratorTermExpired,
+            "Your term as a moderator has ended. Thank you for helping the community!".to_string(),
+        );
+    }
+}
+
+// Appends a staff action to the moderation log and certifies the new head hash
+fn _log_moderation(action: ModerationAction, actor: &str, subject: String, details: String) {
+    MODERATION_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = log.len();
+        let prev_hash = match seq {
+            0 => Vec::new(),
+            _ => log.get(&(seq - 1)).map(|previous| previous.hash).unwrap_or_default(),
+        };
+        let entry = ModerationEntry {
+            seq,
+            action,
+            actor: actor.to_string(),
+            // Keep entries within their bounded size whatever the staff typed
+            subject: subject.chars().take(MAX_TAG_LENGTH).collect(),
+            details: details.chars().take(MAX_NOTE_LENGTH).collect(),
+            timestamp: time(),
+            prev_hash,
+        };
+        let hash = Sha256::digest(Encode!(&entry).unwrap()).to_vec();
+        api::set_certified_data(&hash);
+        log.insert(seq, LoggedModerationEntry { entry, hash });
+    });
+}
+
+// Returns the hash of the latest moderation log entry, empty while the log is empty
+fn _moderation_log_head() -> Vec<u8> {
+    MODERATION_LOG.with(|log| {
+        let log = log.borrow();
+        match log.len() {
+            0 => Vec::new(),
+            length => log.get(&(length - 1)).map(|last| last.hash).unwrap_or_default(),
+        }
+    })
+}
+
+// Sets the certified data to the moderation log head. Certified data doesn't survive upgrades
+fn _certify_moderation_log() {
+    api::set_certified_data(&_moderation_log_head());
+}
+
+// Drops a notification into a user's inbox
+fn _notify(recipient: &str, kind: NotificationKind, message: String) {
+    let id = NOTIFICATION_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment notification id counter");
+    let notification = Notification {
+        id,
+        recipient: recipient.to_string(),
+        kind,
+        message: _localize(recipient, message),
+        created_at: time(),
+        read: false,
+    };
+    let key = NotificationKey {
+        recipient: recipient.to_string(),
+        id,
+    };
+    NOTIFICATIONS.with(|notifications| notifications.borrow_mut().insert(key, notification));
+}
+
+// Returns all the notifications of a user, oldest first
+fn _notifications_of(recipient: &str) -> Vec<Notification> {
+    let start = NotificationKey {
+        recipient: recipient.to_string(),
+        id: 0,
+    };
+    NOTIFICATIONS.with(|notifications| {
+        notifications
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.recipient == recipient)
+            .map(|(_, notification)| notification)
+            .collect()
+    })
+}
+
+// Recomputes the popularity score of every course from its recent activity.
+// Each day's views, likes and enrollments are weighted, and older days count for less
+fn _refresh_popularity() {
+    let today = _today();
+    let from_day = today.saturating_sub(POPULARITY_WINDOW_DAYS);
+    let course_ids: Vec<CourseId> = STORAGE.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect());
+    let mut scores: Vec<PopularityScore> = ACTIVITY.with(|activity| {
+        let activity = activity.borrow();
+        course_ids
+            .into_iter()
+            .map(|course_id| {
+                let mut score = 0.0;
+                for (key, counts) in activity.range(_activity_range(course_id, from_day)) {
+                    let age = today.saturating_sub(key.day) as f64;
+                    let decay = 0.5_f64.powf(age / POPULARITY_HALF_LIFE_DAYS);
+                    let weighted = counts.views + 3 * counts.likes + 5 * counts.enrollments;
+                    score += weighted as f64 * decay;
+                }
+                PopularityScore {
+                    course_id,
+                    score: (score * 1000.0) as u64,
+                }
+            })
+            .collect()
+    });
+    scores.sort_by_key(|entry| Reverse(entry.score));
+    POPULARITY.with(|popularity| *popularity.lock().unwrap() = scores);
+}
+
+// Computes the digest of the last week once it is over: its most active new courses, the most active
+// courses overall and the ones with the most feedback, leaving mature courses out. Users who opted in
+// are notified
+fn _publish_weekly_digest() {
+    let week = _today() / 7;
+    let Some(last_week) = week.checked_sub(1) else {
+        return;
+    };
+    if WEEKLY_DIGESTS.with(|digests| digests.borrow().contains_key(&last_week)) {
+        return;
+    }
+    let (from_day, to_day) = (last_week * 7, week * 7);
+    let (starts_at, ends_at) = (from_day * NANOS_PER_DAY, to_day * NANOS_PER_DAY);
+    let courses: Vec<StoredCourse> = STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, course)| course)
+            .filter(|course| _rating_of(course) != ContentRating::Mature)
+            .collect()
+    });
+    let activity_of = |course_id: CourseId| -> u64 {
+        ACTIVITY.with(|activity| {
+            activity
+                .borrow()
+                .range(_activity_range(course_id, from_day))
+                .take_while(|(key, _)| key.day < to_day)
+                .map(|(_, counts)| counts.views + 3 * counts.likes + 5 * counts.enrollments)
+                .sum()
+        })
+    };
+    let top = |count: &dyn Fn(&StoredCourse) -> u64, new_only: bool| -> Vec<DigestEntry> {
+        let mut entries: Vec<DigestEntry> = courses
+            .iter()
+            .filter(|course| !new_only || (starts_at..ends_at).contains(&course.created_at))
+            .map(|course| DigestEntry {
+                course_id: course.id,
+                title: course.title.clone(),
+                count: count(course),
+            })
+            .filter(|entry| entry.count > 0 || new_only)
+            .collect();
+        entries.sort_by_key(|entry| (Reverse(entry.count), entry.course_id));
+        entries.truncate(DIGEST_SIZE);
+        entries
+    };
+    let digest = WeeklyDigest {
+        week: last_week,
+        starts_at,
+        ends_at,
+        new_courses: top(&|course| activity_of(course.id), true),
+        trending: top(&|course| activity_of(course.id), false),
+        most_reviewed: top(
+            &|course| {
+                _feedback_of(course.id)
+                    .iter()
+                    .filter(|response| (starts_at..ends_at).contains(&response.submitted_at))
+                    .count() as u64
+            },
+            false,
+        ),
+        generated_at: time(),
+    };
+    WEEKLY_DIGESTS.with(|digests| {
+        let mut digests = digests.borrow_mut();
+        digests.insert(last_week, digest.clone());
+        let expired: Vec<u64> = digests.range(..last_week.saturating_sub(MAX_STORED_DIGESTS - 1)).map(|(week, _)| week).collect();
+        for week in expired {
+            digests.remove(&week);
+        }
+    });
+
+    let highlights: Vec<&str> = [&digest.trending, &digest.new_courses, &digest.most_reviewed]
+        .into_iter()
+        .find(|entries| !entries.is_empty())
+        .map(|entries| entries.iter().map(|entry| entry.title.as_str()).collect())
+        .unwrap_or_default();
+    if highlights.is_empty() {
+        return;
+    }
+    let message = format!("The weekly digest is out. Highlights of the week: {}", highlights.join(", "));
+    let subscribers: Vec<String> = USER_SETTINGS.with(|settings| {
+        settings
+            .borrow()
+            .iter()
+            .filter(|(_, settings)| settings.weekly_digest == Some(true))
+            .map(|(key, _)| key.0)
+            .collect()
+    });
+    for address in subscribers {
+        _notify(&address, NotificationKind::WeeklyDigest, message.clone());
     }
 }
 
-// Deletes all courses by a creator based on the address. Only the admin or a moderator can access
-#[ic_cdk::update]
-fn delete_courses_by_creator(address: String) -> Result<Vec<Course>, Error> {
-    let caller = api::caller().to_string(); // Convert caller address to string
-    let is_allowed = {
-        // Check if the caller is the input address
-        if address == caller.to_string() {
-            true
-        } else {
-            // Check if the caller is the admin
-            let admin_address = ADMIN_ADDRESS.with(|admin_address| {
-                admin_address.lock().unwrap().clone()
-            });
-            if let Some(admin) = &admin_address {
-                if caller == *admin {
-                    true
-                } else {
-                    // Check if the caller is one of the moderators
-                    let moderators = MODERATOR_ADDRESSES.with(|moderator_addresses| {
-                        moderator_addresses.lock().unwrap().clone()
-                    });
-                    moderators.contains(&caller.to_string())
-                }
-            } else {
-                false
+// Recomputes the reputation of every creator with a published course. Out of 100 points, up to 40 come
+// from the average content rating in feedback (20 without feedback), up to 25 from completions of their
+// courses (one per 4, capped at 100), up to 20 from the age of their oldest course (capped at a year)
+// and 15 from a clean record, minus 5 for each course taken down
+fn _refresh_reputation() {
+    let now = time();
+    let mut reputations: HashMap<String, CreatorReputation> = HashMap::new();
+    let mut creators: HashMap<CourseId, String> = HashMap::new();
+    STORAGE.with(|storage| {
+        for (id, course) in storage.borrow().iter() {
+            let reputation = reputations
+                .entry(course.creator_address.clone())
+                .or_insert_with(|| CreatorReputation {
+                    address: course.creator_address.clone(),
+                    score: 0,
+                    average_rating: None,
+                    completions: 0,
+                    takedowns: 0,
+                    active_since: course.created_at,
+                });
+            reputation.active_since = reputation.active_since.min(course.created_at);
+            creators.insert(id, course.creator_address);
+        }
+    });
+    ENROLLMENTS.with(|enrollments| {
+        for (key, enrollment) in enrollments.borrow().iter() {
+            if enrollment.completed_at.is_none() {
+                continue;
+            }
+            if let Some(reputation) = creators.get(&key.course_id).and_then(|creator| reputations.get_mut(creator)) {
+                reputation.completions += 1;
             }
         }
-    };
-    if is_allowed {
-        let mut deleted_courses: Vec<Course> = Vec::new(); // Keep track of deleted courses
-        STORAGE.with(|service| {
-            let mut storage = service.borrow_mut();
-            let mut keys_to_remove = Vec::new(); // Keep track of keys to remove
-            // Iterate through storage to find and remove matching courses
-            for (id, course) in storage.iter() {
-                if course.creator_address == address {
-                    // If creator address matches caller, mark for removal
-                    keys_to_remove.push(id.clone());
-                    deleted_courses.push(course.clone()); // Add course to deleted list
-                }
-            }
-            // Remove courses from storage
-            for key in keys_to_remove {
-                storage.remove(&key);
+    });
+    let mut ratings: HashMap<String, (u64, u64)> = HashMap::new();
+    FEEDBACK.with(|feedback| {
+        for (key, response) in feedback.borrow().iter() {
+            if let Some(creator) = creators.get(&key.course_id) {
+                let (total, count) = ratings.entry(creator.clone()).or_default();
+                *total += response.form.content_rating as u64;
+                *count += 1;
             }
-        });
-        if deleted_courses.is_empty() {
-            Err(Error::NotFound {
-                msg: "No courses found for the caller. Nothing to delete.".to_string(),
-            })
-        } else {
-            Ok(deleted_courses)
         }
-    } else {
-        Err(Error::UnAuthorized {
-            msg: ("You are not authorized to delete the course ".to_string()),
+    });
+    TAKEDOWNS.with(|takedowns| {
+        for (_, takedown) in takedowns.borrow().iter() {
+            if let Some(reputation) = reputations.get_mut(&takedown.course.creator_address) {
+                reputation.takedowns += 1;
+            }
+        }
+    });
+    for reputation in reputations.values_mut() {
+        reputation.average_rating = ratings
+            .get(&reputation.address)
+            .map(|(total, count)| total * 100 / count);
+        let rating_points = reputation.average_rating.map_or(20, |average| (average - 100) * 40 / 400);
+        let completion_points = reputation.completions.min(100) / 4;
+        let age_days = now.saturating_sub(reputation.active_since) / NANOS_PER_DAY;
+        let age_points = age_days.min(365) * 20 / 365;
+        let standing_points = 15u64.saturating_sub(5 * reputation.takedowns);
+        reputation.score = (rating_points + completion_points + age_points + standing_points) as u32;
+    }
+    REPUTATION.with(|reputation| *reputation.lock().unwrap() = reputations);
+}
+
+// Recomputes the quality score of every course. Out of 100 points, up to 15 come from lessons
+// (sessions and assignments, 5 each), 15 from an outline of at least two headings, up to 20 from
+// the length of the body (one per 100 characters), 10 from a valid attachment, up to 30 from the
+// average content rating in feedback (15 without feedback) and 10 from a clean record, minus 5 for
+// each staff flag
+fn _refresh_quality() {
+    let courses: Vec<StoredCourse> = STORAGE.with(|storage| storage.borrow().iter().map(|(_, course)| course).collect());
+    let scores: HashMap<CourseId, CourseQuality> = courses
+        .iter()
+        .map(|course| {
+            let lessons = (_sessions_of(course.id).len() + _assignments_of(course.id).len()) as u64;
+            let outline_headings = course.body.lines().filter(|line| line.trim_start().starts_with('#')).count() as u64;
+            let body_length = course.body.chars().count() as u64;
+            let valid_attachment = _validate_attachment_url(&course.attachment_url).is_ok();
+            let feedback = _feedback_of(course.id);
+            let average_rating = (!feedback.is_empty()).then(|| {
+                feedback.iter().map(|response| response.form.content_rating as u64).sum::<u64>() * 100 / feedback.len() as u64
+            });
+            let flagged = POSSIBLE_DUPLICATES.with(|duplicates| duplicates.borrow().contains_key(&course.id));
+            let concern = COURSE_LABELS.with(|labels| {
+                labels.borrow().contains_key(&CourseLabelKey { label: StaffLabel::QualityConcern, course_id: course.id })
+            });
+            let staff_flags = flagged as u64 + concern as u64;
+
+            let lesson_points = (lessons * 5).min(15);
+            let outline_points = if outline_headings >= 2 { 15 } else { 0 };
+            let length_points = (body_length / 100).min(20);
+            let attachment_points = if valid_attachment { 10 } else { 0 };
+            let rating_points = average_rating.map_or(15, |average| average.saturating_sub(100) * 30 / 400);
+            let standing_points = 10u64.saturating_sub(5 * staff_flags);
+            let quality = CourseQuality {
+                course_id: course.id,
+                score: (lesson_points + outline_points + length_points + attachment_points + rating_points + standing_points) as u32,
+                lessons,
+                outline_headings,
+                body_length,
+                valid_attachment,
+                average_rating,
+                staff_flags,
+            };
+            (course.id, quality)
         })
+        .collect();
+    QUALITY.with(|quality| *quality.lock().unwrap() = scores);
+}
+
+// Error types. Every variant carries a stable numeric code, which clients can branch on
+// instead of parsing the message, and optional structured details
+#[derive(candid::CandidType, Deserialize, Serialize)]
+enum Error {
+    NotFound { code: u32, msg: String, details: Option<ErrorDetails> },
+    UnAuthorized { code: u32, msg: String, details: Option<ErrorDetails> },
+    EmptyFields { code: u32, msg: String, details: Option<ErrorDetails> },
+    BannedUser { code: u32, msg: String, details: Option<ErrorDetails> },
+    InvalidInput { code: u32, msg: String, details: Option<ErrorDetails> },
+    RejectedContent { code: u32, msg: String, details: Option<ErrorDetails> },
+    CanisterPaused { code: u32, msg: String, details: Option<ErrorDetails> },
+    UnderMaintenance { code: u32, msg: String, expected_end: u64, details: Option<ErrorDetails> },
+}
+
+// What an error is about: the input field at fault, the id of the missing item,
+// or the number of seconds after which the call can be tried again
+#[derive(candid::CandidType, Clone, Default, Deserialize, Serialize)]
+struct ErrorDetails {
+    field: Option<String>,
+    id: Option<u64>,
+    retry_after: Option<u64>,
+}
+
+impl Error {
+    fn not_found(msg: String) -> Self {
+        Error::NotFound { code: 1, msg: Self::localized(msg), details: None }
+    }
+
+    fn unauthorized(msg: String) -> Self {
+        Error::UnAuthorized { code: 2, msg: Self::localized(msg), details: None }
+    }
+
+    fn empty_fields(msg: String) -> Self {
+        Error::EmptyFields { code: 3, msg: Self::localized(msg), details: None }
+    }
+
+    fn banned_user(msg: String) -> Self {
+        Error::BannedUser { code: 4, msg: Self::localized(msg), details: None }
+    }
+
+    fn invalid_input(msg: String) -> Self {
+        Error::InvalidInput { code: 5, msg: Self::localized(msg), details: None }
+    }
+
+    fn rejected_content(msg: String) -> Self {
+        Error::RejectedContent { code: 6, msg: Self::localized(msg), details: None }
+    }
+
+    fn canister_paused(msg: String) -> Self {
+        Error::CanisterPaused { code: 7, msg: Self::localized(msg), details: None }
+    }
+
+    fn under_maintenance(msg: String, expected_end: u64) -> Self {
+        let retry_after = expected_end.saturating_sub(time()).div_ceil(1_000_000_000);
+        Error::UnderMaintenance { code: 8, msg: Self::localized(msg), expected_end, details: None }.with_retry_after(retry_after)
+    }
+
+    // Gives the message in the caller's language when the catalog has it
+    #[cfg(not(test))]
+    fn localized(msg: String) -> String {
+        _localize(&api::caller().to_string(), msg)
+    }
+
+    // Unit tests run outside of a canister, where there is no caller to localize for
+    #[cfg(test)]
+    fn localized(msg: String) -> String {
+        msg
+    }
+
+    // Names the input field at fault
+    fn with_field(mut self, field: &str) -> Self {
+        self.details().field = Some(field.replace(' ', "_"));
+        self
+    }
+
+    // Gives the id of the missing or conflicting item
+    fn with_id(mut self, id: u64) -> Self {
+        self.details().id = Some(id);
+        self
+    }
+
+    // Tells after how many seconds the call can be tried again
+    fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.details().retry_after = Some(seconds);
+        self
+    }
+
+    fn details(&mut self) -> &mut ErrorDetails {
+        let (Error::NotFound { details, .. }
+        | Error::UnAuthorized { details, .. }
+        | Error::EmptyFields { details, .. }
+        | Error::BannedUser { details, .. }
+        | Error::InvalidInput { details, .. }
+        | Error::RejectedContent { details, .. }
+        | Error::CanisterPaused { details, .. }
+        | Error::UnderMaintenance { details, .. }) = self;
+        details.get_or_insert_with(ErrorDetails::default)
     }
 }
 
-// Deletes all courses of the caller
-#[ic_cdk::update]
-fn delete_my_courses() -> Result<Vec<Course>, Error> {
-    let caller = api::caller().to_string(); // Convert caller address to string
-    let mut deleted_courses: Vec<Course> = Vec::new(); // Keep track of deleted courses
+// need this to generate candid
+ic_cdk::export_candid!();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    STORAGE.with(|service| {
-        let mut storage = service.borrow_mut();
-        let mut keys_to_remove = Vec::new(); // Keep track of keys to remove
+    #[test]
+    fn course_id_is_stored_like_a_u64() {
+        let id = CourseId(42);
+        assert_eq!(id.to_bytes(), 42u64.to_bytes());
+        assert!(CourseId::from_bytes(id.to_bytes()) == id);
+        // Keys keep the numeric order of the ids
+        assert!(CourseId(255).to_bytes() < CourseId(256).to_bytes());
+    }
 
-        // Iterate through storage to find and remove matching courses
-        for (id, course) in storage.iter() {
-            if course.creator_address == caller {
-                // If creator address matches caller, mark for removal
-                keys_to_remove.push(id.clone());
-                deleted_courses.push(course.clone()); // Add course to deleted list
-            }
+    #[test]
+    fn validate_page_defaults_and_bounds() {
+        let page = _validate_page(None).ok().unwrap();
+        assert_eq!((page.offset, page.limit), (0, MAX_PAGE_SIZE));
+        let page = _validate_page(Some(Page { offset: 20, limit: 5 })).ok().unwrap();
+        assert_eq!((page.offset, page.limit), (20, 5));
+        assert!(_validate_page(Some(Page { offset: 0, limit: MAX_PAGE_SIZE })).is_ok());
+        assert!(_validate_page(Some(Page { offset: 0, limit: 0 })).is_err());
+        assert!(_validate_page(Some(Page { offset: 0, limit: MAX_PAGE_SIZE + 1 })).is_err());
+    }
+
+    fn pending_ban(address: &str, hidden_course_ids: Vec<CourseId>) -> PendingBan {
+        PendingBan {
+            address: address.to_string(),
+            reason: None,
+            expires_at: Some(100),
+            proposed_by: "moderator".to_string(),
+            proposed_at: 0,
+            confirm_before: BAN_APPROVAL_WINDOW,
+            hidden_course_ids,
         }
+    }
+
+    #[test]
+    fn taking_a_pending_ban_releases_its_hidden_courses() {
+        let course = StoredCourse {
+            id: CourseId(7),
+            creator_address: "creator".to_string(),
+            ..Default::default()
+        };
+        HELD_COURSES.with(|held| held.borrow_mut().insert(course.id, course.clone()));
+        PENDING_BANS.with(|pending| pending.lock().unwrap().push(pending_ban("creator", vec![course.id])));
+        assert!(_pending_ban("creator").is_some());
+        assert!(_pending_ban("other").is_none());
+
+        let pending = _take_pending_ban("creator").ok().unwrap();
+        assert!(_pending_ban("creator").is_none());
+        assert!(_take_pending_ban("creator").is_err());
+        _release_held_courses(&pending);
+        assert!(HELD_COURSES.with(|held| held.borrow().is_empty()));
+        assert!(_get_course_(&course.id).is_some_and(|course| course.creator_address == "creator"));
+        assert_eq!(_course_ids_by_creator("creator").len(), 1);
+    }
 
-        // Remove courses from storage
-        for key in keys_to_remove {
-            storage.remove(&key);
+    #[test]
+    fn bans_end_at_their_expiry() {
+        let ban = BanRecord {
+            address: "user".to_string(),
+            reason: None,
+            banned_by: "admin".to_string(),
+            banned_at: 0,
+            expires_at: Some(100),
+        };
+        assert!(_ban_in_effect(&ban, 99));
+        assert!(!_ban_in_effect(&ban, 100));
+        assert!(_ban_in_effect(&BanRecord { expires_at: None, ..ban }, u64::MAX));
+    }
+
+    fn course_by(id: u64, creator: &str) -> Course {
+        Course {
+            id: CourseId(id),
+            creator_address: creator.to_string(),
+            title: format!("Course {}", id),
+            keyword: "rust".to_string(),
+            tags: vec!["rust".to_string()],
+            category: "programming".to_string(),
+            ..Default::default()
         }
-    });
+    }
 
-    if deleted_courses.is_empty() {
-        Err(Error::NotFound {
-            msg: "No courses found for the caller. Nothing to delete.".to_string(),
-        })
-    } else {
-        Ok(deleted_courses)
+    fn like(id: u64, address: &str) {
+        let key = EngagementKey {
+            course_id: CourseId(id),
+            address: address.to_string(),
+        };
+        LIKES.with(|likes| likes.borrow_mut().insert(key, 0));
     }
-}
 
-// Bans a creator from adding courses.
-// Deletes all the courses by the creator
-// Only the admin or a moderator can access
-#[ic_cdk::update]
-fn ban_creator(address: String) -> Result<Vec<Course>, Error> {
-    // The caller must be admin or moderator
-    let caller = api::caller().to_string(); // Convert caller address to string
+    #[test]
+    fn account_deletion_removes_only_the_account_data_in_batches() {
+        for id in 1..=3 {
+            do_insert(&course_by(id, "leaver"));
+            like(id, "stayer");
+        }
+        do_insert(&course_by(4, "stayer"));
+        like(4, "leaver");
+        like(4, "stayer");
+        let deletion = AccountDeletion {
+            address: "leaver".to_string(),
+            policy: CoursePolicy::Delete,
+            requested_at: 0,
+            removed_records: 0,
+        };
 
-    // Check if the caller is an admin or moderator
-    let is_authorized: bool = _is_authorized(caller);
+        // Courses come first, the like of the other course in the following batch
+        assert_eq!(_delete_account_batch(&deletion, 2), 2);
+        assert_eq!(_course_ids_by_creator("leaver").len(), 1);
+        assert_eq!(_delete_account_batch(&deletion, 2), 2);
+        assert_eq!(_delete_account_batch(&deletion, 2), 0);
 
-    // Checks if the the input address is admin or a moderator
-    let is_allowed = {
-        let admin_address = ADMIN_ADDRESS.with(|admin_address| {
-            admin_address.lock().unwrap().clone()
-        });
-        if let Some(admin) = &admin_address{
-            if address == *admin{
-                false
-            } else {
-                // Check if the caller is one of the moderators
-                let moderators = MODERATOR_ADDRESSES.with(|moderator_addresses| {
-                    moderator_addresses.lock().unwrap().clone()
-                });
-                if moderators.contains(&address.to_string()) {
-                    false
-                } else {
-                    true
-                }
-            }
-        } else {
-            false
+        assert!(_course_ids_by_creator("leaver").is_empty());
+        assert!(_course_ids_by_creator("stayer") == vec![CourseId(4)]);
+        // Likes of deleted courses go with the courses
+        let likes: Vec<(u64, String)> =
+            LIKES.with(|likes| likes.borrow().iter().map(|(key, _)| (key.course_id.0, key.address)).collect());
+        assert_eq!(likes, vec![(4, "stayer".to_string())]);
+    }
+
+    #[test]
+    fn account_deletion_is_kept_for_the_scheduler_until_completed() {
+        for id in 1..=(ACCOUNT_DELETION_BATCH as u64 + 1) {
+            like(id, "leaver");
         }
-    };
+        let deletion = AccountDeletion {
+            address: "leaver".to_string(),
+            policy: CoursePolicy::Delete,
+            requested_at: 0,
+            removed_records: 0,
+        };
+        let status = _run_account_deletion(deletion);
+        assert!(!status.completed);
+        assert_eq!(status.removed_records, ACCOUNT_DELETION_BATCH as u64);
+        assert_eq!(ACCOUNT_DELETIONS.with(|deletions| deletions.lock().unwrap().len()), 1);
 
-    if is_allowed && is_authorized {
-        // Delete all the courses of the user
-        match delete_courses_by_creator(address.clone()){
-            Ok(course) => {
-                //Add the address to banned list
-                BANNED_ADDRESSES.with(|banned_addresses| {
-                    let mut addresses = banned_addresses.lock().unwrap();
-                    addresses.push(address);
-                });
-                Ok(course)
-            }
-            Err(_) => Err(Error::NotFound {
-                msg: ("No courses found for the address, cannot ban the user".to_string()),
-            }),
+        _continue_account_deletions();
+        assert!(ACCOUNT_DELETIONS.with(|deletions| deletions.lock().unwrap().is_empty()));
+        assert!(LIKES.with(|likes| likes.borrow().is_empty()));
+    }
+
+    #[test]
+    fn data_export_sections_hold_only_the_records_of_the_address() {
+        do_insert(&course_by(1, "exporter"));
+        do_insert(&course_by(2, "other"));
+        like(2, "exporter");
+        like(1, "other");
+
+        let courses = _export_records("exporter", "courses");
+        assert_eq!(courses.len(), 1);
+        let course: serde_json::Value = serde_json::from_str(&courses[0]).unwrap();
+        assert_eq!(course["title"], "Course 1");
+        let likes = _export_records("exporter", "likes");
+        assert_eq!(likes, vec![r#"{"course_id":2,"liked_at":0}"#.to_string()]);
+        assert!(_export_records("exporter", "enrollments").is_empty());
+    }
+
+    #[test]
+    fn render_escapes_html_and_quotes() {
+        let html = _render_markdown("<script>alert(\"hi\")</script> it's <b onclick=\"x\">bold</b>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("<b "));
+        assert!(html.contains("&lt;script&gt;alert(&quot;hi&quot;)&lt;/script&gt;"));
+        assert!(html.contains("it&#39;s"));
+    }
+
+    #[test]
+    fn render_drops_unsafe_link_and_image_targets() {
+        for markdown in ["[click](javascript:alert(1))", "[click](JavaScript:void)", "[mail](data:text/html,x)"] {
+            let html = _render_inline(markdown);
+            assert!(!html.contains("href"), "{} rendered as {}", markdown, html);
+            assert!(html.starts_with("click") || html.starts_with("mail"), "{} rendered as {}", markdown, html);
         }
-    } else {
-        Err(Error::UnAuthorized {
-            msg: ("You are not authorized to ban the user".to_string()),
-        })
+        let html = _render_inline("![logo](javascript:alert(1))");
+        assert!(!html.contains("<img"));
+        let html = _render_inline("[site](https://example.com/?a=\"b\") ![logo](https://example.com/logo.png)");
+        assert!(html.contains("<a href=\"https://example.com/?a=&quot;b&quot;\" rel=\"nofollow noopener\">site</a>"));
+        assert!(html.contains("<img src=\"https://example.com/logo.png\" alt=\"logo\">"));
     }
-}
 
-// Un ban a creator from adding courses
-// Only the admin or a moderator can access
-#[ic_cdk::update]
-fn un_ban_creator(address: String) -> Result<(), Error> {
-    // The caller must be admin or moderator
-    let caller = api::caller().to_string(); // Convert caller address to string
+    #[test]
+    fn render_handles_unclosed_markup() {
+        let html = _render_markdown("```\nlet x = 1 < 2;\n<script>");
+        assert_eq!(html, "<pre><code>let x = 1 &lt; 2;\n&lt;script&gt;\n</code></pre>\n");
+        assert_eq!(_render_inline("[label](https://example.com"), "[label](https://example.com");
+        for markdown in ["[", "![", "](", "`", "**", "*", "***", "![x]", "[x](", "#", "1. ", "> ", "`é", "*é", "**é", "[é](é"] {
+            _render_markdown(markdown);
+            _render_inline(markdown);
+        }
+    }
 
-    // cheks if the caller is the admin or a moderator
-    let is_authorized: bool = _is_authorized(caller);
+    #[test]
+    fn render_emphasis_with_multibyte_characters() {
+        assert_eq!(_render_inline("*héllo* **wörld**"), "<em>héllo</em> <strong>wörld</strong>");
+        assert_eq!(_render_inline("**日本語** *✓*"), "<strong>日本語</strong> <em>✓</em>");
+        assert_eq!(_render_inline("`ß<é>`"), "<code>ß&lt;é&gt;</code>");
+        assert_eq!(_render_inline("* ü"), "* ü");
+    }
 
-    if is_authorized {
-        BANNED_ADDRESSES.with(|banned_addresses| {
-            let mut addresses = banned_addresses.lock().unwrap();
-            if let Some(pos) = addresses.iter().position(|x| *x == address) {
-                addresses.remove(pos);
-                Ok(())
-            } else {
-                Err(Error::NotFound {
-                    msg: "Address not found in banned list.".to_string(),
-                })
-            }
-        })
-    } else {
-        Err(Error::UnAuthorized {
-            msg: ("You are not authorized to ban the user".to_string()),
-        })
+    #[test]
+    fn generics_survive_normalization_and_rendering() {
+        assert_eq!(_normalize_line("title", "Learn  Vec<T>").ok().as_deref(), Some("Learn Vec<T>"));
+        assert_eq!(_normalize_line("title", "Learn Vec<String>").ok().as_deref(), Some("Learn Vec<String>"));
+        let body = "Use `HashMap<K, V>` here.\n\n```rust\nlet v: Vec<String> = Vec::new();\n```";
+        let normalized = _normalize_body(body).ok();
+        assert_eq!(normalized.as_deref(), Some(body));
+        let html = _render_markdown(body);
+        assert!(html.contains("<code>HashMap&lt;K, V&gt;</code>"));
+        assert!(html.contains("let v: Vec&lt;String&gt; = Vec::new();"));
     }
-}
 
-// Internal helper functions
+    #[test]
+    fn rfc3339_timestamps_apply_the_utc_offset() {
+        const SECOND: u64 = 1_000_000_000;
+        assert_eq!(_format_rfc3339(0, 0), "1970-01-01T00:00:00Z");
+        assert_eq!(_format_rfc3339(1_709_303_400 * SECOND, 0), "2024-03-01T14:30:00Z");
+        assert_eq!(_format_rfc3339(1_709_303_400 * SECOND, 60), "2024-03-01T15:30:00+01:00");
+        assert_eq!(_format_rfc3339(1_709_303_400 * SECOND, -330), "2024-03-01T09:00:00-05:30");
+        // The offset can move the date across a leap day
+        assert_eq!(_format_rfc3339(1_709_251_199 * SECOND, 0), "2024-02-29T23:59:59Z");
+        assert_eq!(_format_rfc3339(1_709_251_199 * SECOND, 60), "2024-03-01T00:59:59+01:00");
+    }
 
-//Retreive the course from storage
-fn _get_course_(id: &u64) -> Option<Course> {
-    STORAGE.with(|service| service.borrow().get(id))
-}
+    fn draft_by(id: u64, creator: &str) -> Draft {
+        Draft {
+            id: CourseId(id),
+            creator_address: creator.to_string(),
+            content: CourseUpdatePayLoad {
+                title: Some(format!("Draft {}", id)),
+                keyword: Some("rust".to_string()),
+                ..Default::default()
+            },
+            created_at: 10,
+            saved_at: 20,
+        }
+    }
 
-// Add the course into the storage
-fn do_insert(course: &Course) {
-    STORAGE.with(|service| service.borrow_mut().insert(course.id, course.clone()));
-}
+    #[test]
+    fn drafts_stay_with_their_author_and_read_as_the_course_they_become() {
+        for draft in [draft_by(1, "author"), draft_by(2, "other")] {
+            DRAFTS.with(|drafts| drafts.borrow_mut().insert(draft.id, draft));
+        }
 
-// Checks if the address is the admin
-fn _is_admin(address: String) -> bool {
-    let admin_address = ADMIN_ADDRESS.with(|admin_address| {
-        admin_address.lock().unwrap().clone()
-    });
+        let drafts = _drafts_of("author");
+        assert_eq!(drafts.len(), 1);
+        let course: Course = drafts[0].clone().into();
+        assert_eq!(course.id.0, 1);
+        assert_eq!(course.title, "Draft 1");
+        assert_eq!(course.keyword, "rust");
+        assert!(course.body.is_empty() && course.contact.is_empty());
+        assert_eq!((course.created_at, course.updated_at), (10, Some(20)));
+        assert_eq!(_export_records("author", "drafts").len(), 1);
+    }
 
-    if let Some(admin) = &admin_address {
-        if address == *admin {
-            true
-        } else {
-            false
+    #[test]
+    fn account_deletion_removes_the_drafts_of_the_account() {
+        for draft in [draft_by(1, "leaver"), draft_by(2, "stayer")] {
+            DRAFTS.with(|drafts| drafts.borrow_mut().insert(draft.id, draft));
         }
-    } else {
-        false
+        let deletion = AccountDeletion {
+            address: "leaver".to_string(),
+            policy: CoursePolicy::Delete,
+            requested_at: 0,
+            removed_records: 0,
+        };
+        assert_eq!(_delete_account_batch(&deletion, ACCOUNT_DELETION_BATCH), 1);
+        assert!(_drafts_of("leaver").is_empty());
+        assert_eq!(_drafts_of("stayer").len(), 1);
     }
-}
 
-// Checks if the caller is either the admin or a moderator
-fn _is_authorized(address: String) -> bool {
-    let admin_address = ADMIN_ADDRESS.with(|admin_address| {
-        admin_address.lock().unwrap().clone()
-    });
-    if let Some(admin) = &admin_address {
-        if address == *admin {
-            true
-        } else {
-            // Check if the caller is one of the moderators
-            let moderators = MODERATOR_ADDRESSES.with(|moderator_addresses| {
-                moderator_addresses.lock().unwrap().clone()
-            });
-            moderators.contains(&address.to_string())
+    #[test]
+    fn principals_without_courses_can_be_banned_but_other_text_cannot() {
+        assert!(_course_ids_by_creator("2vxsx-fae").is_empty());
+        assert!(_check_ban_address("2vxsx-fae").is_ok());
+        assert!(_check_ban_address("rrkah-fqaaa-aaaaa-aaaaq-cai").is_ok());
+        for address in ["", "not a principal", "rrkah-fqaaa-aaaaa-aaaaq-caa"] {
+            assert!(_check_ban_address(address).is_err(), "{} was accepted", address);
         }
-    } else {
-        false
     }
-}
 
-// Checks if the caller is either the creator of the id, or the admin or a moderator
-fn _is_allowed(id: u64, caller: String) -> bool {
-    let course = STORAGE.with(|service| service.borrow().get(&id));
-    // Check if the caller is the creator of the course
-    if course.unwrap().creator_address == caller.to_string() {
-        true
-    } else {
-        // Check if the caller is the admin
-        let admin_address = ADMIN_ADDRESS.with(|admin_address| {
-            admin_address.lock().unwrap().clone()
+    #[test]
+    fn bans_with_the_longest_reason_fit_in_stable_memory_and_lapse_at_their_expiry() {
+        let address = "rrkah-fqaaa-aaaaa-aaaaq-cai".repeat(2);
+        let ban = BanRecord {
+            address: address.clone(),
+            reason: Some("\u{1F6AB}".repeat(MAX_NOTE_LENGTH)),
+            banned_by: "2vxsx-fae".repeat(7),
+            banned_at: u64::MAX,
+            expires_at: Some(100),
+        };
+        BANS.with(|bans| bans.borrow_mut().insert(UserKey(address.clone()), ban));
+        let stored = BANS.with(|bans| bans.borrow().get(&UserKey(address))).unwrap();
+        assert_eq!(stored.reason.as_ref().unwrap().chars().count(), MAX_NOTE_LENGTH);
+        assert!(_ban_in_effect(&stored, 99));
+        assert!(!_ban_in_effect(&stored, 100));
+        assert!(_ban_in_effect(&BanRecord { expires_at: None, ..stored }, u64::MAX));
+    }
+
+    #[test]
+    fn bulk_bans_check_their_size_and_spare_the_staff() {
+        assert!(_check_bulk_size(&[]).is_err());
+        assert!(_check_bulk_size(&vec!["2vxsx-fae".to_string(); MAX_BULK_BAN]).is_ok());
+        assert!(_check_bulk_size(&vec!["2vxsx-fae".to_string(); MAX_BULK_BAN + 1]).is_err());
+        assert!(!_is_bannable("user"));
+        ADMIN_ADDRESS.with(|admin| *admin.lock().unwrap() = Some("admin".to_string()));
+        MODERATOR_ADDRESSES.with(|moderators| moderators.lock().unwrap().push("moderator".to_string()));
+        assert!(_is_bannable("user"));
+        assert!(!_is_bannable("admin"));
+        assert!(!_is_bannable("moderator"));
+    }
+
+    #[test]
+    fn banned_courses_are_deleted_in_batches_and_unbanned_addresses_keep_theirs() {
+        for id in 1..=3 {
+            do_insert(&course_by(id, "banned"));
+        }
+        do_insert(&course_by(4, "unbanned"));
+        assert_eq!(_delete_banned_courses("banned", 2), 2);
+        assert_eq!(_course_ids_by_creator("banned").len(), 1);
+        assert_eq!(_delete_banned_courses("banned", 2), 1);
+        assert!(_course_ids_by_creator("banned").is_empty());
+        BAN_CLEANUPS.with(|cleanups| cleanups.lock().unwrap().push("unbanned".to_string()));
+        _continue_ban_cleanups();
+        assert_eq!(_course_ids_by_creator("unbanned").len(), 1);
+        assert!(BAN_CLEANUPS.with(|cleanups| cleanups.lock().unwrap().is_empty()));
+    }
+
+    #[test]
+    fn link_sweeps_stop_running_when_their_guard_is_dropped() {
+        let running = LinkSweepGuard::new();
+        assert!(LINK_SWEEP_RUNNING.with(|running| *running.lock().unwrap()));
+        drop(running);
+        assert!(!LINK_SWEEP_RUNNING.with(|running| *running.lock().unwrap()));
+    }
+
+    #[test]
+    fn message_patterns_match_their_placeholders_in_order() {
+        assert_eq!(_match_pattern("Course with id={} not found", "Course with id=42 not found"), Some(vec!["42"]));
+        assert_eq!(_match_pattern("Course {} by {}", "Course Rust by rust by example"), Some(vec!["Rust", "rust by example"]));
+        assert_eq!(_match_pattern("Nothing to do", "Nothing to do"), Some(Vec::new()));
+        assert_eq!(_match_pattern("Nothing to do", "Nothing to do yet"), None);
+        assert_eq!(_match_pattern("Course with id={} not found", "Course with id=42 was removed"), None);
+        let texts: Vec<&str> = (1..=10).map(|_| "x").chain(std::iter::once("ten")).collect();
+        assert_eq!(_fill_placeholders("{11} {1}", &texts), "ten x");
+        assert_eq!(_fill_placeholders("Curso con id={1} no encontrado", &["42"]), "Curso con id=42 no encontrado");
+        assert_eq!(_placeholder_numbers("{2} de {1} y {x}"), vec![2, 1]);
+    }
+
+    #[test]
+    fn messages_are_given_in_the_language_of_the_user_or_its_primary_language() {
+        let settings = UserSettings {
+            language: Some("es-mx".to_string()),
+            ..Default::default()
+        };
+        USER_SETTINGS.with(|user_settings| user_settings.borrow_mut().insert(UserKey("user".to_string()), settings));
+        for (pattern, translation) in [("Course with id={} not found", "Curso {1} no encontrado"), ("Course with id=1 not found", "El curso 1 no existe")] {
+            let key = MessageKey {
+                language: "es".to_string(),
+                pattern: pattern.to_string(),
+            };
+            let entry = MessageTranslation {
+                language: key.language.clone(),
+                pattern: key.pattern.clone(),
+                translation: translation.to_string(),
+                updated_by: "admin".to_string(),
+                updated_at: 0,
+            };
+            MESSAGE_CATALOG.with(|catalog| catalog.borrow_mut().insert(key, entry));
+        }
+        assert_eq!(_localize("user", "Course with id=7 not found".to_string()), "Curso 7 no encontrado");
+        assert_eq!(_localize("user", "Course with id=1 not found".to_string()), "El curso 1 no existe");
+        assert_eq!(_localize("user", "Please provide a title".to_string()), "Please provide a title");
+        assert_eq!(_localize("someone else", "Course with id=7 not found".to_string()), "Course with id=7 not found");
+    }
+
+    #[test]
+    fn heap_state_saved_before_an_upgrade_is_restored_after_it() {
+        ADMIN_ADDRESS.with(|admin| *admin.lock().unwrap() = Some("admin".to_string()));
+        MODERATOR_TERMS.with(|terms| terms.lock().unwrap().insert("moderator".to_string(), 100));
+        PENDING_BANS.with(|bans| bans.lock().unwrap().push(pending_ban("user", vec![CourseId(7)])));
+        PREVIEW_LENGTH.with(|length| *length.lock().unwrap() = 80);
+        pre_upgrade();
+        ADMIN_ADDRESS.with(|admin| *admin.lock().unwrap() = None);
+        MODERATOR_TERMS.with(|terms| terms.lock().unwrap().clear());
+        PENDING_BANS.with(|bans| bans.lock().unwrap().clear());
+        PREVIEW_LENGTH.with(|length| *length.lock().unwrap() = DEFAULT_PREVIEW_LENGTH);
+
+        let saved = HEAP_STATE.with(|saved| saved.borrow().get().clone());
+        let VersionedHeapState::V1(state) = Decode!(&saved, VersionedHeapState).unwrap();
+        _restore_heap_state(state);
+        assert_eq!(ADMIN_ADDRESS.with(|admin| admin.lock().unwrap().clone()), Some("admin".to_string()));
+        assert_eq!(MODERATOR_TERMS.with(|terms| terms.lock().unwrap().get("moderator").copied()), Some(100));
+        assert_eq!(PENDING_BANS.with(|bans| bans.lock().unwrap()[0].hidden_course_ids[0].0), 7);
+        assert_eq!(PREVIEW_LENGTH.with(|length| *length.lock().unwrap()), 80);
+    }
+
+    #[test]
+    fn course_pages_hold_the_courses_listed_for_the_viewer_in_id_order() {
+        for id in 1..=5 {
+            do_insert(&course_by(id, "creator"));
+        }
+        do_insert(&Course {
+            content_rating: Some(ContentRating::Mature),
+            ..course_by(6, "creator")
         });
-        if let Some(admin) = &admin_address {
-            if caller == *admin {
-                true
-            } else {
-                // Check if the caller is one of the moderators
-                let moderators = MODERATOR_ADDRESSES.with(|moderator_addresses| {
-                    moderator_addresses.lock().unwrap().clone()
-                });
-                moderators.contains(&caller.to_string())
-            }
-        } else {
-            false
+        let viewer = Viewer {
+            address: "learner".to_string(),
+            show_mature: false,
+        };
+        let (courses, total_count) = _courses_from(&viewer, 1, 2);
+        assert_eq!(courses.iter().map(|course| course.id.0).collect::<Vec<u64>>(), vec![2, 3]);
+        assert_eq!(total_count, 5);
+        let (courses, total_count) = _courses_from(&viewer, 4, 10);
+        assert_eq!(courses.len(), 1);
+        assert_eq!(total_count, 5);
+        let (courses, total_count) = _courses_from(&Viewer { show_mature: true, ..viewer }, u64::MAX, 10);
+        assert!(courses.is_empty());
+        assert_eq!(total_count, 6);
+    }
+
+    #[test]
+    fn course_chunks_stop_at_the_size_budget_and_continue_from_the_first_course_left_out() {
+        let viewer = Viewer {
+            address: "learner".to_string(),
+            show_mature: false,
+        };
+        for id in 1..=2500 {
+            do_insert(&Course {
+                body: "a".repeat(700),
+                ..course_by(id, "creator")
+            });
         }
+        let (courses, next_token) = _courses_within_budget(&viewer, None, None, |_| true);
+        let size: usize = courses.iter().map(|course| Encode!(course).unwrap().len()).sum();
+        assert!(size <= CHUNK_SIZE_LIMIT);
+        let next_token = next_token.unwrap();
+        assert_eq!(next_token.0, courses.len() as u64 + 1);
+        let (rest, next_token) = _courses_within_budget(&viewer, Some(next_token), None, |_| true);
+        assert_eq!(courses.len() + rest.len(), 2500);
+        assert!(next_token.is_none());
     }
-}
 
-// Error types
-#[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
-    NotFound { msg: String },
-    UnAuthorized { msg: String },
-    EmptyFields {msg: String},
-    BannedUser {msg: String}
+    #[test]
+    fn courses_sort_by_update_title_and_creator_ignoring_case() {
+        let courses = [
+            (1, "beta", "Zoe", 10, Some(40)),
+            (2, "Alpha", "adam", 20, None),
+            (3, "alpha", "Adam", 30, None),
+        ];
+        for (id, title, creator_name, created_at, updated_at) in courses {
+            do_insert(&Course {
+                title: title.to_string(),
+                creator_name: creator_name.to_string(),
+                created_at,
+                updated_at,
+                ..course_by(id, &format!("creator{}", id))
+            });
+        }
+        let sorted = |sort| _sorted_ids(vec![CourseId(1), CourseId(2), CourseId(3)], sort).iter().map(|id| id.0).collect::<Vec<u64>>();
+        assert_eq!(sorted(Some(SortBy::UpdatedAt)), vec![1, 3, 2]);
+        assert_eq!(sorted(Some(SortBy::Title)), vec![2, 3, 1]);
+        assert_eq!(sorted(Some(SortBy::Creator)), vec![2, 3, 1]);
+        assert_eq!(sorted(None), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn cursor_pages_continue_after_the_last_course_of_the_previous_page() {
+        for id in [2, 4, 6, 8, 10] {
+            do_insert(&course_by(id, "creator"));
+        }
+        let viewer = Viewer {
+            address: "learner".to_string(),
+            show_mature: false,
+        };
+        let ids = |courses: Vec<Course>| courses.iter().map(|course| course.id.0).collect::<Vec<u64>>();
+        let (courses, cursor) = _courses_after(&viewer, None, 2);
+        assert_eq!(ids(courses), vec![2, 4]);
+        assert_eq!(cursor.map(|id| id.0), Some(4));
+        let (courses, cursor) = _courses_after(&viewer, Some(CourseId(5)), 3);
+        assert_eq!(ids(courses), vec![6, 8, 10]);
+        assert!(cursor.is_none());
+        assert!(_courses_after(&viewer, Some(CourseId(u64::MAX)), 3).0.is_empty());
+    }
+
+    #[test]
+    fn search_index_finds_courses_with_every_word_and_ranks_title_matches_first() {
+        assert_eq!(_tokenize("Learn Rust, the hard-way: a guide"), vec!["learn", "rust", "the", "hard", "way", "guide"]);
+        do_insert(&Course {
+            title: "Cooking basics".to_string(),
+            body: "Ownership of the kitchen in rust colored pans".to_string(),
+            ..course_by(1, "creator")
+        });
+        do_insert(&Course {
+            title: "Rust ownership".to_string(),
+            body: "Borrowing explained".to_string(),
+            ..course_by(2, "creator")
+        });
+        do_insert(&Course {
+            title: "Go basics".to_string(),
+            keyword: "go".to_string(),
+            tags: vec!["go".to_string()],
+            ..course_by(3, "creator")
+        });
+        let search = |query: &str| _search_index_matches(_tokenize(query)).iter().map(|id| id.0).collect::<Vec<u64>>();
+        assert_eq!(search("OWNERSHIP rust"), vec![2, 1]);
+        assert_eq!(search("rust basics"), vec![1]);
+        assert!(search("ownership go").is_empty());
+        do_remove(CourseId(2));
+        assert_eq!(search("ownership"), vec![1]);
+    }
+
+    #[test]
+    fn keyword_suggestions_rank_phrases_by_course_count_then_keywords_first() {
+        do_insert(&Course {
+            title: "Rust for beginners".to_string(),
+            keyword: "rust".to_string(),
+            tags: vec!["rust".to_string()],
+            ..course_by(1, "creator")
+        });
+        do_insert(&Course {
+            title: "Rust".to_string(),
+            keyword: "Rustaceans".to_string(),
+            tags: vec!["Rustaceans".to_string()],
+            ..course_by(2, "creator")
+        });
+        do_insert(&Course {
+            title: "Ruby".to_string(),
+            keyword: "ruby".to_string(),
+            tags: vec!["ruby".to_string()],
+            content_rating: Some(ContentRating::Mature),
+            ..course_by(3, "creator")
+        });
+        let viewer = Viewer {
+            address: "learner".to_string(),
+            show_mature: false,
+        };
+        let suggestions = _prefix_suggestions(&viewer, "ru", 10);
+        let texts: Vec<&str> = suggestions.iter().map(|suggestion| suggestion.text.as_str()).collect();
+        assert_eq!(texts, vec!["rust", "rustaceans", "rust for beginners"]);
+        assert_eq!(suggestions[0].course_count, 2);
+        assert!(suggestions[0].is_keyword);
+        assert!(!suggestions[2].is_keyword);
+        assert_eq!(_prefix_suggestions(&viewer, "rust", 1).len(), 1);
+        do_remove(CourseId(1));
+        assert_eq!(_prefix_suggestions(&viewer, "rust ", 10).len(), 0);
+    }
+
+    #[test]
+    fn category_criteria_narrow_the_courses_to_the_category_index() {
+        do_insert(&course_by(1, "creator"));
+        do_insert(&Course {
+            category: "Design".to_string(),
+            ..course_by(2, "creator")
+        });
+        do_insert(&Course {
+            category: "Game design".to_string(),
+            ..course_by(3, "creator")
+        });
+        let candidates = |criteria: &[Criterion], mode, combinator| {
+            _indexed_candidates(&_resolve_criteria(criteria, mode), combinator).map(|ids| ids.iter().map(|id| id.0).collect::<Vec<u64>>())
+        };
+        let design = Criterion::Category("DESIGN".to_string());
+        let rust = Criterion::Keyword("rust".to_string());
+        assert_eq!(candidates(&[design.clone(), rust.clone()], None, Combinator::And), Some(vec![2]));
+        assert_eq!(candidates(std::slice::from_ref(&design), Some(MatchMode::Contains), Combinator::And), Some(vec![2, 3]));
+        assert_eq!(candidates(&[design.clone(), Criterion::Category("Programming".to_string())], None, Combinator::Or), Some(vec![1, 2]));
+        assert_eq!(candidates(&[design.clone(), rust.clone()], None, Combinator::Or), None);
+        assert_eq!(candidates(&[rust], None, Combinator::And), None);
+        do_remove(CourseId(2));
+        assert_eq!(candidates(&[design], None, Combinator::And), Some(Vec::new()));
+    }
+
+    #[test]
+    fn the_first_tag_is_stored_as_the_keyword() {
+        let course = Course {
+            keyword: "stale".to_string(),
+            tags: vec!["Rust".to_string(), "web".to_string()],
+            ..course_by(1, "creator")
+        };
+        let stored = _to_course(_to_stored(&course));
+        assert_eq!(stored.keyword, "Rust");
+        assert_eq!(stored.tags, vec!["Rust", "web"]);
+    }
+
+    #[test]
+    fn records_too_large_for_their_storage_are_refused_instead_of_trapping() {
+        let long = "\u{1d54f}".repeat(MAX_NOTE_LENGTH);
+        let course = Course {
+            tags: (0..MAX_TAGS).map(|tag| format!("{}{}", tag, "\u{1d54f}".repeat(MAX_TAG_LENGTH - 1))).collect(),
+            contact: vec![ContactMethod::Other("\u{1d54f}".repeat(MAX_URL_LENGTH)); MAX_CONTACTS],
+            ..course_by(1, "creator")
+        };
+        let mut takedown = Takedown {
+            course: course_by(1, "creator"),
+            reason: TakedownReason::Spam,
+            note: String::new(),
+            taken_down_by: "moderator".to_string(),
+            taken_down_at: 0,
+            appeal: None,
+        };
+        assert!(_check_fits(&takedown, "takedown").is_ok());
+        takedown.course = course.clone();
+        takedown.note = long.clone();
+        takedown.appeal = Some(Appeal {
+            message: long,
+            appealed_at: 0,
+            rejected_at: None,
+        });
+        assert!(_check_fits(&takedown, "takedown").is_err());
+        let queued = QueuedCourse {
+            course: course.clone(),
+            matched_terms: Vec::new(),
+            queued_at: 0,
+            spam_signals: None,
+        };
+        assert!(_check_fits(&queued, "course").is_err());
+        assert!(_check_fits(&ArchivedCourse { course, archived_at: 0 }, "archived course").is_err());
+    }
 }
 
-// need this to generate candid
-ic_cdk::export_candid!();
\ No newline at end of file